@@ -0,0 +1,14 @@
+#![no_main]
+
+//! Feeds arbitrary bytes to every entry point that accepts source text.
+//! Run with `cargo +nightly fuzz run parse` from the repository root.
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(source) = std::str::from_utf8(data) {
+        let _ = ew::parser::parse(source);
+        let _ = ew::parser::parse_all(source);
+        let _ = ew::lexer::tokenize(source);
+    }
+});
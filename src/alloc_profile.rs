@@ -0,0 +1,97 @@
+//! The counting behind `ew run --alloc-profile`: how many strings, arrays,
+//! maps, big integers and variants a program creates, and where.
+
+use std::collections::{BTreeMap, HashMap};
+
+use crate::interpreter::Val;
+
+/// How many sites the report lists.
+const TOP_SITES: usize = 10;
+
+#[derive(Default)]
+pub(crate) struct AllocProfile {
+    by_type: BTreeMap<&'static str, u64>,
+    /// Keyed by what made the values and where: the expression's place in
+    /// the source, or the function it ran in when that isn't known.
+    by_site: HashMap<(String, String), u64>,
+    /// The user functions being called, innermost last, each described by
+    /// its name and where it was defined.
+    functions: Vec<String>,
+}
+
+impl AllocProfile {
+    pub(crate) fn enter(&mut self, function: String) {
+        self.functions.push(function);
+    }
+
+    pub(crate) fn leave(&mut self) {
+        self.functions.pop();
+    }
+
+    /// Counts `val` as made by `what` at `at`, a `file:line:col`: every
+    /// heap value in it when `deep`, as for a copy, or only the outermost
+    /// one, as for a literal whose elements were counted when they were
+    /// made.
+    pub(crate) fn record(&mut self, what: String, at: Option<String>, val: &Val, deep: bool) {
+        let mut count = 0;
+        self.count(val, deep, &mut count);
+        if count == 0 {
+            return;
+        }
+        let site = match (at, self.functions.last()) {
+            (Some(at), _) => format!("at {}", at),
+            (None, Some(function)) => format!("in {}", function),
+            (None, None) => "at top level".to_string(),
+        };
+        *self.by_site.entry((what, site)).or_default() += count;
+    }
+
+    fn count(&mut self, val: &Val, deep: bool, count: &mut u64) {
+        let children: &[Val] = match val {
+            Val::Str(_) | Val::BigInt(_) => &[],
+            Val::Array(items) | Val::Variant { fields: items, .. } => items,
+            Val::Map(map) => {
+                self.add(val, count);
+                if deep {
+                    for item in map.values() {
+                        self.count(item, deep, count);
+                    }
+                }
+                return;
+            }
+            Val::Frozen { value, .. } => return self.count(value, deep, count),
+            _ => return,
+        };
+        self.add(val, count);
+        if deep {
+            for item in children {
+                self.count(item, deep, count);
+            }
+        }
+    }
+
+    fn add(&mut self, val: &Val, count: &mut u64) {
+        *self.by_type.entry(val.type_name()).or_default() += 1;
+        *count += 1;
+    }
+
+    /// The totals by type and the sites that made the most values.
+    pub(crate) fn report(&self) -> String {
+        let total: u64 = self.by_type.values().sum();
+        let mut out = format!("Allocations: {}\n", total);
+        let mut types: Vec<_> = self.by_type.iter().collect();
+        types.sort_by(|a, b| b.1.cmp(a.1));
+        for (name, count) in types {
+            out.push_str(&format!("  {:<8}{:>10}\n", name, count));
+        }
+        let mut sites: Vec<_> = self.by_site.iter().collect();
+        sites.sort_by(|a, b| b.1.cmp(a.1).then_with(|| a.0.cmp(b.0)));
+        if !sites.is_empty() {
+            out.push_str("Top allocation sites:\n");
+        }
+        for ((what, site), count) in sites.into_iter().take(TOP_SITES) {
+            out.push_str(&format!("  {:>10}  {} {}\n", count, what, site));
+        }
+        out
+    }
+}
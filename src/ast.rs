@@ -1,4 +1,9 @@
-use crate::interpreter::Val;
+use num_bigint::BigInt;
+
+use crate::version::{self, Feature};
+
+/// A whole parsed source file or REPL input, statement by statement.
+pub type Program = Vec<Stmt>;
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Stmt {
@@ -6,9 +11,22 @@ pub enum Stmt {
         name: String,
         params: Vec<String>,
         body: Vec<Stmt>,
+        /// `@name` lines above the `fn`, outermost first.
+        decorators: Vec<String>,
+        /// Where the definition starts in its source, or 0 when it was
+        /// built in Rust.
+        line: usize,
+    },
+
+    Enum {
+        name: String,
+        variants: Vec<Variant>,
     },
 
     Return(Expr),
+    Break(Option<Expr>),
+    /// `break label`, leaving the enclosing loop with that label.
+    BreakLabel(String),
     Assignment {
         name: String,
         value: Expr,
@@ -18,6 +36,53 @@ pub enum Stmt {
         value: Expr,
     },
     Expr(Expr),
+    /// `#pragma version ...` or `#feature(...)`; the parser only accepts
+    /// them before a file's other statements.
+    Pragma(Pragma),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pragma {
+    /// `#pragma version 0.2`: every feature of that language version.
+    Version(String),
+    /// `#feature(name)`: one feature, ahead of the version that has it.
+    Feature(Feature),
+}
+
+impl Pragma {
+    /// The features the pragma turns on; an error for an unknown version.
+    pub fn features(&self) -> Result<Vec<Feature>, String> {
+        match self {
+            Pragma::Version(v) => version::features(v).map(<[_]>::to_vec),
+            Pragma::Feature(feature) => Ok(vec![*feature]),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variant {
+    pub name: String,
+    pub fields: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MatchArm {
+    pub pattern: Pattern,
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Pattern {
+    /// `_`
+    Wildcard,
+    /// A literal compared with `==`, e.g. `0` or `"quit"`.
+    Literal(Expr),
+    /// `Enum::Variant` or `Enum::Variant(a, b)`, binding the fields.
+    Variant {
+        enum_name: String,
+        variant: String,
+        bindings: Vec<String>,
+    },
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -29,40 +94,101 @@ pub enum AssignmentTarget {
     },
 }
 
+/// An expression. Those that make new values also record their `span`,
+/// where they are in the source, for the allocation profile.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Int(i64),
+    BigInt(BigInt),
     Float(f64),
     Bool(bool),
-    Str(String),
-    Array(Vec<Expr>),
+    Str {
+        value: String,
+        span: Span,
+    },
+    Null,
+    Array {
+        items: Vec<Expr>,
+        span: Span,
+    },
     ArrayAccess {
         name: String,
         indices: Vec<Box<Expr>>,
+        span: Span,
+    },
+    /// `name[i]...[start..end]`: part of an array or string. A missing
+    /// bound is that end of it; negative ones count from the end.
+    Slice {
+        name: String,
+        indices: Vec<Box<Expr>>,
+        start: Option<Box<Expr>>,
+        end: Option<Box<Expr>>,
+        span: Span,
     },
     Var(String),
 
     Unary {
         op: UnaryOp,
         expr: Box<Expr>,
+        span: Span,
     },
     Binary {
         op: BinaryOp,
         lhs: Box<Expr>,
         rhs: Box<Expr>,
+        span: Span,
     },
     Call {
         name: String,
         args: Vec<Expr>,
+        span: Span,
+    },
+    /// `...xs` in a call's argument list.
+    Spread(Box<Expr>),
+    /// `Enum::Variant` or `Enum::Variant(args)`.
+    Variant {
+        enum_name: String,
+        variant: String,
+        args: Vec<Expr>,
+        span: Span,
+    },
+    Match {
+        scrutinee: Box<Expr>,
+        arms: Vec<MatchArm>,
     },
     If {
         cond: Box<Expr>,
         then: Vec<Stmt>,
         else_: Vec<Stmt>,
+        /// Where `cond` is in its source, for errors about it.
+        span: Span,
     },
     While {
         cond: Box<Expr>,
         body: Vec<Stmt>,
+        /// Where `cond` is in its source, for errors about it.
+        span: Span,
+    },
+    /// `if let name = value { then } else { else_ }`, taking `then` when
+    /// `value` isn't null.
+    IfLet {
+        name: String,
+        value: Box<Expr>,
+        then: Vec<Stmt>,
+        else_: Vec<Stmt>,
+    },
+    /// `let name = value in body`: `body` with `name` bound in a scope of
+    /// its own.
+    LetIn {
+        name: String,
+        value: Box<Expr>,
+        body: Box<Expr>,
+    },
+    /// `while let name = value { body }`, looping until `value` is null.
+    WhileLet {
+        name: String,
+        value: Box<Expr>,
+        body: Vec<Stmt>,
     },
     For {
         var: String,
@@ -70,9 +196,67 @@ pub enum Expr {
         end: Box<Expr>,
         body: Vec<Stmt>,
     },
+    Loop(Vec<Stmt>),
+    /// `[value for var in source if cond]`, or `{key: value for ...}` to
+    /// build a map instead of an array.
+    Comprehension {
+        key: Option<Box<Expr>>,
+        value: Box<Expr>,
+        var: String,
+        source: Iteration,
+        cond: Option<Box<Expr>>,
+        /// Where `cond` is in its source, when there is one.
+        cond_span: Span,
+        span: Span,
+    },
+    /// A loop with a label that `break label` inside it can target.
+    Labeled {
+        label: String,
+        body: Box<Expr>,
+    },
     Block(Vec<Stmt>),
 }
 
+/// A stretch of source text, from `start` up to `end`, each a 1-based line
+/// and column. It is all zeros when the tree was built in Rust, and every
+/// span equals every other so trees compare by structure alone.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Span {
+    pub start: (usize, usize),
+    pub end: (usize, usize),
+}
+
+impl Span {
+    pub fn is_known(&self) -> bool {
+        self.start.0 > 0
+    }
+}
+
+impl PartialEq for Span {
+    fn eq(&self, _: &Span) -> bool {
+        true
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{}:{}-{}:{}",
+            self.start.0, self.start.1, self.end.0, self.end.1
+        )
+    }
+}
+
+/// What a comprehension's `for` runs over.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Iteration {
+    /// `start..end`, end exclusive.
+    Range { start: Box<Expr>, end: Box<Expr> },
+    /// The elements of an array.
+    Each(Box<Expr>),
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum UnaryOp {
     Neg,
@@ -86,6 +270,7 @@ pub enum BinaryOp {
     Mul,
     Div,
     Mod,
+    Pow,
 
     Eq,
     Ne,
@@ -97,3 +282,596 @@ pub enum BinaryOp {
     And,
     Or,
 }
+
+/// Constructors for building programs in Rust without going through source
+/// text, e.g. `Stmt::func("inc", ["x"], [Stmt::ret(Expr::var("x") + Expr::int(1))])`.
+impl Stmt {
+    pub fn func<P, B>(name: &str, params: P, body: B) -> Stmt
+    where
+        P: IntoIterator,
+        P::Item: Into<String>,
+        B: IntoIterator<Item = Stmt>,
+    {
+        Stmt::Function {
+            name: name.to_string(),
+            params: params.into_iter().map(Into::into).collect(),
+            body: body.into_iter().collect(),
+            decorators: vec![],
+            line: 0,
+        }
+    }
+
+    pub fn ret(value: Expr) -> Stmt {
+        Stmt::Return(value)
+    }
+
+    /// `let name = value`
+    pub fn assign(name: &str, value: Expr) -> Stmt {
+        Stmt::Assignment {
+            name: name.to_string(),
+            value,
+        }
+    }
+
+    /// `name = value`
+    pub fn reassign(name: &str, value: Expr) -> Stmt {
+        Stmt::Reassignment {
+            target: AssignmentTarget::Ident(name.to_string()),
+            value,
+        }
+    }
+
+    /// `name[i][j]... = value`
+    pub fn reassign_index<I>(name: &str, indices: I, value: Expr) -> Stmt
+    where
+        I: IntoIterator<Item = Expr>,
+    {
+        Stmt::Reassignment {
+            target: AssignmentTarget::ArrayAccess {
+                name: name.to_string(),
+                indices: indices.into_iter().map(Box::new).collect(),
+            },
+            value,
+        }
+    }
+
+    pub fn expr(expr: Expr) -> Stmt {
+        Stmt::Expr(expr)
+    }
+}
+
+impl Expr {
+    /// Where the expression is in its source, for those that record it.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Expr::Str { span, .. }
+            | Expr::Array { span, .. }
+            | Expr::ArrayAccess { span, .. }
+            | Expr::Slice { span, .. }
+            | Expr::Unary { span, .. }
+            | Expr::Binary { span, .. }
+            | Expr::Call { span, .. }
+            | Expr::Variant { span, .. }
+            | Expr::Comprehension { span, .. } => Some(*span),
+            _ => None,
+        }
+    }
+
+    pub fn int(n: i64) -> Expr {
+        Expr::Int(n)
+    }
+
+    pub fn float(f: f64) -> Expr {
+        Expr::Float(f)
+    }
+
+    pub fn bool(b: bool) -> Expr {
+        Expr::Bool(b)
+    }
+
+    pub fn str(s: &str) -> Expr {
+        Expr::Str {
+            value: s.to_string(),
+            span: Span::default(),
+        }
+    }
+
+    pub fn var(name: &str) -> Expr {
+        Expr::Var(name.to_string())
+    }
+
+    pub fn array<I: IntoIterator<Item = Expr>>(elems: I) -> Expr {
+        Expr::Array {
+            items: elems.into_iter().collect(),
+            span: Span::default(),
+        }
+    }
+
+    pub fn index<I: IntoIterator<Item = Expr>>(name: &str, indices: I) -> Expr {
+        Expr::ArrayAccess {
+            name: name.to_string(),
+            indices: indices.into_iter().map(Box::new).collect(),
+            span: Span::default(),
+        }
+    }
+
+    pub fn call<I: IntoIterator<Item = Expr>>(name: &str, args: I) -> Expr {
+        Expr::Call {
+            name: name.to_string(),
+            args: args.into_iter().collect(),
+            span: Span::default(),
+        }
+    }
+
+    pub fn binary(op: BinaryOp, lhs: Expr, rhs: Expr) -> Expr {
+        Expr::Binary {
+            op,
+            lhs: Box::new(lhs),
+            rhs: Box::new(rhs),
+            span: Span::default(),
+        }
+    }
+
+    pub fn unary(op: UnaryOp, expr: Expr) -> Expr {
+        Expr::Unary {
+            op,
+            expr: Box::new(expr),
+            span: Span::default(),
+        }
+    }
+
+    pub fn pow(self, rhs: Expr) -> Expr {
+        Expr::binary(BinaryOp::Pow, self, rhs)
+    }
+
+    pub fn cmp_eq(self, rhs: Expr) -> Expr {
+        Expr::binary(BinaryOp::Eq, self, rhs)
+    }
+
+    pub fn cmp_ne(self, rhs: Expr) -> Expr {
+        Expr::binary(BinaryOp::Ne, self, rhs)
+    }
+
+    pub fn cmp_lt(self, rhs: Expr) -> Expr {
+        Expr::binary(BinaryOp::Lt, self, rhs)
+    }
+
+    pub fn cmp_le(self, rhs: Expr) -> Expr {
+        Expr::binary(BinaryOp::Le, self, rhs)
+    }
+
+    pub fn cmp_gt(self, rhs: Expr) -> Expr {
+        Expr::binary(BinaryOp::Gt, self, rhs)
+    }
+
+    pub fn cmp_ge(self, rhs: Expr) -> Expr {
+        Expr::binary(BinaryOp::Ge, self, rhs)
+    }
+
+    pub fn and(self, rhs: Expr) -> Expr {
+        Expr::binary(BinaryOp::And, self, rhs)
+    }
+
+    pub fn or(self, rhs: Expr) -> Expr {
+        Expr::binary(BinaryOp::Or, self, rhs)
+    }
+
+    pub fn if_else<T, E>(cond: Expr, then: T, else_: E) -> Expr
+    where
+        T: IntoIterator<Item = Stmt>,
+        E: IntoIterator<Item = Stmt>,
+    {
+        Expr::If {
+            cond: Box::new(cond),
+            then: then.into_iter().collect(),
+            else_: else_.into_iter().collect(),
+            span: Span::default(),
+        }
+    }
+
+    pub fn while_loop<B: IntoIterator<Item = Stmt>>(cond: Expr, body: B) -> Expr {
+        Expr::While {
+            cond: Box::new(cond),
+            body: body.into_iter().collect(),
+            span: Span::default(),
+        }
+    }
+
+    pub fn for_range<B: IntoIterator<Item = Stmt>>(
+        var: &str,
+        start: Expr,
+        end: Expr,
+        body: B,
+    ) -> Expr {
+        Expr::For {
+            var: var.to_string(),
+            start: Box::new(start),
+            end: Box::new(end),
+            body: body.into_iter().collect(),
+        }
+    }
+
+    pub fn block<B: IntoIterator<Item = Stmt>>(body: B) -> Expr {
+        Expr::Block(body.into_iter().collect())
+    }
+}
+
+macro_rules! impl_binary_operator {
+    ($trait:ident, $method:ident, $op:expr) => {
+        impl std::ops::$trait for Expr {
+            type Output = Expr;
+
+            fn $method(self, rhs: Expr) -> Expr {
+                Expr::binary($op, self, rhs)
+            }
+        }
+    };
+}
+
+impl_binary_operator!(Add, add, BinaryOp::Add);
+impl_binary_operator!(Sub, sub, BinaryOp::Sub);
+impl_binary_operator!(Mul, mul, BinaryOp::Mul);
+impl_binary_operator!(Div, div, BinaryOp::Div);
+impl_binary_operator!(Rem, rem, BinaryOp::Mod);
+
+impl std::ops::Neg for Expr {
+    type Output = Expr;
+
+    fn neg(self) -> Expr {
+        Expr::unary(UnaryOp::Neg, self)
+    }
+}
+
+impl std::ops::Not for Expr {
+    type Output = Expr;
+
+    fn not(self) -> Expr {
+        Expr::unary(UnaryOp::Not, self)
+    }
+}
+
+/// Read-only traversal over the AST.
+///
+/// Every method defaults to walking into the node's children, so an
+/// implementation only overrides the nodes it cares about and calls the
+/// matching `walk_*` function to keep descending.
+pub trait Visitor {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr);
+    }
+}
+
+pub fn walk_stmt<V: Visitor + ?Sized>(v: &mut V, stmt: &Stmt) {
+    match stmt {
+        Stmt::Function { body, .. } => body.iter().for_each(|s| v.visit_stmt(s)),
+        Stmt::Enum { .. } => {}
+        Stmt::Return(expr) | Stmt::Expr(expr) | Stmt::Break(Some(expr)) => v.visit_expr(expr),
+        Stmt::Break(None) | Stmt::BreakLabel(_) | Stmt::Pragma(_) => {}
+        Stmt::Assignment { value, .. } => v.visit_expr(value),
+        Stmt::Reassignment { target, value } => {
+            if let AssignmentTarget::ArrayAccess { indices, .. } = target {
+                indices.iter().for_each(|e| v.visit_expr(e));
+            }
+            v.visit_expr(value);
+        }
+    }
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(v: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Int(_)
+        | Expr::BigInt(_)
+        | Expr::Float(_)
+        | Expr::Bool(_)
+        | Expr::Str { .. }
+        | Expr::Null
+        | Expr::Var(_) => {}
+        Expr::Array { items, .. } => items.iter().for_each(|e| v.visit_expr(e)),
+        Expr::ArrayAccess { indices, .. } => indices.iter().for_each(|e| v.visit_expr(e)),
+        Expr::Slice {
+            indices,
+            start,
+            end,
+            ..
+        } => {
+            indices.iter().for_each(|e| v.visit_expr(e));
+            start.iter().chain(end).for_each(|e| v.visit_expr(e));
+        }
+        Expr::Unary { expr, .. } | Expr::Spread(expr) => v.visit_expr(expr),
+        Expr::Binary { lhs, rhs, .. } => {
+            v.visit_expr(lhs);
+            v.visit_expr(rhs);
+        }
+        Expr::Call { args, .. } | Expr::Variant { args, .. } => {
+            args.iter().for_each(|e| v.visit_expr(e))
+        }
+        Expr::Match { scrutinee, arms } => {
+            v.visit_expr(scrutinee);
+            for arm in arms {
+                if let Pattern::Literal(lit) = &arm.pattern {
+                    v.visit_expr(lit);
+                }
+                arm.body.iter().for_each(|s| v.visit_stmt(s));
+            }
+        }
+        Expr::If {
+            cond, then, else_, ..
+        } => {
+            v.visit_expr(cond);
+            then.iter().for_each(|s| v.visit_stmt(s));
+            else_.iter().for_each(|s| v.visit_stmt(s));
+        }
+        Expr::While { cond, body, .. } => {
+            v.visit_expr(cond);
+            body.iter().for_each(|s| v.visit_stmt(s));
+        }
+        Expr::IfLet {
+            value, then, else_, ..
+        } => {
+            v.visit_expr(value);
+            then.iter().for_each(|s| v.visit_stmt(s));
+            else_.iter().for_each(|s| v.visit_stmt(s));
+        }
+        Expr::LetIn { value, body, .. } => {
+            v.visit_expr(value);
+            v.visit_expr(body);
+        }
+        Expr::WhileLet { value, body, .. } => {
+            v.visit_expr(value);
+            body.iter().for_each(|s| v.visit_stmt(s));
+        }
+        Expr::For {
+            start, end, body, ..
+        } => {
+            v.visit_expr(start);
+            v.visit_expr(end);
+            body.iter().for_each(|s| v.visit_stmt(s));
+        }
+        Expr::Loop(stmts) | Expr::Block(stmts) => stmts.iter().for_each(|s| v.visit_stmt(s)),
+        Expr::Comprehension {
+            key,
+            value,
+            source,
+            cond,
+            ..
+        } => {
+            match source {
+                Iteration::Range { start, end } => {
+                    v.visit_expr(start);
+                    v.visit_expr(end);
+                }
+                Iteration::Each(items) => v.visit_expr(items),
+            }
+            if let Some(cond) = cond {
+                v.visit_expr(cond);
+            }
+            if let Some(key) = key {
+                v.visit_expr(key);
+            }
+            v.visit_expr(value);
+        }
+        Expr::Labeled { body, .. } => v.visit_expr(body),
+    }
+}
+
+/// Like [`Visitor`], but with mutable access so passes can rewrite nodes in
+/// place.
+pub trait VisitorMut {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt);
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+    }
+}
+
+pub fn walk_stmt_mut<V: VisitorMut + ?Sized>(v: &mut V, stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Function { body, .. } => body.iter_mut().for_each(|s| v.visit_stmt_mut(s)),
+        Stmt::Enum { .. } => {}
+        Stmt::Return(expr) | Stmt::Expr(expr) | Stmt::Break(Some(expr)) => v.visit_expr_mut(expr),
+        Stmt::Break(None) | Stmt::BreakLabel(_) | Stmt::Pragma(_) => {}
+        Stmt::Assignment { value, .. } => v.visit_expr_mut(value),
+        Stmt::Reassignment { target, value } => {
+            if let AssignmentTarget::ArrayAccess { indices, .. } = target {
+                indices.iter_mut().for_each(|e| v.visit_expr_mut(e));
+            }
+            v.visit_expr_mut(value);
+        }
+    }
+}
+
+pub fn walk_expr_mut<V: VisitorMut + ?Sized>(v: &mut V, expr: &mut Expr) {
+    match expr {
+        Expr::Int(_)
+        | Expr::BigInt(_)
+        | Expr::Float(_)
+        | Expr::Bool(_)
+        | Expr::Str { .. }
+        | Expr::Null
+        | Expr::Var(_) => {}
+        Expr::Array { items, .. } => items.iter_mut().for_each(|e| v.visit_expr_mut(e)),
+        Expr::ArrayAccess { indices, .. } => indices.iter_mut().for_each(|e| v.visit_expr_mut(e)),
+        Expr::Slice {
+            indices,
+            start,
+            end,
+            ..
+        } => {
+            indices.iter_mut().for_each(|e| v.visit_expr_mut(e));
+            start
+                .iter_mut()
+                .chain(end)
+                .for_each(|e| v.visit_expr_mut(e));
+        }
+        Expr::Unary { expr, .. } | Expr::Spread(expr) => v.visit_expr_mut(expr),
+        Expr::Binary { lhs, rhs, .. } => {
+            v.visit_expr_mut(lhs);
+            v.visit_expr_mut(rhs);
+        }
+        Expr::Call { args, .. } | Expr::Variant { args, .. } => {
+            args.iter_mut().for_each(|e| v.visit_expr_mut(e))
+        }
+        Expr::Match { scrutinee, arms } => {
+            v.visit_expr_mut(scrutinee);
+            for arm in arms {
+                if let Pattern::Literal(lit) = &mut arm.pattern {
+                    v.visit_expr_mut(lit);
+                }
+                arm.body.iter_mut().for_each(|s| v.visit_stmt_mut(s));
+            }
+        }
+        Expr::If {
+            cond, then, else_, ..
+        } => {
+            v.visit_expr_mut(cond);
+            then.iter_mut().for_each(|s| v.visit_stmt_mut(s));
+            else_.iter_mut().for_each(|s| v.visit_stmt_mut(s));
+        }
+        Expr::While { cond, body, .. } => {
+            v.visit_expr_mut(cond);
+            body.iter_mut().for_each(|s| v.visit_stmt_mut(s));
+        }
+        Expr::IfLet {
+            value, then, else_, ..
+        } => {
+            v.visit_expr_mut(value);
+            then.iter_mut().for_each(|s| v.visit_stmt_mut(s));
+            else_.iter_mut().for_each(|s| v.visit_stmt_mut(s));
+        }
+        Expr::LetIn { value, body, .. } => {
+            v.visit_expr_mut(value);
+            v.visit_expr_mut(body);
+        }
+        Expr::WhileLet { value, body, .. } => {
+            v.visit_expr_mut(value);
+            body.iter_mut().for_each(|s| v.visit_stmt_mut(s));
+        }
+        Expr::For {
+            start, end, body, ..
+        } => {
+            v.visit_expr_mut(start);
+            v.visit_expr_mut(end);
+            body.iter_mut().for_each(|s| v.visit_stmt_mut(s));
+        }
+        Expr::Loop(stmts) | Expr::Block(stmts) => {
+            stmts.iter_mut().for_each(|s| v.visit_stmt_mut(s))
+        }
+        Expr::Comprehension {
+            key,
+            value,
+            source,
+            cond,
+            ..
+        } => {
+            match source {
+                Iteration::Range { start, end } => {
+                    v.visit_expr_mut(start);
+                    v.visit_expr_mut(end);
+                }
+                Iteration::Each(items) => v.visit_expr_mut(items),
+            }
+            if let Some(cond) = cond {
+                v.visit_expr_mut(cond);
+            }
+            if let Some(key) = key {
+                v.visit_expr_mut(key);
+            }
+            v.visit_expr_mut(value);
+        }
+        Expr::Labeled { body, .. } => v.visit_expr_mut(body),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    struct VarCounter(usize);
+
+    impl Visitor for VarCounter {
+        fn visit_expr(&mut self, expr: &Expr) {
+            if let Expr::Var(_) = expr {
+                self.0 += 1;
+            }
+            walk_expr(self, expr);
+        }
+    }
+
+    struct ConstFolder;
+
+    impl VisitorMut for ConstFolder {
+        fn visit_expr_mut(&mut self, expr: &mut Expr) {
+            walk_expr_mut(self, expr);
+            if let Expr::Binary {
+                op: BinaryOp::Add,
+                lhs,
+                rhs,
+                ..
+            } = expr
+                && let (Expr::Int(a), Expr::Int(b)) = (lhs.as_ref(), rhs.as_ref())
+            {
+                *expr = Expr::Int(a + b);
+            }
+        }
+    }
+
+    #[test]
+    fn test_builder_matches_parser() {
+        let n = || Expr::var("n");
+        let built = vec![
+            Stmt::func(
+                "fib",
+                ["n"],
+                [Stmt::expr(Expr::if_else(
+                    n().cmp_lt(Expr::int(2)),
+                    [Stmt::ret(n())],
+                    [Stmt::ret(
+                        Expr::call("fib", [n() - Expr::int(1)])
+                            + Expr::call("fib", [n() - Expr::int(2)]),
+                    )],
+                ))],
+            ),
+            Stmt::assign("xs", Expr::array([Expr::int(1), -Expr::var("y")])),
+            Stmt::reassign_index("xs", [Expr::int(0)], !Expr::bool(true)),
+        ];
+        let mut parsed = parse(
+            r#"
+            fn fib(n) {
+                if (n < 2) { return n } else { return fib(n - 1) + fib(n - 2) }
+            }
+            let xs = [1, -y]
+            xs[0] = !true
+        "#,
+        )
+        .unwrap();
+        // Built functions have no source line to record.
+        if let Stmt::Function { line, .. } = &mut parsed[0] {
+            *line = 0;
+        }
+        assert_eq!(built, parsed);
+    }
+
+    #[test]
+    fn test_visitor_reaches_nested_nodes() {
+        let program = parse("fn f(a) { if (a < b) { arr[i] = c } }\nf(d)").unwrap();
+        let mut counter = VarCounter(0);
+        program.iter().for_each(|s| counter.visit_stmt(s));
+        assert_eq!(counter.0, 5);
+    }
+
+    #[test]
+    fn test_visitor_mut_rewrites_in_place() {
+        let mut program = parse("let x = 1 + 2 + 3\nf(4 + 5)").unwrap();
+        program
+            .iter_mut()
+            .for_each(|s| ConstFolder.visit_stmt_mut(s));
+        assert_eq!(program, parse("let x = 6\nf(9)").unwrap());
+    }
+}
@@ -0,0 +1,604 @@
+//! A Jupyter kernel for ew, built with the `jupyter` feature.
+//!
+//! `ew-jupyter install` registers the kernel with Jupyter; Jupyter then
+//! starts it as `ew-jupyter <connection file>`. Every cell runs in one
+//! interpreter, so definitions carry over from cell to cell, and what a cell
+//! prints is sent to the notebook once the cell finishes.
+
+use std::{
+    env, fs,
+    io::{self, Write},
+    process,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use bytes::Bytes;
+use ew::{Interpreter, Redeclaration, Val, bracket_depth, parse};
+use hmac::{Hmac, Mac};
+use serde_json::{Value, json};
+use sha2::Sha256;
+use zeromq::{PubSocket, RepSocket, RouterSocket, Socket, SocketRecv, SocketSend, ZmqMessage};
+
+const PROTOCOL_VERSION: &str = "5.3";
+const DELIMITER: &[u8] = b"<IDS|MSG>";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.iter().map(String::as_str).collect::<Vec<_>>()[..] {
+        ["install"] => install(),
+        [connection_file] => tokio::runtime::Runtime::new()
+            .map_err(|e| e.to_string())
+            .and_then(|runtime| runtime.block_on(serve(connection_file))),
+        _ => Err("usage: ew-jupyter install | ew-jupyter <connection file>".to_string()),
+    };
+    if let Err(e) = result {
+        eprintln!("ew-jupyter: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Writes the kernel spec into the user's Jupyter data directory.
+fn install() -> Result<(), String> {
+    let data = match env::var("JUPYTER_DATA_DIR") {
+        Ok(dir) => dir,
+        Err(_) => {
+            let home = env::var("HOME").map_err(|_| "$HOME is not set")?;
+            format!("{}/.local/share/jupyter", home)
+        }
+    };
+    let dir = format!("{}/kernels/ew", data);
+    let exe = env::current_exe().map_err(|e| e.to_string())?;
+    let spec = json!({
+        "argv": [exe, "{connection_file}"],
+        "display_name": "ew",
+        "language": "ew",
+    });
+    fs::create_dir_all(&dir).map_err(|e| format!("Cannot create {}: {}", dir, e))?;
+    fs::write(format!("{}/kernel.json", dir), spec.to_string())
+        .map_err(|e| format!("Cannot write the kernel spec: {}", e))?;
+    println!("Installed the ew kernel in {}", dir);
+    Ok(())
+}
+
+/// Signs and checks messages with the key from the connection file.
+#[derive(Clone)]
+struct Signer {
+    key: Vec<u8>,
+}
+
+impl Signer {
+    /// Hex HMAC-SHA256 of the four JSON parts, or nothing when the key is
+    /// empty, which turns signing off.
+    fn sign(&self, parts: &[&[u8]]) -> String {
+        if self.key.is_empty() {
+            return String::new();
+        }
+        self.mac(parts)
+            .finalize()
+            .into_bytes()
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect()
+    }
+
+    /// Whether `signature` is what `sign` gives for the parts, compared in
+    /// constant time so that timing doesn't reveal a valid signature.
+    fn verify(&self, parts: &[&[u8]], signature: &[u8]) -> bool {
+        if self.key.is_empty() {
+            return signature.is_empty();
+        }
+        match hex_decode(signature) {
+            Some(tag) => self.mac(parts).verify_slice(&tag).is_ok(),
+            None => false,
+        }
+    }
+
+    fn mac(&self, parts: &[&[u8]]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes any key");
+        for part in parts {
+            mac.update(part);
+        }
+        mac
+    }
+}
+
+/// The bytes that pairs of hex digits spell, or `None` if `hex` is not
+/// made of them.
+fn hex_decode(hex: &[u8]) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) || !hex.iter().all(u8::is_ascii_hexdigit) {
+        return None;
+    }
+    hex.chunks(2)
+        .map(|pair| u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok())
+        .collect()
+}
+
+/// A message of the Jupyter wire protocol.
+#[derive(Debug, Clone)]
+struct Message {
+    /// Routing frames of the client, echoed back in replies.
+    identities: Vec<Bytes>,
+    header: Value,
+    parent_header: Value,
+    content: Value,
+}
+
+impl Message {
+    fn decode(frames: Vec<Bytes>, signer: &Signer) -> Result<Message, String> {
+        let split = frames
+            .iter()
+            .position(|f| f.as_ref() == DELIMITER)
+            .ok_or("Message without a delimiter")?;
+        let (identities, rest) = frames.split_at(split);
+        let [_, signature, header, parent, metadata, content, ..] = rest else {
+            return Err("Message with missing parts".to_string());
+        };
+        if !signer.verify(&[header, parent, metadata, content], signature) {
+            return Err("Message with a bad signature".to_string());
+        }
+        let json = |part: &Bytes| {
+            serde_json::from_slice(part).map_err(|e| format!("Malformed message: {}", e))
+        };
+        Ok(Message {
+            identities: identities.to_vec(),
+            header: json(header)?,
+            parent_header: json(parent)?,
+            content: json(content)?,
+        })
+    }
+
+    fn msg_type(&self) -> &str {
+        self.header["msg_type"].as_str().unwrap_or("")
+    }
+
+    /// A message of `msg_type` replying to, or published on behalf of, this
+    /// one.
+    fn child(&self, msg_type: &str, content: Value) -> Message {
+        Message {
+            identities: self.identities.clone(),
+            header: header(msg_type, &self.header["session"]),
+            parent_header: self.header.clone(),
+            content,
+        }
+    }
+
+    fn encode(&self, signer: &Signer) -> ZmqMessage {
+        let parts = [
+            self.header.to_string(),
+            self.parent_header.to_string(),
+            "{}".to_string(),
+            self.content.to_string(),
+        ];
+        let signature = signer.sign(&parts.each_ref().map(|p| p.as_bytes()));
+        let mut frames = self.identities.clone();
+        frames.push(Bytes::from_static(DELIMITER));
+        frames.push(signature.into());
+        frames.extend(parts.map(Bytes::from));
+        ZmqMessage::try_from(frames).expect("a message always has frames")
+    }
+}
+
+fn header(msg_type: &str, session: &Value) -> Value {
+    static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+    json!({
+        "msg_id": format!("ew-{}-{}", process::id(), NEXT_ID.fetch_add(1, Ordering::Relaxed)),
+        "session": session,
+        "username": "ew",
+        "date": now(),
+        "msg_type": msg_type,
+        "version": PROTOCOL_VERSION,
+    })
+}
+
+/// The current UTC time in ISO 8601.
+fn now() -> String {
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+    let secs = elapsed.as_secs();
+    // Days to a civil date, from Howard Hinnant's `civil_from_days`.
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:06}Z",
+        year,
+        month,
+        day,
+        secs / 3600 % 24,
+        secs / 60 % 60,
+        secs % 60,
+        elapsed.subsec_micros()
+    )
+}
+
+/// What the printing builtins wrote during the current cell.
+#[derive(Clone, Default)]
+struct Output(Arc<Mutex<Vec<u8>>>);
+
+impl Output {
+    fn take(&self) -> String {
+        let bytes = std::mem::take(&mut *self.0.lock().unwrap());
+        String::from_utf8_lossy(&bytes).into_owned()
+    }
+}
+
+impl Write for Output {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// What running a cell produced, in the order the notebook shows it.
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Value(String),
+    Error { ename: &'static str, evalue: String },
+}
+
+struct Kernel {
+    interpreter: Interpreter,
+    output: Output,
+    cancel: Arc<AtomicBool>,
+    execution_count: u64,
+}
+
+impl Kernel {
+    fn new() -> Kernel {
+        let output = Output::default();
+        let interpreter = Interpreter::builder()
+            // Cells are re-run all the time, redefining what they define.
+            .redeclaration(Redeclaration::Lenient)
+            // There is no terminal to read from.
+            .input(io::empty())
+            .output(output.clone())
+            .build();
+        Kernel {
+            cancel: interpreter.cancellation_token(),
+            interpreter,
+            output,
+            execution_count: 0,
+        }
+    }
+
+    /// Runs a cell, returning what it printed and its result; `None` when
+    /// the cell's value is unit and there is nothing to show.
+    fn execute(&mut self, code: &str) -> (String, Option<Outcome>) {
+        // An interrupt stops only the cell it arrived during.
+        self.cancel.store(false, Ordering::Relaxed);
+        let outcome = match parse(code) {
+            Err(e) => Some(Outcome::Error {
+                ename: "ParseError",
+                evalue: e,
+            }),
+            Ok(program) => match self.interpreter.run(&program) {
+                Ok(Val::Unit) => None,
+                Ok(value) => Some(Outcome::Value(format!("{:#}", value))),
+                Err(e) => Some(Outcome::Error {
+                    ename: "RuntimeError",
+                    evalue: e,
+                }),
+            },
+        };
+        (self.output.take(), outcome)
+    }
+
+    fn kernel_info(&self) -> Value {
+        json!({
+            "status": "ok",
+            "protocol_version": PROTOCOL_VERSION,
+            "implementation": "ew",
+            "implementation_version": env!("CARGO_PKG_VERSION"),
+            "language_info": {
+                "name": "ew",
+                "version": env!("CARGO_PKG_VERSION"),
+                "mimetype": "text/x-ew",
+                "file_extension": ".ew",
+            },
+            "banner": format!("ew {}", env!("CARGO_PKG_VERSION")),
+            "help_links": [],
+        })
+    }
+}
+
+struct Connection {
+    transport: String,
+    ip: String,
+    signer: Signer,
+    ports: [u64; 5],
+}
+
+impl Connection {
+    fn read(path: &str) -> Result<Connection, String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("Cannot read {}: {}", path, e))?;
+        let info: Value = serde_json::from_str(&text).map_err(|e| format!("{}: {}", path, e))?;
+        let port = |name: &str| {
+            info[name]
+                .as_u64()
+                .ok_or_else(|| format!("{} has no {}", path, name))
+        };
+        if info["signature_scheme"].as_str().unwrap_or("hmac-sha256") != "hmac-sha256" {
+            return Err("Only hmac-sha256 signatures are supported".to_string());
+        }
+        Ok(Connection {
+            transport: info["transport"].as_str().unwrap_or("tcp").to_string(),
+            ip: info["ip"].as_str().unwrap_or("127.0.0.1").to_string(),
+            signer: Signer {
+                key: info["key"].as_str().unwrap_or("").as_bytes().to_vec(),
+            },
+            ports: [
+                port("shell_port")?,
+                port("iopub_port")?,
+                port("control_port")?,
+                port("stdin_port")?,
+                port("hb_port")?,
+            ],
+        })
+    }
+
+    fn endpoint(&self, port: u64) -> String {
+        format!("{}://{}:{}", self.transport, self.ip, port)
+    }
+}
+
+async fn bind<S: Socket>(connection: &Connection, port: u64) -> Result<S, String> {
+    let mut socket = S::new();
+    let endpoint = connection.endpoint(port);
+    socket
+        .bind(&endpoint)
+        .await
+        .map_err(|e| format!("Cannot bind {}: {}", endpoint, e))?;
+    Ok(socket)
+}
+
+async fn recv(socket: &mut impl SocketRecv, signer: &Signer) -> Result<Message, String> {
+    loop {
+        let frames = socket.recv().await.map_err(|e| e.to_string())?;
+        match Message::decode(frames.into_vec(), signer) {
+            Ok(message) => return Ok(message),
+            Err(e) => eprintln!("ew-jupyter: {}", e),
+        }
+    }
+}
+
+async fn send(socket: &mut impl SocketSend, signer: &Signer, message: &Message) {
+    if let Err(e) = socket.send(message.encode(signer)).await {
+        eprintln!("ew-jupyter: cannot send {}: {}", message.msg_type(), e);
+    }
+}
+
+async fn serve(connection_file: &str) -> Result<(), String> {
+    let connection = Connection::read(connection_file)?;
+    let [shell_port, iopub_port, control_port, stdin_port, hb_port] = connection.ports;
+    let mut shell: RouterSocket = bind(&connection, shell_port).await?;
+    let mut iopub: PubSocket = bind(&connection, iopub_port).await?;
+    let mut control: RouterSocket = bind(&connection, control_port).await?;
+    // Bound so clients can connect, but input requests aren't supported.
+    let _stdin: RouterSocket = bind(&connection, stdin_port).await?;
+    let mut heartbeat: RepSocket = bind(&connection, hb_port).await?;
+    let signer = connection.signer.clone();
+
+    let mut kernel = Kernel::new();
+
+    tokio::spawn(async move {
+        while let Ok(ping) = heartbeat.recv().await {
+            if heartbeat.send(ping).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    // Cells run on this thread, so the control channel gets its own task
+    // to be able to interrupt them.
+    let cancel = kernel.cancel.clone();
+    let control_signer = signer.clone();
+    tokio::spawn(async move {
+        while let Ok(request) = recv(&mut control, &control_signer).await {
+            match request.msg_type() {
+                "interrupt_request" => {
+                    cancel.store(true, Ordering::Relaxed);
+                    let reply = request.child("interrupt_reply", json!({"status": "ok"}));
+                    send(&mut control, &control_signer, &reply).await;
+                }
+                "shutdown_request" => {
+                    let reply = request.child("shutdown_reply", request.content.clone());
+                    send(&mut control, &control_signer, &reply).await;
+                    process::exit(0);
+                }
+                "kernel_info_request" => {
+                    let reply = request.child("kernel_info_reply", Kernel::new().kernel_info());
+                    send(&mut control, &control_signer, &reply).await;
+                }
+                _ => {}
+            }
+        }
+    });
+
+    loop {
+        let request = recv(&mut shell, &signer).await?;
+        let status = |state: &str| {
+            let mut message = request.child("status", json!({"execution_state": state}));
+            message.identities = vec![Bytes::from_static(b"status")];
+            message
+        };
+        send(&mut iopub, &signer, &status("busy")).await;
+        let reply = match request.msg_type() {
+            "kernel_info_request" => Some(request.child("kernel_info_reply", kernel.kernel_info())),
+            "execute_request" => Some(execute(&mut kernel, &request, &mut iopub, &signer).await),
+            "is_complete_request" => {
+                let code = request.content["code"].as_str().unwrap_or("");
+                let content = if bracket_depth(code) > 0 {
+                    json!({"status": "incomplete", "indent": "  "})
+                } else {
+                    json!({"status": "complete"})
+                };
+                Some(request.child("is_complete_reply", content))
+            }
+            "comm_info_request" => {
+                Some(request.child("comm_info_reply", json!({"status": "ok", "comms": {}})))
+            }
+            "shutdown_request" => {
+                let reply = request.child("shutdown_reply", request.content.clone());
+                send(&mut shell, &signer, &reply).await;
+                return Ok(());
+            }
+            _ => None,
+        };
+        if let Some(reply) = reply {
+            send(&mut shell, &signer, &reply).await;
+        }
+        send(&mut iopub, &signer, &status("idle")).await;
+    }
+}
+
+/// Runs an `execute_request`, publishing its input and results on iopub,
+/// and returns the reply.
+async fn execute(
+    kernel: &mut Kernel,
+    request: &Message,
+    iopub: &mut PubSocket,
+    signer: &Signer,
+) -> Message {
+    let code = request.content["code"].as_str().unwrap_or("");
+    let silent = request.content["silent"].as_bool().unwrap_or(false);
+    if !silent {
+        kernel.execution_count += 1;
+    }
+    let count = kernel.execution_count;
+    let publish = |msg_type: &str, content: Value| {
+        let mut message = request.child(msg_type, content);
+        message.identities = vec![Bytes::from(msg_type.to_string())];
+        message
+    };
+
+    send(
+        iopub,
+        signer,
+        &publish(
+            "execute_input",
+            json!({"code": code, "execution_count": count}),
+        ),
+    )
+    .await;
+    let (printed, outcome) = kernel.execute(code);
+    if !printed.is_empty() && !silent {
+        let stream = publish("stream", json!({"name": "stdout", "text": printed}));
+        send(iopub, signer, &stream).await;
+    }
+    match outcome {
+        Some(Outcome::Error { ename, evalue }) => {
+            let traceback: Vec<&str> = evalue.lines().collect();
+            let error = json!({"ename": ename, "evalue": evalue, "traceback": traceback});
+            send(iopub, signer, &publish("error", error.clone())).await;
+            let mut content = error;
+            content["status"] = json!("error");
+            content["execution_count"] = json!(count);
+            request.child("execute_reply", content)
+        }
+        outcome => {
+            if let (Some(Outcome::Value(text)), false) = (outcome, silent) {
+                let result = json!({
+                    "execution_count": count,
+                    "data": {"text/plain": text},
+                    "metadata": {},
+                });
+                send(iopub, signer, &publish("execute_result", result)).await;
+            }
+            request.child(
+                "execute_reply",
+                json!({"status": "ok", "execution_count": count, "user_expressions": {}}),
+            )
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_message_round_trip() {
+        let signer = Signer {
+            key: b"secret".to_vec(),
+        };
+        let request = Message {
+            identities: vec![Bytes::from_static(b"client")],
+            header: header("execute_request", &json!("s1")),
+            parent_header: json!({}),
+            content: json!({"code": "1 + 1"}),
+        };
+        let frames = request.encode(&signer).into_vec();
+        assert_eq!(frames[0].as_ref(), b"client");
+        assert_eq!(frames[1].as_ref(), DELIMITER);
+        assert_eq!(frames[2].len(), 64);
+
+        let decoded = Message::decode(frames.clone(), &signer).unwrap();
+        assert_eq!(decoded.msg_type(), "execute_request");
+        assert_eq!(decoded.content, request.content);
+        let reply = decoded.child("execute_reply", json!({}));
+        assert_eq!(reply.parent_header, request.header);
+        assert_eq!(reply.identities, request.identities);
+
+        let other = Signer {
+            key: b"other".to_vec(),
+        };
+        assert!(Message::decode(frames.clone(), &other).is_err());
+
+        let mut tampered = frames;
+        tampered[2] = Bytes::from(tampered[2].to_ascii_uppercase());
+        assert!(Message::decode(tampered.clone(), &signer).is_ok());
+        tampered[2] = Bytes::from(tampered[2][..62].to_vec());
+        assert!(Message::decode(tampered, &signer).is_err());
+        assert_eq!(hex_decode(b"+f"), None);
+        assert_eq!(hex_decode(b"0aFF"), Some(vec![10, 255]));
+    }
+
+    #[test]
+    fn test_execute_keeps_state() {
+        let mut kernel = Kernel::new();
+        assert_eq!(
+            kernel.execute("let x = 2\nprintln(\"hi\")"),
+            ("hi\n".to_string(), None)
+        );
+        assert_eq!(
+            kernel.execute("let x = x * 3\nx"),
+            (String::new(), Some(Outcome::Value("6".to_string())))
+        );
+        let (_, outcome) = kernel.execute("nope");
+        assert!(matches!(
+            outcome,
+            Some(Outcome::Error {
+                ename: "RuntimeError",
+                ..
+            })
+        ));
+        let (_, outcome) = kernel.execute("let = )");
+        assert!(matches!(
+            outcome,
+            Some(Outcome::Error {
+                ename: "ParseError",
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_now_is_iso_8601() {
+        let now = now();
+        assert_eq!(now.len(), 27, "{}", now);
+        assert!(now.starts_with("20") && now.ends_with('Z'), "{}", now);
+        assert_eq!(&now[10..11], "T");
+    }
+}
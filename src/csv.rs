@@ -0,0 +1,175 @@
+//! The CSV reading and writing behind `csv_parse` and `csv_stringify`.
+
+/// How fields are separated and quoted.
+#[derive(Debug, Clone, Copy)]
+pub struct Dialect {
+    pub delimiter: char,
+    pub quote: char,
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Dialect {
+            delimiter: ',',
+            quote: '"',
+        }
+    }
+}
+
+/// Splits `text` into records of fields. Quoted fields may hold delimiters,
+/// line breaks and doubled quotes; `\r\n` line endings are accepted, and a
+/// trailing line break doesn't start an empty record.
+pub fn parse(text: &str, dialect: Dialect) -> Result<Vec<Vec<String>>, String> {
+    let mut records = Vec::new();
+    let mut record = Vec::new();
+    let mut field = String::new();
+    let mut chars = text.chars().peekable();
+    let mut line = 1;
+    // Whether the current record has anything in it, so that blank input
+    // and a final newline don't produce a record.
+    let mut started = false;
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c == dialect.quote && field.is_empty() => {
+                started = true;
+                let opened_at = line;
+                loop {
+                    match chars.next() {
+                        Some(c) if c == dialect.quote => {
+                            if chars.peek() == Some(&dialect.quote) {
+                                chars.next();
+                                field.push(c);
+                            } else {
+                                break;
+                            }
+                        }
+                        Some(c) => {
+                            if c == '\n' {
+                                line += 1;
+                            }
+                            field.push(c);
+                        }
+                        None => {
+                            return Err(format!("Unterminated quoted field on line {}", opened_at));
+                        }
+                    }
+                }
+                if let Some(&next) = chars.peek()
+                    && next != dialect.delimiter
+                    && next != '\n'
+                    && next != '\r'
+                {
+                    return Err(format!(
+                        "Unexpected {:?} after a quoted field on line {}",
+                        next, line
+                    ));
+                }
+            }
+            c if c == dialect.delimiter => {
+                started = true;
+                record.push(std::mem::take(&mut field));
+            }
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' => {
+                line += 1;
+                if started {
+                    record.push(std::mem::take(&mut field));
+                    records.push(std::mem::take(&mut record));
+                    started = false;
+                }
+            }
+            c => {
+                started = true;
+                field.push(c);
+            }
+        }
+    }
+    if started {
+        record.push(field);
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Joins records into CSV text, one line per record. Fields are quoted
+/// only when they hold the delimiter, the quote or a line break, or when
+/// the field is alone and empty, which would otherwise be a blank line.
+pub fn stringify(records: &[Vec<String>], dialect: Dialect) -> String {
+    let mut out = String::new();
+    for record in records {
+        let fields: Vec<String> = record
+            .iter()
+            .map(|field| {
+                if field.contains([dialect.delimiter, dialect.quote, '\n', '\r'])
+                    || (field.is_empty() && record.len() == 1)
+                {
+                    let q = dialect.quote.to_string();
+                    format!("{0}{1}{0}", q, field.replace(&q, &q.repeat(2)))
+                } else {
+                    field.clone()
+                }
+            })
+            .collect();
+        out.push_str(&fields.join(&dialect.delimiter.to_string()));
+        out.push('\n');
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(rows: &[&[&str]]) -> Vec<Vec<String>> {
+        rows.iter()
+            .map(|r| r.iter().map(|f| f.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_parse() {
+        let d = Dialect::default();
+        assert_eq!(
+            parse("a,b\r\n1,\"x, \"\"y\"\"\"\n,\n", d).unwrap(),
+            rows(&[&["a", "b"], &["1", "x, \"y\""], &["", ""]])
+        );
+        assert_eq!(
+            parse("\"multi\nline\",z", d).unwrap(),
+            rows(&[&["multi\nline", "z"]])
+        );
+        assert!(parse("", d).unwrap().is_empty());
+        assert_eq!(
+            parse("\"open", d).unwrap_err(),
+            "Unterminated quoted field on line 1"
+        );
+        assert!(parse("\"a\"b", d).is_err());
+
+        let semi = Dialect {
+            delimiter: ';',
+            quote: '\'',
+        };
+        assert_eq!(parse("a;'b;c'", semi).unwrap(), rows(&[&["a", "b;c"]]));
+    }
+
+    #[test]
+    fn test_stringify_round_trip() {
+        let d = Dialect::default();
+        let records = rows(&[
+            &["id", "note"],
+            &["1", "says \"hi\", twice"],
+            &["2", "two\nlines"],
+        ]);
+        let text = stringify(&records, d);
+        assert_eq!(
+            text,
+            "id,note\n1,\"says \"\"hi\"\", twice\"\n2,\"two\nlines\"\n"
+        );
+        assert_eq!(parse(&text, d).unwrap(), records);
+
+        let records = rows(&[&[""], &["", ""]]);
+        let text = stringify(&records, d);
+        assert_eq!(text, "\"\"\n,\n");
+        assert_eq!(parse(&text, d).unwrap(), records);
+    }
+}
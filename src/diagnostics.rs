@@ -0,0 +1,137 @@
+//! Warnings: what the resolver and interpreter notice about a program that
+//! is probably a mistake but doesn't stop it, kept apart from errors so a
+//! host can show, filter or fail on them.
+
+use std::{
+    collections::{BTreeSet, HashSet},
+    fmt,
+};
+
+/// What a warning is about, and the name `-W` knows it by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum WarningKind {
+    /// `let x = x + 1` in a loop, declaring a new `x` instead of updating
+    /// the outer one.
+    Shadowing,
+    /// `x = 1` with no `x` in scope.
+    Undeclared,
+    /// A call to a builtin the host has marked deprecated.
+    Deprecated,
+    /// A value converted to another type without being asked, such as a
+    /// non-Bool condition under loose truthiness.
+    Conversion,
+}
+
+impl WarningKind {
+    pub const ALL: [WarningKind; 4] = [
+        WarningKind::Shadowing,
+        WarningKind::Undeclared,
+        WarningKind::Deprecated,
+        WarningKind::Conversion,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            WarningKind::Shadowing => "shadowing",
+            WarningKind::Undeclared => "undeclared",
+            WarningKind::Deprecated => "deprecated",
+            WarningKind::Conversion => "conversion",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<WarningKind> {
+        WarningKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub kind: WarningKind,
+    pub message: String,
+}
+
+/// The message followed by the kind, e.g. `... [shadowing]`.
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} [{}]", self.message, self.kind.name())
+    }
+}
+
+/// Collects warnings until the host takes them, dropping kinds it has
+/// turned off and any warning already given, so a loop doesn't repeat one
+/// on every iteration.
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    warnings: Vec<Warning>,
+    disabled: BTreeSet<WarningKind>,
+    seen: HashSet<(WarningKind, String)>,
+}
+
+impl Diagnostics {
+    pub fn new() -> Diagnostics {
+        Diagnostics::default()
+    }
+
+    pub fn warn(&mut self, kind: WarningKind, message: String) {
+        if self.is_enabled(kind) && self.seen.insert((kind, message.clone())) {
+            self.warnings.push(Warning { kind, message });
+        }
+    }
+
+    pub fn extend(&mut self, warnings: impl IntoIterator<Item = Warning>) {
+        for warning in warnings {
+            self.warn(warning.kind, warning.message);
+        }
+    }
+
+    pub fn set_enabled(&mut self, kind: WarningKind, enabled: bool) {
+        if enabled {
+            self.disabled.remove(&kind);
+        } else {
+            self.disabled.insert(kind);
+        }
+    }
+
+    pub fn is_enabled(&self, kind: WarningKind) -> bool {
+        !self.disabled.contains(&kind)
+    }
+
+    /// The warnings given since they were last taken.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Removes and returns the warnings given since they were last taken.
+    /// Ones already given stay suppressed.
+    pub fn take(&mut self) -> Vec<Warning> {
+        std::mem::take(&mut self.warnings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diagnostics() {
+        let mut diagnostics = Diagnostics::new();
+        diagnostics.warn(WarningKind::Conversion, "a".to_string());
+        diagnostics.warn(WarningKind::Conversion, "a".to_string());
+        diagnostics.set_enabled(WarningKind::Shadowing, false);
+        diagnostics.warn(WarningKind::Shadowing, "b".to_string());
+        assert_eq!(diagnostics.warnings().len(), 1);
+        assert_eq!(diagnostics.warnings()[0].to_string(), "a [conversion]");
+
+        assert_eq!(diagnostics.take().len(), 1);
+        diagnostics.warn(WarningKind::Conversion, "a".to_string());
+        assert!(diagnostics.take().is_empty());
+
+        assert_eq!(
+            WarningKind::from_name("deprecated"),
+            Some(WarningKind::Deprecated)
+        );
+        assert_eq!(WarningKind::from_name("nope"), None);
+    }
+}
@@ -0,0 +1,40 @@
+//! Checksums behind the `md5`, `sha1`, `sha256` and `crc32` builtins, built
+//! with the `crypto` feature. Each returns lowercase hex.
+
+use md5::Md5;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+use crate::encoding::hex_encode;
+
+pub fn md5(bytes: &[u8]) -> String {
+    hex_encode(&Md5::digest(bytes))
+}
+
+pub fn sha1(bytes: &[u8]) -> String {
+    hex_encode(&Sha1::digest(bytes))
+}
+
+pub fn sha256(bytes: &[u8]) -> String {
+    hex_encode(&Sha256::digest(bytes))
+}
+
+pub fn crc32(bytes: &[u8]) -> String {
+    format!("{:08x}", crc32fast::hash(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_digests() {
+        assert_eq!(md5(b""), "d41d8cd98f00b204e9800998ecf8427e");
+        assert_eq!(sha1(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+        assert_eq!(
+            sha256(b"abc"),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(crc32(b"123456789"), "cbf43926");
+    }
+}
@@ -0,0 +1,166 @@
+//! Text encodings behind the `base64_*`, `hex_*` and `url_*` builtins.
+//!
+//! Values are strings, so encoding works on their UTF-8 bytes and decoding
+//! fails unless the bytes it produces are valid UTF-8.
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+/// Standard base64 with `=` padding.
+pub fn base64_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 63) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Accepts padded or unpadded input; whitespace is ignored.
+pub fn base64_decode(text: &str) -> Result<String, String> {
+    let digits: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    let digits = digits
+        .strip_suffix(b"==")
+        .or(digits.strip_suffix(b"="))
+        .unwrap_or(&digits);
+    if digits.len() % 4 == 1 {
+        return Err("Invalid base64: wrong length".to_string());
+    }
+    let mut bytes = Vec::with_capacity(digits.len() * 3 / 4);
+    for chunk in digits.chunks(4) {
+        let mut n = 0u32;
+        for (i, &d) in chunk.iter().enumerate() {
+            let value = BASE64
+                .iter()
+                .position(|&c| c == d)
+                .ok_or_else(|| format!("Invalid base64 character {:?}", d as char))?;
+            n |= (value as u32) << (18 - 6 * i);
+        }
+        bytes.extend(n.to_be_bytes()[1..chunk.len()].iter());
+    }
+    utf8(bytes)
+}
+
+/// Two lowercase digits per byte.
+pub fn hex_encode(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+pub fn hex_decode(text: &str) -> Result<String, String> {
+    if !text.len().is_multiple_of(2) {
+        return Err("Invalid hex: odd number of digits".to_string());
+    }
+    let bytes = (0..text.len())
+        .step_by(2)
+        .map(|i| {
+            text.get(i..i + 2)
+                .and_then(hex_byte)
+                .ok_or_else(|| format!("Invalid hex digits at offset {}", i))
+        })
+        .collect::<Result<Vec<u8>, String>>()?;
+    utf8(bytes)
+}
+
+/// Percent-encodes everything but the unreserved characters of RFC 3986.
+pub fn url_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len());
+    for &b in bytes {
+        if b.is_ascii_alphanumeric() || b"-_.~".contains(&b) {
+            out.push(b as char);
+        } else {
+            out.push_str(&format!("%{:02X}", b));
+        }
+    }
+    out
+}
+
+/// Decodes `%XX` escapes and, as in form data, `+` as a space.
+pub fn url_decode(text: &str) -> Result<String, String> {
+    let input = text.as_bytes();
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut i = 0;
+    while i < input.len() {
+        match input[i] {
+            b'%' => {
+                let byte = text
+                    .get(i + 1..i + 3)
+                    .and_then(hex_byte)
+                    .ok_or_else(|| format!("Invalid percent escape at offset {}", i))?;
+                bytes.push(byte);
+                i += 3;
+            }
+            b'+' => {
+                bytes.push(b' ');
+                i += 1;
+            }
+            b => {
+                bytes.push(b);
+                i += 1;
+            }
+        }
+    }
+    utf8(bytes)
+}
+
+/// The byte two hex digits spell. Checked by hand, since `from_str_radix`
+/// also takes a sign, as in `+f`.
+fn hex_byte(pair: &str) -> Option<u8> {
+    if !pair.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    u8::from_str_radix(pair, 16).ok()
+}
+
+fn utf8(bytes: Vec<u8>) -> Result<String, String> {
+    String::from_utf8(bytes).map_err(|_| "Decoded bytes are not valid UTF-8".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        for (plain, encoded) in [
+            ("", ""),
+            ("f", "Zg=="),
+            ("fo", "Zm8="),
+            ("foo", "Zm9v"),
+            ("foobar", "Zm9vYmFy"),
+            ("héllo?", "aMOpbGxvPw=="),
+        ] {
+            assert_eq!(base64_encode(plain.as_bytes()), encoded);
+            assert_eq!(base64_decode(encoded).unwrap(), plain);
+        }
+        assert_eq!(base64_decode("Zm8").unwrap(), "fo");
+        assert!(base64_decode("Zm9v!").is_err());
+        assert!(base64_decode("Z").is_err());
+        assert!(base64_decode("/w==").is_err());
+    }
+
+    #[test]
+    fn test_hex_and_url() {
+        assert_eq!(hex_encode("Hi é".as_bytes()), "486920c3a9");
+        assert_eq!(hex_decode("486920C3A9").unwrap(), "Hi é");
+        assert!(hex_decode("abc").is_err());
+        assert!(hex_decode("zz").is_err());
+        assert!(hex_decode("+f").is_err());
+
+        assert_eq!(
+            url_encode("a b&c=d/é~".as_bytes()),
+            "a%20b%26c%3Dd%2F%C3%A9~"
+        );
+        assert_eq!(url_decode("a%20b+c%C3%A9").unwrap(), "a b cé");
+        assert!(url_decode("100%").is_err());
+        assert!(url_decode("%zz").is_err());
+        assert!(url_decode("%+f").is_err());
+    }
+}
@@ -0,0 +1,459 @@
+use crate::ast::{
+    AssignmentTarget, BinaryOp, Expr, Iteration, MatchArm, Pattern, Pragma, Stmt, UnaryOp,
+};
+use crate::interpreter::quote;
+
+const INDENT: &str = "    ";
+
+/// Source text for `stmts`, one statement per line. Parsing the result gives
+/// back the same AST apart from function line numbers, though sugar the
+/// parser removes (`x++`, chained comparisons, `memo fn`) comes out in its
+/// expanded form.
+pub fn format_program(stmts: &[Stmt]) -> String {
+    let mut out = String::new();
+    for stmt in stmts {
+        write_stmt(&mut out, stmt, 0);
+        out.push('\n');
+    }
+    out
+}
+
+pub fn format_stmt(stmt: &Stmt) -> String {
+    let mut out = String::new();
+    write_stmt(&mut out, stmt, 0);
+    out
+}
+
+pub fn format_expr(expr: &Expr) -> String {
+    let mut out = String::new();
+    write_expr(&mut out, expr, 0);
+    out
+}
+
+fn write_stmt(out: &mut String, stmt: &Stmt, depth: usize) {
+    match stmt {
+        Stmt::Function {
+            name,
+            params,
+            body,
+            decorators,
+            ..
+        } => {
+            for decorator in decorators {
+                out.push('@');
+                out.push_str(decorator);
+                out.push('\n');
+                out.push_str(&INDENT.repeat(depth));
+            }
+            out.push_str(&format!("fn {}({}) ", name, params.join(", ")));
+            write_block(out, body, depth);
+        }
+        Stmt::Enum { name, variants } => {
+            let variants: Vec<String> = variants
+                .iter()
+                .map(|v| match v.fields.as_slice() {
+                    [] => v.name.clone(),
+                    fields => format!("{}({})", v.name, fields.join(", ")),
+                })
+                .collect();
+            out.push_str(&format!("enum {} {{ {} }}", name, variants.join(", ")));
+        }
+        Stmt::Return(expr) => {
+            out.push_str("return ");
+            write_expr(out, expr, depth);
+        }
+        Stmt::Break(None) => out.push_str("break"),
+        Stmt::Break(Some(expr)) => {
+            out.push_str("break ");
+            write_expr(out, expr, depth);
+        }
+        Stmt::BreakLabel(label) => out.push_str(&format!("break {}", label)),
+        Stmt::Assignment { name, value } => {
+            out.push_str(&format!("let {} = ", name));
+            write_expr(out, value, depth);
+        }
+        Stmt::Reassignment { target, value } => {
+            match target {
+                AssignmentTarget::Ident(name) => out.push_str(name),
+                AssignmentTarget::ArrayAccess { name, indices } => {
+                    write_access(out, name, indices, depth)
+                }
+            }
+            out.push_str(" = ");
+            write_expr(out, value, depth);
+        }
+        Stmt::Expr(expr) => write_expr(out, expr, depth),
+        Stmt::Pragma(p) => out.push_str(&pragma(p)),
+    }
+}
+
+/// `{ ... }` with each statement on its own line, one level deeper.
+fn write_block(out: &mut String, stmts: &[Stmt], depth: usize) {
+    if stmts.is_empty() {
+        out.push_str("{}");
+        return;
+    }
+    out.push_str("{\n");
+    for stmt in stmts {
+        out.push_str(&INDENT.repeat(depth + 1));
+        write_stmt(out, stmt, depth + 1);
+        out.push('\n');
+    }
+    out.push_str(&INDENT.repeat(depth));
+    out.push('}');
+}
+
+fn write_access(out: &mut String, name: &str, indices: &[Box<Expr>], depth: usize) {
+    out.push_str(name);
+    for index in indices {
+        out.push('[');
+        write_expr(out, index, depth);
+        out.push(']');
+    }
+}
+
+fn write_list(out: &mut String, exprs: &[Expr], depth: usize) {
+    for (i, expr) in exprs.iter().enumerate() {
+        if i > 0 {
+            out.push_str(", ");
+        }
+        write_expr(out, expr, depth);
+    }
+}
+
+/// How tightly an expression binds, following the grammar's rule order.
+/// Anything that isn't an operator chain has to be parenthesized to be an
+/// operand.
+fn precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Binary { op, .. } => match op {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::Eq
+            | BinaryOp::Ne
+            | BinaryOp::Lt
+            | BinaryOp::Le
+            | BinaryOp::Gt
+            | BinaryOp::Ge => 3,
+            BinaryOp::Add | BinaryOp::Sub => 4,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 5,
+            BinaryOp::Pow => 6,
+        },
+        Expr::Unary { .. } => 7,
+        Expr::If { .. }
+        | Expr::IfLet { .. }
+        | Expr::LetIn { .. }
+        | Expr::While { .. }
+        | Expr::WhileLet { .. }
+        | Expr::For { .. }
+        | Expr::Loop(_)
+        | Expr::Labeled { .. }
+        | Expr::Match { .. }
+        | Expr::Block(_) => 0,
+        _ => 8,
+    }
+}
+
+fn write_operand(out: &mut String, expr: &Expr, min: u8, depth: usize) {
+    if precedence(expr) < min {
+        out.push('(');
+        write_expr(out, expr, depth);
+        out.push(')');
+    } else {
+        write_expr(out, expr, depth);
+    }
+}
+
+pub(crate) fn pragma(pragma: &Pragma) -> String {
+    match pragma {
+        Pragma::Version(version) => format!("#pragma version {}", version),
+        Pragma::Feature(feature) => format!("#feature({})", feature.name()),
+    }
+}
+
+pub(crate) fn binary_op(op: BinaryOp) -> &'static str {
+    match op {
+        BinaryOp::Add => "+",
+        BinaryOp::Sub => "-",
+        BinaryOp::Mul => "*",
+        BinaryOp::Div => "/",
+        BinaryOp::Mod => "%",
+        BinaryOp::Pow => "**",
+        BinaryOp::Eq => "==",
+        BinaryOp::Ne => "!=",
+        BinaryOp::Lt => "<",
+        BinaryOp::Le => "<=",
+        BinaryOp::Gt => ">",
+        BinaryOp::Ge => ">=",
+        BinaryOp::And => "&&",
+        BinaryOp::Or => "||",
+    }
+}
+
+fn write_expr(out: &mut String, expr: &Expr, depth: usize) {
+    match expr {
+        Expr::Int(n) => out.push_str(&n.to_string()),
+        Expr::BigInt(n) => out.push_str(&n.to_string()),
+        Expr::Float(f) => {
+            let s = f.to_string();
+            out.push_str(&s);
+            if !s.contains('.') {
+                out.push_str(".0");
+            }
+        }
+        Expr::Bool(b) => out.push_str(&b.to_string()),
+        Expr::Str { value: s, .. } => out.push_str(&quote(s)),
+        Expr::Null => out.push_str("null"),
+        Expr::Array { items, .. } => {
+            out.push('[');
+            write_list(out, items, depth);
+            out.push(']');
+        }
+        Expr::ArrayAccess { name, indices, .. } => write_access(out, name, indices, depth),
+        Expr::Slice {
+            name,
+            indices,
+            start,
+            end,
+            ..
+        } => {
+            write_access(out, name, indices, depth);
+            out.push('[');
+            if let Some(start) = start {
+                write_expr(out, start, depth);
+            }
+            out.push_str("..");
+            if let Some(end) = end {
+                write_expr(out, end, depth);
+            }
+            out.push(']');
+        }
+        Expr::Var(name) => out.push_str(name),
+        Expr::Unary { op, expr, .. } => {
+            out.push(match op {
+                UnaryOp::Neg => '-',
+                UnaryOp::Not => '!',
+            });
+            write_operand(out, expr, 7, depth);
+        }
+        Expr::Binary { op, lhs, rhs, .. } => {
+            let prec = precedence(expr);
+            // Operators associate to the left, except `**`. Comparisons
+            // never nest unparenthesized, since `a < b < c` is a chain.
+            let (left, right) = match op {
+                BinaryOp::Pow => (prec + 1, prec),
+                _ if prec == 3 => (prec + 1, prec + 1),
+                _ => (prec, prec + 1),
+            };
+            write_operand(out, lhs, left, depth);
+            out.push_str(&format!(" {} ", binary_op(*op)));
+            write_operand(out, rhs, right, depth);
+        }
+        Expr::Call { name, args, .. } => {
+            out.push_str(name);
+            out.push('(');
+            write_list(out, args, depth);
+            out.push(')');
+        }
+        Expr::Spread(expr) => {
+            out.push_str("...");
+            write_expr(out, expr, depth);
+        }
+        Expr::Variant {
+            enum_name,
+            variant,
+            args,
+            ..
+        } => {
+            out.push_str(&format!("{}::{}", enum_name, variant));
+            if !args.is_empty() {
+                out.push('(');
+                write_list(out, args, depth);
+                out.push(')');
+            }
+        }
+        Expr::Match { scrutinee, arms } => {
+            out.push_str("match (");
+            write_expr(out, scrutinee, depth);
+            out.push_str(") {\n");
+            for arm in arms {
+                out.push_str(&INDENT.repeat(depth + 1));
+                write_arm(out, arm, depth + 1);
+                out.push_str(",\n");
+            }
+            out.push_str(&INDENT.repeat(depth));
+            out.push('}');
+        }
+        Expr::If {
+            cond, then, else_, ..
+        } => {
+            out.push_str("if (");
+            write_expr(out, cond, depth);
+            out.push_str(") ");
+            write_block(out, then, depth);
+            write_else(out, else_, depth);
+        }
+        Expr::IfLet {
+            name,
+            value,
+            then,
+            else_,
+        } => {
+            out.push_str(&format!("if let {} = ", name));
+            write_expr(out, value, depth);
+            out.push(' ');
+            write_block(out, then, depth);
+            write_else(out, else_, depth);
+        }
+        Expr::While { cond, body, .. } => {
+            out.push_str("while (");
+            write_expr(out, cond, depth);
+            out.push_str(") ");
+            write_block(out, body, depth);
+        }
+        Expr::LetIn { name, value, body } => {
+            out.push_str(&format!("let {} = ", name));
+            write_expr(out, value, depth);
+            out.push_str(" in ");
+            write_expr(out, body, depth);
+        }
+        Expr::WhileLet { name, value, body } => {
+            out.push_str(&format!("while let {} = ", name));
+            write_expr(out, value, depth);
+            out.push(' ');
+            write_block(out, body, depth);
+        }
+        Expr::For {
+            var,
+            start,
+            end,
+            body,
+        } => {
+            out.push_str(&format!("for {} in ", var));
+            write_operand(out, start, 1, depth);
+            out.push_str("..");
+            write_operand(out, end, 1, depth);
+            out.push(' ');
+            write_block(out, body, depth);
+        }
+        Expr::Loop(body) => {
+            out.push_str("loop ");
+            write_block(out, body, depth);
+        }
+        Expr::Comprehension {
+            key,
+            value,
+            var,
+            source,
+            cond,
+            ..
+        } => {
+            out.push(if key.is_some() { '{' } else { '[' });
+            if let Some(key) = key {
+                write_expr(out, key, depth);
+                out.push_str(": ");
+            }
+            write_expr(out, value, depth);
+            out.push_str(&format!(" for {} in ", var));
+            match source {
+                Iteration::Range { start, end } => {
+                    write_operand(out, start, 1, depth);
+                    out.push_str("..");
+                    write_operand(out, end, 1, depth);
+                }
+                Iteration::Each(items) => write_expr(out, items, depth),
+            }
+            if let Some(cond) = cond {
+                out.push_str(" if ");
+                write_expr(out, cond, depth);
+            }
+            out.push(if key.is_some() { '}' } else { ']' });
+        }
+        Expr::Labeled { label, body } => {
+            out.push_str(&format!("{}: ", label));
+            write_expr(out, body, depth);
+        }
+        Expr::Block(stmts) => write_block(out, stmts, depth),
+    }
+}
+
+fn write_else(out: &mut String, else_: &[Stmt], depth: usize) {
+    match else_ {
+        [] => {}
+        // The parser turns `else if` into an else block holding the `if`.
+        [Stmt::Expr(nested @ (Expr::If { .. } | Expr::IfLet { .. }))] => {
+            out.push_str(" else ");
+            write_expr(out, nested, depth);
+        }
+        _ => {
+            out.push_str(" else ");
+            write_block(out, else_, depth);
+        }
+    }
+}
+
+fn write_arm(out: &mut String, arm: &MatchArm, depth: usize) {
+    match &arm.pattern {
+        Pattern::Wildcard => out.push('_'),
+        Pattern::Literal(expr) => write_expr(out, expr, depth),
+        Pattern::Variant {
+            enum_name,
+            variant,
+            bindings,
+        } => {
+            out.push_str(&format!("{}::{}", enum_name, variant));
+            if !bindings.is_empty() {
+                out.push_str(&format!("({})", bindings.join(", ")));
+            }
+        }
+    }
+    out.push_str(" => ");
+    match arm.body.as_slice() {
+        [Stmt::Expr(expr)] if !matches!(expr, Expr::Block(_)) => write_expr(out, expr, depth),
+        body => write_block(out, body, depth),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn round_trip(source: &str) {
+        let program = parse(source).unwrap();
+        let formatted = format_program(&program);
+        assert_eq!(
+            parse(&formatted).as_ref(),
+            Ok(&program),
+            "reformatted as:\n{}",
+            formatted
+        );
+    }
+
+    #[test]
+    fn test_format_function() {
+        let source = "@timed\nmemo fn f(a, b) { let c = a + b * 2\nif (c > 10) { return c } else if (c < 0) { -c } else { c ** 2 ** 3 } }";
+        assert_eq!(
+            format_stmt(&parse(source).unwrap()[0]),
+            "@timed\n@memoize\nfn f(a, b) {\n    let c = a + b * 2\n    if (c > 10) {\n        return c\n    } else if (c < 0) {\n        -c\n    } else {\n        c ** 2 ** 3\n    }\n}"
+        );
+        round_trip(source);
+    }
+
+    #[test]
+    fn test_format_round_trip() {
+        round_trip("(1 + 2) * 3 - (4 - 5) - 6 / (7 % 2)");
+        round_trip("(2 ** 3) ** 2 + -(1 + 1) + !(a && b) || c && (d || e)");
+        round_trip("0 <= x < 10 == (a < b)");
+        round_trip("let s = \"a\\\"b\\n\" let xs = [1, 2.0, null, true] xs[0][1] = f(...xs)");
+        round_trip(
+            "enum Shape { Dot, Circle(r) } match (s) { Shape::Circle(r) => r * r, 1 => { 2 } _ => 0 }",
+        );
+        round_trip("outer: for i in 0..n { while (true) { break outer } loop { break 1 } }");
+        round_trip("if let x = f() { x } while let y = g() { y++ } let b = { 1 }");
+        round_trip("(let z = 3 in z) * 2 let a = let x = 1 in let y = 2 in x + y");
+        round_trip("xs[-1] + xs[1..n - 1] + m[i][..2] + s[-3..]");
+        round_trip("[x * x for x in 0..n + 1 if x % 2 == 0] {k: [v] for k in keys(m)}");
+        round_trip("#pragma version 0.2\n#feature(strict_types)\n1 / 2");
+    }
+}
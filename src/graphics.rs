@@ -0,0 +1,225 @@
+//! The canvas behind `window`, `draw_rect`, `draw_circle`, `draw_text` and
+//! `present`, built with the `graphics` feature. Drawing happens in memory;
+//! `present` shows the canvas in the terminal, two pixels per character
+//! cell, using 24-bit color escapes.
+
+pub type Color = [u8; 3];
+
+pub struct Canvas {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+/// Glyphs of the built-in 3x5 font, one row per entry with the leftmost
+/// pixel in bit 2. Lowercase letters are drawn as uppercase.
+const FONT: &[(char, [u8; 5])] = &[
+    ('A', [2, 5, 7, 5, 5]),
+    ('B', [6, 5, 6, 5, 6]),
+    ('C', [3, 4, 4, 4, 3]),
+    ('D', [6, 5, 5, 5, 6]),
+    ('E', [7, 4, 6, 4, 7]),
+    ('F', [7, 4, 6, 4, 4]),
+    ('G', [3, 4, 5, 5, 3]),
+    ('H', [5, 5, 7, 5, 5]),
+    ('I', [7, 2, 2, 2, 7]),
+    ('J', [1, 1, 1, 5, 2]),
+    ('K', [5, 5, 6, 5, 5]),
+    ('L', [4, 4, 4, 4, 7]),
+    ('M', [5, 7, 7, 5, 5]),
+    ('N', [6, 5, 5, 5, 5]),
+    ('O', [2, 5, 5, 5, 2]),
+    ('P', [6, 5, 6, 4, 4]),
+    ('Q', [2, 5, 5, 6, 3]),
+    ('R', [6, 5, 6, 5, 5]),
+    ('S', [3, 4, 2, 1, 6]),
+    ('T', [7, 2, 2, 2, 2]),
+    ('U', [5, 5, 5, 5, 7]),
+    ('V', [5, 5, 5, 5, 2]),
+    ('W', [5, 5, 7, 7, 5]),
+    ('X', [5, 5, 2, 5, 5]),
+    ('Y', [5, 5, 2, 2, 2]),
+    ('Z', [7, 1, 2, 4, 7]),
+    ('0', [7, 5, 5, 5, 7]),
+    ('1', [2, 6, 2, 2, 7]),
+    ('2', [6, 1, 2, 4, 7]),
+    ('3', [6, 1, 2, 1, 6]),
+    ('4', [5, 5, 7, 1, 1]),
+    ('5', [7, 4, 6, 1, 6]),
+    ('6', [3, 4, 6, 5, 2]),
+    ('7', [7, 1, 2, 2, 2]),
+    ('8', [2, 5, 2, 5, 2]),
+    ('9', [2, 5, 3, 1, 6]),
+    (' ', [0, 0, 0, 0, 0]),
+    ('.', [0, 0, 0, 0, 2]),
+    (',', [0, 0, 0, 2, 4]),
+    ('!', [2, 2, 2, 0, 2]),
+    ('?', [6, 1, 2, 0, 2]),
+    (':', [0, 2, 0, 2, 0]),
+    ('-', [0, 0, 7, 0, 0]),
+    ('+', [0, 2, 7, 2, 0]),
+    ('=', [0, 7, 0, 7, 0]),
+    ('/', [1, 1, 2, 4, 4]),
+    ('(', [1, 2, 2, 2, 1]),
+    (')', [4, 2, 2, 2, 4]),
+];
+
+/// `"#rrggbb"` or one of a few color names.
+pub fn parse_color(text: &str) -> Result<Color, String> {
+    let named = match text {
+        "black" => Some([0, 0, 0]),
+        "white" => Some([255, 255, 255]),
+        "gray" => Some([128, 128, 128]),
+        "red" => Some([255, 0, 0]),
+        "green" => Some([0, 255, 0]),
+        "blue" => Some([0, 0, 255]),
+        "yellow" => Some([255, 255, 0]),
+        "cyan" => Some([0, 255, 255]),
+        "magenta" => Some([255, 0, 255]),
+        _ => None,
+    };
+    if let Some(color) = named {
+        return Ok(color);
+    }
+    let hex = text
+        .strip_prefix('#')
+        .filter(|hex| hex.len() == 6 && hex.is_ascii());
+    let channel = |i: usize| hex.and_then(|hex| u8::from_str_radix(&hex[i..i + 2], 16).ok());
+    match (channel(0), channel(2), channel(4)) {
+        (Some(r), Some(g), Some(b)) => Ok([r, g, b]),
+        _ => Err(format!(
+            "Unknown color {:?}; use \"#rrggbb\" or a name like \"red\"",
+            text
+        )),
+    }
+}
+
+impl Canvas {
+    /// A black canvas.
+    pub fn new(width: usize, height: usize) -> Canvas {
+        Canvas {
+            width,
+            height,
+            pixels: vec![[0; 3]; width * height],
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Option<Color> {
+        (x < self.width && y < self.height).then(|| self.pixels[y * self.width + x])
+    }
+
+    /// Sets a pixel; anything off the canvas is ignored.
+    fn plot(&mut self, x: i64, y: i64, color: Color) {
+        if (0..self.width as i64).contains(&x) && (0..self.height as i64).contains(&y) {
+            self.pixels[y as usize * self.width + x as usize] = color;
+        }
+    }
+
+    pub fn fill_rect(&mut self, x: i64, y: i64, w: i64, h: i64, color: Color) {
+        for py in y.max(0)..(y + h).min(self.height as i64) {
+            for px in x.max(0)..(x + w).min(self.width as i64) {
+                self.plot(px, py, color);
+            }
+        }
+    }
+
+    pub fn fill_circle(&mut self, cx: i64, cy: i64, r: i64, color: Color) {
+        for y in (cy - r).max(0)..=(cy + r).min(self.height as i64 - 1) {
+            for x in (cx - r).max(0)..=(cx + r).min(self.width as i64 - 1) {
+                if (x - cx).pow(2) + (y - cy).pow(2) <= r * r {
+                    self.plot(x, y, color);
+                }
+            }
+        }
+    }
+
+    /// Draws `text` in the built-in font with its top-left corner at
+    /// `(x, y)`; each character is 3x5 pixels with a 1 pixel gap.
+    pub fn text(&mut self, x: i64, y: i64, text: &str, color: Color) {
+        for (i, c) in text.chars().enumerate() {
+            let c = c.to_ascii_uppercase();
+            let glyph = FONT
+                .iter()
+                .find(|(g, _)| *g == c)
+                .or_else(|| FONT.iter().find(|(g, _)| *g == '?'))
+                .unwrap()
+                .1;
+            for (row, bits) in glyph.iter().enumerate() {
+                for col in 0..3 {
+                    if bits & (4 >> col) != 0 {
+                        self.plot(x + 4 * i as i64 + col, y + row as i64, color);
+                    }
+                }
+            }
+        }
+    }
+
+    /// The canvas as terminal output: the cursor is moved to the top-left
+    /// corner, and each line of `▀` characters shows two rows of pixels.
+    pub fn render(&self) -> String {
+        let mut out = String::from("\x1b[H");
+        for y in (0..self.height).step_by(2) {
+            let mut last = None;
+            for x in 0..self.width {
+                let top = self.pixels[y * self.width + x];
+                let bottom = self.pixel(x, y + 1).unwrap_or([0; 3]);
+                if last != Some((top, bottom)) {
+                    out.push_str(&format!(
+                        "\x1b[38;2;{};{};{}m\x1b[48;2;{};{};{}m",
+                        top[0], top[1], top[2], bottom[0], bottom[1], bottom[2]
+                    ));
+                    last = Some((top, bottom));
+                }
+                out.push('▀');
+            }
+            out.push_str("\x1b[0m\n");
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shapes_are_clipped() {
+        let red = [255, 0, 0];
+        let mut canvas = Canvas::new(8, 6);
+        canvas.fill_rect(6, -2, 10, 4, red);
+        assert_eq!(canvas.pixel(7, 0), Some(red));
+        assert_eq!(canvas.pixel(7, 1), Some(red));
+        assert_eq!(canvas.pixel(7, 2), Some([0; 3]));
+        assert_eq!(canvas.pixel(5, 0), Some([0; 3]));
+
+        canvas.fill_circle(2, 3, 1, red);
+        assert_eq!(canvas.pixel(2, 2), Some(red));
+        assert_eq!(canvas.pixel(1, 2), Some([0; 3]));
+        assert_eq!(canvas.pixel(8, 0), None);
+    }
+
+    #[test]
+    fn test_text_and_render() {
+        let white = [255, 255, 255];
+        let mut canvas = Canvas::new(8, 5);
+        canvas.text(0, 0, "i1", white);
+        let row = |y| {
+            (0..8)
+                .map(|x| canvas.pixel(x, y) == Some(white))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(row(0), [true, true, true, false, false, true, false, false]);
+
+        let out = Canvas::new(2, 3).render();
+        assert!(out.starts_with("\x1b[H\x1b[38;2;0;0;0m\x1b[48;2;0;0;0m▀▀"));
+        assert_eq!(out.matches('\n').count(), 2);
+    }
+
+    #[test]
+    fn test_colors() {
+        assert_eq!(parse_color("#10ff00"), Ok([16, 255, 0]));
+        assert_eq!(parse_color("yellow"), Ok([255, 255, 0]));
+        assert!(parse_color("#12345").is_err());
+        assert!(parse_color("mauve").is_err());
+    }
+}
@@ -0,0 +1,88 @@
+//! PNG reading and writing behind `image_load` and `image_save`, built with
+//! the `image` feature. Images are 8-bit RGB; other PNG color types are
+//! converted on load, and alpha is dropped.
+
+use std::fs::File;
+use std::io::BufWriter;
+
+use png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
+
+#[derive(Debug)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    /// Row by row from the top-left corner.
+    pub pixels: Vec<[u8; 3]>,
+}
+
+pub fn load(path: &str) -> Result<Image, String> {
+    let failed = |e: &dyn std::fmt::Display| format!("Cannot load image {:?}: {}", path, e);
+    let file = File::open(path).map_err(|e| failed(&e))?;
+    let mut decoder = Decoder::new(file);
+    decoder.set_transformations(Transformations::normalize_to_color8());
+    let mut reader = decoder.read_info().map_err(|e| failed(&e))?;
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buf).map_err(|e| failed(&e))?;
+    let channels = match info.color_type {
+        ColorType::Grayscale => 1,
+        ColorType::GrayscaleAlpha => 2,
+        ColorType::Rgb => 3,
+        ColorType::Rgba => 4,
+        ColorType::Indexed => return Err(failed(&"unexpanded palette")),
+    };
+    let pixels = buf[..info.buffer_size()]
+        .chunks(channels)
+        .map(|px| match px.len() {
+            1 | 2 => [px[0]; 3],
+            _ => [px[0], px[1], px[2]],
+        })
+        .collect();
+    Ok(Image {
+        width: info.width as usize,
+        height: info.height as usize,
+        pixels,
+    })
+}
+
+pub fn save(path: &str, image: &Image) -> Result<(), String> {
+    let failed = |e: &dyn std::fmt::Display| format!("Cannot save image {:?}: {}", path, e);
+    let file = File::create(path).map_err(|e| failed(&e))?;
+    let mut encoder = Encoder::new(
+        BufWriter::new(file),
+        image.width as u32,
+        image.height as u32,
+    );
+    encoder.set_color(ColorType::Rgb);
+    encoder.set_depth(BitDepth::Eight);
+    let mut writer = encoder.write_header().map_err(|e| failed(&e))?;
+    writer
+        .write_image_data(&image.pixels.concat())
+        .map_err(|e| failed(&e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let path = std::env::temp_dir().join(format!("ew-image-{}.png", std::process::id()));
+        let path = path.to_str().unwrap();
+        let image = Image {
+            width: 2,
+            height: 1,
+            pixels: vec![[255, 0, 0], [0, 128, 255]],
+        };
+        save(path, &image).unwrap();
+        let loaded = load(path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!((loaded.width, loaded.height), (2, 1));
+        assert_eq!(loaded.pixels, image.pixels);
+
+        assert!(
+            load("/nonexistent/ew.png")
+                .unwrap_err()
+                .contains("/nonexistent/ew.png")
+        );
+    }
+}
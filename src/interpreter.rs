@@ -1,34 +1,383 @@
 use std::{
-    collections::HashMap,
-    io::{self, Write},
+    collections::{BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    fs,
+    io::{self, BufRead, Read, Write},
+    ops::Range,
+    sync::{
+        Arc, Condvar, LazyLock, Mutex,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        mpsc::{self, Receiver, RecvTimeoutError, Sender, SyncSender},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 
-use crate::ast::{AssignmentTarget, BinaryOp, Expr, Stmt, UnaryOp};
+use num_bigint::BigInt;
+use num_traits::{Signed, ToPrimitive, Zero};
+use rayon::prelude::*;
+
+use crate::alloc_profile::AllocProfile;
+use crate::ast::{
+    AssignmentTarget, BinaryOp, Expr, Iteration, Pattern, Pragma, Program, Span, Stmt, UnaryOp,
+    Variant,
+};
+use crate::csv::{self, Dialect};
+use crate::diagnostics::{Diagnostics, WarningKind};
+use crate::encoding;
+use crate::formatter::{binary_op, format_expr, format_stmt};
+use crate::parser::{bracket_depth, parse};
+use crate::plugin::{NativeFn, Registry};
+use crate::resolver::{mentions, resolve_with_globals};
+use crate::turtle::Turtle;
+use crate::version::{self, Feature};
+use crate::wildcard;
 
+/// A runtime value. Most values are owned and deep-copied on assignment,
+/// but channels and tasks are shared through `Arc`s, so one value can
+/// refer to another. Rather than collecting cycles, the builtins refuse to
+/// form them: `send` won't put a channel on itself or carry a task.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Val {
     Int(i64),
+    BigInt(BigInt),
     Float(f64),
     Bool(bool),
     Str(String),
     Array(Vec<Val>),
+    /// String-keyed values, e.g. a JSON object from the host. Keys are kept
+    /// sorted so output is deterministic.
+    Map(BTreeMap<String, Val>),
 
     Function {
+        name: String,
         params: Vec<String>,
         body: Vec<Stmt>,
+        /// A string literal opening a body of several statements.
+        doc: Option<String>,
+        /// `file:line` of the definition, when the host named the source.
+        defined_at: Option<String>,
+    },
+
+    /// A builtin referred to by name, e.g. the `sqrt` in `memoize(sqrt)`.
+    Builtin(&'static str),
+
+    Variant {
+        enum_name: String,
+        variant: String,
+        fields: Vec<Val>,
+    },
+
+    /// A read-only array from `freeze`. Indexing it gives read-only
+    /// sub-arrays; `origin` names where it came from for error messages.
+    Frozen {
+        origin: String,
+        value: Box<Val>,
+    },
+
+    /// A function wrapped by a builtin decorator such as `memoize`.
+    Decorated {
+        decorator: Decorator,
+        func: Box<Val>,
+    },
+
+    /// A function with its leading arguments filled in by `bind`.
+    Bound {
+        func: Box<Val>,
+        args: Vec<Val>,
+    },
+
+    /// Functions from `compose` or `pipe`, called in order: the first gets
+    /// the arguments and each later one the previous result.
+    Composed(Vec<Val>),
+
+    /// A function from `curry`, collecting arguments over several calls
+    /// until it has one for every parameter.
+    Curried {
+        func: Box<Val>,
+        args: Vec<Val>,
+    },
+
+    /// A function started with `spawn`, shared by every copy of the value.
+    Task(Arc<Task>),
+
+    /// A queue from `channel()`, shared by every copy of the value so that
+    /// `par_map` workers can pass values through it.
+    Channel(Arc<Channel>),
+
+    /// A stateful object owned by the interpreter's resource table, such as
+    /// a random number generator. `kind` names what it refers to.
+    Handle {
+        id: usize,
+        kind: &'static str,
     },
 
+    Null,
     Unit,
 }
 
+impl Val {
+    /// Converts JSON text to a value: objects become maps, and numbers
+    /// become Ints when they are whole and Floats otherwise.
+    pub fn from_json(text: &str) -> Result<Val, String> {
+        fn convert(json: serde_json::Value) -> Val {
+            use serde_json::Value;
+            match json {
+                Value::Null => Val::Null,
+                Value::Bool(b) => Val::Bool(b),
+                Value::Number(n) => match (n.as_i64(), n.as_u64()) {
+                    (Some(i), _) => Val::Int(i),
+                    (None, Some(u)) => Val::BigInt(BigInt::from(u)),
+                    (None, None) => Val::Float(n.as_f64().unwrap_or(f64::NAN)),
+                },
+                Value::String(s) => Val::Str(s),
+                Value::Array(items) => Val::Array(items.into_iter().map(convert).collect()),
+                Value::Object(map) => {
+                    Val::Map(map.into_iter().map(|(k, v)| (k, convert(v))).collect())
+                }
+            }
+        }
+
+        serde_json::from_str(text)
+            .map(convert)
+            .map_err(|e| format!("Invalid JSON: {}", e))
+    }
+
+    /// Makes `value` read-only if it is an array or map; other values are
+    /// immutable already and come back unchanged.
+    pub fn frozen(value: Val, origin: &str) -> Val {
+        match value {
+            Val::Array(_) | Val::Map(_) => Val::Frozen {
+                origin: origin.to_string(),
+                value: Box::new(value),
+            },
+            _ => value,
+        }
+    }
+
+    /// A `fn` statement that defines this function again, with its
+    /// decorators; `None` for anything else, including builtins.
+    pub fn definition(&self) -> Option<Stmt> {
+        match self {
+            Val::Function {
+                name, params, body, ..
+            } => Some(Stmt::Function {
+                name: name.clone(),
+                params: params.clone(),
+                body: body.clone(),
+                decorators: vec![],
+                line: 0,
+            }),
+            Val::Decorated { decorator, func } => {
+                let mut def = func.definition()?;
+                if let Stmt::Function { decorators, .. } = &mut def {
+                    let name = match decorator {
+                        Decorator::Memoize(_) => "memoize",
+                        Decorator::Timed => "timed",
+                    };
+                    decorators.insert(0, name.to_string());
+                }
+                Some(def)
+            }
+            _ => None,
+        }
+    }
+
+    /// Whether the value can be called like a function.
+    fn is_callable(&self) -> bool {
+        matches!(
+            self,
+            Val::Function { .. }
+                | Val::Builtin(_)
+                | Val::Decorated { .. }
+                | Val::Bound { .. }
+                | Val::Composed(_)
+                | Val::Curried { .. }
+        )
+    }
+
+    /// The parameters still to be passed, for functions that name them.
+    fn params(&self) -> Option<Vec<String>> {
+        match self {
+            Val::Function { params, .. } => Some(params.clone()),
+            Val::Decorated { func, .. } => func.params(),
+            Val::Bound { func, args } | Val::Curried { func, args } => {
+                Some(func.params()?.into_iter().skip(args.len()).collect())
+            }
+            _ => None,
+        }
+    }
+
+    /// The value itself, or the array inside a `Frozen`.
+    fn thaw(self) -> Val {
+        match self {
+            Val::Frozen { value, .. } => *value,
+            _ => self,
+        }
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Val::Frozen { value, .. } => value.type_name(),
+            Val::Int(_) | Val::BigInt(_) => "Int",
+            Val::Float(_) => "Float",
+            Val::Bool(_) => "Bool",
+            Val::Str(_) => "Str",
+            Val::Array(_) => "Array",
+            Val::Map(_) => "Map",
+            Val::Function { .. }
+            | Val::Builtin(_)
+            | Val::Decorated { .. }
+            | Val::Bound { .. }
+            | Val::Composed(_)
+            | Val::Curried { .. } => "Function",
+            Val::Variant { .. } => "Variant",
+            Val::Task(_) => "Task",
+            Val::Channel(_) => "Channel",
+            Val::Handle { .. } => "Handle",
+            Val::Null => "Null",
+            Val::Unit => "Unit",
+        }
+    }
+
+    /// How the value shows inside an array or variant: strings are quoted
+    /// so that `["a,b"]` and `["a", "b"]` print differently.
+    fn nested(&self) -> String {
+        match self {
+            Val::Str(s) => quote(s),
+            Val::Frozen { value, .. } => value.nested(),
+            _ => self.to_string(),
+        }
+    }
+
+    /// Source syntax for the value, so that pasting it back into a program
+    /// gives an equal value. Functions have no literal form and keep their
+    /// `<fn name(...)>` rendering.
+    pub fn repr(&self) -> String {
+        match self {
+            Val::Str(s) => quote(s),
+            Val::Frozen { value, .. } => format!("freeze({})", value.repr()),
+            Val::Float(n) => {
+                // `{}` already prints the shortest text that parses back to
+                // the same f64, but drops the `.0` that makes it a float.
+                let s = n.to_string();
+                if n.is_nan() {
+                    "NAN".to_string()
+                } else if n.is_infinite() {
+                    if *n > 0.0 { "INF" } else { "-INF" }.to_string()
+                } else if !s.contains('.') {
+                    format!("{}.0", s)
+                } else {
+                    s
+                }
+            }
+            Val::Array(items) => {
+                let items: Vec<String> = items.iter().map(Val::repr).collect();
+                format!("[{}]", items.join(", "))
+            }
+            Val::Variant {
+                enum_name,
+                variant,
+                fields,
+            } if !fields.is_empty() => {
+                let fields: Vec<String> = fields.iter().map(Val::repr).collect();
+                format!("{}::{}({})", enum_name, variant, fields.join(", "))
+            }
+            // ew has no map literal, so a map is the comprehension that
+            // builds it from its entries.
+            Val::Map(map) => {
+                let entries: Vec<String> = map
+                    .iter()
+                    .map(|(k, v)| format!("[{}, {}]", quote(k), v.repr()))
+                    .collect();
+                format!("{{e[0]: e[1] for e in [{}]}}", entries.join(", "))
+            }
+            _ => self.to_string(),
+        }
+    }
+
+    /// Multi-line rendering for `pprint`: containers that don't fit on one
+    /// line get one element per line, indented by depth.
+    fn pretty(&self, indent: usize) -> String {
+        const WIDTH: usize = 60;
+
+        let (open, close, items) = match self {
+            Val::Frozen { value, .. } => return value.pretty(indent),
+            Val::Array(items) => ("[".to_string(), "]", items),
+            Val::Variant {
+                enum_name,
+                variant,
+                fields,
+            } if !fields.is_empty() => (format!("{}::{}(", enum_name, variant), ")", fields),
+            _ => return self.nested(),
+        };
+
+        let flat = self.nested();
+        if indent + flat.len() <= WIDTH || items.is_empty() {
+            return flat;
+        }
+
+        let pad = "  ".repeat(indent + 1);
+        let mut out = open;
+        for item in items {
+            out.push('\n');
+            out.push_str(&pad);
+            out.push_str(&item.pretty(indent + 1));
+            out.push(',');
+        }
+        out.push('\n');
+        out.push_str(&"  ".repeat(indent));
+        out.push_str(close);
+        out
+    }
+}
+
+/// A string literal for `s`, escaped the way the parser unescapes.
+pub(crate) fn quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\0"),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Decorator {
+    /// Caches results by argument values; the id picks the cache.
+    Memoize(usize),
+    /// Reports how long each call took on stderr.
+    Timed,
+}
+
+static NEXT_MEMO_ID: AtomicUsize = AtomicUsize::new(0);
+
 impl std::fmt::Display for Val {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Val::Int(n) => write!(f, "{}", n),
+            Val::BigInt(n) => write!(f, "{}", n),
             Val::Float(n) => write!(f, "{}", n),
             Val::Bool(b) => write!(f, "{}", b),
             Val::Str(s) => write!(f, "{}", s),
 
+            Val::Array(rows) if f.alternate() && is_grid(rows) => write_grid(f, rows),
+            Val::Frozen { value, .. } => value.fmt(f),
+            Val::Map(map) => {
+                let entries: Vec<String> = map
+                    .iter()
+                    .map(|(k, v)| format!("{}: {}", quote(k), v.nested()))
+                    .collect();
+                write!(f, "{{{}}}", entries.join(", "))
+            }
             Val::Array(arr) => {
                 write!(f, "[")?;
                 let mut first = true;
@@ -37,824 +386,7642 @@ impl std::fmt::Display for Val {
                         write!(f, ", ")?;
                     }
                     first = false;
-                    write!(f, "{}", elem)?;
+                    write!(f, "{}", elem.nested())?;
                 }
                 write!(f, "]")
             }
 
-            Val::Function { params, .. } => write!(f, "<function({})>", params.join(", ")),
+            // The REPL shows a function by its reconstructed definition.
+            Val::Function { .. } | Val::Decorated { .. }
+                if f.alternate() && self.definition().is_some() =>
+            {
+                write!(f, "{}", format_stmt(&self.definition().unwrap()))
+            }
+            Val::Function {
+                name,
+                params,
+                defined_at,
+                ..
+            } => {
+                write!(f, "<fn {}({})", name, params.join(", "))?;
+                if let Some(at) = defined_at {
+                    write!(f, " at {}", at)?;
+                }
+                write!(f, ">")
+            }
+            Val::Builtin(name) => write!(f, "<builtin {}>", name),
+            Val::Decorated { decorator, func } => match decorator {
+                Decorator::Memoize(_) => write!(
+                    f,
+                    "<memoized {}>",
+                    func.to_string().trim_matches(['<', '>'])
+                ),
+                Decorator::Timed => {
+                    write!(f, "<timed {}>", func.to_string().trim_matches(['<', '>']))
+                }
+            },
+            Val::Variant {
+                enum_name,
+                variant,
+                fields,
+            } => {
+                write!(f, "{}::{}", enum_name, variant)?;
+                if !fields.is_empty() {
+                    let fields: Vec<String> = fields.iter().map(Val::nested).collect();
+                    write!(f, "({})", fields.join(", "))?;
+                }
+                Ok(())
+            }
+            Val::Bound { func, args } => {
+                write!(f, "<bound {}", func.to_string().trim_matches(['<', '>']))?;
+                if !args.is_empty() {
+                    let args: Vec<String> = args.iter().map(Val::nested).collect();
+                    write!(f, " with {}", args.join(", "))?;
+                }
+                write!(f, ">")
+            }
+            Val::Composed(funcs) => {
+                let funcs: Vec<String> = funcs
+                    .iter()
+                    .map(|func| func.to_string().trim_matches(['<', '>']).to_string())
+                    .collect();
+                write!(f, "<{}>", funcs.join(" |> "))
+            }
+            Val::Curried { func, .. } => write!(
+                f,
+                "<curried {} needs {}>",
+                func.to_string().trim_matches(['<', '>']),
+                self.params().unwrap_or_default().join(", ")
+            ),
+            Val::Task(_) => write!(f, "<task>"),
+            Val::Channel(_) => write!(f, "<channel>"),
+            Val::Handle { id, kind } => write!(f, "<{} #{}>", kind, id),
+            Val::Null => write!(f, "null"),
             Val::Unit => write!(f, "()"),
         }
     }
 }
 
-struct Frame {
-    local: HashMap<String, Val>,
-    parent: Option<usize>,
+/// Turns the result of a checked integer op into a `Val::Int`, promoting to
+/// a `Val::BigInt` when the op overflowed.
+fn checked(op: BinaryOp, res: Option<i64>, a: i64, b: i64) -> Result<Val, String> {
+    match res {
+        Some(n) => Ok(Val::Int(n)),
+        None => big_bin_op(op, BigInt::from(a), BigInt::from(b)),
+    }
 }
 
-pub struct Interpreter {
-    global: HashMap<String, Val>,
-    stack: Vec<Frame>,
+fn to_big(val: &Val) -> Option<BigInt> {
+    match val {
+        Val::Int(n) => Some(BigInt::from(*n)),
+        Val::BigInt(n) => Some(n.clone()),
+        _ => None,
+    }
 }
 
-enum Flow {
-    Continue(Val),
-    Return(Val),
+/// Demotes a big integer back to `Val::Int` whenever it fits, so that equal
+/// numbers always share a representation.
+fn normalize_big(n: BigInt) -> Val {
+    match n.to_i64() {
+        Some(i) => Val::Int(i),
+        None => Val::BigInt(n),
+    }
 }
 
-impl Interpreter {
-    pub fn new() -> Interpreter {
-        Interpreter {
-            global: HashMap::new(),
-            stack: vec![Frame::new()],
-        }
+fn big_bin_op(op: BinaryOp, a: BigInt, b: BigInt) -> Result<Val, String> {
+    match op {
+        BinaryOp::Add => Ok(normalize_big(a + b)),
+        BinaryOp::Sub => Ok(normalize_big(a - b)),
+        BinaryOp::Mul => Ok(normalize_big(a * b)),
+        BinaryOp::Div if b.is_zero() => Err("Division by zero".to_string()),
+        BinaryOp::Div => Ok(normalize_big(a / b)),
+        BinaryOp::Mod if b.is_zero() => Err("Modulo by zero".to_string()),
+        BinaryOp::Mod => Ok(normalize_big(a % b)),
+        BinaryOp::Eq => Ok(Val::Bool(a == b)),
+        BinaryOp::Ne => Ok(Val::Bool(a != b)),
+        BinaryOp::Lt => Ok(Val::Bool(a < b)),
+        BinaryOp::Le => Ok(Val::Bool(a <= b)),
+        BinaryOp::Gt => Ok(Val::Bool(a > b)),
+        BinaryOp::Ge => Ok(Val::Bool(a >= b)),
+        _ => Err(format!("Cannot apply {:?} to {} and {}", op, a, b)),
     }
+}
 
-    pub fn run(&mut self, source: &Vec<Stmt>) -> Result<Val, String> {
-        let mut res = Val::Unit;
-        for stmt in source {
-            match self.exec_stmt(stmt)? {
-                Flow::Continue(v) => res = v,
-                Flow::Return(v) => return Ok(v),
-            }
-        }
-        Ok(res)
+/// Checks the single task argument of `resume` and `done`.
+fn task_arg(name: &str, args: Vec<Val>) -> Result<Arc<Task>, String> {
+    match <[Val; 1]>::try_from(args) {
+        Ok([Val::Task(task)]) => Ok(task),
+        Ok([other]) => Err(format!("{}() requires a task, got {}", name, other.repr())),
+        Err(args) => Err(format!("{}() takes 1 argument, got {}", name, args.len())),
     }
+}
 
-    fn exec_stmt(&mut self, stmt: &Stmt) -> Result<Flow, String> {
-        match stmt {
-            Stmt::Function { name, params, body } => {
-                self.global.insert(
-                    name.clone(),
-                    Val::Function {
-                        params: params.clone(),
-                        body: body.clone(),
-                    },
-                );
-                Ok(Flow::Continue(Val::Unit))
-            }
-
-            Stmt::Return(expr) => {
-                let retval = self.eval_expr(expr)?;
-                Ok(Flow::Return(retval))
-            }
-
-            Stmt::Assignment { name, value } => {
-                let val = self.eval_expr(value)?;
+/// Checks the `(event, handler)` arguments of `on` and `off`.
+fn event_and_handler(name: &str, args: Vec<Val>) -> Result<(String, Val), String> {
+    if args.len() != 2 {
+        return Err(format!("{}() takes 2 arguments, got {}", name, args.len()));
+    }
+    let [event, handler]: [Val; 2] = args.try_into().unwrap();
+    match (event, handler) {
+        (Val::Str(event), handler) if handler.is_callable() => Ok((event, handler)),
+        (event, handler) => Err(format!(
+            "{}() requires an event name and a function, got {} and {}",
+            name,
+            event.repr(),
+            handler.repr()
+        )),
+    }
+}
 
-                if let Some(exists) = self.lookup_mut(name) {
-                    return Err(format!(
-                        "The variable already exists: [{} = {}]",
-                        name, exists
-                    ));
-                } else {
-                    self.stack
-                        .last_mut()
-                        .expect("Call Stack Should Not Be Empty")
-                        .local
-                        .insert(name.clone(), val);
-                }
+/// Natural ordering for `sort` and the sorted-array helpers: numbers with
+/// numbers of the same kind, strings with strings.
+fn compare(name: &str, a: &Val, b: &Val) -> Result<std::cmp::Ordering, String> {
+    let ord = match (a, b) {
+        (Val::Str(x), Val::Str(y)) => Some(x.cmp(y)),
+        (Val::Int(x), Val::Int(y)) => Some(x.cmp(y)),
+        (Val::Float(x), Val::Float(y)) => x.partial_cmp(y),
+        (Val::Int(_) | Val::BigInt(_), Val::Int(_) | Val::BigInt(_)) => {
+            Some(to_big(a).cmp(&to_big(b)))
+        }
+        (
+            Val::Int(_) | Val::BigInt(_) | Val::Float(_),
+            Val::Int(_) | Val::BigInt(_) | Val::Float(_),
+        ) => as_f64(a)
+            .zip(as_f64(b))
+            .and_then(|(x, y)| x.partial_cmp(&y)),
+        _ => {
+            return Err(format!(
+                "{}() cannot compare {} with {}",
+                name,
+                a.type_name(),
+                b.type_name()
+            ));
+        }
+    };
+    ord.ok_or_else(|| format!("{}() cannot order NaN", name))
+}
 
-                Ok(Flow::Continue(Val::Unit))
-            }
+/// Index of the first element of the sorted `items` for which `past` holds,
+/// by binary search.
+fn partition_point(
+    items: &[Val],
+    mut past: impl FnMut(&Val) -> Result<bool, String>,
+) -> Result<usize, String> {
+    let (mut lo, mut hi) = (0, items.len());
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if past(&items[mid])? {
+            hi = mid;
+        } else {
+            lo = mid + 1;
+        }
+    }
+    Ok(lo)
+}
 
-            Stmt::Reassignment { target, value } => {
-                let val = self.eval_expr(value)?;
-                match target {
-                    AssignmentTarget::Ident(name) => {
-                        if let Some(exists) = self.lookup_mut(name) {
-                            *exists = val;
-                        } else {
-                            return Err(format!("The variable [{}] does not exist", name));
-                        }
-                    }
-                    AssignmentTarget::ArrayAccess { name, indices } => {
-                        let evaluated_indices: Result<Vec<usize>, String> = indices
-                            .iter()
-                            .map(|expr| {
-                                let idx_val = self.eval_expr(expr)?;
-                                match idx_val {
-                                    Val::Int(n) => Ok(n as usize),
-                                    _ => Err(format!(
-                                        "Array index must be an integer, got {:?}",
-                                        idx_val
-                                    )),
-                                }
-                            })
-                            .collect();
-                        let evaluated_indices = evaluated_indices?;
-                        let var = self
-                            .lookup_mut(name)
-                            .ok_or_else(|| format!("The variable [{}] does not exist", name))?;
-                        let mut cur = var;
+/// Checks the arguments of the `f(arr, x)` sorted-array helpers.
+fn array_and_value<'a>(name: &str, args: &'a [Val]) -> Result<(&'a [Val], &'a Val), String> {
+    if args.len() != 2 {
+        return Err(format!("{}() takes 2 arguments, got {}", name, args.len()));
+    }
+    match &args[0] {
+        Val::Array(items) => Ok((items, &args[1])),
+        other => Err(format!(
+            "{}() requires a sorted array, got {}",
+            name,
+            other.repr()
+        )),
+    }
+}
 
-                        for &idx in &evaluated_indices[..evaluated_indices.len() - 1] {
-                            match cur {
-                                Val::Array(arr) => {
-                                    if idx >= arr.len() {
-                                        return Err(format!("Array index out of bounds: {}", idx));
-                                    }
-                                    cur = &mut arr[idx];
-                                }
-                                _ => return Err(format!("Cannot index into {:?}", cur)),
-                            }
-                        }
+/// FNV-1a over a tagged encoding of `val`, so the result is the same on
+/// every run and platform (unlike `std`'s randomly seeded hasher). Values
+/// that can change in place, like arrays, are rejected.
+fn stable_hash(val: &Val, state: &mut u64) -> Result<(), String> {
+    fn feed(state: &mut u64, bytes: &[u8]) {
+        for b in bytes {
+            *state ^= *b as u64;
+            *state = state.wrapping_mul(0x100000001b3);
+        }
+    }
 
-                        let final_idx = evaluated_indices[evaluated_indices.len() - 1];
-                        match cur {
-                            Val::Array(arr) => {
-                                if final_idx >= arr.len() {
-                                    return Err(format!(
-                                        "Array index out of bounds: {}",
-                                        final_idx
-                                    ));
-                                }
-                                arr[final_idx] = val;
-                            }
-                            Val::Str(s) => {
-                                let mut chars: Vec<char> = s.chars().collect();
-                                if final_idx >= chars.len() {
-                                    return Err(format!(
-                                        "String index out of bounds: {}",
-                                        final_idx
-                                    ));
-                                }
-                                match &val {
-                                    Val::Str(new_char) => {
-                                        let new_chars: Vec<char> = new_char.chars().collect();
-                                        if new_chars.len() != 1 {
-                                            return Err(format!(
-                                                "Can only assign single character to string index, got string of length {}",
-                                                new_chars.len()
-                                            ));
-                                        }
-                                        chars[final_idx] = new_chars[0];
-                                        *s = chars.into_iter().collect();
-                                    }
-                                    _ => {
-                                        return Err(format!(
-                                            "Can only assign string to string index, got {:?}",
-                                            val
-                                        ));
-                                    }
-                                }
-                            }
-                            _ => return Err(format!("Cannot index into {:?}", cur)),
-                        }
-                    }
-                }
-                Ok(Flow::Continue(Val::Unit))
+    match val {
+        Val::Int(n) => {
+            feed(state, b"i");
+            feed(state, &n.to_le_bytes());
+        }
+        Val::BigInt(n) => {
+            feed(state, b"I");
+            feed(state, &n.to_signed_bytes_le());
+        }
+        Val::Float(f) => {
+            // 0.0 == -0.0, so they must hash alike.
+            let f = if *f == 0.0 { 0.0 } else { *f };
+            feed(state, b"f");
+            feed(state, &f.to_bits().to_le_bytes());
+        }
+        Val::Bool(b) => feed(state, if *b { b"t" } else { b"F" }),
+        Val::Str(s) => {
+            feed(state, b"s");
+            feed(state, &(s.len() as u64).to_le_bytes());
+            feed(state, s.as_bytes());
+        }
+        Val::Null => feed(state, b"n"),
+        Val::Frozen { value, .. } => {
+            let Val::Array(items) = value.as_ref() else {
+                return stable_hash(value, state);
+            };
+            feed(state, b"a");
+            feed(state, &(items.len() as u64).to_le_bytes());
+            for item in items {
+                // Elements of a frozen array are read-only too.
+                stable_hash(&Val::frozen(item.clone(), ""), state)?;
             }
-
-            Stmt::Expr(expr) => {
-                let value = self.eval_expr(expr)?;
-                Ok(Flow::Continue(value))
+        }
+        Val::Variant {
+            enum_name,
+            variant,
+            fields,
+        } => {
+            feed(state, b"v");
+            stable_hash(&Val::Str(format!("{}::{}", enum_name, variant)), state)?;
+            feed(state, &(fields.len() as u64).to_le_bytes());
+            for field in fields {
+                stable_hash(field, state)?;
             }
         }
+        _ => return Err(format!("hash() cannot hash {} values", val.type_name())),
     }
+    Ok(())
+}
 
-    fn eval_expr(&mut self, expr: &Expr) -> Result<Val, String> {
-        match expr {
-            Expr::Int(i) => Ok(Val::Int(*i)),
-            Expr::Bool(b) => Ok(Val::Bool(*b)),
-            Expr::Float(f) => Ok(Val::Float(*f)),
-            Expr::Str(s) => Ok(Val::Str(s.clone())),
-            Expr::Array(arr) => {
-                let res: Vec<Val> = arr
-                    .iter()
-                    .map(|e| self.eval_expr(e))
-                    .collect::<Result<Vec<Val>, String>>()?;
-                Ok(Val::Array(res))
-            }
-
-            Expr::Var(name) => self.lookup(name),
-
-            Expr::Unary { op, expr } => {
-                let val = self.eval_expr(expr)?;
-
-                match (op, val) {
-                    (UnaryOp::Neg, Val::Int(i)) => Ok(Val::Int(-i)),
-                    (UnaryOp::Neg, Val::Float(f)) => Ok(Val::Float(-f)),
-                    (UnaryOp::Not, Val::Bool(b)) => Ok(Val::Bool(!b)),
-                    (op, val) => Err(format!("Cannot apply {:?} to {:?}", op, val)),
-                }
-            }
+/// Stable merge sort with a comparison that can fail, since a user
+/// comparator may error or be inconsistent (which `slice::sort_by` is
+/// allowed to panic on).
+fn merge_sort(
+    mut items: Vec<Val>,
+    cmp: &mut impl FnMut(&Val, &Val) -> Result<std::cmp::Ordering, String>,
+) -> Result<Vec<Val>, String> {
+    if items.len() <= 1 {
+        return Ok(items);
+    }
+    let right = items.split_off(items.len() / 2);
+    let left = merge_sort(items, cmp)?;
+    let right = merge_sort(right, cmp)?;
 
-            Expr::Binary { op, lhs, rhs } => {
-                let l = self.eval_expr(lhs)?;
-                let r = self.eval_expr(rhs)?;
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let mut left = left.into_iter().peekable();
+    let mut right = right.into_iter().peekable();
+    while let (Some(l), Some(r)) = (left.peek(), right.peek()) {
+        // Taking from the left on ties keeps equal elements in order.
+        if cmp(l, r)? == std::cmp::Ordering::Greater {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
+    }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
 
-                self.eval_bin_op(*op, l, r)
-            }
+/// Predefined names that a program may still shadow with `let`.
+fn constant(name: &str) -> Option<Val> {
+    match name {
+        "NAN" => Some(Val::Float(f64::NAN)),
+        "INF" => Some(Val::Float(f64::INFINITY)),
+        _ => None,
+    }
+}
 
-            Expr::Call { name, args } => {
-                if let Some(builtin) = Self::builtins().get(name.as_str()) {
-                    let arg_vals: Vec<Val> = args
-                        .iter()
-                        .map(|a| self.eval_expr(a))
-                        .collect::<Result<_, _>>()?;
-                    return builtin(arg_vals);
-                }
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
 
-                let func = self.lookup(name)?;
+fn as_f64(val: &Val) -> Option<f64> {
+    match val {
+        Val::Int(n) => Some(*n as f64),
+        Val::BigInt(n) => n.to_f64(),
+        Val::Float(f) => Some(*f),
+        _ => None,
+    }
+}
 
-                if let Val::Function { params, body } = func {
-                    let arg_vals: Vec<Val> = args
-                        .iter()
-                        .map(|a| self.eval_expr(a))
-                        .collect::<Result<_, _>>()?;
+/// Signature and one-line description of every builtin, for `help()`.
+pub(crate) fn builtin_doc(name: &str) -> Option<(&'static str, &'static str)> {
+    Some(match name {
+        "print" => (
+            "print(...values)",
+            "Prints the values with no separator or newline.",
+        ),
+        "println" => (
+            "println(...values)",
+            "Prints the values separated by spaces, then a newline.",
+        ),
+        "log_debug" => (
+            "log_debug(message, fields)",
+            "Logs a debug record with an optional map of fields.",
+        ),
+        "log_info" => (
+            "log_info(message, fields)",
+            "Logs an info record with an optional map of fields.",
+        ),
+        "log_warn" => (
+            "log_warn(message, fields)",
+            "Logs a warning record with an optional map of fields.",
+        ),
+        "log_error" => (
+            "log_error(message, fields)",
+            "Logs an error record with an optional map of fields.",
+        ),
+        "sin" => ("sin(x)", "Sine of x radians."),
+        "cos" => ("cos(x)", "Cosine of x radians."),
+        "floor" => ("floor(x)", "Largest integer not greater than x."),
+        "abs" => ("abs(x)", "Absolute value of x."),
+        "sqrt" => ("sqrt(x)", "Square root of x, as a float."),
+        "len" => (
+            "len(v)",
+            "Number of characters in a string or elements in an array or map.",
+        ),
+        "clear" => ("clear()", "Clears the terminal."),
+        "sleep" => ("sleep(ms)", "Pauses for ms milliseconds."),
+        "bool" => ("bool(v)", "Whether v is truthy."),
+        "parse_int" => ("parse_int(s)", "Parses s as an integer, or returns null."),
+        "parse_float" => ("parse_float(s)", "Parses s as a float, or returns null."),
+        "to_fixed" => (
+            "to_fixed(x, digits)",
+            "Formats x with the given number of decimals, at most 100.",
+        ),
+        "format_number" => (
+            "format_number(x, decimals, thousands_sep?)",
+            "x with the given number of decimals and its digits grouped in threes by thousands_sep, a comma by default.",
+        ),
+        "to_scientific" => (
+            "to_scientific(x, digits)",
+            "x in scientific notation with the given number of decimals, such as 1.23e4.",
+        ),
+        "round" => (
+            "round(x, digits)",
+            "x rounded to the given number of decimals.",
+        ),
+        "approx_eq" => (
+            "approx_eq(a, b, eps)",
+            "Whether a and b differ by at most eps.",
+        ),
+        "is_nan" => ("is_nan(x)", "Whether x is the float NAN."),
+        "is_inf" => ("is_inf(x)", "Whether x is INF or -INF."),
+        "repr" => (
+            "repr(v)",
+            "v as source code that evaluates back to an equal value.",
+        ),
+        "str" => ("str(v)", "v as text, the way print shows it."),
+        "pprint" => (
+            "pprint(v)",
+            "Prints v with nested values spread over indented lines.",
+        ),
+        "help" => ("help(f)", "Prints the signature and documentation of f."),
+        "memoize" => (
+            "memoize(f)",
+            "Wraps f so results are cached by argument values.",
+        ),
+        "timed" => (
+            "timed(f)",
+            "Wraps f so each call logs its duration at the info level.",
+        ),
+        "time" => (
+            "time(f)",
+            "Calls f() and returns [result, elapsed milliseconds].",
+        ),
+        "bench" => (
+            "bench(f, iters)",
+            "Calls f() iters times; a map of the min, mean and max milliseconds.",
+        ),
+        "bind" => (
+            "bind(f, args...)",
+            "A function calling f with args before its own arguments.",
+        ),
+        "compose" => ("compose(f, g)", "A function computing f(g(args...))."),
+        "pipe" => (
+            "pipe(f, g, ...)",
+            "A function passing its arguments to f, then the result through g and the rest in turn.",
+        ),
+        "base64_encode" => (
+            "base64_encode(s)",
+            "The base64 encoding of s's UTF-8 bytes.",
+        ),
+        "base64_decode" => ("base64_decode(s)", "The string whose base64 encoding is s."),
+        "hex_encode" => ("hex_encode(s)", "s's UTF-8 bytes as lowercase hex digits."),
+        "hex_decode" => ("hex_decode(s)", "The string whose hex encoding is s."),
+        "url_encode" => (
+            "url_encode(s)",
+            "s with everything but letters, digits and -_.~ percent-encoded.",
+        ),
+        "url_decode" => (
+            "url_decode(s)",
+            "s with %XX escapes decoded and + read as a space.",
+        ),
+        "glob_match" => (
+            "glob_match(pattern, name)",
+            "Whether name matches a glob such as *.txt; * and ? stop at /, and [a-z] matches a set.",
+        ),
+        "like" => (
+            "like(s, pattern)",
+            "Whether s matches pattern, where * is any run of characters and ? any one.",
+        ),
+        "load_plugin" => (
+            "load_plugin(path)",
+            "Adds the builtins of the native library at path; needs the `plugins` feature.",
+        ),
+        "mkdir" => (
+            "mkdir(path)",
+            "Creates a directory and any missing parents.",
+        ),
+        "remove_file" => ("remove_file(path)", "Deletes a file."),
+        "remove_dir" => ("remove_dir(path)", "Deletes an empty directory."),
+        "copy_file" => (
+            "copy_file(from, to)",
+            "Copies a file, replacing to if it exists.",
+        ),
+        "rename" => ("rename(from, to)", "Moves or renames a file or directory."),
+        "file_size" => ("file_size(path)", "The size of a file in bytes."),
+        "is_dir" => ("is_dir(path)", "Whether path exists and is a directory."),
+        "is_file" => (
+            "is_file(path)",
+            "Whether path exists and is a regular file.",
+        ),
+        "modified_time" => (
+            "modified_time(path)",
+            "When path was last modified, in seconds since the Unix epoch.",
+        ),
+        "image_load" => (
+            "image_load(path)",
+            "A PNG file as {width, height, pixels}, with pixels[y][x] = [r, g, b].",
+        ),
+        "image_save" => (
+            "image_save(path, img)",
+            "Writes an image shaped like image_load's result as a PNG file.",
+        ),
+        "pixel" => (
+            "pixel(img, x, y)",
+            "The [r, g, b] pixel of img at column x, row y.",
+        ),
+        "forward" => (
+            "forward(n)",
+            "Moves the turtle n steps ahead, drawing a line unless the pen is up.",
+        ),
+        "turn" => ("turn(deg)", "Turns the turtle clockwise by deg degrees."),
+        "pen_up" => ("pen_up()", "Stops the turtle drawing as it moves."),
+        "pen_down" => ("pen_down()", "Makes the turtle draw as it moves."),
+        "save_svg" => (
+            "save_svg(path)",
+            "Writes the turtle's drawing to an SVG file.",
+        ),
+        "window" => (
+            "window(w, h)",
+            "Opens a w x h pixel canvas, shown in the terminal by present().",
+        ),
+        "draw_rect" => (
+            "draw_rect(x, y, w, h, color)",
+            "Fills a rectangle on the canvas.",
+        ),
+        "draw_circle" => (
+            "draw_circle(x, y, r, color)",
+            "Fills a circle on the canvas.",
+        ),
+        "draw_text" => (
+            "draw_text(x, y, text, color)",
+            "Writes text on the canvas in a 3x5 pixel font.",
+        ),
+        "present" => ("present()", "Shows the canvas in the terminal."),
+        "poll_events" => (
+            "poll_events()",
+            "The names of the keys pressed since the last call, without waiting.",
+        ),
+        "key_pressed" => (
+            "key_pressed()",
+            "Whether a key press is waiting to be read, without waiting.",
+        ),
+        "read_key" => (
+            "read_key(timeout_ms)",
+            "The next key pressed, e.g. \"a\", \"Up\" or \"ctrl+x\", or null if none comes within the timeout.",
+        ),
+        "read_line" => (
+            "read_line()",
+            "The next line of stdin without its line ending, or null at the end.",
+        ),
+        "read_all_stdin" => ("read_all_stdin()", "The rest of stdin as one string."),
+        "lines_stdin" => ("lines_stdin()", "The rest of stdin as an array of lines."),
+        "csv_parse" => (
+            "csv_parse(text, header?, delimiter?, quote?)",
+            "The records of CSV text as arrays of strings, or as maps keyed by the first row's \
+             fields when header is true.",
+        ),
+        "csv_stringify" => (
+            "csv_stringify(rows, columns?, delimiter?, quote?)",
+            "CSV text for an array of arrays, or of maps under a header of the columns or else of every key.",
+        ),
+        "uuid" => ("uuid(rng?)", "A random (version 4) UUID string."),
+        "random_hex" => (
+            "random_hex(n, rng?)",
+            "A string of n random hex digits; n can be at most 1048576.",
+        ),
+        "random_string" => (
+            "random_string(n, charset, rng?)",
+            "A string of n characters drawn at random from charset; n can be at most 1048576.",
+        ),
+        "md5" => ("md5(s)", "The MD5 digest of s's UTF-8 bytes, in hex."),
+        "sha1" => ("sha1(s)", "The SHA-1 digest of s's UTF-8 bytes, in hex."),
+        "sha256" => (
+            "sha256(s)",
+            "The SHA-256 digest of s's UTF-8 bytes, in hex.",
+        ),
+        "crc32" => (
+            "crc32(s)",
+            "The CRC-32 checksum of s's UTF-8 bytes, in hex.",
+        ),
+        "curry" => (
+            "curry(f)",
+            "A function taking f's arguments over several calls; it calls f once it has them all.",
+        ),
+        "matrix" => (
+            "matrix(rows, cols, init)",
+            "A rows x cols array of arrays filled with init.",
+        ),
+        "transpose" => ("transpose(m)", "Swaps the rows and columns of a matrix."),
+        "matmul" => ("matmul(a, b)", "Matrix product of a and b."),
+        "dot" => ("dot(a, b)", "Sum of the pairwise products of two arrays."),
+        "divmod" => (
+            "divmod(a, b)",
+            "[floor(a / b), remainder], the remainder taking b's sign.",
+        ),
+        "sort" => (
+            "sort(arr)",
+            "A sorted copy of an array of all numbers or all strings.",
+        ),
+        "sort_by" => (
+            "sort_by(arr, cmp)",
+            "A stably sorted copy, ordered by cmp(a, b) returning -1, 0 or 1.",
+        ),
+        "bsearch" => (
+            "bsearch(arr, x)",
+            "Index of x in the sorted arr, or null if absent.",
+        ),
+        "lower_bound" => (
+            "lower_bound(arr, x)",
+            "First index in the sorted arr whose element is >= x.",
+        ),
+        "upper_bound" => (
+            "upper_bound(arr, x)",
+            "First index in the sorted arr whose element is > x.",
+        ),
+        "is_sorted" => ("is_sorted(arr)", "Whether arr is in non-decreasing order."),
+        "copy" => ("copy(v)", "A copy of v."),
+        "deep_copy" => ("deep_copy(v)", "A copy of v and everything nested in it."),
+        "deep_eq" => (
+            "deep_eq(a, b)",
+            "Whether a and b are structurally equal; functions compare by definition, and NAN equals nothing.",
+        ),
+        "hash" => (
+            "hash(v)",
+            "A stable integer hash of a number, string, bool, null, enum value or frozen array.",
+        ),
+        "freeze" => (
+            "freeze(arr, origin)",
+            "A read-only view of arr; origin, if given, is named in errors.",
+        ),
+        "is_frozen" => ("is_frozen(v)", "Whether v came from freeze()."),
+        "keys" => ("keys(map)", "The keys of a map, in sorted order."),
+        "on" => (
+            "on(event, handler)",
+            "Registers handler to run when the host dispatches event.",
+        ),
+        "on_interrupt" => (
+            "on_interrupt(handler)",
+            "Calls handler() on SIGINT or SIGTERM, then stops the program.",
+        ),
+        "off" => (
+            "off(event, handler)",
+            "Removes one registration of handler; returns whether there was one.",
+        ),
+        "spawn" => (
+            "spawn(f)",
+            "Makes a task that starts running f when first resumed.",
+        ),
+        "yield" => (
+            "yield(value)",
+            "Hands value to whoever resumed the running task and waits to be resumed again.",
+        ),
+        "resume" => (
+            "resume(task)",
+            "Runs the task to its next yield and returns the value, or null once it has returned.",
+        ),
+        "done" => ("done(task)", "Whether the task has returned."),
+        "par_map" => (
+            "par_map(arr, f)",
+            "Applies f to each element on a thread pool; f may not print or assign globals.",
+        ),
+        "channel" => (
+            "channel()",
+            "A new queue for passing values between par_map workers.",
+        ),
+        "send" => (
+            "send(ch, value)",
+            "Puts a copy of value on the channel, unless it would lead back to the channel or holds a task.",
+        ),
+        "recv" => (
+            "recv(ch)",
+            "Takes the oldest value off the channel, waiting for one if needed.",
+        ),
+        "try_recv" => (
+            "try_recv(ch)",
+            "Takes the oldest value off the channel, or null if it is empty.",
+        ),
+        "rng" => (
+            "rng(seed)",
+            "A handle to a random number generator seeded with seed.",
+        ),
+        "rand" => ("rand(rng)", "The generator's next float in [0, 1)."),
+        "close" => (
+            "close(handle)",
+            "Releases the handle; returns whether it was still open.",
+        ),
+        "source" => (
+            "source(f)",
+            "The definition of f, reconstructed from its syntax tree.",
+        ),
+        _ => return None,
+    })
+}
 
-                    if params.len() != arg_vals.len() {
-                        return Err(format!(
-                            "Function {} expects {} arguments, got {}",
-                            name,
-                            params.len(),
-                            arg_vals.len()
-                        ));
-                    }
+/// The text `help()` prints for `val`.
+fn describe(val: &Val) -> Result<String, String> {
+    match val {
+        Val::Function {
+            name, params, doc, ..
+        } => Ok(format!(
+            "fn {}({})\n    {}",
+            name,
+            params.join(", "),
+            doc.as_deref().unwrap_or("(no documentation)")
+        )),
+        Val::Builtin(name) => {
+            let (signature, doc) = builtin_doc(name).unwrap_or((name, "(no documentation)"));
+            Ok(format!("{}\n    {}", signature, doc))
+        }
+        Val::Decorated { func, .. } | Val::Bound { func, .. } | Val::Curried { func, .. } => {
+            describe(func)
+        }
+        Val::Composed(funcs) => {
+            let parts: Vec<String> = funcs.iter().map(describe).collect::<Result<_, _>>()?;
+            Ok(parts.join("\n"))
+        }
+        _ => Err(format!("help() requires a function, got {}", val.repr())),
+    }
+}
 
-                    let mut frame = Frame::new();
+fn decorate(name: &str, decorator: Decorator, mut args: Vec<Val>) -> Result<Val, String> {
+    match args.pop() {
+        Some(func) if func.is_callable() => Ok(Val::Decorated {
+            decorator,
+            func: Box::new(func),
+        }),
+        other => Err(format!(
+            "{}() requires a function, got {}",
+            name,
+            other.unwrap_or(Val::Unit).repr()
+        )),
+    }
+}
 
-                    for (param, arg) in params.iter().zip(arg_vals) {
-                        frame.local.insert(param.clone(), arg);
-                    }
+/// Advances a xorshift64* generator and returns its next output.
+fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state >> 12;
+    *state ^= *state << 25;
+    *state ^= *state >> 27;
+    state.wrapping_mul(0x2545f4914f6cdd1d)
+}
 
-                    self.stack.push(frame);
+/// The dialect from the optional trailing `delimiter` and `quote`
+/// arguments of the CSV builtins.
+fn csv_dialect(name: &str, args: &[Val]) -> Result<Dialect, String> {
+    let single_char = |what: &str, value: &Val| match value {
+        Val::Str(s) if s.chars().count() == 1 => Ok(s.chars().next().unwrap()),
+        _ => Err(format!(
+            "{}() requires the {} to be one character, got {}",
+            name, what, value
+        )),
+    };
+    let mut dialect = Dialect::default();
+    if let Some(delimiter) = args.first() {
+        dialect.delimiter = single_char("delimiter", delimiter)?;
+    }
+    if let Some(quote) = args.get(1) {
+        dialect.quote = single_char("quote", quote)?;
+    }
+    Ok(dialect)
+}
 
-                    let mut res = Val::Unit;
-                    for stmt in body {
-                        match self.exec_stmt(&stmt)? {
-                            Flow::Continue(v) => res = v,
-                            Flow::Return(v) => {
-                                self.stack.pop();
-                                return Ok(v);
-                            }
-                        }
-                    }
+/// The one numeric argument of `name`, as a float. It must be finite, since
+/// the turtle would otherwise write NaN or inf into its drawing.
+fn number_arg(name: &str, args: &[Val]) -> Result<f64, String> {
+    match args {
+        [Val::Int(n)] => Ok(*n as f64),
+        [Val::Float(n)] if n.is_finite() => Ok(*n),
+        [Val::Float(n)] => Err(format!("{}() requires a finite number, got {}", name, n)),
+        [other] => Err(format!(
+            "{}() requires a number, got {}",
+            name,
+            other.repr()
+        )),
+        _ => Err(format!("{}() takes 1 argument, got {}", name, args.len())),
+    }
+}
 
-                    self.stack.pop();
-                    Ok(res)
-                } else {
-                    Err(format!("'{}' is not a function", func))
+/// An image as `{"width": w, "height": h, "pixels": rows}`, where each
+/// row is an array of `[r, g, b]` pixels.
+#[cfg(feature = "image")]
+fn image_to_val(image: crate::image::Image) -> Val {
+    let rows = image.pixels.chunks(image.width.max(1)).map(|row| {
+        let row = row
+            .iter()
+            .map(|px| Val::Array(px.iter().map(|&c| Val::Int(c as i64)).collect()));
+        Val::Array(row.collect())
+    });
+    Val::Map(BTreeMap::from([
+        ("width".to_string(), Val::Int(image.width as i64)),
+        ("height".to_string(), Val::Int(image.height as i64)),
+        ("pixels".to_string(), Val::Array(rows.collect())),
+    ]))
+}
+
+/// The image described by a value shaped like `image_to_val`'s; the size
+/// is taken from the pixel rows, which must all be the same length.
+#[cfg(feature = "image")]
+fn val_to_image(val: &Val) -> Result<crate::image::Image, String> {
+    let bad = || "image_save() requires a map with rows of [r, g, b] pixels".to_string();
+    let rows = match val {
+        Val::Map(map) => match map.get("pixels") {
+            Some(Val::Array(rows)) => rows,
+            _ => return Err(bad()),
+        },
+        _ => return Err(bad()),
+    };
+    let mut pixels = Vec::new();
+    let mut width = None;
+    for row in rows {
+        let Val::Array(row) = row else {
+            return Err(bad());
+        };
+        if *width.get_or_insert(row.len()) != row.len() {
+            return Err("image_save() requires pixel rows of equal length".to_string());
+        }
+        for px in row {
+            let channel = |c: &Val| match c {
+                Val::Int(c) => {
+                    u8::try_from(*c).map_err(|_| format!("Color channel {} is not in 0..=255", c))
                 }
+                _ => Err(bad()),
+            };
+            match px {
+                Val::Array(px) if px.len() == 3 => {
+                    pixels.push([channel(&px[0])?, channel(&px[1])?, channel(&px[2])?])
+                }
+                _ => return Err(bad()),
             }
+        }
+    }
+    Ok(crate::image::Image {
+        width: width.unwrap_or(0),
+        height: rows.len(),
+        pixels,
+    })
+}
 
-            Expr::If { cond, then, else_ } => {
-                let cond = self.eval_expr(cond)?;
-                if let Val::Bool(b) = cond {
-                    let branch = if b { then } else { else_ };
+/// The first argument of `name` as a path, checking that `name` got
+/// `arity` arguments.
+fn path_arg<'a>(name: &str, args: &'a [Val], arity: usize) -> Result<&'a str, String> {
+    if args.len() != arity {
+        return Err(format!(
+            "{}() takes {} argument(s), got {}",
+            name,
+            arity,
+            args.len()
+        ));
+    }
+    match &args[0] {
+        Val::Str(path) => Ok(path),
+        other => Err(format!(
+            "{}() requires a path string, got {}",
+            name,
+            other.repr()
+        )),
+    }
+}
 
-                    let mut res = Val::Unit;
-                    for stmt in branch {
-                        match self.exec_stmt(stmt)? {
-                            Flow::Continue(v) => res = v,
-                            Flow::Return(v) => return Ok(v),
-                        }
-                    }
-                    Ok(res)
-                } else {
-                    Err(format!("Condition Must be a Boolean, got {:?}", cond))
-                }
-            }
+fn path_pair<'a>(name: &str, args: &'a [Val]) -> Result<(&'a str, &'a str), String> {
+    Ok((path_arg(name, args, 2)?, path_arg(name, &args[1..], 1)?))
+}
 
-            Expr::While { cond, body } => {
-                loop {
-                    let cond = self.eval_expr(cond)?;
-                    if let Val::Bool(b) = cond {
-                        if !b {
-                            break;
-                        }
+/// An OS error as a runtime error naming the builtin and the path.
+fn fs_error(name: &str, path: &str, e: io::Error) -> String {
+    format!("{}() failed for {}: {}", name, quote(path), e)
+}
 
-                        for stmt in body {
-                            match self.exec_stmt(stmt)? {
-                                Flow::Continue(_) => {}
-                                Flow::Return(v) => return Ok(v),
-                            }
-                        }
-                    } else {
-                        return Err(format!("While condition Must be a Boolean, got {:?}", cond));
-                    }
-                }
-                Ok(Val::Unit)
-            }
+/// The text of one CSV field; null is an empty field.
+fn csv_field(value: &Val) -> Result<String, String> {
+    match value {
+        Val::Str(s) => Ok(s.clone()),
+        Val::Null => Ok(String::new()),
+        Val::Int(_) | Val::BigInt(_) | Val::Float(_) | Val::Bool(_) => Ok(value.to_string()),
+        other => Err(format!(
+            "A CSV field must be a scalar, got {}",
+            other.repr()
+        )),
+    }
+}
 
-            Expr::For {
-                var,
-                start,
-                end,
-                body,
-            } => {
-                let st = self.eval_expr(start)?;
-                let en = self.eval_expr(end)?;
+/// The integer arguments of `name`, which takes exactly `N` of them.
+#[cfg(feature = "graphics")]
+fn int_args<const N: usize>(name: &str, args: &[Val]) -> Result<[i64; N], String> {
+    let ints: Vec<i64> = args
+        .iter()
+        .map(|arg| match arg {
+            Val::Int(n) => Ok(*n),
+            other => Err(format!(
+                "{}() requires integers, got {}",
+                name,
+                other.repr()
+            )),
+        })
+        .collect::<Result<_, _>>()?;
+    <[i64; N]>::try_from(ints)
+        .map_err(|ints| format!("{}() requires {} integers, got {}", name, N, ints.len()))
+}
 
-                let (sti, eni) = match (st, en) {
-                    (Val::Int(i), Val::Int(j)) => (i, j),
-                    (a, b) => {
-                        return Err(format!(
-                            "The range must evaluate to ineteger bounds, got {}..{}",
-                            a, b
-                        ));
-                    }
-                };
+/// Splits the trailing color string off the arguments of a drawing builtin.
+#[cfg(feature = "graphics")]
+fn color_arg(name: &str, mut args: Vec<Val>) -> Result<(String, Vec<Val>), String> {
+    match args.pop() {
+        Some(Val::Str(color)) => Ok((color, args)),
+        other => Err(format!(
+            "{}() requires a color as its last argument, got {}",
+            name,
+            other.unwrap_or(Val::Unit).repr()
+        )),
+    }
+}
 
-                let parent_idx = Some(self.stack.len() - 1);
-                let frame = Frame {
-                    local: HashMap::new(),
-                    parent: parent_idx,
-                };
-                self.stack.push(frame);
-                for i in sti..eni {
-                    if let Some(frame) = self.stack.last_mut() {
-                        frame.local.insert(var.clone(), Val::Int(i));
-                    }
-                    for stmt in body {
-                        match self.exec_stmt(stmt)? {
-                            Flow::Continue(_) => {}
-                            Flow::Return(v) => {
-                                self.stack.pop();
-                                return Ok(v);
-                            }
-                        }
-                    }
-                }
+/// `line` without its `\n` or `\r\n` ending.
+fn strip_newline(mut line: String) -> String {
+    if line.ends_with('\n') {
+        line.pop();
+        if line.ends_with('\r') {
+            line.pop();
+        }
+    }
+    line
+}
 
-                self.stack.pop();
-                return Ok(Val::Unit);
-            }
+/// A non-negative integer argument of `name`, as the length of a random
+/// string, which can be at most `MAX_RANDOM_LENGTH`.
+fn count(name: &str, n: &Val) -> Result<usize, String> {
+    match n {
+        Val::Int(n) if *n > MAX_RANDOM_LENGTH as i64 => Err(format!(
+            "{}() can make at most {} characters, got {}",
+            name, MAX_RANDOM_LENGTH, n
+        )),
+        Val::Int(n) if *n >= 0 => Ok(*n as usize),
+        _ => Err(format!(
+            "{}() requires a non-negative length, got {}",
+            name,
+            n.repr()
+        )),
+    }
+}
 
-            Expr::Block(stmts) => {
-                let mut res = Val::Unit;
-                for stmt in stmts {
-                    match self.exec_stmt(stmt)? {
-                        Flow::Continue(v) => res = v,
-                        Flow::Return(v) => return Ok(v),
-                    }
-                }
-                Ok(res)
-            }
+/// Applies a string-to-string conversion to the one argument of `name`.
+fn convert(
+    name: &str,
+    args: Vec<Val>,
+    f: impl Fn(&str) -> Result<String, String>,
+) -> Result<Val, String> {
+    match <[Val; 1]>::try_from(args) {
+        Ok([Val::Str(s)]) => f(&s).map(Val::Str),
+        Ok([other]) => Err(format!(
+            "{}() requires a string, got {}",
+            name,
+            other.repr()
+        )),
+        Err(args) => Err(format!("{}() takes 1 argument, got {}", name, args.len())),
+    }
+}
 
-            Expr::ArrayAccess { name, indices } => {
-                let val = self.lookup(name)?;
-                let mut cur = &val;
+/// `index` as a position in something `len` long, if it is one. Negative
+/// indices count from the end, so `-1` is the last element.
+fn normalize_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { index + len as i64 } else { index };
+    usize::try_from(index).ok().filter(|&i| i < len)
+}
 
-                for expr in indices {
-                    let idx_val = self.eval_expr(expr)?;
-                    let idx = match idx_val {
-                        Val::Int(i) => i as usize,
-                        _ => {
-                            return Err(format!(
-                                "Array index must be an integer, got {:?}",
-                                idx_val
-                            ));
-                        }
-                    };
+/// The positions `start..end` covers in something `len` long. Negative
+/// bounds count from the end, and bounds past either end stop at it.
+fn slice_range(start: Option<i64>, end: Option<i64>, len: usize) -> Range<usize> {
+    let clamp = |bound: i64| {
+        let bound = if bound < 0 { bound + len as i64 } else { bound };
+        bound.clamp(0, len as i64) as usize
+    };
+    let start = start.map_or(0, clamp);
+    start..end.map_or(len, clamp).max(start)
+}
 
-                    match cur {
-                        Val::Array(arr) => {
-                            if idx >= arr.len() {
-                                return Err(format!("Array index out of bounds: {}", idx));
-                            }
-                            cur = &arr[idx];
-                        }
-                        Val::Str(s) => {
-                            let chars: Vec<char> = s.chars().collect();
-                            if idx >= chars.len() {
-                                return Err(format!("String index out of bounds: {}", idx));
-                            }
-                            return Ok(Val::Str(chars[idx].to_string()));
-                        }
-                        _ => return Err(format!("Cannot index into {:?}", cur)),
-                    }
-                }
+fn out_of_bounds(kind: &str, index: i64, len: usize) -> String {
+    format!(
+        "{} index {} is out of bounds for length {}",
+        kind, index, len
+    )
+}
+
+/// `x` rounded to `digits` decimals, or to a power of ten when `digits`
+/// is negative, with halves going away from zero. `x` is left alone when
+/// it has no digits that far down.
+fn round_to(x: f64, digits: i64) -> f64 {
+    let scale = 10f64.powi(digits.clamp(-308, 308) as i32);
+    let scaled = x * scale;
+    if scaled.is_finite() && scaled.abs() < (1u64 << 53) as f64 {
+        scaled.round() / scale
+    } else {
+        x
+    }
+}
+
+/// `x` with `digits` decimals. Halves go away from zero, as with `round`,
+/// where formatting alone takes them to even.
+fn fixed(x: f64, digits: usize) -> String {
+    let tie = exact_decimals(x) == digits + 1;
+    format!("{:.*}", digits, away_from_tie(x, tie))
+}
+
+/// `x` in scientific notation with `digits` decimals, rounded like
+/// `fixed`.
+fn scientific(x: f64, digits: usize) -> String {
+    let exact = format!("{:.*}", exact_decimals(x), x.abs()).replace('.', "");
+    let significant = exact.trim_start_matches('0').trim_end_matches('0');
+    let tie = significant.len() == digits + 2 && significant.ends_with('5');
+    format!("{:.*e}", digits, away_from_tie(x, tie))
+}
+
+/// How many decimals it takes to write `x` out exactly: one for every
+/// power of two below one in its lowest set bit.
+fn exact_decimals(x: f64) -> usize {
+    if x == 0.0 || !x.is_finite() {
+        return 0;
+    }
+    let bits = x.abs().to_bits();
+    let (mantissa, exponent) = match (bits >> 52) as i32 {
+        0 => (bits, -1074),
+        biased => (bits & ((1 << 52) - 1) | 1 << 52, biased - 1075),
+    };
+    (-(exponent + mantissa.trailing_zeros() as i32)).max(0) as usize
+}
+
+/// `x`, or when it lies exactly halfway between the two results it could
+/// be formatted as, the next float away from zero, which is formatted as
+/// the one further out.
+fn away_from_tie(x: f64, tie: bool) -> f64 {
+    match tie {
+        true if x > 0.0 => x.next_up(),
+        true => x.next_down(),
+        false => x,
+    }
+}
+
+/// How many digits to show after the point, at most `MAX_DIGITS`.
+fn digit_count(name: &str, digits: &Val) -> Result<usize, String> {
+    match digits {
+        Val::Int(d) if *d > MAX_DIGITS as i64 => Err(format!(
+            "{}() can show at most {} digits, got {}",
+            name, MAX_DIGITS, d
+        )),
+        Val::Int(d) if *d >= 0 => Ok(*d as usize),
+        other => Err(format!(
+            "{}() requires a non-negative digit count, got {}",
+            name,
+            other.repr()
+        )),
+    }
+}
+
+fn string_pair(name: &str, args: Vec<Val>) -> Result<(String, String), String> {
+    match <[Val; 2]>::try_from(args) {
+        Ok([Val::Str(a), Val::Str(b)]) => Ok((a, b)),
+        Ok([a, b]) => Err(format!(
+            "{}() requires two strings, got {} and {}",
+            name,
+            a.repr(),
+            b.repr()
+        )),
+        Err(args) => Err(format!("{}() takes 2 arguments, got {}", name, args.len())),
+    }
+}
 
-                Ok(cur.clone())
+/// Chains `funcs` first to last, flattening nested compositions.
+fn composed(name: &str, funcs: Vec<Val>) -> Result<Val, String> {
+    if funcs.is_empty() {
+        return Err(format!("{}() requires at least one function", name));
+    }
+    let mut chain = Vec::new();
+    for func in funcs {
+        match func {
+            Val::Composed(inner) => chain.extend(inner),
+            func if func.is_callable() => chain.push(func),
+            other => {
+                return Err(format!(
+                    "{}() requires functions, got {}",
+                    name,
+                    other.repr()
+                ));
             }
         }
     }
+    Ok(Val::Composed(chain))
+}
 
-    fn eval_bin_op(&self, op: BinaryOp, left: Val, right: Val) -> Result<Val, String> {
-        match (op, &left, &right) {
-            (BinaryOp::Add, Val::Int(a), Val::Int(b)) => Ok(Val::Int(a + b)),
-            (BinaryOp::Sub, Val::Int(a), Val::Int(b)) => Ok(Val::Int(a - b)),
-            (BinaryOp::Mul, Val::Int(a), Val::Int(b)) => Ok(Val::Int(a * b)),
-            (BinaryOp::Div, Val::Int(a), Val::Int(b)) => {
-                if *b == 0 {
-                    Err("Division by zero".to_string())
-                } else {
-                    Ok(Val::Int(a / b))
-                }
+/// A non-empty array of equally long, non-empty arrays of scalars.
+fn is_grid(rows: &[Val]) -> bool {
+    let Some(Val::Array(first)) = rows.first() else {
+        return false;
+    };
+    !first.is_empty()
+        && rows.iter().all(|row| match row {
+            Val::Array(row) => {
+                row.len() == first.len() && row.iter().all(|v| !matches!(v, Val::Array(_)))
             }
+            _ => false,
+        })
+}
 
-            (BinaryOp::Mod, Val::Int(a), Val::Int(b)) => {
-                if *b == 0 {
-                    Err("Modulo by zero".to_string())
-                } else {
-                    Ok(Val::Int(a % b))
-                }
-            }
+/// Prints a matrix one row per line with right-aligned columns.
+fn write_grid(f: &mut std::fmt::Formatter<'_>, rows: &[Val]) -> std::fmt::Result {
+    let cells: Vec<Vec<String>> = rows
+        .iter()
+        .map(|row| match row {
+            Val::Array(row) => row.iter().map(Val::nested).collect(),
+            _ => unreachable!(),
+        })
+        .collect();
+    let width = cells.iter().flatten().map(|c| c.len()).max().unwrap_or(0);
 
-            (BinaryOp::Add, Val::Float(a), Val::Float(b)) => Ok(Val::Float(a + b)),
-            (BinaryOp::Sub, Val::Float(a), Val::Float(b)) => Ok(Val::Float(a - b)),
-            (BinaryOp::Mul, Val::Float(a), Val::Float(b)) => Ok(Val::Float(a * b)),
-            (BinaryOp::Div, Val::Float(a), Val::Float(b)) => {
-                if *b == 0f64 {
-                    Err("Division by zero".to_string())
-                } else {
-                    Ok(Val::Float(a / b))
-                }
-            }
+    for (i, row) in cells.iter().enumerate() {
+        let open = if i == 0 { "[" } else { " " };
+        let close = if i == cells.len() - 1 { "]" } else { "\n" };
+        let row: Vec<String> = row.iter().map(|c| format!("{:>width$}", c)).collect();
+        write!(f, "{}[{}]{}", open, row.join(", "), close)?;
+    }
+    Ok(())
+}
 
-            (BinaryOp::Mod, Val::Float(a), Val::Float(b)) => {
-                if *b == 0f64 {
-                    Err("Modulo by zero".to_string())
-                } else {
-                    Ok(Val::Float(a % b))
-                }
-            }
+/// Checks that `val` is a rectangular array of arrays and returns its rows.
+fn matrix_rows<'a>(name: &str, val: &'a Val) -> Result<Vec<&'a [Val]>, String> {
+    let err = || {
+        format!(
+            "{}() requires a matrix (array of equal-length arrays), got {}",
+            name,
+            val.repr()
+        )
+    };
+    let Val::Array(rows) = val else {
+        return Err(err());
+    };
+    let rows = rows
+        .iter()
+        .map(|row| match row {
+            Val::Array(row) => Ok(row.as_slice()),
+            _ => Err(err()),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if rows.windows(2).any(|w| w[0].len() != w[1].len()) {
+        return Err(err());
+    }
+    Ok(rows)
+}
 
-            (BinaryOp::Add, Val::Str(a), Val::Str(b)) => Ok(Val::Str(a.clone() + b)),
-            (BinaryOp::Mul, Val::Str(a), Val::Int(i)) => Ok(Val::Str(a.repeat(*i as usize))),
+/// The sum of pairwise products of `a` and `b`, using the language's own
+/// arithmetic so the result matches what `+` and `*` would give.
+fn dot_product(a: &[Val], b: &[Val]) -> Result<Val, String> {
+    let mut products = a
+        .iter()
+        .zip(b)
+        .map(|(x, y)| Interpreter::eval_bin_op(BinaryOp::Mul, x.clone(), y.clone()));
+    let Some(first) = products.next() else {
+        return Ok(Val::Int(0));
+    };
+    products.try_fold(first?, |acc, prod| {
+        Interpreter::eval_bin_op(BinaryOp::Add, acc, prod?)
+    })
+}
 
-            (BinaryOp::Add, Val::Array(a), Val::Array(i)) => {
-                let mut res = a.clone();
-                res.extend(i.clone());
-                Ok(Val::Array(res))
-            }
+/// `false`, zero, empty strings and arrays, `null` and `()` are falsy;
+/// everything else is truthy.
+fn truthy(val: &Val) -> bool {
+    match val {
+        Val::Bool(b) => *b,
+        Val::Int(n) => *n != 0,
+        Val::BigInt(n) => !n.is_zero(),
+        Val::Float(f) => *f != 0.0,
+        Val::Str(s) => !s.is_empty(),
+        Val::Frozen { value, .. } => truthy(value),
+        Val::Map(map) => !map.is_empty(),
+        Val::Array(arr) => !arr.is_empty(),
+        Val::Function { .. }
+        | Val::Builtin(_)
+        | Val::Decorated { .. }
+        | Val::Bound { .. }
+        | Val::Composed(_)
+        | Val::Curried { .. }
+        | Val::Variant { .. }
+        | Val::Task(_)
+        | Val::Channel(_)
+        | Val::Handle { .. } => true,
+        Val::Null | Val::Unit => false,
+    }
+}
 
-            (BinaryOp::Eq, Val::Int(a), Val::Int(b)) => Ok(Val::Bool(a == b)),
-            (BinaryOp::Ne, Val::Int(a), Val::Int(b)) => Ok(Val::Bool(a != b)),
-            (BinaryOp::Gt, Val::Int(a), Val::Int(b)) => Ok(Val::Bool(a > b)),
-            (BinaryOp::Ge, Val::Int(a), Val::Int(b)) => Ok(Val::Bool(a >= b)),
-            (BinaryOp::Lt, Val::Int(a), Val::Int(b)) => Ok(Val::Bool(a < b)),
-            (BinaryOp::Le, Val::Int(a), Val::Int(b)) => Ok(Val::Bool(a <= b)),
+/// The error a program stops with once its cancellation token is set.
+pub const CANCELLED: &str = "Evaluation cancelled";
 
-            (BinaryOp::Eq, Val::Float(a), Val::Float(b)) => Ok(Val::Bool(a == b)),
-            (BinaryOp::Ne, Val::Float(a), Val::Float(b)) => Ok(Val::Bool(a != b)),
-            (BinaryOp::Gt, Val::Float(a), Val::Float(b)) => Ok(Val::Bool(a > b)),
-            (BinaryOp::Ge, Val::Float(a), Val::Float(b)) => Ok(Val::Bool(a >= b)),
-            (BinaryOp::Lt, Val::Float(a), Val::Float(b)) => Ok(Val::Bool(a < b)),
-            (BinaryOp::Le, Val::Float(a), Val::Float(b)) => Ok(Val::Bool(a <= b)),
+/// The error that carries `Interpreter::escaping` out of an expression; it
+/// only reaches the host if something drops the flow on the way.
+const ESCAPING: &str = "'break' or 'return' left an expression it cannot leave";
+/// The error from `run_with_timeout` when the program ran out of time.
+pub const TIMEOUT: &str = "Evaluation timed out";
+/// The error a program stops with after SIGINT or SIGTERM, once its
+/// `on_interrupt` handler has run.
+pub const INTERRUPTED: &str = "Interrupted";
+/// How long an `on_interrupt` handler may run before it is cancelled too.
+const INTERRUPT_GRACE: Duration = Duration::from_secs(5);
+/// How often `sleep` and `recv` check, while they wait, whether the
+/// program has been cancelled.
+const WAIT_SLICE: Duration = Duration::from_millis(10);
+/// The longest string `random_hex` and `random_string` make, so a bad
+/// length is an error rather than the process running out of memory.
+const MAX_RANDOM_LENGTH: usize = 1 << 20;
+/// The most digits `to_fixed` and the other number formatters show after
+/// the point, as in JavaScript's `toFixed`.
+const MAX_DIGITS: usize = 100;
 
-            (BinaryOp::Eq, Val::Bool(a), Val::Bool(b)) => Ok(Val::Bool(a == b)),
-            (BinaryOp::Ne, Val::Bool(a), Val::Bool(b)) => Ok(Val::Bool(a != b)),
-            (BinaryOp::And, Val::Bool(a), Val::Bool(b)) => Ok(Val::Bool(*a && *b)),
-            (BinaryOp::Or, Val::Bool(a), Val::Bool(b)) => Ok(Val::Bool(*a || *b)),
+type Builtin = fn(Vec<Val>) -> Result<Val, String>;
+/// Builtins with effects outside their result, refused inside `par_map`.
+const IMPURE_BUILTINS: &[&str] = &[
+    "print",
+    "println",
+    "pprint",
+    "log_debug",
+    "log_info",
+    "log_warn",
+    "log_error",
+    "help",
+    "clear",
+    "sleep",
+    "timed",
+    "on",
+    "off",
+    "on_interrupt",
+    "spawn",
+    "yield",
+    "resume",
+    "par_map",
+    "rng",
+    "rand",
+    "close",
+    "uuid",
+    "random_hex",
+    "random_string",
+    "mkdir",
+    "remove_file",
+    "remove_dir",
+    "copy_file",
+    "rename",
+    "load_plugin",
+    "read_line",
+    "read_all_stdin",
+    "lines_stdin",
+    "key_pressed",
+    "read_key",
+    "window",
+    "draw_rect",
+    "draw_circle",
+    "draw_text",
+    "present",
+    "poll_events",
+    "forward",
+    "turn",
+    "pen_up",
+    "pen_down",
+    "save_svg",
+    "image_save",
+];
+/// A builtin that needs the interpreter, e.g. to call a function argument.
+type Intrinsic = fn(&mut Interpreter, Vec<Val>) -> Result<Val, String>;
 
-            _ => Err(format!(
-                "Cannot apply {:?} to {:?} and {:?}",
-                op, left, right
-            )),
+/// A queue of values. Values are cloned on `send`, so the receiver gets a
+/// deep copy that the sender can no longer change.
+pub struct Channel {
+    queue: Mutex<VecDeque<Val>>,
+    ready: Condvar,
+}
+
+impl Channel {
+    fn new() -> Channel {
+        Channel {
+            queue: Mutex::new(VecDeque::new()),
+            ready: Condvar::new(),
         }
     }
 
-    fn lookup(&mut self, name: &str) -> Result<Val, String> {
-        let mut current_idx = self.stack.len() - 1;
+    fn send(&self, val: Val) {
+        self.queue.lock().unwrap().push_back(val);
+        self.ready.notify_one();
+    }
 
+    /// The oldest value, waiting for one to be sent if there is none, or
+    /// until `cancel` is set.
+    fn recv(&self, cancel: &AtomicBool) -> Result<Val, String> {
+        let mut queue = self.queue.lock().unwrap();
         loop {
-            let frame = &self.stack[current_idx];
-
-            if let Some(val) = frame.local.get(name) {
-                return Ok(val.clone());
+            if let Some(val) = queue.pop_front() {
+                return Ok(val);
             }
-
-            match frame.parent {
-                Some(parent_idx) => current_idx = parent_idx,
-                None => break,
+            if cancel.load(Ordering::Relaxed) {
+                return Err(CANCELLED.to_string());
             }
+            queue = self.ready.wait_timeout(queue, WAIT_SLICE).unwrap().0;
         }
+    }
 
-        if let Some(val) = self.global.get(name) {
-            return Ok(val.clone());
+    fn try_recv(&self) -> Option<Val> {
+        self.queue.lock().unwrap().pop_front()
+    }
+
+    /// Whether sending `val` would leave the channel holding itself,
+    /// through a channel in `val` or one queued on those, and so on: a
+    /// cycle of `Arc`s that would never be freed.
+    fn would_hold_itself(&self, val: &Val) -> bool {
+        let mut pending = Vec::new();
+        channels_in(val, &mut pending);
+        let mut seen = HashSet::new();
+        while let Some(ch) = pending.pop() {
+            if std::ptr::eq(&*ch, self) {
+                return true;
+            }
+            if seen.insert(Arc::as_ptr(&ch)) {
+                for queued in ch.queue.lock().unwrap().iter() {
+                    channels_in(queued, &mut pending);
+                }
+            }
         }
+        false
+    }
+}
 
-        Err(format!("Undefined Variable: {}", name))
+/// Calls `f` with every channel and task `val` holds, at any depth,
+/// without looking inside them.
+fn shared_in(val: &Val, f: &mut dyn FnMut(&Val)) {
+    match val {
+        Val::Channel(_) | Val::Task(_) => f(val),
+        Val::Array(items) | Val::Composed(items) | Val::Variant { fields: items, .. } => {
+            items.iter().for_each(|item| shared_in(item, f));
+        }
+        Val::Map(map) => map.values().for_each(|item| shared_in(item, f)),
+        Val::Frozen { value: func, .. } | Val::Decorated { func, .. } => shared_in(func, f),
+        Val::Bound { func, args } | Val::Curried { func, args } => {
+            shared_in(func, f);
+            args.iter().for_each(|arg| shared_in(arg, f));
+        }
+        _ => {}
     }
+}
 
-    fn lookup_mut(&mut self, name: &str) -> Option<&mut Val> {
-        let mut search_idx = self.stack.len() - 1;
+/// Adds the channels `val` holds, at any depth, to `out`, including those
+/// of the functions of tasks not yet started.
+fn channels_in(val: &Val, out: &mut Vec<Arc<Channel>>) {
+    shared_in(val, &mut |shared| match shared {
+        Val::Channel(ch) => out.push(ch.clone()),
+        Val::Task(task) => {
+            if let Ok(state) = task.state.try_lock()
+                && let TaskState::Ready(func) = &*state
+            {
+                channels_in(func, out);
+            }
+        }
+        _ => {}
+    });
+}
 
-        let mut found_idx = None;
-        loop {
-            let frame = &self.stack[search_idx];
+/// Whether `val` holds a task, at any depth.
+fn holds_task(val: &Val) -> bool {
+    let mut found = false;
+    shared_in(val, &mut |shared| found |= matches!(shared, Val::Task(_)));
+    found
+}
 
-            if frame.local.contains_key(name) {
-                found_idx = Some(search_idx);
-                break;
-            }
+/// Channels are equal only to themselves.
+impl PartialEq for Channel {
+    fn eq(&self, other: &Channel) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
 
-            match frame.parent {
-                Some(parent_idx) => search_idx = parent_idx,
-                None => break,
-            }
+impl std::fmt::Debug for Channel {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Channel")
+    }
+}
+
+/// Something a `Val::Handle` refers to. Whatever a variant owns is
+/// released when it is dropped, on `close` or with the interpreter.
+enum Resource {
+    /// xorshift64* state for `rand`; never zero.
+    Rng(u64),
+}
+
+impl Resource {
+    fn kind(&self) -> &'static str {
+        match self {
+            Resource::Rng(_) => "Rng",
         }
+    }
+}
 
-        if let Some(idx) = found_idx {
-            return self.stack[idx].local.get_mut(name);
+/// The interpreter's open resources, by handle id. Ids are never reused,
+/// so a stale handle can't reach a newer resource.
+#[derive(Default)]
+struct Resources {
+    next: usize,
+    open: HashMap<usize, Resource>,
+}
+
+impl Resources {
+    fn insert(&mut self, resource: Resource) -> Val {
+        let id = self.next;
+        self.next += 1;
+        let kind = resource.kind();
+        self.open.insert(id, resource);
+        Val::Handle { id, kind }
+    }
+
+    fn get_mut(&mut self, name: &str, handle: &Val) -> Result<&mut Resource, String> {
+        match handle {
+            Val::Handle { id, kind } => self
+                .open
+                .get_mut(id)
+                .ok_or_else(|| format!("{} #{} is closed", kind, id)),
+            _ => Err(format!(
+                "{}() requires a handle, got {}",
+                name,
+                handle.repr()
+            )),
         }
+    }
 
-        self.global.get_mut(name)
+    /// Drops the resource; returns whether it was still open.
+    fn close(&mut self, handle: &Val) -> Result<bool, String> {
+        match handle {
+            Val::Handle { id, .. } => Ok(self.open.remove(id).is_some()),
+            _ => Err(format!("close() requires a handle, got {}", handle.repr())),
+        }
     }
+}
 
-    fn builtins() -> HashMap<&'static str, fn(Vec<Val>) -> Result<Val, String>> {
-        let mut map: HashMap<&'static str, fn(Vec<Val>) -> Result<Val, String>> = HashMap::new();
+/// A function started with `spawn`. It runs on a thread of its own, in a
+/// copy of the interpreter that shares its definitions, options and
+/// output, taking turns with whoever resumes it: `resume` lets it run to
+/// its next `yield` and waits for the value. The thread ends when the
+/// function returns, or with an error from `yield` once the last copy of
+/// the task is dropped.
+///
+/// Nothing the thread holds can lead back to the task, which would keep
+/// both alive forever: its copy of the interpreter only shares function
+/// definitions, and tasks can't be sent over the channels it may be given.
+pub struct Task {
+    state: Mutex<TaskState>,
+}
 
-        map.insert("print", |args| {
-            for v in &args {
-                print!("{}", v);
-            }
-            io::stdout().flush().unwrap();
-            Ok(Val::Unit)
-        });
+enum TaskState {
+    /// Spawned but never resumed.
+    Ready(Val),
+    /// Waiting in `yield`. Sending on `wake` lets it run on, and `values`
+    /// gives what it yields next, `None` once it returns, or its error.
+    Suspended {
+        wake: SyncSender<()>,
+        values: Receiver<Result<Option<Val>, String>>,
+    },
+    Done,
+}
 
-        map.insert("println", |args| {
-            for (i, v) in args.iter().enumerate() {
-                if i > 0 {
-                    print!(" ");
-                }
-                print!("{}", v);
-            }
-            println!();
-            Ok(Val::Unit)
-        });
-        map.insert("sin", |args: Vec<Val>| -> Result<Val, String> {
-            if args.len() != 1 {
-                return Err(format!("sin() takes 1 argument, got {}", args.len()));
+/// The running side of a task: where its `yield` hands values over and
+/// waits to be resumed.
+struct Yielder {
+    values: SyncSender<Result<Option<Val>, String>>,
+    wake: Mutex<Receiver<()>>,
+}
+
+/// The error `yield` stops a task with once nothing can resume it.
+const ABANDONED: &str = "Task abandoned: nothing can resume it";
+
+/// Tasks are equal only to themselves.
+impl PartialEq for Task {
+    fn eq(&self, other: &Task) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl std::fmt::Debug for Task {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Task")
+    }
+}
+
+struct Frame {
+    local: HashMap<String, Val>,
+    parent: Option<usize>,
+}
+
+/// What reloading a program gives: the functions whose definitions changed.
+type Reload = Result<Vec<String>, String>;
+/// A program waiting to be reloaded and where its outcome goes.
+type QueuedReload = (Program, Sender<Reload>);
+
+/// A handle for swapping new function definitions into an interpreter
+/// while it runs; see `Interpreter::reloader`.
+#[derive(Clone, Default)]
+pub struct Reloader {
+    pending: Arc<AtomicBool>,
+    queue: Arc<Mutex<Vec<QueuedReload>>>,
+}
+
+impl Reloader {
+    /// Queues `program` for `Interpreter::reload` at the start of the
+    /// running program's next block. The receiver gets the outcome, or
+    /// nothing if the program finishes first.
+    pub fn reload(&self, program: Program) -> Receiver<Reload> {
+        let (done, outcome) = mpsc::channel();
+        self.queue.lock().unwrap().push((program, done));
+        self.pending.store(true, Ordering::Relaxed);
+        outcome
+    }
+}
+
+/// Builtins a host can switch off together with `InterpreterBuilder::disable`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BuiltinGroup {
+    /// Printing and reading stdin or the keyboard.
+    Io,
+    /// The filesystem, images on disk, `sleep` and `load_plugin`.
+    Os,
+    /// Network access. ew has no network builtins yet, so this is empty.
+    Net,
+    /// The canvas and the turtle.
+    Graphics,
+}
+
+impl BuiltinGroup {
+    pub fn builtins(self) -> &'static [&'static str] {
+        match self {
+            BuiltinGroup::Io => &[
+                "print",
+                "println",
+                "pprint",
+                "help",
+                "clear",
+                "read_line",
+                "read_all_stdin",
+                "lines_stdin",
+                "key_pressed",
+                "read_key",
+            ],
+            BuiltinGroup::Os => &[
+                "sleep",
+                "mkdir",
+                "remove_file",
+                "remove_dir",
+                "copy_file",
+                "rename",
+                "file_size",
+                "is_dir",
+                "is_file",
+                "modified_time",
+                "image_load",
+                "image_save",
+                "save_svg",
+                "load_plugin",
+            ],
+            BuiltinGroup::Net => &[],
+            BuiltinGroup::Graphics => &[
+                "window",
+                "draw_rect",
+                "draw_circle",
+                "draw_text",
+                "present",
+                "poll_events",
+                "forward",
+                "turn",
+                "pen_up",
+                "pen_down",
+            ],
+        }
+    }
+}
+
+/// Configures an interpreter before it runs anything, for hosts embedding
+/// ew. `Interpreter::new()` is the same as `Interpreter::builder().build()`.
+#[derive(Default)]
+pub struct InterpreterBuilder {
+    disabled: Vec<BuiltinGroup>,
+    redeclaration: Redeclaration,
+    truthiness: Truthiness,
+    division: Division,
+    array_growth: ArrayGrowth,
+    type_checking: TypeChecking,
+    config: Option<Val>,
+    constants: Vec<(String, Val)>,
+    deprecated: Vec<(String, String)>,
+    max_call_depth: Option<usize>,
+    input: Option<Box<dyn BufRead + Send>>,
+    output: Option<Box<dyn Write + Send>>,
+    log_output: Option<Box<dyn Write + Send>>,
+    log_level: LogLevel,
+    log_format: LogFormat,
+    globals: Vec<(String, Val)>,
+    source_name: Option<String>,
+}
+
+impl InterpreterBuilder {
+    /// Makes every builtin in `group` fail when called.
+    pub fn disable(mut self, group: BuiltinGroup) -> Self {
+        self.disabled.push(group);
+        self
+    }
+
+    pub fn redeclaration(mut self, policy: Redeclaration) -> Self {
+        self.redeclaration = policy;
+        self
+    }
+
+    pub fn truthiness(mut self, truthiness: Truthiness) -> Self {
+        self.truthiness = truthiness;
+        self
+    }
+
+    pub fn division(mut self, division: Division) -> Self {
+        self.division = division;
+        self
+    }
+
+    pub fn array_growth(mut self, growth: ArrayGrowth) -> Self {
+        self.array_growth = growth;
+        self
+    }
+
+    pub fn type_checking(mut self, checking: TypeChecking) -> Self {
+        self.type_checking = checking;
+        self
+    }
+
+    /// See `Interpreter::set_config`.
+    pub fn config(mut self, config: Val) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    /// See `Interpreter::define_constant`.
+    pub fn constant(mut self, name: &str, value: Val) -> Self {
+        self.constants.push((name.to_string(), value));
+        self
+    }
+
+    /// See `Interpreter::deprecate`.
+    pub fn deprecate(mut self, builtin: &str, advice: &str) -> Self {
+        self.deprecated
+            .push((builtin.to_string(), advice.to_string()));
+        self
+    }
+
+    /// Stops programs whose function calls nest deeper than `depth`, before
+    /// they can overflow the host's stack.
+    pub fn max_call_depth(mut self, depth: usize) -> Self {
+        self.max_call_depth = Some(depth);
+        self
+    }
+
+    /// See `Interpreter::set_input`.
+    pub fn input(mut self, input: impl BufRead + Send + 'static) -> Self {
+        self.input = Some(Box::new(input));
+        self
+    }
+
+    /// Where `print`, `println`, `pprint` and `help` write instead of stdout.
+    pub fn output(mut self, output: impl Write + Send + 'static) -> Self {
+        self.output = Some(Box::new(output));
+        self
+    }
+
+    /// See `Interpreter::set_log_output`.
+    pub fn log_output(mut self, output: impl Write + Send + 'static) -> Self {
+        self.log_output = Some(Box::new(output));
+        self
+    }
+
+    /// See `Interpreter::set_log_level`.
+    pub fn log_level(mut self, level: LogLevel) -> Self {
+        self.log_level = level;
+        self
+    }
+
+    pub fn log_format(mut self, format: LogFormat) -> Self {
+        self.log_format = format;
+        self
+    }
+
+    /// Declares a top-level variable before the program runs.
+    pub fn global(mut self, name: &str, value: Val) -> Self {
+        self.globals.push((name.to_string(), value));
+        self
+    }
+
+    /// See `Interpreter::set_source_name`.
+    pub fn source_name(mut self, name: &str) -> Self {
+        self.source_name = Some(name.to_string());
+        self
+    }
+
+    pub fn build(self) -> Interpreter {
+        let mut interpreter = Interpreter::new();
+        interpreter.redeclaration = self.redeclaration;
+        interpreter.truthiness = self.truthiness;
+        interpreter.division = self.division;
+        interpreter.array_growth = self.array_growth;
+        interpreter.type_checking = self.type_checking;
+        if let Some(config) = self.config {
+            interpreter.set_config(config);
+        }
+        for (name, value) in self.constants {
+            interpreter.define_constant(&name, value);
+        }
+        for (builtin, advice) in self.deprecated {
+            interpreter.deprecate(&builtin, &advice);
+        }
+        interpreter.max_call_depth = self.max_call_depth;
+        if let Some(input) = self.input {
+            interpreter.input = Arc::new(Mutex::new(input));
+        }
+        if let Some(output) = self.output {
+            interpreter.output = Arc::new(Mutex::new(output));
+        }
+        if let Some(output) = self.log_output {
+            interpreter.log_output = Arc::new(Mutex::new(output));
+        }
+        interpreter.log_level = self.log_level;
+        interpreter.log_format = self.log_format;
+        interpreter.disabled = self
+            .disabled
+            .iter()
+            .flat_map(|g| g.builtins())
+            .copied()
+            .collect();
+        interpreter.stack[0].local.extend(self.globals);
+        interpreter.source_name = self.source_name;
+        interpreter
+    }
+}
+
+/// What `let` does when the name is already declared in the same scope.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Redeclaration {
+    /// Redeclaring is a runtime error.
+    #[default]
+    Strict,
+    /// The new binding replaces the old one, like a fresh `let` in most
+    /// scripting languages.
+    Lenient,
+}
+
+/// How `if` and `while` treat a condition that isn't a Bool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Truthiness {
+    /// Non-Bool conditions are a runtime error.
+    #[default]
+    Strict,
+    /// Non-Bool conditions are converted like `bool()` does.
+    Loose,
+}
+
+/// What `/` does when both operands are integers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Division {
+    /// `7 / 2` is `3`, truncating toward zero.
+    #[default]
+    Truncate,
+    /// `7 / 2` is `3.5`; use `divmod` for integer division.
+    Float,
+}
+
+/// What assigning just past the end of an array, to `arr[len(arr)]`, does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ArrayGrowth {
+    /// It is out of bounds, like any other index past the end.
+    #[default]
+    Strict,
+    /// It appends the value. Indices further out are still errors.
+    Append,
+}
+
+/// Whether `Truthiness::Loose` may convert conditions, and how plainly
+/// `==` between different types is reported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TypeChecking {
+    /// Conditions follow the truthiness setting, and `1 == "1"` or
+    /// `1 == 1.0` fails like any other unsupported operation.
+    #[default]
+    Lenient,
+    /// A condition that isn't a Bool is a runtime error whatever the
+    /// truthiness, and comparing values of different types fails with an
+    /// error that says so. `null` may still be compared with anything.
+    Strict,
+}
+
+/// How severe a `log_*` record is. Records below the interpreter's level,
+/// `Info` unless the host sets another, are dropped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub enum LogLevel {
+    Debug,
+    #[default]
+    Info,
+    Warn,
+    Error,
+}
+
+impl LogLevel {
+    pub fn name(self) -> &'static str {
+        match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+        }
+    }
+}
+
+/// How `log_*` records are written, one per line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LogFormat {
+    /// `INFO saved path=out.txt bytes=120`
+    #[default]
+    Text,
+    /// `{"level":"info","message":"saved","fields":{"bytes":120,"path":"out.txt"}}`
+    Json,
+}
+
+pub struct Interpreter {
+    global: HashMap<String, Val>,
+    enums: HashMap<String, Vec<Variant>>,
+    /// Results of memoized calls, keyed by cache id and the arguments.
+    memo: HashMap<(usize, String), Val>,
+    stack: Vec<Frame>,
+    redeclaration: Redeclaration,
+    truthiness: Truthiness,
+    division: Division,
+    array_growth: ArrayGrowth,
+    type_checking: TypeChecking,
+    /// What the programs' `#pragma version` declared, `DEFAULT_VERSION`
+    /// if none did, and every feature turned on by it or `#feature`.
+    version: String,
+    features: BTreeSet<Feature>,
+    /// Host-provided settings, visible to scripts as `CONFIG`.
+    config: Option<Val>,
+    /// Read-only globals from `define_constant`.
+    constants: HashMap<String, Val>,
+    /// Functions registered with `on(event, handler)`, in registration order.
+    handlers: HashMap<String, Vec<Val>>,
+    /// Set on the copy a task runs in; see `Task`.
+    yielder: Option<Yielder>,
+    /// Set on the per-thread copies `par_map` runs functions in: globals
+    /// can't be assigned and builtins with side effects can't be called.
+    sandboxed: bool,
+    /// Checked at the start of every block; see `cancellation_token`.
+    cancel: Arc<AtomicBool>,
+    /// The function from `on_interrupt`, and the flag its signals set
+    /// along with `cancel`.
+    interrupt_handler: Option<Val>,
+    interrupted: Arc<AtomicBool>,
+    signals: Signals,
+    reloader: Reloader,
+    resources: Resources,
+    /// The file being run, for locating definitions in messages.
+    source_name: Option<String>,
+    /// Set by `enable_alloc_profile`.
+    alloc_profile: Option<AllocProfile>,
+    /// Where the innermost expression with a span being evaluated is, for
+    /// the allocation profile; only kept up to date while it's on.
+    site: Span,
+    /// Input given to `eval_source_incremental` that still has brackets open.
+    pending: String,
+    /// Where `read_line` and the other stdin builtins read from; stdin
+    /// unless the host calls `set_input`.
+    input: Arc<Mutex<Box<dyn BufRead + Send>>>,
+    /// Where `print` and the other printing builtins write; stdout unless
+    /// the host gives another writer to the builder.
+    output: Arc<Mutex<Box<dyn Write + Send>>>,
+    /// Where the `log_*` builtins write; stderr unless the host gives
+    /// another writer.
+    log_output: Arc<Mutex<Box<dyn Write + Send>>>,
+    log_level: LogLevel,
+    log_format: LogFormat,
+    /// Builtins the host switched off; see `InterpreterBuilder::disable`.
+    disabled: Vec<&'static str>,
+    /// Builtins the host marked deprecated, with advice on what to use
+    /// instead; see `Interpreter::deprecate`.
+    deprecated: HashMap<String, String>,
+    /// Builtins from `register` and `load_plugin`, by name.
+    natives: HashMap<&'static str, NativeFn>,
+    diagnostics: Diagnostics,
+    /// How deeply function calls may nest before the program is stopped.
+    max_call_depth: Option<usize>,
+    call_depth: usize,
+    /// A `break` or `return` inside an expression, such as the value of a
+    /// `let`, on its way out to the statement it leaves; see `exec_stmts`.
+    escaping: Option<Flow>,
+    turtle: Turtle,
+    /// What `window` opened and the `draw_*` builtins draw on.
+    #[cfg(feature = "graphics")]
+    canvas: Option<crate::graphics::Canvas>,
+    /// xorshift64* state behind `uuid` and the `random_*` builtins when no
+    /// `rng()` handle is passed; seeded from the clock, never zero.
+    rng: u64,
+}
+
+/// Signal registrations, removed when dropped.
+#[derive(Default)]
+struct Signals(Vec<signal_hook::SigId>);
+
+impl Drop for Signals {
+    fn drop(&mut self) {
+        for id in self.0.drain(..) {
+            signal_hook::low_level::unregister(id);
+        }
+    }
+}
+
+enum Flow {
+    Continue(Val),
+    Return(Val),
+    /// Leaves the innermost loop, or the one with the given label.
+    Break(Option<String>, Val),
+}
+
+impl Interpreter {
+    pub fn new() -> Interpreter {
+        Interpreter {
+            global: HashMap::new(),
+            enums: HashMap::new(),
+            memo: HashMap::new(),
+            stack: vec![Frame::new()],
+            redeclaration: Redeclaration::default(),
+            truthiness: Truthiness::default(),
+            division: Division::default(),
+            array_growth: ArrayGrowth::default(),
+            type_checking: TypeChecking::default(),
+            version: version::DEFAULT_VERSION.to_string(),
+            features: BTreeSet::new(),
+            config: None,
+            constants: HashMap::new(),
+            handlers: HashMap::new(),
+            yielder: None,
+            sandboxed: false,
+            cancel: Arc::new(AtomicBool::new(false)),
+            interrupt_handler: None,
+            interrupted: Arc::new(AtomicBool::new(false)),
+            signals: Signals::default(),
+            reloader: Reloader::default(),
+            resources: Resources::default(),
+            source_name: None,
+            alloc_profile: None,
+            site: Span::default(),
+            pending: String::new(),
+            input: Arc::new(Mutex::new(Box::new(io::BufReader::new(io::stdin())))),
+            output: Arc::new(Mutex::new(Box::new(io::stdout()))),
+            log_output: Arc::new(Mutex::new(Box::new(io::stderr()))),
+            log_level: LogLevel::default(),
+            log_format: LogFormat::default(),
+            disabled: Vec::new(),
+            deprecated: HashMap::new(),
+            natives: HashMap::new(),
+            diagnostics: Diagnostics::new(),
+            max_call_depth: None,
+            call_depth: 0,
+            escaping: None,
+            turtle: Turtle::default(),
+            #[cfg(feature = "graphics")]
+            canvas: None,
+            rng: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(1, |t| t.as_nanos() as u64 | 1),
+        }
+    }
+
+    /// The generator state to draw from: the given `rng()` handle's, or the
+    /// interpreter's own.
+    fn random_state(&mut self, name: &str, rng: Option<&Val>) -> Result<&mut u64, String> {
+        match rng {
+            Some(handle) => match self.resources.get_mut(name, handle)? {
+                Resource::Rng(state) => Ok(state),
+            },
+            None => Ok(&mut self.rng),
+        }
+    }
+
+    /// A copy to run a `par_map` function in, sharing the definitions and
+    /// options but none of the event handlers, tasks or memo caches.
+    fn sandbox(&self) -> Interpreter {
+        Interpreter {
+            sandboxed: true,
+            ..self.task_copy()
+        }
+    }
+
+    /// A copy to run a task in, like `sandbox` but sharing the input and
+    /// outputs and free to call any builtin.
+    fn task_copy(&self) -> Interpreter {
+        Interpreter {
+            global: self.global.clone(),
+            enums: self.enums.clone(),
+            redeclaration: self.redeclaration,
+            truthiness: self.truthiness,
+            division: self.division,
+            array_growth: self.array_growth,
+            type_checking: self.type_checking,
+            version: self.version.clone(),
+            features: self.features.clone(),
+            config: self.config.clone(),
+            constants: self.constants.clone(),
+            cancel: self.cancel.clone(),
+            input: self.input.clone(),
+            output: self.output.clone(),
+            log_output: self.log_output.clone(),
+            log_level: self.log_level,
+            log_format: self.log_format,
+            disabled: self.disabled.clone(),
+            deprecated: self.deprecated.clone(),
+            natives: self.natives.clone(),
+            max_call_depth: self.max_call_depth,
+            ..Interpreter::new()
+        }
+    }
+
+    pub fn set_redeclaration(&mut self, policy: Redeclaration) {
+        self.redeclaration = policy;
+    }
+
+    pub fn set_truthiness(&mut self, truthiness: Truthiness) {
+        self.truthiness = truthiness;
+    }
+
+    pub fn set_division(&mut self, division: Division) {
+        self.division = division;
+    }
+
+    pub fn set_type_checking(&mut self, checking: TypeChecking) {
+        self.type_checking = checking;
+    }
+
+    /// The language version the programs run so far declared with
+    /// `#pragma version`, or `version::DEFAULT_VERSION`.
+    pub fn version(&self) -> &str {
+        &self.version
+    }
+
+    pub fn has_feature(&self, feature: Feature) -> bool {
+        self.features.contains(&feature)
+    }
+
+    /// Switches to the semantics `feature` gates, as `#feature` does, for
+    /// the rest of the interpreter's life.
+    pub fn enable_feature(&mut self, feature: Feature) {
+        match feature {
+            Feature::StrictTypes => self.type_checking = TypeChecking::Strict,
+            Feature::FloatDivision => self.division = Division::Float,
+        }
+        self.features.insert(feature);
+    }
+
+    pub fn set_array_growth(&mut self, growth: ArrayGrowth) {
+        self.array_growth = growth;
+    }
+
+    /// Drops `log_*` records less severe than `level`.
+    pub fn set_log_level(&mut self, level: LogLevel) {
+        self.log_level = level;
+    }
+
+    pub fn set_log_format(&mut self, format: LogFormat) {
+        self.log_format = format;
+    }
+
+    /// Replaces stderr as where the `log_*` builtins write, e.g. with a file
+    /// or a buffer the host collects records from.
+    pub fn set_log_output(&mut self, output: impl Write + Send + 'static) {
+        self.log_output = Arc::new(Mutex::new(Box::new(output)));
+    }
+
+    /// Exposes `config` to scripts as the read-only global `CONFIG`, which
+    /// they can neither assign nor shadow with `let`.
+    pub fn set_config(&mut self, config: Val) {
+        self.config = Some(Val::frozen(config, "CONFIG"));
+    }
+
+    /// Declares a global that scripts can read everywhere, functions
+    /// included, but not assign; a `let` may still shadow it.
+    pub fn define_constant(&mut self, name: &str, value: Val) {
+        self.constants
+            .insert(name.to_string(), Val::frozen(value, name));
+    }
+
+    /// A builder for hosts that need more than the defaults, e.g. to switch
+    /// off builtin groups or redirect input and output.
+    pub fn builder() -> InterpreterBuilder {
+        InterpreterBuilder::default()
+    }
+
+    pub fn run(&mut self, source: &[Stmt]) -> Result<Val, String> {
+        let res = self.exec_stmts(source);
+        // An error must never leave a half-finished call on the stack, or
+        // every later REPL input would run inside it.
+        self.stack.truncate(1);
+        self.call_depth = 0;
+        self.escaping = None;
+        if self.interrupted.swap(false, Ordering::Relaxed) {
+            return Err(self.handle_interrupt());
+        }
+        match res? {
+            Flow::Continue(v) | Flow::Return(v) => Ok(v),
+            Flow::Break(None, _) => Err("'break' outside of a loop".to_string()),
+            Flow::Break(Some(label), _) => Err(format!("No loop labeled '{}'", label)),
+        }
+    }
+
+    /// Runs one statement against the state left by earlier calls, as the
+    /// REPL does with each input.
+    pub fn eval_stmt(&mut self, stmt: &Stmt) -> Result<Val, String> {
+        self.run(std::slice::from_ref(stmt))
+    }
+
+    /// The top-level variable or function called `name`, if the programs
+    /// run so far defined one.
+    pub fn variable(&self, name: &str) -> Option<&Val> {
+        self.stack[0]
+            .local
+            .get(name)
+            .or_else(|| self.global.get(name))
+    }
+
+    /// Feeds the next piece of source, such as a line typed by a user.
+    /// While brackets are left open the input is held back and `Ok(None)`
+    /// returned; once a later piece closes them everything held is parsed
+    /// and run. A parse error discards the held input.
+    pub fn eval_source_incremental(&mut self, source: &str) -> Result<Option<Val>, String> {
+        self.pending.push_str(source);
+        self.pending.push('\n');
+        if bracket_depth(&self.pending) > 0 {
+            return Ok(None);
+        }
+        let program = parse(&std::mem::take(&mut self.pending))?;
+        self.run(&program).map(Some)
+    }
+
+    /// Like `run`, but gives up with a `TIMEOUT` error once `timeout` has
+    /// passed. The program is stopped at its next block, or while it waits
+    /// in `sleep` or `recv`; one that finishes after the deadline has timed
+    /// out all the same.
+    pub fn run_with_timeout(&mut self, source: &[Stmt], timeout: Duration) -> Result<Val, String> {
+        self.run_within(source, timeout)
+            .unwrap_or_else(|| Err(TIMEOUT.to_string()))
+    }
+
+    /// `run_with_timeout`, giving `None` when time ran out rather than an
+    /// error a program could also have raised itself.
+    pub(crate) fn run_within(
+        &mut self,
+        source: &[Stmt],
+        timeout: Duration,
+    ) -> Option<Result<Val, String>> {
+        let deadline = Instant::now() + timeout;
+        let cancel = self.cancel.clone();
+        let (done, finished) = mpsc::channel::<()>();
+        let watchdog = thread::spawn(move || {
+            let timed_out = finished.recv_timeout(timeout) == Err(RecvTimeoutError::Timeout);
+            if timed_out {
+                cancel.store(true, Ordering::Relaxed);
             }
-            match &args[0] {
-                Val::Int(n) => Ok(Val::Float((*n as f64).sin())),
-                Val::Float(f) => Ok(Val::Float(f.sin())),
-                _ => Err(format!("sin() requires a number, got {:?}", args[0])),
+            timed_out
+        });
+
+        let res = self.run(source);
+        drop(done);
+        if watchdog.join().unwrap_or(false) {
+            self.cancel.store(false, Ordering::Relaxed);
+            return None;
+        }
+        (Instant::now() < deadline).then_some(res)
+    }
+
+    /// A handle the host can use, from any thread, to give the running
+    /// program new definitions of its functions.
+    pub fn reloader(&self) -> Reloader {
+        self.reloader.clone()
+    }
+
+    /// Defines the functions and enums in `program` again, leaving every
+    /// variable and every function it no longer has as they are; its other
+    /// statements don't run. Gives the names of the functions it changed,
+    /// in program order.
+    pub fn reload(&mut self, program: &[Stmt]) -> Result<Vec<String>, String> {
+        let mut changed = Vec::new();
+        for stmt in program {
+            match stmt {
+                Stmt::Function { name, .. } => {
+                    let old = self.global.get(name).and_then(Val::definition);
+                    self.exec_stmt(stmt)?;
+                    let new = self.global.get(name).and_then(Val::definition);
+                    if old.is_none() || old != new {
+                        changed.push(name.clone());
+                    }
+                }
+                Stmt::Enum { .. } => {
+                    self.exec_stmt(stmt)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(changed)
+    }
+
+    /// Runs the `on_interrupt` handler once a signal has stopped the
+    /// program, cancelling it too after `INTERRUPT_GRACE` or another
+    /// signal. Gives the error the program ends with.
+    fn handle_interrupt(&mut self) -> String {
+        self.cancel.store(false, Ordering::Relaxed);
+        let Some(handler) = self.interrupt_handler.clone() else {
+            return INTERRUPTED.to_string();
+        };
+        let cancel = self.cancel.clone();
+        let (done, finished) = mpsc::channel::<()>();
+        let watchdog = thread::spawn(move || {
+            if finished.recv_timeout(INTERRUPT_GRACE) == Err(RecvTimeoutError::Timeout) {
+                cancel.store(true, Ordering::Relaxed);
             }
         });
+        let res = self.call_value("on_interrupt", handler, Vec::new());
+        drop(done);
+        watchdog.join().ok();
+        self.stack.truncate(1);
+        self.call_depth = 0;
+        self.cancel.store(false, Ordering::Relaxed);
+        self.interrupted.store(false, Ordering::Relaxed);
+        match res {
+            Ok(_) => INTERRUPTED.to_string(),
+            Err(e) => format!("{}; the on_interrupt handler failed: {}", INTERRUPTED, e),
+        }
+    }
+
+    fn apply_reloads(&mut self) {
+        self.reloader.pending.store(false, Ordering::Relaxed);
+        let queue = std::mem::take(&mut *self.reloader.queue.lock().unwrap());
+        for (program, done) in queue {
+            done.send(self.reload(&program)).ok();
+        }
+    }
+
+    /// A flag the host can set, from any thread, to stop the running
+    /// program with a `CANCELLED` error. It stays set until cleared, so
+    /// later runs fail too.
+    pub fn cancellation_token(&self) -> Arc<AtomicBool> {
+        self.cancel.clone()
+    }
+
+    /// Names the source of the programs run next, usually a file path, so
+    /// functions can report where they were defined.
+    pub fn set_source_name(&mut self, name: &str) {
+        self.source_name = Some(name.to_string());
+    }
+
+    /// Starts counting the strings, arrays, maps, big integers and variants
+    /// the programs run next create, by type and by where they were made.
+    pub fn enable_alloc_profile(&mut self) {
+        self.alloc_profile = Some(AllocProfile::default());
+    }
+
+    /// What `enable_alloc_profile` counted so far: the totals by type and
+    /// the top allocation sites. `None` if it was never enabled.
+    pub fn alloc_report(&self) -> Option<String> {
+        self.alloc_profile.as_ref().map(AllocProfile::report)
+    }
+
+    /// Makes calls to `builtin` give a deprecation warning carrying
+    /// `advice`, such as what to call instead.
+    pub fn deprecate(&mut self, builtin: &str, advice: &str) {
+        self.deprecated
+            .insert(builtin.to_string(), advice.to_string());
+    }
+
+    /// Adds the builtins in `registry`, returning their names. A name ew
+    /// already has a builtin for is an error, and nothing is added then;
+    /// registering a name again replaces the earlier native builtin.
+    pub fn register(&mut self, registry: Registry) -> Result<Vec<String>, String> {
+        if let Some((name, _)) = registry
+            .builtins
+            .iter()
+            .find(|(name, _)| Self::builtin_name(name).is_some())
+        {
+            return Err(format!("{}() is already a builtin", name));
+        }
+        let mut names = Vec::new();
+        for (name, f) in registry.builtins {
+            // `Val::Builtin` holds a `&'static str`, so each name is leaked
+            // once; hosts register a handful of builtins, not a stream.
+            let key = match self.natives.get_key_value(name.as_str()) {
+                Some((&key, _)) => key,
+                None => Box::leak(name.clone().into_boxed_str()),
+            };
+            self.natives.insert(key, f);
+            names.push(name);
+        }
+        Ok(names)
+    }
+
+    fn native_name(&self, name: &str) -> Option<&'static str> {
+        self.natives.get_key_value(name).map(|(&k, _)| k)
+    }
+
+    /// Adds the resolver's warnings about `program` to the diagnostics,
+    /// for hosts that want them before running it.
+    pub fn check(&mut self, program: &[Stmt]) {
+        let mut globals: Vec<&str> = self.constants.keys().map(String::as_str).collect();
+        if self.config.is_some() {
+            globals.push("CONFIG");
+        }
+        self.diagnostics
+            .extend(resolve_with_globals(program, &globals));
+    }
+
+    /// The warnings collected so far, from `check` and from running
+    /// programs, and which kinds are collected at all.
+    pub fn diagnostics(&self) -> &Diagnostics {
+        &self.diagnostics
+    }
+
+    pub fn diagnostics_mut(&mut self) -> &mut Diagnostics {
+        &mut self.diagnostics
+    }
+
+    /// Counts `val` with the allocation profiler, if it's on; see
+    /// `AllocProfile::record`.
+    fn allocated(&mut self, what: impl FnOnce() -> String, val: &Val, deep: bool) {
+        if let Some(profile) = &mut self.alloc_profile {
+            let (line, col) = self.site.start;
+            let at = self.site.is_known().then(|| match &self.source_name {
+                Some(file) => format!("{}:{}:{}", file, line, col),
+                None => format!("{}:{}", line, col),
+            });
+            profile.record(what(), at, val, deep);
+        }
+    }
+
+    /// The canvas from `window`, for the drawing builtin `name`.
+    #[cfg(feature = "graphics")]
+    fn canvas_mut(&mut self, name: &str) -> Result<&mut crate::graphics::Canvas, String> {
+        self.canvas
+            .as_mut()
+            .ok_or_else(|| format!("{}() needs a window; call window(w, h) first", name))
+    }
+
+    /// Replaces stdin as the source of `read_line`, `read_all_stdin` and
+    /// `lines_stdin`, e.g. with a file or an in-memory buffer.
+    pub fn set_input(&mut self, input: impl BufRead + Send + 'static) {
+        self.input = Arc::new(Mutex::new(Box::new(input)));
+    }
+
+    /// `log_debug(message, fields)` and the other levels: writes a record
+    /// if `level` passes the filter. `fields` is an optional map.
+    fn log(&mut self, level: LogLevel, args: Vec<Val>) -> Result<Val, String> {
+        let name = format!("log_{}", level.name());
+        let (message, fields) = match args.as_slice() {
+            [message] => (message, None),
+            [message, fields] => (message, Some(fields)),
+            _ => {
+                return Err(format!(
+                    "{}() takes 1 or 2 arguments, got {}",
+                    name,
+                    args.len()
+                ));
+            }
+        };
+        let empty = BTreeMap::new();
+        let fields = match fields {
+            None => &empty,
+            Some(Val::Map(fields)) => fields,
+            Some(other) => {
+                return Err(format!(
+                    "{}() fields must be a map, got {}",
+                    name,
+                    other.type_name()
+                ));
+            }
+        };
+        if level < self.log_level {
+            return Ok(Val::Unit);
+        }
+        let message = match message {
+            Val::Str(s) => s.clone(),
+            other => other.to_string(),
+        };
+        let line = crate::log::line(self.log_format, level, &message, fields);
+        let mut output = self.log_output.lock().unwrap();
+        writeln!(output, "{}", line)
+            .and_then(|()| output.flush())
+            .map_err(|e| format!("Cannot write log: {}", e))?;
+        Ok(Val::Unit)
+    }
+
+    fn write_output(&mut self, text: &str) -> Result<Val, String> {
+        let mut output = self.output.lock().unwrap();
+        output
+            .write_all(text.as_bytes())
+            .and_then(|()| output.flush())
+            .map_err(|e| format!("Cannot write output: {}", e))?;
+        Ok(Val::Unit)
+    }
+
+    /// How many handles scripts have opened and not yet closed.
+    pub fn open_handles(&self) -> usize {
+        self.resources.open.len()
+    }
+
+    /// Calls every handler the script registered for `event` with `args`,
+    /// in the order they were registered, and returns their results. Stops
+    /// at the first handler that fails.
+    pub fn dispatch(&mut self, event: &str, args: Vec<Val>) -> Result<Vec<Val>, String> {
+        let handlers = self.handlers.get(event).cloned().unwrap_or_default();
+        let res = handlers
+            .into_iter()
+            .map(|handler| self.call_value(event, handler, args.clone()))
+            .collect();
+        self.stack.truncate(1);
+        res
+    }
+
+    /// Events that currently have at least one handler.
+    pub fn events(&self) -> Vec<&str> {
+        let mut events: Vec<&str> = self
+            .handlers
+            .iter()
+            .filter(|(_, handlers)| !handlers.is_empty())
+            .map(|(event, _)| event.as_str())
+            .collect();
+        events.sort();
+        events
+    }
+
+    fn exec_stmt(&mut self, stmt: &Stmt) -> Result<Flow, String> {
+        match stmt {
+            Stmt::Function {
+                name,
+                params,
+                body,
+                decorators,
+                line,
+            } => {
+                // A lone string is the function's result, not its docstring.
+                let doc = match body.as_slice() {
+                    [Stmt::Expr(Expr::Str { value: doc, .. }), _, ..] => Some(doc.clone()),
+                    _ => None,
+                };
+                let mut func = Val::Function {
+                    name: name.clone(),
+                    params: params.clone(),
+                    body: body.clone(),
+                    doc,
+                    defined_at: match (&self.source_name, line) {
+                        (Some(file), 1..) => Some(format!("{}:{}", file, line)),
+                        _ => None,
+                    },
+                };
+                // The decorator nearest the `fn` is applied first.
+                for decorator in decorators.iter().rev() {
+                    func = self.call_named(decorator, vec![func])?;
+                }
+                self.global.insert(name.clone(), func);
+                Ok(Flow::Continue(Val::Unit))
+            }
+
+            Stmt::Enum { name, variants } => {
+                self.enums.insert(name.clone(), variants.clone());
+                Ok(Flow::Continue(Val::Unit))
+            }
+
+            Stmt::Return(expr) => {
+                let retval = self.eval_expr(expr)?;
+                Ok(Flow::Return(retval))
+            }
+
+            Stmt::Break(expr) => {
+                let val = match expr {
+                    Some(expr) => self.eval_expr(expr)?,
+                    None => Val::Unit,
+                };
+                Ok(Flow::Break(None, val))
+            }
+
+            Stmt::BreakLabel(label) => Ok(Flow::Break(Some(label.clone()), Val::Unit)),
+
+            Stmt::Assignment { name, value } => {
+                if name == "CONFIG" && self.config.is_some() {
+                    return Err("CONFIG is read-only".to_string());
+                }
+                let val = self.eval_expr(value)?;
+                let frame = self
+                    .stack
+                    .last_mut()
+                    .expect("Call Stack Should Not Be Empty");
+
+                // Only the innermost frame is checked, so a block may shadow
+                // a variable from an enclosing scope.
+                if let Some(exists) = frame.local.get(name)
+                    && self.redeclaration == Redeclaration::Strict
+                {
+                    return Err(format!(
+                        "The variable already exists: [{} = {}]",
+                        name, exists
+                    ));
+                }
+                frame.local.insert(name.clone(), val);
+
+                Ok(Flow::Continue(Val::Unit))
+            }
+
+            Stmt::Reassignment { target, value } => {
+                let (AssignmentTarget::Ident(name) | AssignmentTarget::ArrayAccess { name, .. }) =
+                    target;
+                // `CONFIG` lives outside the frames and `let` can't shadow it,
+                // so there is nothing here that could be assigned.
+                if name == "CONFIG" && self.config.is_some() {
+                    return Err("CONFIG is read-only".to_string());
+                }
+                if self.constants.contains_key(name) && self.lookup_mut(name).is_none() {
+                    return Err(format!("{} is a constant", name));
+                }
+                self.check_sandbox_write(name)?;
+                if let Some(flow) = self.try_append_in_place(target, value)? {
+                    return Ok(flow);
+                }
+                let val = self.eval_expr(value)?;
+                match target {
+                    AssignmentTarget::Ident(name) => {
+                        if let Some(exists) = self.lookup_mut(name) {
+                            *exists = val;
+                        } else {
+                            return Err(format!(
+                                "The variable [{0}] does not exist; use `let {0} = ...` to declare it",
+                                name
+                            ));
+                        }
+                    }
+                    AssignmentTarget::ArrayAccess { name, indices } => {
+                        if let Some(Val::Frozen { origin, .. }) = self.lookup_mut(name) {
+                            return Err(format!(
+                                "Cannot modify {}: it is frozen ({})",
+                                name, origin
+                            ));
+                        }
+                        let evaluated_indices: Result<Vec<i64>, String> = indices
+                            .iter()
+                            .map(|expr| {
+                                let idx_val = self.eval_expr(expr)?;
+                                match idx_val {
+                                    Val::Int(n) => Ok(n),
+                                    _ => Err(format!(
+                                        "Array index must be an integer, got {}",
+                                        idx_val.type_name()
+                                    )),
+                                }
+                            })
+                            .collect();
+                        let evaluated_indices = evaluated_indices?;
+                        let growth = self.array_growth;
+                        let var = self
+                            .lookup_mut(name)
+                            .ok_or_else(|| format!("The variable [{}] does not exist", name))?;
+                        if let Val::Frozen { origin, .. } = var {
+                            return Err(format!(
+                                "Cannot modify {}: it is frozen ({})",
+                                name, origin
+                            ));
+                        }
+                        let mut cur = var;
+
+                        for &idx in &evaluated_indices[..evaluated_indices.len() - 1] {
+                            match cur {
+                                Val::Array(arr) => {
+                                    let i = normalize_index(idx, arr.len())
+                                        .ok_or_else(|| out_of_bounds("Array", idx, arr.len()))?;
+                                    cur = &mut arr[i];
+                                }
+                                Val::Frozen { origin, .. } => {
+                                    return Err(format!(
+                                        "Cannot modify {}: it is frozen ({})",
+                                        name, origin
+                                    ));
+                                }
+                                _ => return Err(format!("Cannot index into {}", cur.type_name())),
+                            }
+                        }
+
+                        let final_idx = evaluated_indices[evaluated_indices.len() - 1];
+                        match cur {
+                            Val::Array(arr) => match normalize_index(final_idx, arr.len()) {
+                                Some(i) => arr[i] = val,
+                                None if growth == ArrayGrowth::Append
+                                    && final_idx == arr.len() as i64 =>
+                                {
+                                    arr.push(val)
+                                }
+                                None => return Err(out_of_bounds("Array", final_idx, arr.len())),
+                            },
+                            Val::Str(s) => {
+                                let mut chars: Vec<char> = s.chars().collect();
+                                let Some(final_idx) = normalize_index(final_idx, chars.len())
+                                else {
+                                    return Err(out_of_bounds("String", final_idx, chars.len()));
+                                };
+                                match &val {
+                                    Val::Str(new_char) => {
+                                        let new_chars: Vec<char> = new_char.chars().collect();
+                                        if new_chars.len() != 1 {
+                                            return Err(format!(
+                                                "Can only assign single character to string index, got string of length {}",
+                                                new_chars.len()
+                                            ));
+                                        }
+                                        chars[final_idx] = new_chars[0];
+                                        *s = chars.into_iter().collect();
+                                    }
+                                    _ => {
+                                        return Err(format!(
+                                            "Can only assign string to string index, got {}",
+                                            val.type_name()
+                                        ));
+                                    }
+                                }
+                            }
+                            Val::Frozen { origin, .. } => {
+                                return Err(format!(
+                                    "Cannot modify {}: it is frozen ({})",
+                                    name, origin
+                                ));
+                            }
+                            _ => return Err(format!("Cannot index into {}", cur.type_name())),
+                        }
+                    }
+                }
+                Ok(Flow::Continue(Val::Unit))
+            }
+
+            Stmt::Expr(expr) => self.eval_flow(expr),
+
+            Stmt::Pragma(pragma) => {
+                for feature in pragma.features()? {
+                    self.enable_feature(feature);
+                }
+                if let Pragma::Version(version) = pragma {
+                    self.version = version.clone();
+                }
+                Ok(Flow::Continue(Val::Unit))
+            }
+        }
+    }
+
+    fn eval_expr(&mut self, expr: &Expr) -> Result<Val, String> {
+        let span = match (&self.alloc_profile, expr.span()) {
+            (Some(_), Some(span)) => span,
+            _ => return self.eval_expr_at(expr),
+        };
+        let outer = std::mem::replace(&mut self.site, span);
+        let res = self.eval_expr_at(expr);
+        self.site = outer;
+        res
+    }
+
+    /// `eval_expr` once `site` is set for `expr`.
+    fn eval_expr_at(&mut self, expr: &Expr) -> Result<Val, String> {
+        match expr {
+            Expr::Int(i) => Ok(Val::Int(*i)),
+            Expr::BigInt(i) => Ok(Val::BigInt(i.clone())),
+            Expr::Bool(b) => Ok(Val::Bool(*b)),
+            Expr::Float(f) => Ok(Val::Float(*f)),
+            Expr::Str { value, .. } => {
+                let val = Val::Str(value.clone());
+                self.allocated(|| "string literal".to_string(), &val, false);
+                Ok(val)
+            }
+            Expr::Null => Ok(Val::Null),
+            Expr::Comprehension {
+                key,
+                value,
+                var,
+                source,
+                cond,
+                cond_span,
+                ..
+            } => {
+                let cond = cond.as_deref().map(|cond| (cond, *cond_span));
+                let val = self.eval_comprehension(key.as_deref(), value, var, source, cond)?;
+                self.allocated(|| "comprehension".to_string(), &val, false);
+                Ok(val)
+            }
+            Expr::Array { items, .. } => {
+                let res: Vec<Val> = items
+                    .iter()
+                    .map(|e| self.eval_expr(e))
+                    .collect::<Result<Vec<Val>, String>>()?;
+                let val = Val::Array(res);
+                self.allocated(|| "array literal".to_string(), &val, false);
+                Ok(val)
+            }
+
+            Expr::Var(name) => self.lookup(name),
+
+            Expr::Spread(_) => Err("'...' is only allowed in call arguments".to_string()),
+
+            Expr::Unary { op, expr, .. } => {
+                let val = self.eval_expr(expr)?;
+
+                let val = match (op, val) {
+                    (UnaryOp::Neg, Val::Int(i)) => i
+                        .checked_neg()
+                        .map(Val::Int)
+                        .unwrap_or_else(|| Val::BigInt(-BigInt::from(i))),
+                    (UnaryOp::Neg, Val::BigInt(i)) => normalize_big(-i),
+                    (UnaryOp::Neg, Val::Float(f)) => Val::Float(-f),
+                    (UnaryOp::Not, Val::Bool(b)) => Val::Bool(!b),
+                    (op, val) => return Err(format!("Cannot apply {:?} to {}", op, val.repr())),
+                };
+                self.allocated(|| "negation".to_string(), &val, false);
+                Ok(val)
+            }
+
+            Expr::Binary { op, lhs, rhs, .. } => {
+                let mut l = self.eval_expr(lhs)?.thaw();
+                let mut r = self.eval_expr(rhs)?.thaw();
+
+                let ints = |v: &Val| matches!(v, Val::Int(_) | Val::BigInt(_));
+                if *op == BinaryOp::Div && self.division == Division::Float && ints(&l) && ints(&r)
+                {
+                    l = Val::Float(as_f64(&l).unwrap_or(f64::NAN));
+                    r = Val::Float(as_f64(&r).unwrap_or(f64::NAN));
+                }
+
+                if matches!(op, BinaryOp::Eq | BinaryOp::Ne)
+                    && self.type_checking == TypeChecking::Strict
+                    && l.type_name() != r.type_name()
+                    && l != Val::Null
+                    && r != Val::Null
+                {
+                    return Err(format!(
+                        "Cannot compare {} with {} using '{}' under strict type checking",
+                        l.type_name(),
+                        r.type_name(),
+                        binary_op(*op)
+                    ));
+                }
+
+                let val = Self::eval_bin_op(*op, l, r)?;
+                self.allocated(|| format!("'{}'", binary_op(*op)), &val, true);
+                Ok(val)
+            }
+
+            Expr::Call { name, args, .. } => {
+                let arg_vals = self.eval_args(args)?;
+                self.call_named(name, arg_vals)
+            }
+
+            Expr::Variant {
+                enum_name,
+                variant,
+                args,
+                ..
+            } => {
+                let arity = self
+                    .enums
+                    .get(enum_name)
+                    .ok_or_else(|| format!("Undefined enum: {}", enum_name))?
+                    .iter()
+                    .find(|v| &v.name == variant)
+                    .ok_or_else(|| format!("Enum {} has no variant {}", enum_name, variant))?
+                    .fields
+                    .len();
+
+                let fields = self.eval_args(args)?;
+                if arity != fields.len() {
+                    return Err(format!(
+                        "Variant {}::{} expects {} fields, got {}",
+                        enum_name,
+                        variant,
+                        arity,
+                        fields.len()
+                    ));
+                }
+                let val = Val::Variant {
+                    enum_name: enum_name.clone(),
+                    variant: variant.clone(),
+                    fields,
+                };
+                self.allocated(|| format!("{}::{}", enum_name, variant), &val, false);
+                Ok(val)
+            }
+
+            Expr::If { .. }
+            | Expr::IfLet { .. }
+            | Expr::LetIn { .. }
+            | Expr::Match { .. }
+            | Expr::While { .. }
+            | Expr::WhileLet { .. }
+            | Expr::For { .. }
+            | Expr::Loop(_)
+            | Expr::Labeled { .. }
+            | Expr::Block(_) => match self.eval_flow(expr)? {
+                Flow::Continue(v) => Ok(v),
+                // An expression has only a value to give, so the flow
+                // travels up as an error until a statement takes it back.
+                flow => {
+                    self.escaping = Some(flow);
+                    Err(ESCAPING.to_string())
+                }
+            },
+
+            Expr::ArrayAccess { name, indices, .. } => self.access(name, indices),
+
+            Expr::Slice {
+                name,
+                indices,
+                start,
+                end,
+                ..
+            } => {
+                let val = match indices.is_empty() {
+                    true => self.lookup(name)?,
+                    false => self.access(name, indices)?,
+                };
+                let mut bound = |bound: &Option<Box<Expr>>| match bound {
+                    None => Ok(None),
+                    Some(expr) => match self.eval_expr(expr)? {
+                        Val::Int(n) => Ok(Some(n)),
+                        other => Err(format!(
+                            "Slice bound must be an integer, got {}",
+                            other.repr()
+                        )),
+                    },
+                };
+                let (start, end) = (bound(start)?, bound(end)?);
+                let (val, origin) = match val {
+                    Val::Frozen { origin, value } => (*value, Some(origin)),
+                    val => (val, None),
+                };
+                let part = match val {
+                    Val::Array(mut items) => {
+                        let range = slice_range(start, end, items.len());
+                        items.truncate(range.end);
+                        Val::Array(items.split_off(range.start))
+                    }
+                    Val::Str(s) => {
+                        let chars: Vec<char> = s.chars().collect();
+                        Val::Str(chars[slice_range(start, end, chars.len())].iter().collect())
+                    }
+                    other => return Err(format!("Cannot slice {}", other.repr())),
+                };
+                self.allocated(|| format!("slice of {}", name), &part, false);
+                Ok(match origin {
+                    Some(origin) => Val::frozen(part, &origin),
+                    None => part,
+                })
+            }
+        }
+    }
+
+    /// The element `name[indices...]`.
+    fn access(&mut self, name: &str, indices: &[Box<Expr>]) -> Result<Val, String> {
+        let val = self.lookup(name)?;
+        let mut cur = &val;
+        // Where the outermost frozen array on the way down came from; what
+        // is read out of it is read-only too.
+        let mut origin = None;
+
+        for expr in indices {
+            let idx_val = self.eval_expr(expr)?;
+            if let Val::Frozen { origin: o, value } = cur {
+                origin.get_or_insert(o);
+                cur = value;
+            }
+            if let Val::Map(map) = cur {
+                let Val::Str(key) = &idx_val else {
+                    return Err(format!(
+                        "Map key must be a string, got {}",
+                        idx_val.type_name()
+                    ));
+                };
+                cur = map
+                    .get(key)
+                    .ok_or_else(|| format!("Map has no key {}", quote(key)))?;
+                continue;
+            }
+            let idx = match idx_val {
+                Val::Int(i) => i,
+                _ => {
+                    return Err(format!(
+                        "Array index must be an integer, got {}",
+                        idx_val.type_name()
+                    ));
+                }
+            };
+
+            match cur {
+                Val::Array(arr) => {
+                    let i = normalize_index(idx, arr.len())
+                        .ok_or_else(|| out_of_bounds("Array", idx, arr.len()))?;
+                    cur = &arr[i];
+                }
+                Val::Str(s) => {
+                    let chars: Vec<char> = s.chars().collect();
+                    let i = normalize_index(idx, chars.len())
+                        .ok_or_else(|| out_of_bounds("String", idx, chars.len()))?;
+                    return Ok(Val::Str(chars[i].to_string()));
+                }
+                _ => return Err(format!("Cannot index into {}", cur.type_name())),
+            }
+        }
+
+        let element = match (cur, origin) {
+            (Val::Frozen { .. }, _) | (_, None) => cur.clone(),
+            (_, Some(origin)) => Val::frozen(cur.clone(), origin),
+        };
+        self.allocated(|| format!("element of {}", name), &element, true);
+        Ok(element)
+    }
+
+    fn eval_flow(&mut self, expr: &Expr) -> Result<Flow, String> {
+        match expr {
+            Expr::If {
+                cond,
+                then,
+                else_,
+                span,
+            } => {
+                if self.eval_condition(cond, "If", *span)? {
+                    self.exec_block(then)
+                } else {
+                    self.exec_block(else_)
+                }
+            }
+
+            Expr::While { cond, body, span } => {
+                while self.eval_condition(cond, "While", *span)? {
+                    match self.exec_block(body)? {
+                        Flow::Continue(_) => {}
+                        Flow::Break(None, v) => return Ok(Flow::Continue(v)),
+                        flow => return Ok(flow),
+                    }
+                }
+                Ok(Flow::Continue(Val::Unit))
+            }
+
+            Expr::IfLet {
+                name,
+                value,
+                then,
+                else_,
+            } => match self.eval_expr(value)? {
+                Val::Null => self.exec_block(else_),
+                val => self.exec_bound(name, val, then),
+            },
+
+            Expr::WhileLet { name, value, body } => {
+                loop {
+                    let val = self.eval_expr(value)?;
+                    if val == Val::Null {
+                        break;
+                    }
+                    match self.exec_bound(name, val, body)? {
+                        Flow::Continue(_) => {}
+                        Flow::Break(None, v) => return Ok(Flow::Continue(v)),
+                        flow => return Ok(flow),
+                    }
+                }
+                Ok(Flow::Continue(Val::Unit))
+            }
+
+            Expr::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                let st = self.eval_expr(start)?;
+                let en = self.eval_expr(end)?;
+
+                let (sti, eni) = match (st, en) {
+                    (Val::Int(i), Val::Int(j)) => (i, j),
+                    (a, b) => {
+                        return Err(format!(
+                            "The range must evaluate to ineteger bounds, got {}..{}",
+                            a, b
+                        ));
+                    }
+                };
+
+                // The loop variable lives in its own frame, and every iteration
+                // gets a fresh child frame for the body.
+                let frame = Frame::child(self.stack.len() - 1);
+                self.with_frame(frame, |this| {
+                    for i in sti..eni {
+                        if let Some(frame) = this.stack.last_mut() {
+                            frame.local.insert(var.clone(), Val::Int(i));
+                        }
+                        match this.exec_block(body)? {
+                            Flow::Continue(_) => {}
+                            Flow::Break(None, v) => return Ok(Flow::Continue(v)),
+                            flow => return Ok(flow),
+                        }
+                    }
+                    Ok(Flow::Continue(Val::Unit))
+                })
+            }
+
+            Expr::Match { scrutinee, arms } => {
+                let val = self.eval_expr(scrutinee)?;
+
+                for arm in arms {
+                    if let Some(bindings) = self.match_pattern(&arm.pattern, &val)? {
+                        let mut frame = Frame::child(self.stack.len() - 1);
+                        frame.local.extend(bindings);
+                        return self.with_frame(frame, |this| this.exec_stmts(&arm.body));
+                    }
+                }
+
+                Err(format!("No match arm for {}", val))
+            }
+
+            Expr::Labeled { label, body } => match self.eval_flow(body)? {
+                Flow::Break(Some(l), v) if &l == label => Ok(Flow::Continue(v)),
+                flow => Ok(flow),
+            },
+
+            Expr::Loop(body) => loop {
+                match self.exec_block(body)? {
+                    Flow::Continue(_) => {}
+                    Flow::Break(None, v) => return Ok(Flow::Continue(v)),
+                    flow => return Ok(flow),
+                }
+            },
+
+            Expr::Block(stmts) => self.exec_block(stmts),
+
+            Expr::LetIn { name, value, body } => {
+                let val = self.eval_expr(value)?;
+                let mut frame = Frame::child(self.stack.len() - 1);
+                frame.local.insert(name.clone(), val);
+                self.with_frame(frame, |this| this.eval_flow(body))
+            }
+
+            _ => Ok(Flow::Continue(self.eval_expr(expr)?)),
+        }
+    }
+
+    /// Calls the builtin or function called `name`.
+    fn call_named(&mut self, name: &str, args: Vec<Val>) -> Result<Val, String> {
+        // A function the program defines wins over a builtin of the same
+        // name, so adding builtins doesn't break scripts that use the name.
+        let func = match self.lookup(name) {
+            Ok(func) if func.is_callable() => func,
+            found => match Self::builtin_name(name).or_else(|| self.native_name(name)) {
+                Some(builtin) => Val::Builtin(builtin),
+                None => found?,
+            },
+        };
+        self.call_value(name, func, args)
+    }
+
+    fn call_value(&mut self, name: &str, func: Val, args: Vec<Val>) -> Result<Val, String> {
+        match func {
+            Val::Builtin(builtin) => {
+                if self.sandboxed && IMPURE_BUILTINS.contains(&builtin) {
+                    return Err(format!("{}() is not allowed inside par_map", builtin));
+                }
+                if self.disabled.contains(&builtin) {
+                    return Err(format!("{}() is disabled in this interpreter", builtin));
+                }
+                if let Some(advice) = self.deprecated.get(builtin) {
+                    let message = format!("{}() is deprecated; {}", builtin, advice);
+                    self.diagnostics.warn(WarningKind::Deprecated, message);
+                }
+                // Most builtins only read their arguments, so they get the
+                // plain array; these few care whether it is frozen.
+                let args = if ["freeze", "is_frozen", "hash"].contains(&builtin) {
+                    args
+                } else {
+                    args.into_iter().map(Val::thaw).collect()
+                };
+                let val = match Self::builtins().get(builtin) {
+                    Some(f) => f(args),
+                    None => match self.natives.get(builtin).cloned() {
+                        Some(f) => f(args),
+                        None => Self::intrinsics()[builtin](self, args),
+                    },
+                }?;
+                self.allocated(|| format!("{}()", builtin), &val, false);
+                Ok(val)
+            }
+
+            Val::Function {
+                params,
+                body,
+                defined_at,
+                ..
+            } => {
+                if params.len() != args.len() {
+                    let at = defined_at.map(|at| format!(" (defined at {})", at));
+                    return Err(format!(
+                        "Function {}{} expects {} arguments, got {}",
+                        name,
+                        at.unwrap_or_default(),
+                        params.len(),
+                        args.len()
+                    ));
+                }
+
+                let mut frame = Frame::new();
+
+                for (param, arg) in params.iter().zip(args) {
+                    frame.local.insert(param.clone(), arg);
+                }
+
+                if self
+                    .max_call_depth
+                    .is_some_and(|max| self.call_depth >= max)
+                {
+                    return Err(format!(
+                        "Maximum call depth of {} exceeded in {}",
+                        self.call_depth, name
+                    ));
+                }
+                self.call_depth += 1;
+                let caller = std::mem::take(&mut self.site);
+                if let Some(profile) = &mut self.alloc_profile {
+                    profile.enter(match &defined_at {
+                        Some(at) => format!("{} ({})", name, at),
+                        None => name.to_string(),
+                    });
+                }
+                let res = self.with_frame(frame, |this| this.exec_stmts(&body));
+                if let Some(profile) = &mut self.alloc_profile {
+                    profile.leave();
+                }
+                self.site = caller;
+                self.call_depth -= 1;
+                let res = res?;
+
+                // Without a `return`, a function gives the value of its last
+                // statement, as Rust does: an `if`, `match` or block in tail
+                // position gives the value of the branch taken, and a `let`,
+                // loop or `if` without a taken branch gives `()`.
+                match res {
+                    Flow::Continue(v) | Flow::Return(v) => Ok(v),
+                    Flow::Break(..) => Err(format!("'break' outside of a loop in {}", name)),
+                }
+            }
+
+            Val::Decorated {
+                decorator: Decorator::Memoize(id),
+                func,
+            } => {
+                let key = (id, format!("{:?}", args));
+                if let Some(hit) = self.memo.get(&key) {
+                    return Ok(hit.clone());
+                }
+                let res = self.call_value(name, *func, args)?;
+                self.memo.insert(key, res.clone());
+                Ok(res)
+            }
+
+            Val::Decorated {
+                decorator: Decorator::Timed,
+                func,
+            } => {
+                let start = Instant::now();
+                let res = self.call_value(name, *func, args);
+                let took = format!("{}() took {:.3?}", name, start.elapsed());
+                let logged = self.log(LogLevel::Info, vec![Val::Str(took)]);
+                let val = res?;
+                logged?;
+                Ok(val)
+            }
+
+            Val::Bound { func, args: bound } => {
+                let args = bound.into_iter().chain(args).collect();
+                self.call_value(name, *func, args)
+            }
+
+            Val::Curried { func, args: given } => {
+                let params = func.params().unwrap_or_default();
+                let remaining = &params[given.len()..];
+                if args.len() > remaining.len() {
+                    return Err(format!(
+                        "Curried {} expects at most {} more argument(s) ({}), got {}",
+                        name,
+                        remaining.len(),
+                        remaining.join(", "),
+                        args.len()
+                    ));
+                }
+                let complete = args.len() == remaining.len();
+                let args: Vec<Val> = given.into_iter().chain(args).collect();
+                if complete {
+                    self.call_value(name, *func, args)
+                } else {
+                    Ok(Val::Curried { func, args })
+                }
+            }
+
+            Val::Composed(funcs) => {
+                let mut funcs = funcs.into_iter();
+                let first = funcs.next().unwrap();
+                let mut res = self.call_value(name, first, args)?;
+                for func in funcs {
+                    res = self.call_value(name, func, vec![res])?;
+                }
+                Ok(res)
+            }
+
+            _ => Err(format!("'{}' is not a function", func)),
+        }
+    }
+
+    /// Builds the array or map of a comprehension. Like a `for` loop, the
+    /// variable lives in a frame of its own.
+    fn eval_comprehension(
+        &mut self,
+        key: Option<&Expr>,
+        value: &Expr,
+        var: &str,
+        source: &Iteration,
+        cond: Option<(&Expr, Span)>,
+    ) -> Result<Val, String> {
+        let items: Vec<Val> = match source {
+            Iteration::Range { start, end } => {
+                match (self.eval_expr(start)?, self.eval_expr(end)?) {
+                    (Val::Int(i), Val::Int(j)) => (i..j).map(Val::Int).collect(),
+                    (a, b) => {
+                        return Err(format!(
+                            "The range must evaluate to integer bounds, got {}..{}",
+                            a, b
+                        ));
+                    }
+                }
+            }
+            Iteration::Each(items) => match self.eval_expr(items)?.thaw() {
+                Val::Array(items) => items,
+                other => {
+                    return Err(format!(
+                        "A comprehension needs a range or an array, got {}",
+                        other.repr()
+                    ));
+                }
+            },
+        };
+
+        let frame = Frame::child(self.stack.len() - 1);
+        self.with_frame(frame, |this| {
+            let mut array = Vec::new();
+            let mut map = BTreeMap::new();
+            for item in items {
+                if let Some(frame) = this.stack.last_mut() {
+                    frame.local.insert(var.to_string(), item);
+                }
+                if let Some((cond, span)) = cond
+                    && !this.eval_condition(cond, "Comprehension", span)?
+                {
+                    continue;
+                }
+                match key {
+                    Some(key) => {
+                        let Val::Str(k) = this.eval_expr(key)? else {
+                            return Err("Map key must be a string".to_string());
+                        };
+                        map.insert(k, this.eval_expr(value)?);
+                    }
+                    None => array.push(this.eval_expr(value)?),
+                }
+            }
+            Ok(match key {
+                Some(_) => Val::Map(map),
+                None => Val::Array(array),
+            })
+        })
+    }
+
+    /// Evaluates call arguments left to right, flattening `...array` spreads.
+    fn eval_args(&mut self, args: &[Expr]) -> Result<Vec<Val>, String> {
+        let mut vals = Vec::with_capacity(args.len());
+        for arg in args {
+            match arg {
+                Expr::Spread(inner) => match self.eval_expr(inner)?.thaw() {
+                    Val::Array(items) => vals.extend(items),
+                    other => return Err(format!("Cannot spread {} into arguments", other.repr())),
+                },
+                _ => vals.push(self.eval_expr(arg)?),
+            }
+        }
+        Ok(vals)
+    }
+
+    /// Returns the variables bound by `pattern` if it matches `val`.
+    fn match_pattern(
+        &mut self,
+        pattern: &Pattern,
+        val: &Val,
+    ) -> Result<Option<Vec<(String, Val)>>, String> {
+        match pattern {
+            Pattern::Wildcard => Ok(Some(vec![])),
+            Pattern::Literal(lit) => {
+                let lit = self.eval_expr(lit)?;
+                Ok((&lit == val).then(Vec::new))
+            }
+            Pattern::Variant {
+                enum_name,
+                variant,
+                bindings,
+            } => {
+                let Val::Variant {
+                    enum_name: e,
+                    variant: v,
+                    fields,
+                } = val
+                else {
+                    return Ok(None);
+                };
+                if e != enum_name || v != variant {
+                    return Ok(None);
+                }
+                if bindings.len() != fields.len() {
+                    return Err(format!(
+                        "Pattern {}::{} binds {} fields, but the variant has {}",
+                        enum_name,
+                        variant,
+                        bindings.len(),
+                        fields.len()
+                    ));
+                }
+                Ok(Some(bindings.iter().cloned().zip(fields.clone()).collect()))
+            }
+        }
+    }
+
+    /// Evaluates `cond` as the test of an `if`, `while` or comprehension,
+    /// naming it, and `span` when known, if it isn't a Bool.
+    fn eval_condition(&mut self, cond: &Expr, kind: &str, span: Span) -> Result<bool, String> {
+        match self.eval_expr(cond)? {
+            Val::Bool(b) => Ok(b),
+            v if self.truthiness == Truthiness::Loose
+                && self.type_checking == TypeChecking::Lenient =>
+            {
+                self.diagnostics.warn(
+                    WarningKind::Conversion,
+                    format!(
+                        "{} condition `{}` converted from {} to Bool",
+                        kind,
+                        format_expr(cond),
+                        v.type_name()
+                    ),
+                );
+                Ok(truthy(&v))
+            }
+            v => {
+                let at = match span.is_known() {
+                    true => format!(" at {}", span),
+                    false => String::new(),
+                };
+                Err(format!(
+                    "{} condition `{}`{} must be a Boolean, got {} (use bool(...) to test truthiness)",
+                    kind,
+                    format_expr(cond),
+                    at,
+                    v.repr()
+                ))
+            }
+        }
+    }
+
+    /// Runs `stmts` in a new child frame of the current one, so that any
+    /// `let` inside the block is dropped once the block finishes.
+    fn exec_block(&mut self, stmts: &[Stmt]) -> Result<Flow, String> {
+        let frame = Frame::child(self.stack.len() - 1);
+        self.with_frame(frame, |this| this.exec_stmts(stmts))
+    }
+
+    /// Like `exec_block`, but with `name` already bound to `val` in the
+    /// block's frame.
+    fn exec_bound(&mut self, name: &str, val: Val, stmts: &[Stmt]) -> Result<Flow, String> {
+        let mut frame = Frame::child(self.stack.len() - 1);
+        frame.local.insert(name.to_string(), val);
+        self.with_frame(frame, |this| this.exec_stmts(stmts))
+    }
+
+    /// Runs `f` with `frame` pushed, and drops it (plus anything `f` left
+    /// behind) afterwards, whether `f` succeeded or not.
+    fn with_frame<T>(
+        &mut self,
+        frame: Frame,
+        f: impl FnOnce(&mut Self) -> Result<T, String>,
+    ) -> Result<T, String> {
+        let depth = self.stack.len();
+        self.stack.push(frame);
+        let res = f(self);
+        self.stack.truncate(depth);
+        res
+    }
+
+    fn exec_stmts(&mut self, stmts: &[Stmt]) -> Result<Flow, String> {
+        // Every loop iteration and call runs a block, so checking here is
+        // enough to stop any program.
+        if self.cancel.load(Ordering::Relaxed) {
+            return Err(CANCELLED.to_string());
+        }
+        if self.reloader.pending.load(Ordering::Relaxed) {
+            self.apply_reloads();
+        }
+        let mut res = Val::Unit;
+        for stmt in stmts {
+            let flow = match self.exec_stmt(stmt) {
+                Ok(flow) => flow,
+                Err(e) => self.escaping.take().ok_or(e)?,
+            };
+            match flow {
+                Flow::Continue(v) => res = v,
+                flow => return Ok(flow),
+            }
+        }
+        Ok(Flow::Continue(res))
+    }
+
+    fn eval_bin_op(op: BinaryOp, left: Val, right: Val) -> Result<Val, String> {
+        match (op, &left, &right) {
+            (BinaryOp::Add, Val::Int(a), Val::Int(b)) => checked(op, a.checked_add(*b), *a, *b),
+            (BinaryOp::Sub, Val::Int(a), Val::Int(b)) => checked(op, a.checked_sub(*b), *a, *b),
+            (BinaryOp::Mul, Val::Int(a), Val::Int(b)) => checked(op, a.checked_mul(*b), *a, *b),
+            (BinaryOp::Div, Val::Int(a), Val::Int(b)) => {
+                if *b == 0 {
+                    Err("Division by zero".to_string())
+                } else {
+                    checked(op, a.checked_div(*b), *a, *b)
+                }
+            }
+
+            (BinaryOp::Mod, Val::Int(a), Val::Int(b)) => {
+                if *b == 0 {
+                    Err("Modulo by zero".to_string())
+                } else {
+                    checked(op, a.checked_rem(*b), *a, *b)
+                }
+            }
+
+            (BinaryOp::Pow, Val::Int(a), Val::Int(b)) => match u32::try_from(*b) {
+                Ok(exp) => Ok(a
+                    .checked_pow(exp)
+                    .map(Val::Int)
+                    .unwrap_or_else(|| normalize_big(BigInt::from(*a).pow(exp)))),
+                Err(_) if *b < 0 => Ok(Val::Float((*a as f64).powf(*b as f64))),
+                Err(_) => Err(format!("Exponent too large: {}", b)),
+            },
+            (BinaryOp::Pow, Val::BigInt(a), Val::Int(b)) => match u32::try_from(*b) {
+                Ok(exp) => Ok(normalize_big(a.pow(exp))),
+                Err(_) => Err(format!("Exponent out of range for big integer: {}", b)),
+            },
+            (BinaryOp::Pow, Val::Float(a), Val::Float(b)) => Ok(Val::Float(a.powf(*b))),
+            (BinaryOp::Pow, Val::Float(a), Val::Int(b)) => Ok(Val::Float(a.powf(*b as f64))),
+
+            (_, Val::BigInt(_), Val::Int(_) | Val::BigInt(_))
+            | (_, Val::Int(_), Val::BigInt(_)) => {
+                big_bin_op(op, to_big(&left).unwrap(), to_big(&right).unwrap())
+            }
+
+            (BinaryOp::Add, Val::Float(a), Val::Float(b)) => Ok(Val::Float(a + b)),
+            (BinaryOp::Sub, Val::Float(a), Val::Float(b)) => Ok(Val::Float(a - b)),
+            (BinaryOp::Mul, Val::Float(a), Val::Float(b)) => Ok(Val::Float(a * b)),
+            (BinaryOp::Div, Val::Float(a), Val::Float(b)) => {
+                if *b == 0f64 {
+                    Err("Division by zero".to_string())
+                } else {
+                    Ok(Val::Float(a / b))
+                }
+            }
+
+            (BinaryOp::Mod, Val::Float(a), Val::Float(b)) => {
+                if *b == 0f64 {
+                    Err("Modulo by zero".to_string())
+                } else {
+                    Ok(Val::Float(a % b))
+                }
+            }
+
+            (BinaryOp::Add, Val::Str(a), Val::Str(b)) => Ok(Val::Str(a.clone() + b)),
+            (BinaryOp::Mul, Val::Str(a), Val::Int(i)) => match usize::try_from(*i) {
+                Ok(n) => Ok(Val::Str(a.repeat(n))),
+                Err(_) => Err(format!("Cannot repeat a string {} times", i)),
+            },
+
+            (BinaryOp::Add, Val::Array(a), Val::Array(i)) => {
+                let mut res = a.clone();
+                res.extend(i.clone());
+                Ok(Val::Array(res))
+            }
+
+            (BinaryOp::Eq, Val::Int(a), Val::Int(b)) => Ok(Val::Bool(a == b)),
+            (BinaryOp::Ne, Val::Int(a), Val::Int(b)) => Ok(Val::Bool(a != b)),
+            (BinaryOp::Gt, Val::Int(a), Val::Int(b)) => Ok(Val::Bool(a > b)),
+            (BinaryOp::Ge, Val::Int(a), Val::Int(b)) => Ok(Val::Bool(a >= b)),
+            (BinaryOp::Lt, Val::Int(a), Val::Int(b)) => Ok(Val::Bool(a < b)),
+            (BinaryOp::Le, Val::Int(a), Val::Int(b)) => Ok(Val::Bool(a <= b)),
+
+            // Float comparisons follow IEEE 754: NaN is unequal to everything,
+            // itself included, and every ordering comparison with it is false.
+            (BinaryOp::Eq, Val::Float(a), Val::Float(b)) => Ok(Val::Bool(a == b)),
+            (BinaryOp::Ne, Val::Float(a), Val::Float(b)) => Ok(Val::Bool(a != b)),
+            (BinaryOp::Gt, Val::Float(a), Val::Float(b)) => Ok(Val::Bool(a > b)),
+            (BinaryOp::Ge, Val::Float(a), Val::Float(b)) => Ok(Val::Bool(a >= b)),
+            (BinaryOp::Lt, Val::Float(a), Val::Float(b)) => Ok(Val::Bool(a < b)),
+            (BinaryOp::Le, Val::Float(a), Val::Float(b)) => Ok(Val::Bool(a <= b)),
+
+            (BinaryOp::Eq, Val::Bool(a), Val::Bool(b)) => Ok(Val::Bool(a == b)),
+            (BinaryOp::Ne, Val::Bool(a), Val::Bool(b)) => Ok(Val::Bool(a != b)),
+            (BinaryOp::And, Val::Bool(a), Val::Bool(b)) => Ok(Val::Bool(*a && *b)),
+            (BinaryOp::Or, Val::Bool(a), Val::Bool(b)) => Ok(Val::Bool(*a || *b)),
+
+            (BinaryOp::Eq, Val::Variant { .. }, Val::Variant { .. }) => {
+                Ok(Val::Bool(left == right))
+            }
+            (BinaryOp::Ne, Val::Variant { .. }, Val::Variant { .. }) => {
+                Ok(Val::Bool(left != right))
+            }
+
+            (BinaryOp::Eq, Val::Null, _) | (BinaryOp::Eq, _, Val::Null) => {
+                Ok(Val::Bool(left == right))
+            }
+            (BinaryOp::Ne, Val::Null, _) | (BinaryOp::Ne, _, Val::Null) => {
+                Ok(Val::Bool(left != right))
+            }
+
+            _ => Err(format!(
+                "Cannot apply {:?} to {} and {}",
+                op,
+                left.repr(),
+                right.repr()
+            )),
+        }
+    }
+
+    fn lookup(&mut self, name: &str) -> Result<Val, String> {
+        let mut current_idx = self.stack.len() - 1;
+
+        loop {
+            let frame = &self.stack[current_idx];
+
+            if let Some(val) = frame.local.get(name) {
+                let val = val.clone();
+                self.allocated(|| format!("copy of {}", name), &val, true);
+                return Ok(val);
+            }
+
+            match frame.parent {
+                Some(parent_idx) => current_idx = parent_idx,
+                None => break,
+            }
+        }
+
+        if let Some(val) = self.global.get(name) {
+            let val = val.clone();
+            self.allocated(|| format!("copy of {}", name), &val, true);
+            return Ok(val);
+        }
+
+        if let Some(builtin) = Self::builtin_name(name).or_else(|| self.native_name(name)) {
+            return Ok(Val::Builtin(builtin));
+        }
+
+        if let (Some(config), "CONFIG") = (&self.config, name) {
+            return Ok(config.clone());
+        }
+
+        if let Some(val) = self.constants.get(name) {
+            return Ok(val.clone());
+        }
+
+        if let Some(val) = constant(name) {
+            return Ok(val);
+        }
+
+        Err(format!("Undefined Variable: {}", name))
+    }
+
+    /// Fast path for `s = s + expr`, or a longer chain like `s = s + a + b`,
+    /// on strings: appends to the existing buffer instead of building a
+    /// fresh copy, so building text in a loop is linear rather than
+    /// quadratic. Returns `None` when the statement doesn't have that shape
+    /// and should be evaluated normally.
+    fn try_append_in_place(
+        &mut self,
+        target: &AssignmentTarget,
+        value: &Expr,
+    ) -> Result<Option<Flow>, String> {
+        let AssignmentTarget::Ident(name) = target else {
+            return Ok(None);
+        };
+        // `s + a + b` nests to the left, so the operands come out last first.
+        let mut tails = Vec::new();
+        let mut root = value;
+        while let Expr::Binary {
+            op: BinaryOp::Add,
+            lhs,
+            rhs,
+            ..
+        } = root
+        {
+            tails.push(rhs.as_ref());
+            root = lhs;
+        }
+        if !matches!(root, Expr::Var(v) if v == name)
+            || !matches!(self.lookup_mut(name), Some(Val::Str(_)))
+            || tails.is_empty()
+        {
+            return Ok(None);
+        }
+        // An operand that reads the variable must see it before any of the
+        // appends, which only the ordinary evaluation guarantees.
+        if tails.iter().any(|tail| mentions(tail, name)) {
+            return Ok(None);
+        }
+
+        // Every operand is evaluated before anything is appended, so a
+        // failing one leaves the variable as it was.
+        let tails = tails
+            .into_iter()
+            .rev()
+            .map(|tail| self.eval_expr(tail))
+            .collect::<Result<Vec<Val>, String>>()?;
+        let var = self
+            .lookup_mut(name)
+            .ok_or_else(|| format!("The variable [{}] does not exist", name))?;
+        match var {
+            Val::Str(s) if tails.iter().all(|tail| matches!(tail, Val::Str(_))) => {
+                for tail in tails {
+                    if let Val::Str(tail) = tail {
+                        s.push_str(&tail);
+                    }
+                }
+            }
+            // Not a string append after all (or an operand reassigned the
+            // variable); defer to the ordinary `+`.
+            var => {
+                let mut val = var.clone();
+                for tail in tails {
+                    val = Self::eval_bin_op(BinaryOp::Add, val, tail)?;
+                }
+                *self.lookup_mut(name).unwrap() = val;
+            }
+        }
+        Ok(Some(Flow::Continue(Val::Unit)))
+    }
+
+    fn lookup_mut(&mut self, name: &str) -> Option<&mut Val> {
+        if let Some(idx) = self.frame_of(name) {
+            return self.stack[idx].local.get_mut(name);
+        }
+
+        self.global.get_mut(name)
+    }
+
+    /// Inside `par_map` only the function's own variables may change.
+    fn check_sandbox_write(&self, name: &str) -> Result<(), String> {
+        if self.sandboxed && self.frame_of(name).is_none() && self.global.contains_key(name) {
+            return Err(format!("Cannot assign to global {} inside par_map", name));
+        }
+        Ok(())
+    }
+
+    /// Index of the visible frame that binds `name`, if any.
+    fn frame_of(&self, name: &str) -> Option<usize> {
+        let mut search_idx = self.stack.len() - 1;
+
+        loop {
+            let frame = &self.stack[search_idx];
+
+            if frame.local.contains_key(name) {
+                return Some(search_idx);
+            }
+
+            match frame.parent {
+                Some(parent_idx) => search_idx = parent_idx,
+                None => return None,
+            }
+        }
+    }
+
+    /// The builtin called `name`, looked up in the cached tables, so
+    /// resolving a name on every call costs two hash lookups.
+    fn builtin_name(name: &str) -> Option<&'static str> {
+        Self::builtins()
+            .get_key_value(name)
+            .map(|(&k, _)| k)
+            .or_else(|| Self::intrinsics().get_key_value(name).map(|(&k, _)| k))
+    }
+
+    /// The builtins that need the interpreter, built on first use.
+    fn intrinsics() -> &'static HashMap<&'static str, Intrinsic> {
+        static INTRINSICS: LazyLock<HashMap<&'static str, Intrinsic>> =
+            LazyLock::new(Interpreter::make_intrinsics);
+        &INTRINSICS
+    }
+
+    fn make_intrinsics() -> HashMap<&'static str, Intrinsic> {
+        let mut map: HashMap<&'static str, Intrinsic> = HashMap::new();
+
+        map.insert("on", |this, args| {
+            let (event, handler) = event_and_handler("on", args)?;
+            this.handlers.entry(event).or_default().push(handler);
+            Ok(Val::Unit)
+        });
+
+        map.insert("on_interrupt", |this, args| {
+            match <[Val; 1]>::try_from(args) {
+                Ok([handler]) if handler.is_callable() => {
+                    if this.signals.0.is_empty() {
+                        for signal in [signal_hook::consts::SIGINT, signal_hook::consts::SIGTERM] {
+                            for flag in [&this.cancel, &this.interrupted] {
+                                let id = signal_hook::flag::register(signal, flag.clone())
+                                    .map_err(|e| format!("on_interrupt() failed: {}", e))?;
+                                this.signals.0.push(id);
+                            }
+                        }
+                    }
+                    this.interrupt_handler = Some(handler);
+                    Ok(Val::Unit)
+                }
+                Ok([other]) => Err(format!(
+                    "on_interrupt() requires a function, got {}",
+                    other.repr()
+                )),
+                Err(args) => Err(format!(
+                    "on_interrupt() takes 1 argument, got {}",
+                    args.len()
+                )),
+            }
+        });
+
+        map.insert("off", |this, args| {
+            let (event, handler) = event_and_handler("off", args)?;
+            let handlers = this.handlers.entry(event).or_default();
+            match handlers.iter().position(|h| *h == handler) {
+                Some(i) => {
+                    handlers.remove(i);
+                    Ok(Val::Bool(true))
+                }
+                None => Ok(Val::Bool(false)),
+            }
+        });
+
+        map.insert("spawn", |_, args| match <[Val; 1]>::try_from(args) {
+            Ok([func]) if func.is_callable() => Ok(Val::Task(Arc::new(Task {
+                state: Mutex::new(TaskState::Ready(func)),
+            }))),
+            Ok([other]) => Err(format!("spawn() requires a function, got {}", other.repr())),
+            Err(args) => Err(format!("spawn() takes 1 argument, got {}", args.len())),
+        });
+
+        map.insert("yield", |this, args| {
+            let value = match <[Val; 1]>::try_from(args) {
+                Ok([value]) => value,
+                Err(args) if args.is_empty() => Val::Unit,
+                Err(args) => {
+                    return Err(format!(
+                        "yield() takes 0 or 1 arguments, got {}",
+                        args.len()
+                    ));
+                }
+            };
+            let yielder = this
+                .yielder
+                .as_ref()
+                .ok_or("yield() called outside a task")?;
+            yielder
+                .values
+                .send(Ok(Some(value)))
+                .map_err(|_| ABANDONED)?;
+            yielder.wake.lock().unwrap().recv().map_err(|_| ABANDONED)?;
+            Ok(Val::Unit)
+        });
+
+        map.insert("resume", |this, args| {
+            let task = task_arg("resume", args)?;
+            let mut state = task
+                .state
+                .try_lock()
+                .map_err(|_| "resume() called on a task that is already running")?;
+            let (wake, values) = match std::mem::replace(&mut *state, TaskState::Done) {
+                TaskState::Ready(func) => {
+                    let (values_tx, values) = mpsc::sync_channel(0);
+                    let (wake, wake_rx) = mpsc::sync_channel(0);
+                    let mut worker = this.task_copy();
+                    worker.yielder = Some(Yielder {
+                        values: values_tx.clone(),
+                        wake: Mutex::new(wake_rx),
+                    });
+                    // As much stack as a main thread, for recursive tasks.
+                    thread::Builder::new()
+                        .stack_size(8 << 20)
+                        .spawn(move || {
+                            let res = worker.call_value("task", func, Vec::new());
+                            let _ = values_tx.send(res.map(|_| None));
+                        })
+                        .map_err(|e| format!("resume() cannot start the task: {}", e))?;
+                    (wake, values)
+                }
+                TaskState::Suspended { wake, values } => {
+                    wake.send(())
+                        .map_err(|_| "resume() lost the task's thread")?;
+                    (wake, values)
+                }
+                TaskState::Done => return Ok(Val::Null),
+            };
+            match values.recv() {
+                Ok(Ok(Some(value))) => {
+                    *state = TaskState::Suspended { wake, values };
+                    Ok(value)
+                }
+                Ok(Ok(None)) => Ok(Val::Null),
+                Ok(Err(e)) => Err(e),
+                Err(_) => Err("resume() lost the task's thread".to_string()),
+            }
+        });
+
+        map.insert("done", |_, args| {
+            let task = task_arg("done", args)?;
+            let state = task.state.try_lock();
+            Ok(Val::Bool(matches!(state.as_deref(), Ok(TaskState::Done))))
+        });
+
+        map.insert("par_map", |this, args| {
+            let (items, func) = match <[Val; 2]>::try_from(args) {
+                Ok([Val::Array(items), func]) => (items, func),
+                Ok([other, _]) => {
+                    return Err(format!("par_map() requires an array, got {}", other.repr()));
+                }
+                Err(args) => {
+                    return Err(format!("par_map() takes 2 arguments, got {}", args.len()));
+                }
+            };
+            let template = this.sandbox();
+            items
+                .into_par_iter()
+                .map_init(
+                    || template.sandbox(),
+                    |worker, item| worker.call_value("par_map", func.clone(), vec![item]),
+                )
+                .collect::<Result<Vec<_>, _>>()
+                .map(Val::Array)
+        });
+
+        map.insert("time", |this, args| match <[Val; 1]>::try_from(args) {
+            Ok([func]) if func.is_callable() => {
+                let start = Instant::now();
+                let res = this.call_value("time", func, Vec::new())?;
+                Ok(Val::Array(vec![res, Val::Float(millis(start.elapsed()))]))
+            }
+            Ok([other]) => Err(format!("time() requires a function, got {}", other.repr())),
+            Err(args) => Err(format!("time() takes 1 argument, got {}", args.len())),
+        });
+
+        map.insert("bench", |this, args| {
+            let (func, iters) = match <[Val; 2]>::try_from(args) {
+                Ok([func, Val::Int(iters)]) if func.is_callable() && iters > 0 => (func, iters),
+                Ok([func, _]) if func.is_callable() => {
+                    return Err("bench() requires a positive number of iterations".to_string());
+                }
+                Ok([other, _]) => {
+                    return Err(format!("bench() requires a function, got {}", other.repr()));
+                }
+                Err(args) => {
+                    return Err(format!("bench() takes 2 arguments, got {}", args.len()));
+                }
+            };
+            let (mut min, mut max, mut total) = (f64::INFINITY, 0.0f64, 0.0);
+            for _ in 0..iters {
+                let start = Instant::now();
+                this.call_value("bench", func.clone(), Vec::new())?;
+                let elapsed = millis(start.elapsed());
+                min = min.min(elapsed);
+                max = max.max(elapsed);
+                total += elapsed;
+            }
+            Ok(Val::Map(BTreeMap::from([
+                ("min".to_string(), Val::Float(min)),
+                ("mean".to_string(), Val::Float(total / iters as f64)),
+                ("max".to_string(), Val::Float(max)),
+            ])))
+        });
+
+        map.insert("rng", |this, args| {
+            let seed = match args.as_slice() {
+                [Val::Int(seed)] => *seed as u64,
+                [other] => {
+                    return Err(format!(
+                        "rng() requires an integer seed, got {}",
+                        other.repr()
+                    ));
+                }
+                _ => return Err(format!("rng() takes 1 argument, got {}", args.len())),
+            };
+            // xorshift gets stuck at zero, so swap in an arbitrary constant.
+            let state = if seed == 0 { 0x9e3779b97f4a7c15 } else { seed };
+            Ok(this.resources.insert(Resource::Rng(state)))
+        });
+
+        map.insert("rand", |this, args| {
+            if args.len() != 1 {
+                return Err(format!("rand() takes 1 argument, got {}", args.len()));
+            }
+            match this.resources.get_mut("rand", &args[0])? {
+                Resource::Rng(state) => {
+                    let bits = xorshift(state) >> 11;
+                    Ok(Val::Float(bits as f64 / (1u64 << 53) as f64))
+                }
+            }
+        });
+
+        map.insert("uuid", |this, args| {
+            if args.len() > 1 {
+                return Err(format!(
+                    "uuid() takes at most 1 argument, got {}",
+                    args.len()
+                ));
+            }
+            let state = this.random_state("uuid", args.first())?;
+            let mut bytes = [xorshift(state).to_be_bytes(), xorshift(state).to_be_bytes()].concat();
+            // Version 4 (random), RFC 4122 variant.
+            bytes[6] = bytes[6] & 0x0f | 0x40;
+            bytes[8] = bytes[8] & 0x3f | 0x80;
+            let hex = encoding::hex_encode(&bytes);
+            Ok(Val::Str(format!(
+                "{}-{}-{}-{}-{}",
+                &hex[..8],
+                &hex[8..12],
+                &hex[12..16],
+                &hex[16..20],
+                &hex[20..]
+            )))
+        });
+
+        map.insert("sleep", |this, args| {
+            let ms = match args.as_slice() {
+                [Val::Int(ms)] if *ms >= 0 => *ms as u64,
+                [other] => {
+                    return Err(format!(
+                        "sleep() requires a non-negative integer (milliseconds), got {}",
+                        other.repr()
+                    ));
+                }
+                _ => return Err(format!("sleep() takes 1 argument, got {}", args.len())),
+            };
+            // In slices, so that a timeout or cancellation stops it.
+            let deadline = Instant::now() + Duration::from_millis(ms);
+            loop {
+                if this.cancel.load(Ordering::Relaxed) {
+                    return Err(CANCELLED.to_string());
+                }
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Ok(Val::Unit);
+                }
+                thread::sleep(left.min(WAIT_SLICE));
+            }
+        });
+
+        map.insert("recv", |this, args| match args.as_slice() {
+            [Val::Channel(ch)] => ch.recv(&this.cancel),
+            [other] => Err(format!("recv() requires a channel, got {}", other.repr())),
+            _ => Err(format!("recv() takes 1 argument, got {}", args.len())),
+        });
+
+        map.insert("random_hex", |this, args| {
+            let (n, rng) = match args.as_slice() {
+                [n] => (n, None),
+                [n, rng] => (n, Some(rng)),
+                _ => {
+                    return Err(format!(
+                        "random_hex() takes 1 or 2 arguments, got {}",
+                        args.len()
+                    ));
+                }
+            };
+            let n = count("random_hex", n)?;
+            let state = this.random_state("random_hex", rng)?;
+            let digits =
+                (0..n).map(|_| char::from_digit((xorshift(state) >> 60) as u32, 16).unwrap());
+            Ok(Val::Str(digits.collect()))
+        });
+
+        map.insert("random_string", |this, args| {
+            let (n, charset, rng) = match args.as_slice() {
+                [n, Val::Str(charset)] => (n, charset, None),
+                [n, Val::Str(charset), rng] => (n, charset, Some(rng)),
+                [_, other] | [_, other, _] => {
+                    return Err(format!(
+                        "random_string() requires a charset string, got {}",
+                        other.repr()
+                    ));
+                }
+                _ => {
+                    return Err(format!(
+                        "random_string() takes 2 or 3 arguments, got {}",
+                        args.len()
+                    ));
+                }
+            };
+            let n = count("random_string", n)?;
+            let charset: Vec<char> = charset.chars().collect();
+            if charset.is_empty() {
+                return Err("random_string() requires a non-empty charset".to_string());
+            }
+            let state = this.random_state("random_string", rng)?;
+            let chars = (0..n).map(|_| charset[(xorshift(state) % charset.len() as u64) as usize]);
+            Ok(Val::Str(chars.collect()))
+        });
+
+        #[cfg(feature = "graphics")]
+        {
+            use crate::graphics::{Canvas, parse_color};
+            map.insert("window", |this, args| {
+                let [w, h] = int_args("window", &args)?;
+                if w <= 0 || h <= 0 {
+                    return Err(format!(
+                        "window() requires a positive size, got {}x{}",
+                        w, h
+                    ));
+                }
+                this.canvas = Some(Canvas::new(w as usize, h as usize));
+                print!("\x1b[2J");
+                Ok(Val::Unit)
+            });
+            map.insert("draw_rect", |this, args| {
+                let (color, args) = color_arg("draw_rect", args)?;
+                let [x, y, w, h] = int_args("draw_rect", &args)?;
+                this.canvas_mut("draw_rect")?
+                    .fill_rect(x, y, w, h, parse_color(&color)?);
+                Ok(Val::Unit)
+            });
+            map.insert("draw_circle", |this, args| {
+                let (color, args) = color_arg("draw_circle", args)?;
+                let [x, y, r] = int_args("draw_circle", &args)?;
+                this.canvas_mut("draw_circle")?
+                    .fill_circle(x, y, r, parse_color(&color)?);
+                Ok(Val::Unit)
+            });
+            map.insert("draw_text", |this, args| {
+                let (color, mut args) = color_arg("draw_text", args)?;
+                let text = match args.pop() {
+                    Some(text) => text.to_string(),
+                    None => return Err("draw_text() takes 4 arguments, got 1".to_string()),
+                };
+                let [x, y] = int_args("draw_text", &args)?;
+                this.canvas_mut("draw_text")?
+                    .text(x, y, &text, parse_color(&color)?);
+                Ok(Val::Unit)
+            });
+            map.insert("present", |this, args| {
+                if !args.is_empty() {
+                    return Err(format!("present() takes 0 arguments, got {}", args.len()));
+                }
+                print!("{}", this.canvas_mut("present")?.render());
+                io::stdout()
+                    .flush()
+                    .map_err(|e| format!("present() failed: {}", e))?;
+                Ok(Val::Unit)
+            });
+            map.insert("poll_events", |_, args| {
+                if !args.is_empty() {
+                    return Err(format!(
+                        "poll_events() takes 0 arguments, got {}",
+                        args.len()
+                    ));
+                }
+                let keys = crate::term::pending_keys()?;
+                Ok(Val::Array(keys.into_iter().map(Val::Str).collect()))
+            });
+        }
+        #[cfg(not(feature = "graphics"))]
+        for name in [
+            "window",
+            "draw_rect",
+            "draw_circle",
+            "draw_text",
+            "present",
+            "poll_events",
+        ] {
+            map.insert(name, |_, _| {
+                Err("Graphics need ew to be built with the `graphics` feature".to_string())
+            });
+        }
+
+        map.insert("forward", |this, args| {
+            let distance = number_arg("forward", &args)?;
+            this.turtle.forward(distance)?;
+            Ok(Val::Unit)
+        });
+
+        map.insert("turn", |this, args| {
+            let degrees = number_arg("turn", &args)?;
+            this.turtle.turn(degrees);
+            Ok(Val::Unit)
+        });
+
+        map.insert("pen_up", |this, args| {
+            if !args.is_empty() {
+                return Err(format!("pen_up() takes 0 arguments, got {}", args.len()));
+            }
+            this.turtle.set_pen(false);
+            Ok(Val::Unit)
+        });
+
+        map.insert("pen_down", |this, args| {
+            if !args.is_empty() {
+                return Err(format!("pen_down() takes 0 arguments, got {}", args.len()));
+            }
+            this.turtle.set_pen(true);
+            Ok(Val::Unit)
+        });
+
+        map.insert("save_svg", |this, args| {
+            let path = path_arg("save_svg", &args, 1)?;
+            fs::write(path, this.turtle.to_svg()).map_err(|e| fs_error("save_svg", path, e))?;
+            Ok(Val::Unit)
+        });
+
+        map.insert("log_debug", |this, args| this.log(LogLevel::Debug, args));
+        map.insert("log_info", |this, args| this.log(LogLevel::Info, args));
+        map.insert("log_warn", |this, args| this.log(LogLevel::Warn, args));
+        map.insert("log_error", |this, args| this.log(LogLevel::Error, args));
+
+        map.insert("print", |this, args| {
+            let text: String = args.iter().map(Val::to_string).collect();
+            this.write_output(&text)
+        });
+
+        map.insert("println", |this, args| {
+            let text: Vec<String> = args.iter().map(Val::to_string).collect();
+            this.write_output(&format!("{}\n", text.join(" ")))
+        });
+
+        map.insert("pprint", |this, args| {
+            if args.len() != 1 {
+                return Err(format!("pprint() takes 1 argument, got {}", args.len()));
+            }
+            this.write_output(&format!("{}\n", args[0].pretty(0)))
+        });
+
+        map.insert("load_plugin", |this, args| {
+            let path = path_arg("load_plugin", &args, 1)?;
+            #[cfg(feature = "plugins")]
+            {
+                let names = this.register(crate::plugin::load(path)?)?;
+                Ok(Val::Array(names.into_iter().map(Val::Str).collect()))
+            }
+            #[cfg(not(feature = "plugins"))]
+            {
+                let _ = this;
+                Err(format!(
+                    "Cannot load plugin {}: ew was built without the `plugins` feature",
+                    path
+                ))
+            }
+        });
+
+        map.insert("help", |this, args| {
+            if args.len() != 1 {
+                return Err(format!("help() takes 1 argument, got {}", args.len()));
+            }
+            this.write_output(&format!("{}\n", describe(&args[0])?))
+        });
+
+        map.insert("read_line", |this, args| {
+            if !args.is_empty() {
+                return Err(format!("read_line() takes 0 arguments, got {}", args.len()));
+            }
+            let mut line = String::new();
+            let read = this.input.lock().unwrap().read_line(&mut line);
+            match read.map_err(|e| format!("read_line() failed: {}", e))? {
+                0 => Ok(Val::Null),
+                _ => Ok(Val::Str(strip_newline(line))),
+            }
+        });
+
+        map.insert("read_all_stdin", |this, args| {
+            if !args.is_empty() {
+                return Err(format!(
+                    "read_all_stdin() takes 0 arguments, got {}",
+                    args.len()
+                ));
+            }
+            let mut text = String::new();
+            let read = this.input.lock().unwrap().read_to_string(&mut text);
+            read.map_err(|e| format!("read_all_stdin() failed: {}", e))?;
+            Ok(Val::Str(text))
+        });
+
+        map.insert("lines_stdin", |this, args| {
+            if !args.is_empty() {
+                return Err(format!(
+                    "lines_stdin() takes 0 arguments, got {}",
+                    args.len()
+                ));
+            }
+            let mut input = this.input.lock().unwrap();
+            let lines = (&mut *input).lines().map(|line| {
+                line.map(Val::Str)
+                    .map_err(|e| format!("lines_stdin() failed: {}", e))
+            });
+            Ok(Val::Array(lines.collect::<Result<_, _>>()?))
+        });
+
+        map.insert("close", |this, args| {
+            if args.len() != 1 {
+                return Err(format!("close() takes 1 argument, got {}", args.len()));
+            }
+            this.resources.close(&args[0]).map(Val::Bool)
+        });
+
+        map.insert("sort_by", |this, args| {
+            if args.len() != 2 {
+                return Err(format!("sort_by() takes 2 arguments, got {}", args.len()));
+            }
+            let mut args = args.into_iter();
+            let (Some(Val::Array(items)), Some(cmp)) = (args.next(), args.next()) else {
+                return Err("sort_by() requires an array and a comparison function".to_string());
+            };
+            let sorted = merge_sort(items, &mut |a, b| match this.call_value(
+                "sort_by",
+                cmp.clone(),
+                vec![a.clone(), b.clone()],
+            )? {
+                Val::Int(n) => Ok(n.cmp(&0)),
+                other => Err(format!(
+                    "sort_by() comparison must return -1, 0 or 1, got {}",
+                    other.repr()
+                )),
+            })?;
+            Ok(Val::Array(sorted))
+        });
+
+        map
+    }
+
+    /// The pure builtins, built on first use.
+    fn builtins() -> &'static HashMap<&'static str, Builtin> {
+        static BUILTINS: LazyLock<HashMap<&'static str, Builtin>> =
+            LazyLock::new(Interpreter::make_builtins);
+        &BUILTINS
+    }
+
+    fn make_builtins() -> HashMap<&'static str, Builtin> {
+        let mut map: HashMap<&'static str, Builtin> = HashMap::new();
+
+        map.insert("sin", |args: Vec<Val>| -> Result<Val, String> {
+            if args.len() != 1 {
+                return Err(format!("sin() takes 1 argument, got {}", args.len()));
+            }
+            match &args[0] {
+                Val::Int(n) => Ok(Val::Float((*n as f64).sin())),
+                Val::BigInt(n) => Ok(Val::Float(n.to_f64().unwrap_or(f64::NAN).sin())),
+                Val::Float(f) => Ok(Val::Float(f.sin())),
+                _ => Err(format!("sin() requires a number, got {}", args[0].repr())),
+            }
+        });
+
+        map.insert("cos", |args: Vec<Val>| -> Result<Val, String> {
+            if args.len() != 1 {
+                return Err(format!("cos() takes 1 argument, got {}", args.len()));
+            }
+            match &args[0] {
+                Val::Int(n) => Ok(Val::Float((*n as f64).cos())),
+                Val::BigInt(n) => Ok(Val::Float(n.to_f64().unwrap_or(f64::NAN).cos())),
+                Val::Float(f) => Ok(Val::Float(f.cos())),
+                _ => Err(format!("cos() requires a number, got {}", args[0].repr())),
+            }
+        });
+
+        map.insert("floor", |args: Vec<Val>| -> Result<Val, String> {
+            if args.len() != 1 {
+                return Err(format!("floor() takes 1 argument, got {}", args.len()));
+            }
+            match &args[0] {
+                Val::Int(n) => Ok(Val::Int(*n)),
+                Val::BigInt(n) => Ok(Val::BigInt(n.clone())),
+                Val::Float(f) => Ok(Val::Int(f.floor() as i64)),
+                _ => Err(format!("floor() requires a number, got {}", args[0].repr())),
+            }
+        });
+
+        map.insert("abs", |args: Vec<Val>| -> Result<Val, String> {
+            if args.len() != 1 {
+                return Err(format!("abs() takes 1 argument, got {}", args.len()));
+            }
+            match &args[0] {
+                Val::Int(n) => Ok(n
+                    .checked_abs()
+                    .map(Val::Int)
+                    .unwrap_or_else(|| Val::BigInt(BigInt::from(*n).abs()))),
+                Val::BigInt(n) => Ok(Val::BigInt(n.abs())),
+                Val::Float(f) => Ok(Val::Float(f.abs())),
+                _ => Err(format!("abs() requires a number, got {}", args[0].repr())),
+            }
+        });
+
+        map.insert("sqrt", |args: Vec<Val>| -> Result<Val, String> {
+            if args.len() != 1 {
+                return Err(format!("sqrt() takes 1 argument, got {}", args.len()));
+            }
+            match &args[0] {
+                Val::Int(n) => Ok(Val::Float((*n as f64).sqrt())),
+                Val::BigInt(n) => Ok(Val::Float(n.to_f64().unwrap_or(f64::NAN).sqrt())),
+                Val::Float(f) => Ok(Val::Float(f.sqrt())),
+                _ => Err(format!("sqrt() requires a number, got {}", args[0].repr())),
+            }
+        });
+
+        map.insert("len", |args: Vec<Val>| -> Result<Val, String> {
+            if args.len() != 1 {
+                return Err(format!("len() takes 1 argument, got {}", args.len()));
+            }
+            match &args[0] {
+                Val::Array(arr) => Ok(Val::Int(arr.len() as i64)),
+                Val::Map(map) => Ok(Val::Int(map.len() as i64)),
+                Val::Str(s) => Ok(Val::Int(s.chars().count() as i64)),
+                _ => Err(format!(
+                    "len() requires an array or string, got {}",
+                    args[0].repr()
+                )),
+            }
+        });
+
+        map.insert("clear", |args: Vec<Val>| -> Result<Val, String> {
+            if !args.is_empty() {
+                return Err(format!("clear() takes no arguments, got {}", args.len()));
+            }
+            print!("\x1B[2J\x1B[1;1H");
+            Ok(Val::Unit)
+        });
+
+        map.insert("bool", |args: Vec<Val>| -> Result<Val, String> {
+            if args.len() != 1 {
+                return Err(format!("bool() takes 1 argument, got {}", args.len()));
+            }
+            Ok(Val::Bool(truthy(&args[0])))
+        });
+
+        map.insert("parse_int", |args: Vec<Val>| -> Result<Val, String> {
+            if args.len() != 1 {
+                return Err(format!("parse_int() takes 1 argument, got {}", args.len()));
+            }
+            match &args[0] {
+                Val::Str(s) => Ok(s
+                    .trim()
+                    .parse()
+                    .map(Val::Int)
+                    .or_else(|_| s.trim().parse().map(normalize_big))
+                    .unwrap_or(Val::Null)),
+                _ => Err(format!(
+                    "parse_int() requires a string, got {}",
+                    args[0].repr()
+                )),
+            }
+        });
+
+        map.insert("base64_encode", |args| {
+            convert("base64_encode", args, |s| {
+                Ok(encoding::base64_encode(s.as_bytes()))
+            })
+        });
+        map.insert("base64_decode", |args| {
+            convert("base64_decode", args, encoding::base64_decode)
+        });
+        map.insert("hex_encode", |args| {
+            convert("hex_encode", args, |s| {
+                Ok(encoding::hex_encode(s.as_bytes()))
+            })
+        });
+        map.insert("hex_decode", |args| {
+            convert("hex_decode", args, encoding::hex_decode)
+        });
+        map.insert("url_encode", |args| {
+            convert("url_encode", args, |s| {
+                Ok(encoding::url_encode(s.as_bytes()))
+            })
+        });
+        map.insert("url_decode", |args| {
+            convert("url_decode", args, encoding::url_decode)
+        });
+        map.insert("glob_match", |args| {
+            let (pattern, name) = string_pair("glob_match", args)?;
+            wildcard::glob_match(&pattern, &name).map(Val::Bool)
+        });
+        map.insert("like", |args| {
+            let (s, pattern) = string_pair("like", args)?;
+            Ok(Val::Bool(wildcard::like(&s, &pattern)))
+        });
+
+        #[cfg(feature = "crypto")]
+        {
+            use crate::digest;
+            map.insert("md5", |args| {
+                convert("md5", args, |s| Ok(digest::md5(s.as_bytes())))
+            });
+            map.insert("sha1", |args| {
+                convert("sha1", args, |s| Ok(digest::sha1(s.as_bytes())))
+            });
+            map.insert("sha256", |args| {
+                convert("sha256", args, |s| Ok(digest::sha256(s.as_bytes())))
+            });
+            map.insert("crc32", |args| {
+                convert("crc32", args, |s| Ok(digest::crc32(s.as_bytes())))
+            });
+        }
+        #[cfg(not(feature = "crypto"))]
+        for name in ["md5", "sha1", "sha256", "crc32"] {
+            map.insert(name, |_| {
+                Err("Digests need ew to be built with the `crypto` feature".to_string())
+            });
+        }
+
+        #[cfg(feature = "term")]
+        {
+            use crate::term;
+            map.insert("key_pressed", |args| {
+                if !args.is_empty() {
+                    return Err(format!(
+                        "key_pressed() takes 0 arguments, got {}",
+                        args.len()
+                    ));
+                }
+                term::key_pressed().map(Val::Bool)
+            });
+            map.insert("read_key", |args| {
+                let timeout = match args.as_slice() {
+                    [Val::Int(ms)] if *ms >= 0 => Duration::from_millis(*ms as u64),
+                    [other] => {
+                        return Err(format!(
+                            "read_key() requires a non-negative timeout in milliseconds, got {}",
+                            other.repr()
+                        ));
+                    }
+                    _ => return Err(format!("read_key() takes 1 argument, got {}", args.len())),
+                };
+                Ok(term::read_key(timeout)?.map_or(Val::Null, Val::Str))
+            });
+        }
+        #[cfg(not(feature = "term"))]
+        for name in ["key_pressed", "read_key"] {
+            map.insert(name, |_| {
+                Err("Reading keys needs ew to be built with the `term` feature".to_string())
+            });
+        }
+
+        #[cfg(feature = "image")]
+        {
+            use crate::image;
+            map.insert("image_load", |args| {
+                let path = path_arg("image_load", &args, 1)?;
+                Ok(image_to_val(image::load(path)?))
+            });
+            map.insert("image_save", |args| {
+                let path = path_arg("image_save", &args, 2)?;
+                image::save(path, &val_to_image(&args[1])?)?;
+                Ok(Val::Unit)
+            });
+        }
+        #[cfg(not(feature = "image"))]
+        for name in ["image_load", "image_save"] {
+            map.insert(name, |_| {
+                Err("Images need ew to be built with the `image` feature".to_string())
+            });
+        }
+
+        map.insert("pixel", |args| match args.as_slice() {
+            [Val::Map(img), Val::Int(x), Val::Int(y)] => {
+                let row = match img.get("pixels") {
+                    Some(Val::Array(rows)) => usize::try_from(*y).ok().and_then(|y| rows.get(y)),
+                    _ => return Err("pixel() requires an image from image_load()".to_string()),
+                };
+                match row {
+                    Some(Val::Array(row)) => usize::try_from(*x)
+                        .ok()
+                        .and_then(|x| row.get(x))
+                        .cloned()
+                        .ok_or_else(|| {
+                            format!("pixel() position ({}, {}) is outside the image", x, y)
+                        }),
+                    _ => Err(format!(
+                        "pixel() position ({}, {}) is outside the image",
+                        x, y
+                    )),
+                }
+            }
+            _ if args.len() != 3 => Err(format!("pixel() takes 3 arguments, got {}", args.len())),
+            _ => Err("pixel() requires an image and integer coordinates".to_string()),
+        });
+
+        map.insert("parse_float", |args: Vec<Val>| -> Result<Val, String> {
+            if args.len() != 1 {
+                return Err(format!(
+                    "parse_float() takes 1 argument, got {}",
+                    args.len()
+                ));
+            }
+            match &args[0] {
+                Val::Str(s) => Ok(s.trim().parse().map(Val::Float).unwrap_or(Val::Null)),
+                _ => Err(format!(
+                    "parse_float() requires a string, got {}",
+                    args[0].repr()
+                )),
+            }
+        });
+
+        map.insert("to_fixed", |args: Vec<Val>| -> Result<Val, String> {
+            if args.len() != 2 {
+                return Err(format!("to_fixed() takes 2 arguments, got {}", args.len()));
+            }
+            let digits = digit_count("to_fixed", &args[1])?;
+            match &args[0] {
+                Val::Int(n) => Ok(Val::Str(fixed(*n as f64, digits))),
+                Val::Float(f) => Ok(Val::Str(fixed(*f, digits))),
+                _ => Err(format!(
+                    "to_fixed() requires a number, got {}",
+                    args[0].repr()
+                )),
+            }
+        });
+
+        map.insert("format_number", |args| {
+            if !(2..=3).contains(&args.len()) {
+                return Err(format!(
+                    "format_number() takes 2 or 3 arguments, got {}",
+                    args.len()
+                ));
+            }
+            let decimals = digit_count("format_number", &args[1])?;
+            let separator = match args.get(2) {
+                None => ",",
+                Some(Val::Str(s)) => s,
+                Some(other) => {
+                    return Err(format!(
+                        "format_number() requires a separator string, got {}",
+                        other.repr()
+                    ));
+                }
+            };
+            // Integers are written out exactly rather than through a float.
+            let text = match &args[0] {
+                Val::Int(_) | Val::BigInt(_) if decimals > 0 => {
+                    format!("{}.{}", args[0], "0".repeat(decimals))
+                }
+                Val::Int(_) | Val::BigInt(_) => args[0].to_string(),
+                Val::Float(f) if f.is_finite() => fixed(*f, decimals),
+                Val::Float(_) => return Ok(Val::Str(args[0].to_string())),
+                other => {
+                    return Err(format!(
+                        "format_number() requires a number, got {}",
+                        other.repr()
+                    ));
+                }
+            };
+            let (sign, text) = match text.strip_prefix('-') {
+                Some(rest) => ("-", rest),
+                None => ("", text.as_str()),
+            };
+            let (whole, fraction) = text.split_at(text.find('.').unwrap_or(text.len()));
+            let mut grouped = String::new();
+            for (i, digit) in whole.chars().enumerate() {
+                if i > 0 && (whole.len() - i) % 3 == 0 {
+                    grouped.push_str(separator);
+                }
+                grouped.push(digit);
+            }
+            Ok(Val::Str(format!("{}{}{}", sign, grouped, fraction)))
+        });
+
+        map.insert("to_scientific", |args| {
+            if args.len() != 2 {
+                return Err(format!(
+                    "to_scientific() takes 2 arguments, got {}",
+                    args.len()
+                ));
+            }
+            let digits = digit_count("to_scientific", &args[1])?;
+            let x = match &args[0] {
+                Val::Int(n) => *n as f64,
+                Val::BigInt(n) => n.to_f64().unwrap_or(f64::NAN),
+                Val::Float(f) => *f,
+                other => {
+                    return Err(format!(
+                        "to_scientific() requires a number, got {}",
+                        other.repr()
+                    ));
+                }
+            };
+            Ok(Val::Str(scientific(x, digits)))
+        });
+
+        map.insert("sort", |args| {
+            if args.len() != 1 {
+                return Err(format!("sort() takes 1 argument, got {}", args.len()));
+            }
+            let Some(Val::Array(items)) = args.into_iter().next() else {
+                return Err("sort() requires an array".to_string());
+            };
+            let sorted = merge_sort(items, &mut |a, b| compare("sort", a, b))?;
+            Ok(Val::Array(sorted))
+        });
+
+        map.insert("lower_bound", |args| {
+            let (items, x) = array_and_value("lower_bound", &args)?;
+            let i = partition_point(items, |v| Ok(compare("lower_bound", v, x)?.is_ge()))?;
+            Ok(Val::Int(i as i64))
+        });
+
+        map.insert("upper_bound", |args| {
+            let (items, x) = array_and_value("upper_bound", &args)?;
+            let i = partition_point(items, |v| Ok(compare("upper_bound", v, x)?.is_gt()))?;
+            Ok(Val::Int(i as i64))
+        });
+
+        map.insert("bsearch", |args| {
+            let (items, x) = array_and_value("bsearch", &args)?;
+            let i = partition_point(items, |v| Ok(compare("bsearch", v, x)?.is_ge()))?;
+            match items.get(i) {
+                Some(v) if compare("bsearch", v, x)?.is_eq() => Ok(Val::Int(i as i64)),
+                _ => Ok(Val::Null),
+            }
+        });
+
+        map.insert("is_sorted", |args| {
+            if args.len() != 1 {
+                return Err(format!("is_sorted() takes 1 argument, got {}", args.len()));
+            }
+            let Val::Array(items) = &args[0] else {
+                return Err(format!(
+                    "is_sorted() requires an array, got {}",
+                    args[0].repr()
+                ));
+            };
+            for pair in items.windows(2) {
+                if compare("is_sorted", &pair[0], &pair[1])?.is_gt() {
+                    return Ok(Val::Bool(false));
+                }
+            }
+            Ok(Val::Bool(true))
+        });
+
+        // Arrays are values: assignment and argument passing already copy
+        // them, so `copy` and `deep_copy` agree. Both exist so scripts can
+        // state intent, and keep working if arrays become shared later.
+        map.insert("copy", |args| {
+            if args.len() != 1 {
+                return Err(format!("copy() takes 1 argument, got {}", args.len()));
+            }
+            Ok(args[0].clone())
+        });
+
+        map.insert("deep_copy", |args| {
+            if args.len() != 1 {
+                return Err(format!("deep_copy() takes 1 argument, got {}", args.len()));
+            }
+            Ok(args[0].clone())
+        });
+
+        map.insert("deep_eq", |args| {
+            if args.len() != 2 {
+                return Err(format!("deep_eq() takes 2 arguments, got {}", args.len()));
+            }
+            Ok(Val::Bool(args[0] == args[1]))
+        });
+
+        map.insert("csv_parse", |args| {
+            let (text, header, dialect) = match args.as_slice() {
+                [Val::Str(text)] => (text, false, &args[1..]),
+                [Val::Str(text), Val::Bool(header), dialect @ ..] if dialect.len() <= 2 => {
+                    (text, *header, dialect)
+                }
+                [Val::Str(_), other, ..] if args.len() <= 4 => {
+                    return Err(format!(
+                        "csv_parse() requires header to be a bool, got {}",
+                        other.repr()
+                    ));
+                }
+                [other, ..] if args.len() <= 4 => {
+                    return Err(format!(
+                        "csv_parse() requires a string, got {}",
+                        other.repr()
+                    ));
+                }
+                _ => {
+                    return Err(format!(
+                        "csv_parse() takes 1 to 4 arguments, got {}",
+                        args.len()
+                    ));
+                }
+            };
+            let dialect = csv_dialect("csv_parse", dialect)?;
+            let mut records = csv::parse(text, dialect)?.into_iter();
+            let row = |fields: Vec<String>| Val::Array(fields.into_iter().map(Val::Str).collect());
+            if !header {
+                return Ok(Val::Array(records.map(row).collect()));
+            }
+            let Some(names) = records.next() else {
+                return Ok(Val::Array(vec![]));
+            };
+            // Each name keys a map, so a repeated one would lose a column.
+            if let Some(name) = names
+                .iter()
+                .enumerate()
+                .find_map(|(i, name)| names[..i].contains(name).then_some(name))
+            {
+                return Err(format!("CSV header has the column {:?} twice", name));
+            }
+            let rows = records.enumerate().map(|(i, fields)| {
+                if fields.len() != names.len() {
+                    return Err(format!(
+                        "CSV record {} has {} fields, but the header has {}",
+                        i + 2,
+                        fields.len(),
+                        names.len()
+                    ));
+                }
+                Ok(Val::Map(
+                    names
+                        .iter()
+                        .cloned()
+                        .zip(fields.into_iter().map(Val::Str))
+                        .collect(),
+                ))
+            });
+            Ok(Val::Array(rows.collect::<Result<_, _>>()?))
+        });
+
+        map.insert("csv_stringify", |args| {
+            let (rows, columns, dialect) = match args.as_slice() {
+                [Val::Array(rows), Val::Array(columns), dialect @ ..] if dialect.len() <= 2 => {
+                    (rows, Some(columns), dialect)
+                }
+                [Val::Array(rows), dialect @ ..] if dialect.len() <= 2 => (rows, None, dialect),
+                [other, ..] if args.len() <= 4 && !matches!(other, Val::Array(_)) => {
+                    return Err(format!(
+                        "csv_stringify() requires an array of rows, got {}",
+                        other.repr()
+                    ));
+                }
+                _ => {
+                    return Err(format!(
+                        "csv_stringify() takes 1 to 4 arguments, got {}",
+                        args.len()
+                    ));
+                }
+            };
+            let dialect = csv_dialect("csv_stringify", dialect)?;
+            let columns = columns
+                .map(|columns| {
+                    columns
+                        .iter()
+                        .map(|column| match column {
+                            Val::Str(s) => Ok(s.clone()),
+                            other => Err(format!(
+                                "csv_stringify() columns must be strings, got {}",
+                                other.repr()
+                            )),
+                        })
+                        .collect::<Result<Vec<_>, _>>()
+                })
+                .transpose()?;
+            // Maps are written under a header of the columns, or of every
+            // key in the order the rows first have it.
+            let header = match (rows.first(), &columns) {
+                (Some(Val::Map(_)), None) => {
+                    let mut seen = HashSet::new();
+                    let keys = rows.iter().filter_map(|row| match row {
+                        Val::Map(map) => Some(map.keys()),
+                        _ => None,
+                    });
+                    Some(
+                        keys.flatten()
+                            .filter(|k| seen.insert(*k))
+                            .cloned()
+                            .collect(),
+                    )
+                }
+                (_, columns) => columns.clone(),
+            };
+            let mut records: Vec<Vec<String>> = header.iter().cloned().collect();
+            for row in rows {
+                let fields: Vec<&Val> = match (row, &header) {
+                    (Val::Map(map), Some(header)) => {
+                        if let Some(key) = map.keys().find(|k| !header.contains(k)) {
+                            return Err(format!(
+                                "csv_stringify() row has the key {} that isn't a column",
+                                quote(key)
+                            ));
+                        }
+                        header
+                            .iter()
+                            .map(|k| map.get(k).unwrap_or(&Val::Null))
+                            .collect()
+                    }
+                    (Val::Array(fields), _) if !matches!(rows.first(), Some(Val::Map(_))) => {
+                        fields.iter().collect()
+                    }
+                    (other, _) => {
+                        return Err(format!(
+                            "csv_stringify() rows must all be arrays or all be maps, got {}",
+                            other.repr()
+                        ));
+                    }
+                };
+                records.push(
+                    fields
+                        .into_iter()
+                        .map(csv_field)
+                        .collect::<Result<_, _>>()?,
+                );
+            }
+            Ok(Val::Str(csv::stringify(&records, dialect)))
+        });
+
+        map.insert("mkdir", |args| {
+            let path = path_arg("mkdir", &args, 1)?;
+            fs::create_dir_all(path).map_err(|e| fs_error("mkdir", path, e))?;
+            Ok(Val::Unit)
+        });
+
+        map.insert("remove_file", |args| {
+            let path = path_arg("remove_file", &args, 1)?;
+            fs::remove_file(path).map_err(|e| fs_error("remove_file", path, e))?;
+            Ok(Val::Unit)
+        });
+
+        map.insert("remove_dir", |args| {
+            let path = path_arg("remove_dir", &args, 1)?;
+            fs::remove_dir(path).map_err(|e| fs_error("remove_dir", path, e))?;
+            Ok(Val::Unit)
+        });
+
+        map.insert("copy_file", |args| {
+            let (from, to) = path_pair("copy_file", &args)?;
+            fs::copy(from, to).map_err(|e| fs_error("copy_file", from, e))?;
+            Ok(Val::Unit)
+        });
+
+        map.insert("rename", |args| {
+            let (from, to) = path_pair("rename", &args)?;
+            fs::rename(from, to).map_err(|e| fs_error("rename", from, e))?;
+            Ok(Val::Unit)
+        });
+
+        map.insert("file_size", |args| {
+            let path = path_arg("file_size", &args, 1)?;
+            let meta = fs::metadata(path).map_err(|e| fs_error("file_size", path, e))?;
+            Ok(Val::Int(meta.len() as i64))
+        });
+
+        map.insert("is_dir", |args| {
+            let path = path_arg("is_dir", &args, 1)?;
+            Ok(Val::Bool(fs::metadata(path).is_ok_and(|m| m.is_dir())))
+        });
+
+        map.insert("is_file", |args| {
+            let path = path_arg("is_file", &args, 1)?;
+            Ok(Val::Bool(fs::metadata(path).is_ok_and(|m| m.is_file())))
+        });
+
+        map.insert("modified_time", |args| {
+            let path = path_arg("modified_time", &args, 1)?;
+            let modified = fs::metadata(path)
+                .and_then(|m| m.modified())
+                .map_err(|e| fs_error("modified_time", path, e))?;
+            let secs = match modified.duration_since(UNIX_EPOCH) {
+                Ok(since) => since.as_secs_f64(),
+                Err(before) => -before.duration().as_secs_f64(),
+            };
+            Ok(Val::Float(secs))
+        });
+
+        map.insert("keys", |args| {
+            if args.len() != 1 {
+                return Err(format!("keys() takes 1 argument, got {}", args.len()));
+            }
+            match &args[0] {
+                Val::Map(map) => Ok(Val::Array(map.keys().cloned().map(Val::Str).collect())),
+                _ => Err(format!("keys() requires a map, got {}", args[0].repr())),
+            }
+        });
+
+        map.insert("freeze", |args| {
+            let origin = match args.get(1) {
+                None => "freeze()".to_string(),
+                Some(Val::Str(origin)) => origin.clone(),
+                Some(other) => {
+                    return Err(format!(
+                        "freeze() requires a string origin, got {}",
+                        other.repr()
+                    ));
+                }
+            };
+            if args.is_empty() || args.len() > 2 {
+                return Err(format!(
+                    "freeze() takes 1 or 2 arguments, got {}",
+                    args.len()
+                ));
+            }
+            Ok(match args.into_iter().next().unwrap() {
+                frozen @ Val::Frozen { .. } => frozen,
+                val => Val::frozen(val, &origin),
+            })
+        });
+
+        map.insert("is_frozen", |args| {
+            if args.len() != 1 {
+                return Err(format!("is_frozen() takes 1 argument, got {}", args.len()));
+            }
+            Ok(Val::Bool(matches!(args[0], Val::Frozen { .. })))
+        });
+
+        map.insert("hash", |args| {
+            if args.len() != 1 {
+                return Err(format!("hash() takes 1 argument, got {}", args.len()));
+            }
+            let mut state = 0xcbf29ce484222325;
+            stable_hash(&args[0], &mut state)?;
+            Ok(Val::Int(state as i64))
+        });
+
+        map.insert("channel", |args| {
+            if !args.is_empty() {
+                return Err(format!("channel() takes 0 arguments, got {}", args.len()));
+            }
+            Ok(Val::Channel(Arc::new(Channel::new())))
+        });
+
+        map.insert("send", |args| match args.as_slice() {
+            [Val::Channel(ch), val] if ch.would_hold_itself(val) => {
+                Err("send() can't put a channel on itself".to_string())
+            }
+            // A task receiving itself would keep its own thread alive.
+            [Val::Channel(_), val] if holds_task(val) => {
+                Err("send() can't carry a task".to_string())
+            }
+            [Val::Channel(ch), val] => {
+                ch.send(val.clone());
+                Ok(Val::Unit)
+            }
+            [other, _] => Err(format!("send() requires a channel, got {}", other.repr())),
+            _ => Err(format!("send() takes 2 arguments, got {}", args.len())),
+        });
+
+        map.insert("try_recv", |args| match args.as_slice() {
+            [Val::Channel(ch)] => Ok(ch.try_recv().unwrap_or(Val::Null)),
+            [other] => Err(format!(
+                "try_recv() requires a channel, got {}",
+                other.repr()
+            )),
+            _ => Err(format!("try_recv() takes 1 argument, got {}", args.len())),
+        });
+
+        map.insert("source", |args| match args.as_slice() {
+            [Val::Builtin(name)] => Err(format!("{} is a builtin and has no source", name)),
+            [func] => func
+                .definition()
+                .map(|def| Val::Str(format_stmt(&def)))
+                .ok_or_else(|| format!("source() requires a function, got {}", func.repr())),
+            _ => Err(format!("source() takes 1 argument, got {}", args.len())),
+        });
+
+        map.insert("divmod", |args| {
+            if args.len() != 2 {
+                return Err(format!("divmod() takes 2 arguments, got {}", args.len()));
+            }
+            match (&args[0], &args[1]) {
+                (Val::Int(_), Val::Int(0)) => Err("Division by zero".to_string()),
+                (Val::Int(a), Val::Int(b)) => {
+                    // i128 can't overflow here, even for i64::MIN / -1.
+                    let (a, b) = (*a as i128, *b as i128);
+                    let (mut q, mut r) = (a / b, a % b);
+                    if r != 0 && (r < 0) != (b < 0) {
+                        q -= 1;
+                        r += b;
+                    }
+                    let int = |n: i128| {
+                        i64::try_from(n)
+                            .map(Val::Int)
+                            .unwrap_or_else(|_| Val::BigInt(BigInt::from(n)))
+                    };
+                    Ok(Val::Array(vec![int(q), int(r)]))
+                }
+                (Val::Float(a), Val::Float(b)) => {
+                    let q = (a / b).floor();
+                    Ok(Val::Array(vec![Val::Float(q), Val::Float(a - b * q)]))
+                }
+                _ => Err(format!(
+                    "divmod() requires two integers or two floats, got {} and {}",
+                    args[0].repr(),
+                    args[1].repr()
+                )),
+            }
+        });
+
+        map.insert("round", |args| {
+            if args.len() != 2 {
+                return Err(format!("round() takes 2 arguments, got {}", args.len()));
+            }
+            let Val::Int(digits) = args[1] else {
+                return Err(format!(
+                    "round() requires an integer digit count, got {}",
+                    args[1].repr()
+                ));
+            };
+            match &args[0] {
+                Val::Int(_) | Val::BigInt(_) if digits >= 0 => Ok(args[0].clone()),
+                Val::Int(_) | Val::BigInt(_) | Val::Float(_) => {
+                    let x = as_f64(&args[0]).unwrap_or(f64::NAN);
+                    Ok(Val::Float(round_to(x, digits)))
+                }
+                _ => Err(format!("round() requires a number, got {}", args[0].repr())),
+            }
+        });
+
+        map.insert("approx_eq", |args| {
+            if args.len() != 3 {
+                return Err(format!("approx_eq() takes 3 arguments, got {}", args.len()));
+            }
+            match (as_f64(&args[0]), as_f64(&args[1]), as_f64(&args[2])) {
+                (Some(a), Some(b), Some(eps)) => Ok(Val::Bool(a == b || (a - b).abs() <= eps)),
+                _ => Err(format!(
+                    "approx_eq() requires numbers, got {}, {} and {}",
+                    args[0].repr(),
+                    args[1].repr(),
+                    args[2].repr()
+                )),
+            }
+        });
+
+        map.insert("is_nan", |args| {
+            if args.len() != 1 {
+                return Err(format!("is_nan() takes 1 argument, got {}", args.len()));
+            }
+            match as_f64(&args[0]) {
+                Some(x) => Ok(Val::Bool(x.is_nan())),
+                None => Err(format!(
+                    "is_nan() requires a number, got {}",
+                    args[0].repr()
+                )),
+            }
+        });
+
+        map.insert("is_inf", |args| {
+            if args.len() != 1 {
+                return Err(format!("is_inf() takes 1 argument, got {}", args.len()));
+            }
+            match &args[0] {
+                Val::Float(x) => Ok(Val::Bool(x.is_infinite())),
+                Val::Int(_) | Val::BigInt(_) => Ok(Val::Bool(false)),
+                _ => Err(format!(
+                    "is_inf() requires a number, got {}",
+                    args[0].repr()
+                )),
+            }
+        });
+
+        map.insert("repr", |args| {
+            if args.len() != 1 {
+                return Err(format!("repr() takes 1 argument, got {}", args.len()));
+            }
+            Ok(Val::Str(args[0].repr()))
+        });
+
+        map.insert("str", |args| {
+            if args.len() != 1 {
+                return Err(format!("str() takes 1 argument, got {}", args.len()));
+            }
+            Ok(Val::Str(args[0].to_string()))
+        });
+
+        map.insert("memoize", |args| {
+            if args.len() != 1 {
+                return Err(format!("memoize() takes 1 argument, got {}", args.len()));
+            }
+            decorate(
+                "memoize",
+                Decorator::Memoize(NEXT_MEMO_ID.fetch_add(1, Ordering::Relaxed)),
+                args,
+            )
+        });
+
+        map.insert("timed", |args| {
+            if args.len() != 1 {
+                return Err(format!("timed() takes 1 argument, got {}", args.len()));
+            }
+            decorate("timed", Decorator::Timed, args)
+        });
+
+        map.insert("bind", |args| {
+            let mut args = args.into_iter();
+            match args.next() {
+                // Binding more arguments extends the list instead of nesting.
+                Some(Val::Bound { func, args: bound }) => Ok(Val::Bound {
+                    func,
+                    args: bound.into_iter().chain(args).collect(),
+                }),
+                Some(func) if func.is_callable() => Ok(Val::Bound {
+                    func: Box::new(func),
+                    args: args.collect(),
+                }),
+                other => Err(format!(
+                    "bind() requires a function, got {}",
+                    other.unwrap_or(Val::Unit).repr()
+                )),
+            }
+        });
+
+        map.insert("compose", |args| {
+            if args.len() != 2 {
+                return Err(format!("compose() takes 2 arguments, got {}", args.len()));
+            }
+            composed("compose", args.into_iter().rev().collect())
+        });
+
+        map.insert("pipe", |args| composed("pipe", args));
+
+        map.insert("curry", |args| match <[Val; 1]>::try_from(args) {
+            Ok([func]) if func.params().is_some() => Ok(Val::Curried {
+                func: Box::new(func),
+                args: vec![],
+            }),
+            Ok([other]) => Err(format!(
+                "curry() requires a function with named parameters, got {}",
+                other
+            )),
+            Err(args) => Err(format!("curry() takes 1 argument, got {}", args.len())),
+        });
+
+        map.insert("matrix", |args| {
+            if args.len() != 3 {
+                return Err(format!("matrix() takes 3 arguments, got {}", args.len()));
+            }
+            let (rows, cols) = match (&args[0], &args[1]) {
+                (Val::Int(r), Val::Int(c)) if *r >= 0 && *c >= 0 => (*r as usize, *c as usize),
+                _ => {
+                    return Err(format!(
+                        "matrix() requires non-negative row and column counts, got {} and {}",
+                        args[0].repr(),
+                        args[1].repr()
+                    ));
+                }
+            };
+            let row = Val::Array(vec![args[2].clone(); cols]);
+            Ok(Val::Array(vec![row; rows]))
+        });
+
+        map.insert("transpose", |args| {
+            if args.len() != 1 {
+                return Err(format!("transpose() takes 1 argument, got {}", args.len()));
+            }
+            let rows = matrix_rows("transpose", &args[0])?;
+            let cols = rows.first().map_or(0, |r| r.len());
+            Ok(Val::Array(
+                (0..cols)
+                    .map(|j| Val::Array(rows.iter().map(|r| r[j].clone()).collect()))
+                    .collect(),
+            ))
+        });
+
+        map.insert("matmul", |args| {
+            if args.len() != 2 {
+                return Err(format!("matmul() takes 2 arguments, got {}", args.len()));
+            }
+            let a = matrix_rows("matmul", &args[0])?;
+            let b = matrix_rows("matmul", &args[1])?;
+            let inner = a.first().map_or(0, |r| r.len());
+            if inner != b.len() {
+                return Err(format!(
+                    "matmul() dimension mismatch: {}x{} times {}x{}",
+                    a.len(),
+                    inner,
+                    b.len(),
+                    b.first().map_or(0, |r| r.len())
+                ));
+            }
+            let cols = b.first().map_or(0, |r| r.len());
+            let b_cols: Vec<Vec<Val>> = (0..cols)
+                .map(|j| b.iter().map(|r| r[j].clone()).collect())
+                .collect();
+            a.iter()
+                .map(|row| {
+                    b_cols
+                        .iter()
+                        .map(|col| dot_product(row, col))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map(Val::Array)
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .map(Val::Array)
+        });
+
+        map.insert("dot", |args| {
+            if args.len() != 2 {
+                return Err(format!("dot() takes 2 arguments, got {}", args.len()));
+            }
+            match (&args[0], &args[1]) {
+                (Val::Array(a), Val::Array(b)) if a.len() == b.len() => dot_product(a, b),
+                _ => Err(format!(
+                    "dot() requires two arrays of equal length, got {} and {}",
+                    args[0].repr(),
+                    args[1].repr()
+                )),
+            }
+        });
+
+        map
+    }
+}
+
+impl Default for Interpreter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Frame {
+    pub fn new() -> Frame {
+        Frame {
+            local: HashMap::new(),
+            parent: None,
+        }
+    }
+
+    fn child(parent: usize) -> Frame {
+        Frame {
+            local: HashMap::new(),
+            parent: Some(parent),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn run(source: &str) -> Result<Val, String> {
+        let program = parse(source)?;
+        // Unoptimized builds use several KB of stack per interpreted call,
+        // so give the recursive programs below the room of a main thread
+        // rather than a test thread's 2 MB.
+        std::thread::scope(|scope| {
+            std::thread::Builder::new()
+                .stack_size(8 << 20)
+                .spawn_scoped(scope, || Interpreter::new().run(&program))
+                .unwrap()
+                .join()
+                .unwrap()
+        })
+    }
+
+    #[test]
+    fn test_literal() {
+        assert_eq!(run("42").unwrap(), Val::Int(42));
+        assert_eq!(run("true").unwrap(), Val::Bool(true));
+        assert_eq!(run("false").unwrap(), Val::Bool(false));
+    }
+
+    #[test]
+    fn test_arithmetic() {
+        assert_eq!(run("1 + 2").unwrap(), Val::Int(3));
+        assert_eq!(run("10 - 3").unwrap(), Val::Int(7));
+        assert_eq!(run("4 * 5").unwrap(), Val::Int(20));
+        assert_eq!(run("15 / 3").unwrap(), Val::Int(5));
+        assert_eq!(run("17 % 5").unwrap(), Val::Int(2));
+    }
+
+    #[test]
+    fn test_integer_promotion() {
+        assert_eq!(
+            run("9223372036854775807 + 1").unwrap().to_string(),
+            "9223372036854775808"
+        );
+        assert_eq!(
+            run("4611686018427387904 * 4 / 4").unwrap(),
+            Val::Int(4611686018427387904)
+        );
+        assert_eq!(run("-9223372036854775808").unwrap(), Val::Int(i64::MIN));
+        assert_eq!(
+            run("99999999999999999999 > 9223372036854775807").unwrap(),
+            Val::Bool(true)
+        );
+        assert!(run("99999999999999999999 % 0").is_err());
+        assert_eq!(
+            run(r#"parse_int("99999999999999999999")"#)
+                .unwrap()
+                .to_string(),
+            "99999999999999999999"
+        );
+    }
+
+    #[test]
+    fn test_bigint_factorial() {
+        let source = r#"
+            fn factorial(n) {
+                if (n <= 1) {
+                    return 1
+                } else {
+                    return n * factorial(n - 1)
+                }
+            }
+            factorial(30)
+        "#;
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            "265252859812191058636308480000000"
+        );
+    }
+
+    #[test]
+    fn test_logic_precedence() {
+        assert_eq!(run("1 < 2 && 3 < 4").unwrap(), Val::Bool(true));
+        assert_eq!(run("false && true || true").unwrap(), Val::Bool(true));
+        assert_eq!(run("!(true && false)").unwrap(), Val::Bool(true));
+    }
+
+    #[test]
+    fn test_power() {
+        assert_eq!(run("2 ** 10").unwrap(), Val::Int(1024));
+        assert_eq!(run("2 ** 3 ** 2").unwrap(), Val::Int(512));
+        assert_eq!(run("-2 ** 2").unwrap(), Val::Int(4));
+        assert_eq!(run("2 ** -1").unwrap(), Val::Float(0.5));
+        assert_eq!(run("2.0 ** 0.5").unwrap(), Val::Float(2f64.sqrt()));
+        assert_eq!(run("2 ** 64").unwrap().to_string(), "18446744073709551616");
+    }
+
+    #[test]
+    fn test_string_repeat() {
+        assert_eq!(run(r#""ab" * 3"#).unwrap(), Val::Str("ababab".to_string()));
+        assert!(run(r#""ab" * -1"#).is_err());
+    }
+
+    #[test]
+    fn test_comparison() {
+        assert_eq!(run("1 < 2").unwrap(), Val::Bool(true));
+        assert_eq!(run("2 > 1").unwrap(), Val::Bool(true));
+        assert_eq!(run("1 == 1").unwrap(), Val::Bool(true));
+        assert_eq!(run("1 != 2").unwrap(), Val::Bool(true));
+    }
+
+    #[test]
+    fn test_variables() {
+        assert_eq!(run("let x = 42\nx").unwrap(), Val::Int(42));
+    }
+
+    #[test]
+    fn test_function() {
+        let source = r#"
+            fn add(a, b) {
+                return a + b
+            }
+            add(3, 4)
+        "#;
+        assert_eq!(run(source).unwrap(), Val::Int(7));
+    }
+
+    #[test]
+    fn test_conditional() {
+        let source = r#"
+            if (true) {
+                42
+            } else {
+                0
+            }
+        "#;
+        assert_eq!(run(source).unwrap(), Val::Int(42));
+
+        let source = r#"
+            if (false) {
+                42
+            } else {
+                0
+            }
+        "#;
+        assert_eq!(run(source).unwrap(), Val::Int(0));
+    }
+
+    #[test]
+    fn test_else_if_chain() {
+        let source = r#"
+            fn grade(n) {
+                if (n >= 90) {
+                    return "A"
+                } else if (n >= 80) {
+                    return "B"
+                } else if (n >= 70) {
+                    return "C"
+                } else {
+                    return "F"
+                }
+            }
+            [grade(95), grade(85), grade(75), grade(10)]
+        "#;
+        assert_eq!(run(source).unwrap().to_string(), r#"["A", "B", "C", "F"]"#);
+    }
+
+    #[test]
+    fn test_truthiness() {
+        assert_eq!(
+            run(r#"[bool(0), bool(2), bool(""), bool("x"), bool([]), bool(null)]"#)
+                .unwrap()
+                .to_string(),
+            "[false, true, false, true, false, false]"
+        );
+
+        let source = "let n = 0\nif (len([1])) { n = 1 }\nwhile (3 - n) { n = n + 1 }\nn";
+        let err = run(source).unwrap_err();
+        assert!(err.contains("bool("), "{}", err);
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_truthiness(Truthiness::Loose);
+        assert_eq!(
+            interpreter.run(&parse(source).unwrap()).unwrap(),
+            Val::Int(3)
+        );
+    }
+
+    #[test]
+    fn test_condition_errors_name_the_condition() {
+        let err = run("let xs = [1]\nwhile (len(xs)) {\n  xs = []\n}").unwrap_err();
+        assert_eq!(
+            err,
+            "While condition `len(xs)` at 2:8-2:15 must be a Boolean, got 1 \
+             (use bool(...) to test truthiness)"
+        );
+        let err = run("if (1) { 2 }").unwrap_err();
+        assert!(
+            err.starts_with("If condition `1` at 1:5-1:6 must be"),
+            "{}",
+            err
+        );
+        let err = run("[x for x in [1] if x + 1]").unwrap_err();
+        assert!(
+            err.starts_with("Comprehension condition `x + 1` at 1:20-1:25 must be"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn test_warnings() {
+        let mut interpreter = Interpreter::builder()
+            .deprecate("sqrt", "use x ** 0.5")
+            .truthiness(Truthiness::Loose)
+            .build();
+        let program =
+            parse("let n = 1\nfor i in 0..3 { let n = [n, sqrt(4.0)] }\nif (n) { 1 }").unwrap();
+        interpreter.check(&program);
+        interpreter.run(&program).unwrap();
+        let kinds: Vec<_> = interpreter
+            .diagnostics()
+            .warnings()
+            .iter()
+            .map(|warning| warning.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            [
+                WarningKind::Shadowing,
+                WarningKind::Deprecated,
+                WarningKind::Conversion
+            ]
+        );
+        let messages: Vec<_> = interpreter
+            .diagnostics_mut()
+            .take()
+            .into_iter()
+            .map(|warning| warning.message)
+            .collect();
+        assert_eq!(messages[1], "sqrt() is deprecated; use x ** 0.5");
+        assert_eq!(messages[2], "If condition `n` converted from Int to Bool");
+
+        interpreter
+            .diagnostics_mut()
+            .set_enabled(WarningKind::Deprecated, false);
+        interpreter.run(&parse("sqrt(9.0)").unwrap()).unwrap();
+        assert!(interpreter.diagnostics().warnings().is_empty());
+    }
+
+    #[test]
+    fn test_native_builtins() {
+        let mut registry = Registry::new();
+        registry.add("double", |args| match args.as_slice() {
+            [Val::Int(n)] => Ok(Val::Int(n * 2)),
+            _ => Err("double() takes an integer".to_string()),
+        });
+        let mut interpreter = Interpreter::new();
+        assert_eq!(interpreter.register(registry).unwrap(), ["double"]);
+        let program = parse("[double(2), par_map([1, 2], double)]").unwrap();
+        assert_eq!(
+            interpreter.run(&program).unwrap().to_string(),
+            "[4, [2, 4]]"
+        );
+        assert!(interpreter.run(&parse("double(\"x\")").unwrap()).is_err());
+
+        let mut clash = Registry::new();
+        clash.add("len", |_| Ok(Val::Unit));
+        assert_eq!(
+            interpreter.register(clash).unwrap_err(),
+            "len() is already a builtin"
+        );
+
+        #[cfg(not(feature = "plugins"))]
+        assert!(
+            run("load_plugin(\"libfoo.so\")")
+                .unwrap_err()
+                .contains("without the `plugins` feature")
+        );
+    }
+
+    #[test]
+    fn test_pragmas() {
+        let mut interpreter = Interpreter::new();
+        assert_eq!(interpreter.version(), "0.1");
+        let program = parse("#pragma version 0.2\n7 / 2").unwrap();
+        assert_eq!(interpreter.run(&program).unwrap(), Val::Float(3.5));
+        assert_eq!(interpreter.version(), "0.2");
+        assert!(interpreter.has_feature(Feature::FloatDivision));
+        assert!(!interpreter.has_feature(Feature::StrictTypes));
+
+        assert_eq!(run("7 / 2").unwrap(), Val::Int(3));
+        let err = run("#feature(strict_types)\n1 == \"1\"").unwrap_err();
+        assert!(err.contains("strict type checking"), "{}", err);
+        assert!(run("#pragma version 0.1\n#feature(strict_types)\nif (1) { 2 }").is_err());
+    }
+
+    #[test]
+    fn test_type_checking() {
+        // By default only null compares with other types.
+        assert!(run(r#"1 == "1""#).is_err());
+        assert!(run("2 == 2.0").is_err());
+        assert_eq!(
+            run("[[1] == null, null != 0]").unwrap().to_string(),
+            "[false, true]"
+        );
+
+        let mut interpreter = Interpreter::builder()
+            .type_checking(TypeChecking::Strict)
+            .truthiness(Truthiness::Loose)
+            .build();
+        let err = interpreter.run(&parse(r#"1 == "1""#).unwrap()).unwrap_err();
+        assert_eq!(
+            err,
+            "Cannot compare Int with Str using '==' under strict type checking"
+        );
+        assert!(interpreter.run(&parse("2 != 2.0").unwrap()).is_err());
+        assert_eq!(
+            interpreter.run(&parse("[1] == null").unwrap()).unwrap(),
+            Val::Bool(false)
+        );
+        let err = interpreter
+            .run(&parse("if (1) { 2 }").unwrap())
+            .unwrap_err();
+        assert!(err.contains("must be a Boolean"), "{}", err);
+    }
+
+    #[test]
+    fn test_while_loop() {
+        let source = r#"
+            let x = 0
+            while (x < 5) {
+                x = x + 1
+            }
+            x
+        "#;
+        assert_eq!(run(source).unwrap(), Val::Int(5));
+    }
+
+    #[test]
+    fn test_factorial_iterative() {
+        let source = r#"
+            fn factorial(n) {
+                let result = 1
+                while (n > 1) {
+                    result = result * n
+                    n = n - 1
+                }
+                return result
+            }
+            factorial(5)
+        "#;
+        assert_eq!(run(source).unwrap(), Val::Int(120));
+    }
+
+    #[test]
+    fn test_factorial_recursive() {
+        let source = r#"
+            fn factorial(n) {
+                if (n <= 1) {
+                    return 1
+                } else {
+                    return n * factorial(n - 1)
+                }
+            }
+            factorial(5)
+        "#;
+        assert_eq!(run(source).unwrap(), Val::Int(120));
+    }
+
+    #[test]
+    fn test_fibonacci_recursive() {
+        let source = r#"
+            fn fib(n) {
+                if (n < 2) {
+                    return n
+                } else {
+                    return fib(n - 1) + fib(n - 2)
+                }
+            }
+            fib(10)
+        "#;
+        assert_eq!(run(source).unwrap(), Val::Int(55));
+    }
+
+    #[test]
+    fn test_fibonacci_iterative() {
+        let source = r#"
+            fn fib(n) {
+                if (n < 2) {
+                    return n
+                } else {
+                    let a = 0
+                    let b = 1
+                    let i = 2
+                    let temp = 0
+                    while (i <= n) {
+                        temp = a + b
+                        a = b
+                        b = temp
+                        i = i + 1
+                    }
+                    return b
+                }
+            }
+            fib(10)
+        "#;
+        assert_eq!(run(source).unwrap(), Val::Int(55));
+    }
+
+    #[test]
+    fn test_nested_calls() {
+        let source = r#"
+            fn double(x) {
+                return x * 2
+            }
+            fn quadruple(x) {
+                return double(double(x))
+            }
+            quadruple(5)
+        "#;
+        assert_eq!(run(source).unwrap(), Val::Int(20));
+    }
+
+    #[test]
+    fn test_parse_builtins() {
+        assert_eq!(run(r#"parse_int(" 42 ")"#).unwrap(), Val::Int(42));
+        assert_eq!(run(r#"parse_int("4x2")"#).unwrap(), Val::Null);
+        assert_eq!(run(r#"parse_float("2.5")"#).unwrap(), Val::Float(2.5));
+        assert_eq!(
+            run(r#"parse_float("abc") == null"#).unwrap(),
+            Val::Bool(true)
+        );
+        assert_eq!(
+            run("to_fixed(3.14159, 2)").unwrap(),
+            Val::Str("3.14".to_string())
+        );
+        assert!(run("to_fixed(1.0, -1)").is_err());
+        assert_eq!(
+            run("to_fixed(1.5, 99999999999)").unwrap_err(),
+            "to_fixed() can show at most 100 digits, got 99999999999"
+        );
+        let Val::Str(s) = run("to_fixed(1.5, 100)").unwrap() else {
+            panic!("to_fixed() should give a string");
+        };
+        assert_eq!(s.len(), 102);
+    }
+
+    #[test]
+    fn test_number_formatting() {
+        let source = r#"
+            [format_number(1234567, 0), format_number(-1234567.891, 2, " "),
+             format_number(999, 1, ","), format_number(12345678901234567890, 0, "_"),
+             format_number(0.5, 0, ","), to_scientific(12345, 2), to_scientific(0.00042, 1)]
+        "#;
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            r#"["1,234,567", "-1 234 567.89", "999.0", "12_345_678_901_234_567_890", "1", "1.23e4", "4.2e-4"]"#
+        );
+        // Halves go away from zero, as with round(), in every formatter;
+        // 1.005 is a little under halfway as a float, so it goes down.
+        let source = r#"
+            [format_number(2.5, 0), format_number(-2.5, 0), format_number(1234.125, 2),
+             round(2.5, 0), round(-2.5, 0), format_number(10.0 ** 20, 1),
+             to_fixed(2.5, 0), to_fixed(0.125, 2), to_fixed(-0.125, 2), to_fixed(1.005, 2),
+             to_scientific(125, 1), to_scientific(-0.125, 1), to_scientific(0.5, 0)]
+        "#;
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            r#"["3", "-3", "1,234.13", 3, -3, "100,000,000,000,000,000,000.0", "3", "0.13", "-0.13", "1.00", "1.3e2", "-1.3e-1", "5e-1"]"#
+        );
+        assert_eq!(
+            run("format_number(1.5, 65536)").unwrap_err(),
+            "format_number() can show at most 100 digits, got 65536"
+        );
+        assert_eq!(
+            run("to_scientific(1.5, 65536)").unwrap_err(),
+            "to_scientific() can show at most 100 digits, got 65536"
+        );
+        assert!(run("format_number(1, -1)").is_err());
+        assert!(run("format_number(\"1\", 0)").is_err());
+    }
+
+    #[test]
+    fn test_block_scoping() {
+        let source = r#"
+            if (true) {
+                let inner = 1
+            }
+            inner
+        "#;
+        assert!(run(source).is_err());
+
+        let source = r#"
+            let total = 0
+            let i = 0
+            while (i < 3) {
+                let step = i * 2
+                total = total + step
+                i = i + 1
+            }
+            total
+        "#;
+        assert_eq!(run(source).unwrap(), Val::Int(6));
+
+        let source = r#"
+            let total = 0
+            for i in 0..3 {
+                let sq = i * i
+                total = total + sq
+            }
+            total
+        "#;
+        assert_eq!(run(source).unwrap(), Val::Int(5));
+    }
+
+    #[test]
+    fn test_block_shadowing() {
+        let source = r#"
+            let x = 1
+            let seen = 0
+            {
+                let x = 2
+                seen = x
+            }
+            [x, seen]
+        "#;
+        assert_eq!(
+            run(source).unwrap(),
+            Val::Array(vec![Val::Int(1), Val::Int(2)])
+        );
+
+        let source = r#"
+            let x = 1
+            if (true) {
+                x = 5
+            }
+            x
+        "#;
+        assert_eq!(run(source).unwrap(), Val::Int(5));
+    }
+
+    #[test]
+    fn test_loop_break() {
+        let source = r#"
+            let i = 0
+            let found = loop {
+                i = i + 1
+                if (i * i > 50) {
+                    break i
+                }
+            }
+            found
+        "#;
+        assert_eq!(run(source).unwrap(), Val::Int(8));
+
+        let source = r#"
+            let n = 0
+            while (true) {
+                n = n + 1
+                if (n == 3) { break }
+            }
+            for i in 0..10 {
+                if (i == 4) { break }
+                n = n + i
+            }
+            n
+        "#;
+        assert_eq!(run(source).unwrap(), Val::Int(9));
+
+        let source = r#"
+            fn first_even(arr) {
+                loop {
+                    return arr[0]
+                }
+            }
+            first_even([4])
+        "#;
+        assert_eq!(run(source).unwrap(), Val::Int(4));
+
+        assert!(run("break 1").is_err());
+        assert!(run("fn f() { break }\nloop { f() }").is_err());
+    }
+
+    #[test]
+    fn test_sorting() {
+        assert_eq!(
+            run(r#"[sort([3, 1, 99999999999999999999, -2]), sort(["b", "a"]), sort([2.5, 1.0]), sort([2.5, 1, -0.5]), sort([])]"#)
+                .unwrap()
+                .to_string(),
+            r#"[[-2, 1, 3, 99999999999999999999], ["a", "b"], [1, 2.5], [-0.5, 1, 2.5], []]"#
+        );
+        let err = run(r#"sort([1, "a"])"#).unwrap_err();
+        assert_eq!(err, "sort() cannot compare Int with Str");
+        assert!(run("sort([1.0, NAN])").is_err());
+
+        // Stable: pairs with equal keys keep their original order.
+        let source = r#"
+            fn by_first(a, b) {
+                if (a[0] < b[0]) { -1 } else if (a[0] > b[0]) { 1 } else { 0 }
+            }
+            sort_by([[2, "x"], [1, "y"], [2, "a"], [1, "b"]], by_first)
+        "#;
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            r#"[[1, "y"], [1, "b"], [2, "x"], [2, "a"]]"#
+        );
+        assert!(run("fn bad(a, b) { true }\nsort_by([1, 2], bad)").is_err());
+        assert!(run("let f = sort_by\nf([2, 1], sort)").is_err());
+    }
+
+    #[test]
+    fn test_function_display() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_source_name("examples/fib.ew");
+        let source = "\nfn fib(n) { n }\nlet f = fib\nf";
+        let fib = interpreter.run(&parse(source).unwrap()).unwrap();
+        assert_eq!(fib.to_string(), "<fn fib(n) at examples/fib.ew:2>");
+        let err = interpreter.run(&parse("f(1, 2)").unwrap()).unwrap_err();
+        assert_eq!(
+            err,
+            "Function f (defined at examples/fib.ew:2) expects 1 arguments, got 2"
+        );
+        assert_eq!(
+            run("fn add(a, b) { a + b } [add, sqrt]")
+                .unwrap()
+                .to_string(),
+            "[<fn add(a, b)>, <builtin sqrt>]"
+        );
+    }
+
+    #[test]
+    fn test_source() {
+        let source = r#"
+            @memoize
+            fn add(a, b) { a + b }
+            source(add)
+        "#;
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            "@memoize\nfn add(a, b) {\n    a + b\n}"
+        );
+        let add = run("fn add(a, b) { a + b } add").unwrap();
+        assert_eq!(format!("{}", add), "<fn add(a, b)>");
+        assert_eq!(format!("{:#}", add), "fn add(a, b) {\n    a + b\n}");
+        assert!(run("source(sqrt)").unwrap_err().contains("builtin"));
+        assert!(run("source(1)").is_err());
+    }
+
+    #[test]
+    fn test_handles() {
+        let source = r#"
+            let a = rng(7)
+            let b = rng(7)
+            let x = rand(a)
+            [x == rand(b), rand(a) != x, 0.0 <= x < 1.0, a]
+        "#;
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            "[true, true, true, <Rng #0>]"
+        );
+
+        let mut interpreter = Interpreter::new();
+        let source = "let r = rng(0) let s = rng(1) [close(r), close(r), rand(s) < 1.0]";
+        assert_eq!(
+            interpreter
+                .run(&parse(source).unwrap())
+                .unwrap()
+                .to_string(),
+            "[true, false, true]"
+        );
+        assert_eq!(interpreter.open_handles(), 1);
+        let err = interpreter.run(&parse("rand(r)").unwrap()).unwrap_err();
+        assert_eq!(err, "Rng #0 is closed");
+        assert!(run("close(1)").is_err());
+    }
+
+    #[test]
+    fn test_timeout() {
+        let mut interpreter = Interpreter::new();
+        let spin = parse("let n = 0 while (true) { n++ }").unwrap();
+        assert_eq!(
+            interpreter.run_with_timeout(&spin, Duration::from_millis(50)),
+            Err(TIMEOUT.to_string())
+        );
+        let quick = parse("fn f(n) { n * 2 } f(21)").unwrap();
+        assert_eq!(
+            interpreter.run_with_timeout(&quick, Duration::from_secs(10)),
+            Ok(Val::Int(42))
+        );
+
+        // Blocking builtins are stopped too.
+        for source in ["sleep(3000)", "recv(channel())", "sleep(100)"] {
+            let start = Instant::now();
+            assert_eq!(
+                interpreter.run_with_timeout(&parse(source).unwrap(), Duration::from_millis(50)),
+                Err(TIMEOUT.to_string()),
+                "{}",
+                source
+            );
+            assert!(start.elapsed() < Duration::from_secs(1), "{}", source);
+        }
+        // Only running out of time is a timeout, not an error that says so.
+        let mut registry = Registry::new();
+        registry.add("stall", |_| Err(TIMEOUT.to_string()));
+        interpreter.register(registry).unwrap();
+        assert_eq!(
+            interpreter.run_within(&parse("stall()").unwrap(), Duration::from_secs(10)),
+            Some(Err(TIMEOUT.to_string()))
+        );
+
+        let token = interpreter.cancellation_token();
+        token.store(true, Ordering::Relaxed);
+        assert_eq!(interpreter.run(&quick), Err(CANCELLED.to_string()));
+        token.store(false, Ordering::Relaxed);
+        assert_eq!(interpreter.run(&quick), Ok(Val::Int(42)));
+    }
+
+    #[test]
+    #[cfg(feature = "graphics")]
+    fn test_graphics() {
+        let mut interpreter = Interpreter::new();
+        assert!(
+            interpreter
+                .run(&parse(r#"draw_rect(0, 0, 1, 1, "red")"#).unwrap())
+                .is_err()
+        );
+        let source = r##"
+            window(10, 6)
+            draw_rect(1, 1, 3, 2, "red")
+            draw_circle(7, 3, 1, "#00ff00")
+            draw_text(0, 0, "hi", "white")
+        "##;
+        interpreter.run(&parse(source).unwrap()).unwrap();
+        let canvas = interpreter.canvas.as_ref().unwrap();
+        assert_eq!(canvas.pixel(3, 2), Some([255, 0, 0]));
+        assert_eq!(canvas.pixel(7, 4), Some([0, 255, 0]));
+        assert_eq!(canvas.pixel(0, 0), Some([255, 255, 255]));
+        assert!(
+            interpreter
+                .run(&parse(r#"draw_circle(1, 1, "red")"#).unwrap())
+                .is_err()
+        );
+        assert!(
+            interpreter
+                .run(&parse(r#"draw_rect(0, 0, 1, 1, "plaid")"#).unwrap())
+                .is_err()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "image")]
+    fn test_images() {
+        let path = std::env::temp_dir().join(format!("ew-img-{}.png", std::process::id()));
+        let source = format!(
+            r#"
+            let path = "{}"
+            image_save(path, {{k: [[[x * 100, 0, 255 - x * 100] for x in 0..3]] for k in ["pixels"]}})
+            let img = image_load(path)
+            // Invert every pixel.
+            let rows = [[[255 - p[0], 255 - p[1], 255 - p[2]] for p in row] for row in img["pixels"]]
+            image_save(path, {{k: rows for k in ["pixels"]}})
+            let out = image_load(path)
+            [img["width"], img["height"], pixel(img, 2, 0), pixel(out, 0, 0)]
+        "#,
+            path.display()
+        );
+        let res = run(&source);
+        fs::remove_file(&path).unwrap();
+        assert_eq!(
+            res.unwrap().to_string(),
+            "[3, 1, [200, 0, 55], [255, 255, 0]]"
+        );
+        assert!(run("image_save(\"x.png\", 1)").is_err());
+    }
+
+    #[test]
+    fn test_pixel() {
+        let source = r#"
+            let img = {k: [[[1, 2, 3], [4, 5, 6]]] for k in ["pixels"]}
+            [pixel(img, 1, 0), pixel(img, 0, 0)]
+        "#;
+        assert_eq!(run(source).unwrap().to_string(), "[[4, 5, 6], [1, 2, 3]]");
+        let img = r#"let img = {k: [[[1, 2, 3]]] for k in ["pixels"]}"#;
+        assert!(run(&format!("{}\npixel(img, 1, 0)", img)).is_err());
+        assert!(run(&format!("{}\npixel(img, 0, -1)", img)).is_err());
+        assert!(run("pixel(1, 0, 0)").is_err());
+    }
+
+    #[test]
+    fn test_turtle() {
+        let path = std::env::temp_dir().join(format!("ew-turtle-{}.svg", std::process::id()));
+        let source = format!(
+            r#"
+            for i in 0..3 {{
+                forward(30)
+                turn(120)
+            }}
+            pen_up()
+            forward(-5.5)
+            pen_down()
+            turn(90)
+            forward(10)
+            save_svg("{}")
+        "#,
+            path.display()
+        );
+        run(&source).unwrap();
+        let svg = fs::read_to_string(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(svg.matches("<path").count(), 2);
+        assert!(
+            svg.contains(r#"d="M 0 0 L 0 -30 L 25.98 -15 L 0 0""#),
+            "{}",
+            svg
+        );
+        assert!(svg.contains(r#"d="M 0 5.5 L 10 5.5""#), "{}", svg);
+        assert!(run("turn(\"left\")").is_err());
+        assert_eq!(
+            run("forward(NAN)"),
+            Err("forward() requires a finite number, got NaN".to_string())
+        );
+        assert!(run("turn(-INF)").is_err());
+        // Programs that define these names keep their own functions.
+        assert_eq!(run("fn turn(x) { x * 2 }\nturn(4)"), Ok(Val::Int(8)));
+    }
+
+    #[test]
+    fn test_incremental_eval() {
+        let mut interpreter = Interpreter::new();
+        let program = parse("let n = 2\nn * 5").unwrap();
+        assert_eq!(interpreter.eval_stmt(&program[0]), Ok(Val::Unit));
+        assert_eq!(interpreter.eval_stmt(&program[1]), Ok(Val::Int(10)));
+
+        assert_eq!(
+            interpreter.eval_source_incremental("fn add(a, b) {"),
+            Ok(None)
+        );
+        assert_eq!(interpreter.eval_source_incremental("  a + b"), Ok(None));
+        assert_eq!(
+            interpreter.eval_source_incremental("}"),
+            Ok(Some(Val::Unit))
+        );
+        assert_eq!(
+            interpreter.eval_source_incremental("add(1, n)"),
+            Ok(Some(Val::Int(3)))
+        );
+
+        assert!(interpreter.eval_source_incremental("let = )").is_err());
+        assert_eq!(
+            interpreter.eval_source_incremental("n"),
+            Ok(Some(Val::Int(2)))
+        );
+    }
+
+    #[test]
+    fn test_builder() {
+        let out = crate::Captured::default();
+        let mut interpreter = Interpreter::builder()
+            .disable(BuiltinGroup::Os)
+            .division(Division::Float)
+            .max_call_depth(20)
+            .input(io::Cursor::new("typed\n"))
+            .output(out.clone())
+            .global("limit", Val::Int(3))
+            .build();
+        let mut run_src = |src: &str| interpreter.run(&parse(src).unwrap());
+
+        run_src("print(\"a\", 1)\nprintln(7 / 2, read_line())\npprint([limit])").unwrap();
+        assert_eq!(
+            String::from_utf8(out.0.lock().unwrap().clone()).unwrap(),
+            "a13.5 typed\n[3]\n"
+        );
+        assert_eq!(
+            run_src("is_file(\"Cargo.toml\")"),
+            Err("is_file() is disabled in this interpreter".to_string())
+        );
+        assert_eq!(
+            run_src("fn down(n) { if (n == 0) { 0 } else { down(n - 1) } }\ndown(19)"),
+            Ok(Val::Int(0))
+        );
+        assert_eq!(
+            run_src("down(20)"),
+            Err("Maximum call depth of 20 exceeded in down".to_string())
+        );
+        // The depth is reset after the error.
+        assert_eq!(run_src("down(10)"), Ok(Val::Int(0)));
+    }
+
+    #[test]
+    fn test_time_and_bench() {
+        let Val::Array(timed) = run("fn f() { 6 * 7 }\ntime(f)").unwrap() else {
+            panic!("time() should return an array");
+        };
+        assert!(matches!(timed.as_slice(), [Val::Int(42), Val::Float(ms)] if *ms >= 0.0));
+
+        let source = r#"
+            fn f() { sort([3, 1, 2]) }
+            let b = bench(f, 5)
+            [keys(b), b["min"] <= b["mean"] && b["mean"] <= b["max"]]
+        "#;
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            r#"[["max", "mean", "min"], true]"#
+        );
+        assert!(run("fn f() { 1 }\nbench(f, 0)").is_err());
+        assert!(run("time(1)").is_err());
+    }
+
+    #[test]
+    fn test_log() {
+        let out = crate::Captured::default();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_log_output(out.clone());
+        let program = parse(
+            r#"
+            let keys = ["n", "path", "tags"]
+            let values = [3, "my file.txt", ["a"]]
+            let fields = {keys[i]: values[i] for i in 0..3}
+            log_debug("hidden")
+            log_info("saved", fields)
+            log_error(404)
+            "#,
+        )
+        .unwrap();
+        interpreter.run(&program).unwrap();
+        assert_eq!(
+            String::from_utf8(out.0.lock().unwrap().clone()).unwrap(),
+            "INFO saved n=3 path=\"my file.txt\" tags=[\"a\"]\nERROR 404\n"
+        );
+
+        let out = crate::Captured::default();
+        let mut interpreter = Interpreter::builder()
+            .log_output(out.clone())
+            .log_level(LogLevel::Debug)
+            .log_format(LogFormat::Json)
+            .build();
+        interpreter.run(&program).unwrap();
+        assert_eq!(
+            String::from_utf8(out.0.lock().unwrap().clone()).unwrap(),
+            r#"{"level":"debug","message":"hidden"}
+{"level":"info","message":"saved","fields":{"n":3,"path":"my file.txt","tags":["a"]}}
+{"level":"error","message":"404"}
+"#
+        );
+
+        interpreter.set_log_level(LogLevel::Error);
+        let mut run_src = |src: &str| interpreter.run(&parse(src).unwrap());
+        assert_eq!(
+            run_src("log_warn(\"x\", [1])"),
+            Err("log_warn() fields must be a map, got Array".to_string())
+        );
+        assert!(run_src("log_info()").is_err());
+    }
+
+    #[test]
+    fn test_stdin() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_input(io::Cursor::new("first\r\nsecond\nthird\nfourth"));
+        let program =
+            parse("[read_line(), read_line(), lines_stdin(), read_line(), read_all_stdin()]")
+                .unwrap();
+        assert_eq!(
+            interpreter.run(&program).unwrap().to_string(),
+            r#"["first", "second", ["third", "fourth"], null, ""]"#
+        );
+
+        interpreter.set_input(io::Cursor::new("a b\nc\n"));
+        let program = parse("read_all_stdin()").unwrap();
+        assert_eq!(
+            interpreter.run(&program),
+            Ok(Val::Str("a b\nc\n".to_string()))
+        );
+        assert!(run("read_line(1)").is_err());
+    }
+
+    #[test]
+    fn test_channels() {
+        let source = r#"
+            let ch = channel()
+            let xs = [1, 2]
+            send(ch, xs)
+            xs[0] = 100
+            send(ch, "two")
+            [recv(ch), try_recv(ch), try_recv(ch)]
+        "#;
+        assert_eq!(run(source).unwrap().to_string(), r#"[[1, 2], "two", null]"#);
+
+        // Workers on other threads send to the same channel.
+        let source = r#"
+            fn report(pair) {
+                send(pair[0], pair[1] * pair[1])
+                pair[1]
+            }
+            let ch = channel()
+            par_map([[ch, 1], [ch, 2], [ch, 3]], report)
+            recv(ch) + recv(ch) + recv(ch)
+        "#;
+        assert_eq!(run(source), Ok(Val::Int(14)));
+
+        assert!(run("send(1, 2)").is_err());
+        assert!(run("recv([])").is_err());
+
+        // A channel may carry another, such as one to reply on, but never
+        // itself, which would keep it alive forever.
+        let source = r#"
+            let requests = channel()
+            let replies = channel()
+            send(requests, [replies, 2])
+            let request = recv(requests)
+            send(request[0], request[1] * 10)
+            recv(replies)
+        "#;
+        assert_eq!(run(source), Ok(Val::Int(20)));
+        assert_eq!(
+            run("let c = channel()\nsend(c, c)"),
+            Err("send() can't put a channel on itself".to_string())
+        );
+        assert!(run("let c = channel()\nsend(c, {k: [c] for k in [\"a\"]})").is_err());
+        let source = r#"
+            let a = channel()
+            let b = channel()
+            send(a, b)
+            send(b, [a])
+        "#;
+        assert!(run(source).is_err());
+    }
+
+    #[test]
+    fn test_par_map() {
+        let source = r#"
+            fn collatz(n) {
+                let steps = 0
+                while (n != 1) {
+                    if (n % 2 == 0) { n = n / 2 } else { n = 3 * n + 1 }
+                    steps++
+                }
+                steps
+            }
+            let xs = []
+            let seq = []
+            for i in 1..201 {
+                xs = xs + [i]
+                seq = seq + [collatz(i)]
+            }
+            deep_eq(par_map(xs, collatz), seq)
+        "#;
+        assert_eq!(run(source), Ok(Val::Bool(true)));
+        assert_eq!(
+            run("par_map([1, 4, 9], sqrt)").unwrap().to_string(),
+            "[1, 2, 3]"
+        );
+
+        let err = run("fn noisy(x) { println(x) } par_map([1], noisy)").unwrap_err();
+        assert!(err.contains("println() is not allowed"), "{}", err);
+        let err = run("fn count(x) { total = x } fn total() {} par_map([1], count)").unwrap_err();
+        assert!(err.contains("Cannot assign to global total"), "{}", err);
+        assert!(run("par_map(1, sqrt)").is_err());
+    }
+
+    #[test]
+    fn test_tasks() {
+        let source = r#"
+            fn producer() {
+                let i = 0
+                while (i < 3) {
+                    yield(i * 10)
+                    i++
+                }
+            }
+            let task = spawn(producer)
+            let got = []
+            let value = resume(task)
+            while (!done(task)) {
+                got = got + [value]
+                value = resume(task)
+            }
+            [got, value, resume(task), done(task)]
+        "#;
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            "[[0, 10, 20], null, null, true]"
+        );
+
+        // A task only runs when resumed, so one that never returns can
+        // still hand out values, and its output comes between theirs.
+        let source = r#"
+            fn naturals() {
+                let i = 0
+                while (true) {
+                    println("made", i)
+                    yield(i)
+                    i++
+                }
+            }
+            let task = spawn(naturals)
+            let got = [resume(task), resume(task)]
+            println("got", got)
+            [resume(task), done(task)]
+        "#;
+        let (value, output) = crate::eval_captured(source).unwrap();
+        assert_eq!(value.to_string(), "[2, false]");
+        assert_eq!(output, "made 0\nmade 1\ngot [0, 1]\nmade 2\n");
+
+        let source = r#"
+            fn fails() {
+                yield(1)
+                1 + "a"
+            }
+            let t = spawn(fails)
+            resume(t)
+            resume(t)
+        "#;
+        assert!(run(source).is_err());
+
+        // Nested tasks yield to their own consumer.
+        let source = r#"
+            fn inner() { yield("in") }
+            fn outer() {
+                let t = spawn(inner)
+                yield(resume(t))
+                yield("out")
+            }
+            let t = spawn(outer)
+            [resume(t), resume(t)]
+        "#;
+        assert_eq!(run(source).unwrap().to_string(), r#"["in", "out"]"#);
+
+        // A task given a channel could otherwise receive itself on it and
+        // keep its own thread alive.
+        let source = r#"
+            fn f() { yield(1) }
+            let ch = channel()
+            send(ch, [spawn(f)])
+        "#;
+        assert_eq!(run(source), Err("send() can't carry a task".to_string()));
+
+        assert!(run("yield(1)").is_err());
+        assert!(run("resume(1)").is_err());
+        assert!(run("spawn(1)").is_err());
+    }
+
+    #[test]
+    fn test_event_handlers() {
+        let mut interpreter = Interpreter::new();
+        let source = r#"
+            fn double(x) { x * 2 }
+            fn square(x) { x * x }
+            fn fail(x) { x + "a" }
+            on("tick", double)
+            on("tick", square)
+            on("tick", double)
+            on("broken", fail)
+        "#;
+        interpreter.run(&parse(source).unwrap()).unwrap();
+
+        assert_eq!(interpreter.events(), vec!["broken", "tick"]);
+        assert_eq!(
+            interpreter.dispatch("tick", vec![Val::Int(3)]).unwrap(),
+            vec![Val::Int(6), Val::Int(9), Val::Int(6)]
+        );
+        assert_eq!(interpreter.dispatch("nothing", vec![]).unwrap(), vec![]);
+        assert!(interpreter.dispatch("broken", vec![Val::Int(1)]).is_err());
+        assert!(interpreter.dispatch("tick", vec![]).is_err());
+
+        // `off` removes one registration at a time, oldest first.
+        let off = "[off(\"tick\", double), off(\"tick\", sqrt)]";
+        assert_eq!(
+            interpreter.run(&parse(off).unwrap()).unwrap().to_string(),
+            "[true, false]"
+        );
+        assert_eq!(
+            interpreter.dispatch("tick", vec![Val::Int(3)]).unwrap(),
+            vec![Val::Int(9), Val::Int(6)]
+        );
+        assert!(run("on(\"x\", 1)").is_err());
+    }
+
+    #[test]
+    fn test_config() {
+        let config = Val::from_json(
+            r#"{"name": "demo", "limits": {"max": 3, "ratio": 0.5}, "tags": ["a", "b"], "big": 18446744073709551615}"#,
+        )
+        .unwrap();
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_config(config);
+        let mut run_src = |src: &str| interpreter.run(&parse(src).unwrap());
+
+        assert_eq!(
+            run_src(r#"[CONFIG["name"], CONFIG["limits"]["max"] + 1, len(CONFIG), keys(CONFIG["limits"]), CONFIG["big"]]"#)
+                .unwrap()
+                .to_string(),
+            r#"["demo", 4, 4, ["max", "ratio"], 18446744073709551615]"#
+        );
+        assert_eq!(
+            run_src(r#"CONFIG["limits"]"#).unwrap().to_string(),
+            r#"{"max": 3, "ratio": 0.5}"#
+        );
+        assert!(run_src(r#"CONFIG["missing"]"#).is_err());
+        assert!(run_src(r#"CONFIG[0]"#).is_err());
+        assert_eq!(
+            run_src(r#"CONFIG["name"] = "x""#).unwrap_err(),
+            "CONFIG is read-only"
+        );
+        assert!(run_src("CONFIG = 1").is_err());
+        assert_eq!(
+            run_src("let CONFIG = 5"),
+            Err("CONFIG is read-only".to_string())
+        );
+        assert_eq!(
+            run_src("fn f(x) { let CONFIG = x }\nf(1)"),
+            Err("CONFIG is read-only".to_string())
+        );
+        assert!(
+            run_src(
+                r#"let t = CONFIG["tags"]
+t[0] = "z""#
+            )
+            .is_err()
+        );
+        interpreter.check(&parse(r#"CONFIG["name"] = "x""#).unwrap());
+        assert!(interpreter.diagnostics().warnings().is_empty());
+
+        assert!(run("CONFIG").is_err());
+        assert!(Val::from_json("{nope").is_err());
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_on_interrupt() {
+        let out = crate::Captured::default();
+        let mut interpreter = Interpreter::builder().output(out.clone()).build();
+        let mut run_src = |src: &str| interpreter.run(&parse(src).unwrap());
+
+        assert!(run_src("on_interrupt(1)").is_err());
+        run_src("fn cleanup() { println(\"bye\") }\non_interrupt(cleanup)").unwrap();
+        signal_hook::low_level::raise(signal_hook::consts::SIGTERM).unwrap();
+        assert_eq!(run_src("loop { }"), Err(INTERRUPTED.to_string()));
+        assert_eq!(
+            String::from_utf8(out.0.lock().unwrap().clone()).unwrap(),
+            "bye\n"
+        );
+        // Later runs aren't affected.
+        assert_eq!(run_src("1 + 1"), Ok(Val::Int(2)));
+    }
+
+    #[test]
+    fn test_variable() {
+        let mut interpreter = Interpreter::new();
+        interpreter
+            .run(&parse("let a = 1\nfn f() { let b = 2 }\nif (true) { let c = 3 }").unwrap())
+            .unwrap();
+        assert_eq!(interpreter.variable("a"), Some(&Val::Int(1)));
+        assert!(interpreter.variable("f").is_some_and(Val::is_callable));
+        assert_eq!(interpreter.variable("b"), None);
+        assert_eq!(interpreter.variable("c"), None);
+    }
+
+    #[test]
+    fn test_reload() {
+        let mut interpreter = Interpreter::new();
+        let run_src = |interpreter: &mut Interpreter, src: &str| {
+            interpreter.run(&parse(src).unwrap()).unwrap().to_string()
+        };
+        run_src(
+            &mut interpreter,
+            "let n = 1\nfn f() { 1 }\n@memoize\nfn g() { 2 }",
+        );
+        let changed = interpreter
+            .reload(&parse("fn f() { 10 }\n@memoize\nfn g() { 2 }\nfn h() { n }\nn = 5").unwrap());
+        assert_eq!(changed, Ok(vec!["f".to_string(), "h".to_string()]));
+        assert_eq!(run_src(&mut interpreter, "[n, f(), g()]"), "[1, 10, 2]");
+
+        // A queued reload lands at the start of the next block, which here
+        // is the program itself.
+        let outcome = interpreter
+            .reloader()
+            .reload(parse("fn f() { 100 }").unwrap());
+        assert_eq!(run_src(&mut interpreter, "f()"), "100");
+        assert_eq!(outcome.recv(), Ok(Ok(vec!["f".to_string()])));
+        assert!(
+            interpreter
+                .reload(&parse("@nope\nfn f() { 1 }").unwrap())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_constants() {
+        let mut interpreter = Interpreter::builder()
+            .constant("SIZE", Val::Int(3))
+            .constant("NAMES", Val::Array(vec![Val::Str("a".to_string())]))
+            .build();
+        interpreter.define_constant("MODE", Val::Str("fast".to_string()));
+        let mut run_src = |src: &str| interpreter.run(&parse(src).unwrap());
+
+        assert_eq!(
+            run_src("fn f() { [SIZE * 2, MODE, NAMES[0]] }\nf()")
+                .unwrap()
+                .to_string(),
+            r#"[6, "fast", "a"]"#
+        );
+        assert_eq!(run_src("SIZE = 4"), Err("SIZE is a constant".to_string()));
+        assert_eq!(
+            run_src("NAMES[0] = \"b\""),
+            Err("NAMES is a constant".to_string())
+        );
+        assert!(run_src("let n = NAMES\nn[0] = \"b\"").is_err());
+        assert_eq!(run_src("let SIZE = 5\nSIZE = 6\nSIZE"), Ok(Val::Int(6)));
+        interpreter.check(&parse("fn f() { MODE = \"slow\" }").unwrap());
+        assert!(interpreter.diagnostics().warnings().is_empty());
+        assert!(run("SIZE").is_err());
+    }
+
+    #[test]
+    fn test_freeze() {
+        let source = r#"
+            let cfg = freeze([[1, 2], ["x"]], "settings")
+            let row = cfg[0]
+            [cfg, len(cfg), row[1], cfg[0][1] + 1, is_frozen(cfg), is_frozen(row), is_frozen(sort([2, 1])), cfg + [3]]
+        "#;
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            r#"[[[1, 2], ["x"]], 2, 2, 3, true, true, false, [[1, 2], ["x"], 3]]"#
+        );
+
+        let err = run("let cfg = freeze([1], \"settings\")\ncfg[0] = 2").unwrap_err();
+        assert_eq!(err, "Cannot modify cfg: it is frozen (settings)");
+        assert!(run("let cfg = freeze([[1]])\nlet row = cfg[0]\nrow[0] = 2").is_err());
+        // Rebinding the name is fine; only the value is read-only.
+        assert_eq!(
+            run("let a = freeze([1])\na = [2]\na[0] = 3\na")
+                .unwrap()
+                .to_string(),
+            "[3]"
+        );
+
+        // A frozen array inside a plain one reads and refuses writes alike.
+        let source =
+            "let b = [freeze([[1], 2], \"inner\")]\n[b[0][1], b[0][0][0], is_frozen(b[0][0])]";
+        assert_eq!(run(source).unwrap().to_string(), "[2, 1, true]");
+        let err = run("let b = [freeze([1, 2], \"inner\")]\nb[0][0] = 5").unwrap_err();
+        assert_eq!(err, "Cannot modify b: it is frozen (inner)");
+        assert_eq!(run("let n = 5\nn[0]").unwrap_err(), "Cannot index into Int");
+
+        assert_eq!(run("freeze(5)").unwrap(), Val::Int(5));
+        assert!(run("hash(freeze([1, [2]]))").is_ok());
+        assert!(run("hash([1])").is_err());
+    }
+
+    #[test]
+    fn test_hash() {
+        // Fixed values: the hash must not change between runs or releases.
+        assert_eq!(run("hash(null)").unwrap(), Val::Int(-5808549176572512143));
+        assert_eq!(run("hash(0.0) == hash(-0.0)").unwrap(), Val::Bool(true));
+        // An Int never equals a Float, so they are free to hash apart.
+        assert_eq!(run("hash(1) == hash(1.0)").unwrap(), Val::Bool(false));
+        assert!(run("1 == 1.0").is_err());
+        assert_eq!(run("deep_eq(1, 1.0)").unwrap(), Val::Bool(false));
+        assert_eq!(
+            run(r#"hash("ab") == hash("a" + "b")"#).unwrap(),
+            Val::Bool(true)
+        );
+        let source = "enum P { Pt(x, y) }\n[hash(P::Pt(1, 2)) == hash(P::Pt(1, 2)), hash(P::Pt(1, 2)) == hash(P::Pt(2, 1))]";
+        assert_eq!(run(source).unwrap().to_string(), "[true, false]");
+
+        assert_eq!(
+            run("hash([1])").unwrap_err(),
+            "hash() cannot hash Array values"
+        );
+        assert!(run("fn f() { 1 }\nhash(f)").is_err());
+    }
+
+    #[test]
+    fn test_copy_and_deep_eq() {
+        let source = r#"
+            let a = [[1, 2], ["x"]]
+            let b = deep_copy(a)
+            b[0][1] = 5
+            let c = copy(a)
+            [a, b, deep_eq(a, c), deep_eq(a, b), deep_eq([NAN], [NAN]), deep_eq(1, 1.0)]
+        "#;
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            r#"[[[1, 2], ["x"]], [[1, 5], ["x"]], true, false, false, false]"#
+        );
+        assert_eq!(
+            run("fn f() { 1 }\nfn g() { 1 }\n[deep_eq(f, f), deep_eq(sqrt, sqrt)]")
+                .unwrap()
+                .to_string(),
+            "[true, true]"
+        );
+    }
+
+    #[test]
+    fn test_sorted_array_helpers() {
+        let source = r#"
+            let xs = [1, 3, 3, 3, 7]
+            [bsearch(xs, 3) != null, bsearch(xs, 4), bsearch(xs, 7), lower_bound(xs, 3), upper_bound(xs, 3), lower_bound(xs, 0), upper_bound(xs, 9)]
+        "#;
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            "[true, null, 4, 1, 4, 0, 5]"
+        );
+        assert_eq!(
+            run(r#"[is_sorted([1, 2, 2]), is_sorted(["b", "a"]), is_sorted([]), bsearch([], 1)]"#)
+                .unwrap()
+                .to_string(),
+            "[true, false, true, null]"
+        );
+        assert!(run(r#"bsearch([1, 2], "a")"#).is_err());
+        assert!(run("is_sorted(3)").is_err());
+    }
+
+    #[test]
+    fn test_labeled_break() {
+        let source = r#"
+            let found = null
+            outer: for i in 1..10 {
+                for j in 1..10 {
+                    if (i * j == 42) {
+                        found = [i, j]
+                        break outer
+                    }
+                }
+            }
+            found
+        "#;
+        assert_eq!(run(source).unwrap().to_string(), "[6, 7]");
+
+        let source = r#"
+            let n = 0
+            outer: while (true) {
+                loop {
+                    n++
+                    if (n == 3) { break outer }
+                    break
+                }
+            }
+            n
+        "#;
+        assert_eq!(run(source).unwrap(), Val::Int(3));
+
+        // Also from inside an expression, past the inner loop.
+        let source = r#"
+            let seen = []
+            outer: loop {
+                for i in 0..5 {
+                    let x = if (i == 2) { break outer } else { i * i }
+                    seen = seen + [x]
+                }
+            }
+            seen
+        "#;
+        assert_eq!(run(source).unwrap().to_string(), "[0, 1]");
+
+        // Without a matching label it is an ordinary break with a value.
+        let source = "let outer = 5\ninner: loop { break outer }";
+        assert_eq!(run(source).unwrap(), Val::Int(5));
+    }
+
+    #[test]
+    fn test_if_let_and_while_let() {
+        let source = r#"
+            fn to_num(s) {
+                if let n = parse_int(s) { n * 2 } else { -1 }
+            }
+            [to_num("21"), to_num("x")]
+        "#;
+        assert_eq!(run(source).unwrap().to_string(), "[42, -1]");
+
+        let source = r#"
+            let items = ["3", "4", "stop", "5"]
+            let i = 0
+            let total = 0
+            while let n = parse_int(items[i]) {
+                total = total + n
+                i++
+            }
+            [total, i]
+        "#;
+        assert_eq!(run(source).unwrap().to_string(), "[7, 2]");
+
+        // The binding is scoped to the block.
+        assert!(run("if let x = 1 { x }\nx").is_err());
+    }
+
+    #[test]
+    fn test_let_in() {
+        assert_eq!(run("let x = 10 in x * x").unwrap(), Val::Int(100));
+        assert_eq!(run("let x = 1 in let y = 2 in x + y").unwrap(), Val::Int(3));
+        assert_eq!(run("let y = let x = 2 in x * x\ny").unwrap(), Val::Int(4));
+
+        // The binding shadows, and is gone after the body.
+        let source = r#"
+            let x = 1
+            let y = let x = x + 1 in x * 10
+            [x, y]
+        "#;
+        assert_eq!(run(source).unwrap().to_string(), "[1, 20]");
+        assert!(run("let z = let x = 1 in x\nx").is_err());
+
+        let source = r#"
+            fn f(n) {
+                let half = n / 2 in if (half > 2) { return half } else { 0 }
+            }
+            [f(10), f(2)]
+        "#;
+        assert_eq!(run(source).unwrap().to_string(), "[5, 0]");
+    }
+
+    #[test]
+    fn test_division_modes() {
+        assert_eq!(run("7 / 2").unwrap(), Val::Int(3));
+        assert_eq!(
+            run("[divmod(7, 2), divmod(-7, 2), divmod(7, -2), divmod(7.5, 2.0)]")
+                .unwrap()
+                .to_string(),
+            "[[3, 1], [-4, 1], [-4, -1], [3, 1.5]]"
+        );
+        assert_eq!(
+            run("divmod(-9223372036854775807 - 1, -1)")
+                .unwrap()
+                .to_string(),
+            "[9223372036854775808, 0]"
+        );
+        assert!(run("divmod(1, 0)").is_err());
+
+        let mut interpreter = Interpreter::new();
+        interpreter.set_division(Division::Float);
+        let program = parse("let d = divmod(7, 2)\n[7 / 2, 6 / 3, 7.0 / 2.0, d[0]]").unwrap();
+        assert_eq!(
+            interpreter.run(&program).unwrap().to_string(),
+            "[3.5, 2, 3.5, 3]"
+        );
+    }
+
+    #[test]
+    fn test_negative_indices_and_slices() {
+        let source = r#"
+            let xs = [1, 2, 3, 4, 5]
+            let s = "héllo"
+            xs[-1] = 50
+            s[-2] = "L"
+            [xs[-1], xs[-5], s[-1], s, xs[1..3], xs[-3..-1], xs[..2], xs[3..], xs[..]]
+        "#;
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            r#"[50, 1, "o", "hélLo", [2, 3], [3, 4], [1, 2], [4, 50], [1, 2, 3, 4, 50]]"#
+        );
+
+        // Bounds past either end stop there, and a backwards slice is empty.
+        let source = r#"
+            let grid = [[1, 2, 3], [4, 5, 6]]
+            let s = "héllo"
+            [grid[-1][-2..], grid[0][-10..10], grid[0][2..1], s[1..-1], s[-3..]]
+        "#;
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            r#"[[5, 6], [1, 2, 3], [], "éll", "llo"]"#
+        );
+
+        assert_eq!(
+            run("let xs = [1, 2]\nxs[-3]").unwrap_err(),
+            "Array index -3 is out of bounds for length 2"
+        );
+        assert!(run("let xs = [1, 2]\nxs[-3] = 0").is_err());
+        assert!(run("let xs = [1, 2]\nxs[0.5..]").is_err());
+        assert!(run("let n = 5\nn[1..]").is_err());
+        assert!(parse("let xs = [1]\nxs[0..1] = 2").is_err());
+        // Slicing a frozen array gives a frozen array.
+        assert!(run("let xs = freeze([1, 2, 3])\nlet ys = xs[1..]\nys[0] = 0").is_err());
+    }
+
+    #[test]
+    fn test_array_growth() {
+        assert_eq!(
+            run("let xs = [1, 2]\nxs[2] = 3").unwrap_err(),
+            "Array index 2 is out of bounds for length 2"
+        );
+        assert_eq!(
+            run("let xs = [[1]]\nxs[0][-2]").unwrap_err(),
+            "Array index -2 is out of bounds for length 1"
+        );
+        assert_eq!(
+            run("let s = \"héllo\"\ns[5]").unwrap_err(),
+            "String index 5 is out of bounds for length 5"
+        );
+
+        let mut interpreter = Interpreter::builder()
+            .array_growth(ArrayGrowth::Append)
+            .build();
+        let program =
+            parse("let xs = [1]\nfor i in 1..4 { xs[len(xs)] = i * 10 }\nxs[0] = 0\nxs").unwrap();
+        assert_eq!(
+            interpreter.run(&program).unwrap().to_string(),
+            "[0, 10, 20, 30]"
+        );
+        let program = parse("let ys = [[]]\nys[0][0] = 1\nys[0][2] = 3").unwrap();
+        assert_eq!(
+            interpreter.run(&program).unwrap_err(),
+            "Array index 2 is out of bounds for length 1"
+        );
+    }
+
+    #[test]
+    fn test_float_helpers() {
+        assert_eq!(run("round(1.23456, 3)").unwrap(), Val::Float(1.235));
+        assert_eq!(run("round(1250.0, -2)").unwrap(), Val::Float(1300.0));
+        assert_eq!(run("round(7, 1)").unwrap(), Val::Int(7));
+        assert_eq!(
+            run("approx_eq(0.1 + 0.2, 0.3, 0.000001)").unwrap(),
+            Val::Bool(true)
+        );
+        assert_eq!(run("approx_eq(1, 2, 0.5)").unwrap(), Val::Bool(false));
+        assert_eq!(run("approx_eq(INF, INF, 0.1)").unwrap(), Val::Bool(true));
+
+        assert_eq!(
+            run("[is_nan(NAN), is_nan(1.0), is_inf(-INF), is_inf(3)]")
+                .unwrap()
+                .to_string(),
+            "[true, false, true, false]"
+        );
+        // NaN compares unequal to everything, itself included.
+        assert_eq!(
+            run("[NAN == NAN, NAN != NAN, NAN < 1.0, NAN >= 1.0, INF > 1.0]")
+                .unwrap()
+                .to_string(),
+            "[false, true, false, false, true]"
+        );
+        assert_eq!(run("[NAN, -INF]").unwrap().repr(), "[NAN, -INF]");
+        assert_eq!(run("let INF = 1\nINF").unwrap(), Val::Int(1));
+    }
+
+    #[test]
+    fn test_repr_round_trips() {
+        let source = r#"
+            enum E { A(x) }
+            [1, -2, 0.1 + 0.2, 3.0, "q\"uote\\\n", [true, null], E::A("s"), 99999999999999999999]
+        "#;
+        let val = run(source).unwrap();
+        let repr = val.repr();
+        assert_eq!(
+            repr,
+            r#"[1, -2, 0.30000000000000004, 3.0, "q\"uote\\\n", [true, null], E::A("s"), 99999999999999999999]"#
+        );
+        assert_eq!(run(&format!("enum E {{ A(x) }}\n{}", repr)).unwrap(), val);
+
+        let val = Val::from_json(r#"{"0": 0, "b": {"c": [1.5, "d"]}, "e": {}}"#).unwrap();
+        let repr = val.repr();
+        assert_eq!(
+            repr,
+            r#"{e[0]: e[1] for e in [["0", 0], ["b", {e[0]: e[1] for e in [["c", [1.5, "d"]]]}], ["e", {e[0]: e[1] for e in []}]]}"#
+        );
+        assert_eq!(run(&repr).unwrap(), val);
+
+        assert_eq!(run(r#"str("a")"#).unwrap(), Val::Str("a".to_string()));
+        assert_eq!(run(r#"repr("a")"#).unwrap(), Val::Str("\"a\"".to_string()));
+        assert_eq!(run("str([1.0])").unwrap(), Val::Str("[1]".to_string()));
+    }
+
+    #[test]
+    fn test_nested_display_and_pprint() {
+        assert_eq!(
+            run(r#"[["a,b"], ["a", "b"], "c"]"#).unwrap().to_string(),
+            r#"[["a,b"], ["a", "b"], "c"]"#
+        );
+        assert_eq!(run(r#""top level""#).unwrap().to_string(), "top level");
+
+        let v = run("[[1, 2], [3, 4]]").unwrap();
+        assert_eq!(v.pretty(0), "[[1, 2], [3, 4]]");
+
+        let v = run(
+            r#"[["alpha", "beta", "gamma", "delta"], ["epsilon", "zeta", "eta", "theta"], 42]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            v.pretty(0),
+            "[\n  [\"alpha\", \"beta\", \"gamma\", \"delta\"],\n  [\"epsilon\", \"zeta\", \"eta\", \"theta\"],\n  42,\n]"
+        );
+    }
+
+    #[test]
+    fn test_docstrings_and_help() {
+        let source = r#"
+            @memoize
+            fn area(w, h) {
+                "Area of a w by h rectangle."
+                w * h
+            }
+            fn greeting() { "hi" }
+            [area, greeting]
+        "#;
+        let Val::Array(funcs) = run(source).unwrap() else {
+            panic!("expected an array");
+        };
+        assert_eq!(
+            describe(&funcs[0]).unwrap(),
+            "fn area(w, h)\n    Area of a w by h rectangle."
+        );
+        assert_eq!(
+            describe(&funcs[1]).unwrap(),
+            "fn greeting()\n    (no documentation)"
+        );
+        assert_eq!(
+            run("fn greeting() { \"hi\" }\ngreeting()").unwrap(),
+            Val::Str("hi".to_string())
+        );
+
+        assert_eq!(
+            describe(&run("sqrt").unwrap()).unwrap(),
+            "sqrt(x)\n    Square root of x, as a float."
+        );
+        let builtins = Interpreter::builtins();
+        let intrinsics = Interpreter::intrinsics();
+        for name in builtins.keys().chain(intrinsics.keys()) {
+            assert!(builtin_doc(name).is_some(), "{} has no documentation", name);
+        }
+        assert!(run("help(1)").is_err());
+    }
+
+    #[test]
+    fn test_decorators() {
+        let source = r#"
+            @memoize
+            fn fib(n) {
+                if (n < 2) { n } else { fib(n - 1) + fib(n - 2) }
+            }
+            fib(30)
+        "#;
+        assert_eq!(run(source).unwrap(), Val::Int(832040));
+
+        let source = r#"
+            fn slow(n) { n * 2 }
+            let fast = memoize(slow)
+            @timed
+            fn twice(n) { n + n }
+            [twice(3) + fast(3) + fast(3), fast, twice]
+        "#;
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            "[18, <memoized fn slow(n)>, <timed fn twice(n)>]"
+        );
+
+        // Without the cache this would make over a billion calls.
+        let source = r#"
+            memo fn paths(r, c) {
+                if (r == 0 || c == 0) { 1 } else { paths(r - 1, c) + paths(r, c - 1) }
+            }
+            paths(16, 16)
+        "#;
+        assert_eq!(run(source).unwrap(), Val::Int(601080390));
+
+        assert!(run("@nope\nfn f() { 1 }").is_err());
+        assert!(run("memoize(1)").is_err());
+
+        let out = crate::Captured::default();
+        let mut interpreter = Interpreter::builder()
+            .log_output(out.clone())
+            .log_format(LogFormat::Json)
+            .build();
+        let program = parse("@timed\nfn f() { 1 }\nf()").unwrap();
+        assert_eq!(interpreter.run(&program), Ok(Val::Int(1)));
+        let log = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        assert!(
+            log.starts_with(r#"{"level":"info","message":"f() took "#),
+            "{}",
+            log
+        );
+        interpreter.set_log_level(LogLevel::Warn);
+        out.0.lock().unwrap().clear();
+        interpreter.run(&program).unwrap();
+        assert!(out.0.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_spread_args() {
+        let source = r#"
+            fn add3(a, b, c) { a + b + c }
+            fn forward(args) { add3(...args) }
+            enum P { Pt(x, y) }
+            [forward([1, 2, 3]), add3(1, ...[2, 3]), len(...["abc"]), P::Pt(...[1, 2])]
+        "#;
+        assert_eq!(run(source).unwrap().to_string(), "[6, 6, 3, P::Pt(1, 2)]");
+        assert!(run("fn f(a) { a }\nf(...1)").is_err());
+        assert!(run("fn f(a) { a }\nf(...[1, 2])").is_err());
+        assert!(run("[...[1]]").is_err());
+    }
+
+    #[test]
+    fn test_errors_restore_frames() {
+        let mut interpreter = Interpreter::new();
+        let mut run_src = |src: &str| interpreter.run(&parse(src).unwrap());
 
-        map.insert("cos", |args: Vec<Val>| -> Result<Val, String> {
-            if args.len() != 1 {
-                return Err(format!("cos() takes 1 argument, got {}", args.len()));
-            }
-            match &args[0] {
-                Val::Int(n) => Ok(Val::Float((*n as f64).cos())),
-                Val::Float(f) => Ok(Val::Float(f.cos())),
-                _ => Err(format!("cos() requires a number, got {:?}", args[0])),
-            }
-        });
+        run_src("let x = 1\nfn f(a) { let local = a\nmissing + 1 }").unwrap();
+        assert!(run_src("f(1)").is_err());
+        assert!(run_src("for i in 0..3 { let y = i\nf(i) }").is_err());
+        assert!(run_src("match (1) { 1 => undefined_var }").is_err());
+        assert!(run_src("{ let z = 1\nz + \"s\" }").is_err());
 
-        map.insert("floor", |args: Vec<Val>| -> Result<Val, String> {
-            if args.len() != 1 {
-                return Err(format!("floor() takes 1 argument, got {}", args.len()));
-            }
-            match &args[0] {
-                Val::Int(n) => Ok(Val::Int(*n)),
-                Val::Float(f) => Ok(Val::Int(f.floor() as i64)),
-                _ => Err(format!("floor() requires a number, got {:?}", args[0])),
-            }
-        });
+        // The session is still at the top level: globals resolve and
+        // locals from the failed calls are gone.
+        assert_eq!(run_src("x + 1").unwrap(), Val::Int(2));
+        assert!(run_src("local").is_err());
+        assert!(run_src("y").is_err());
+        run_src("let x2 = 3").unwrap();
+        assert_eq!(interpreter.stack.len(), 1);
+    }
 
-        map.insert("abs", |args: Vec<Val>| -> Result<Val, String> {
-            if args.len() != 1 {
-                return Err(format!("abs() takes 1 argument, got {}", args.len()));
-            }
-            match &args[0] {
-                Val::Int(n) => Ok(Val::Int(n.abs())),
-                Val::Float(f) => Ok(Val::Float(f.abs())),
-                _ => Err(format!("abs() requires a number, got {:?}", args[0])),
+    #[test]
+    fn test_increment_decrement() {
+        let source = r#"
+            let i = 0
+            let counts = [0, 0]
+            while (i < 5) {
+                counts[i % 2]++
+                i++
             }
-        });
+            counts[0]--
+            [i, counts]
+        "#;
+        assert_eq!(run(source).unwrap().to_string(), "[5, [2, 2]]");
+        assert!(run("x++").is_err());
 
-        map.insert("sqrt", |args: Vec<Val>| -> Result<Val, String> {
-            if args.len() != 1 {
-                return Err(format!("sqrt() takes 1 argument, got {}", args.len()));
-            }
-            match &args[0] {
-                Val::Int(n) => Ok(Val::Float((*n as f64).sqrt())),
-                Val::Float(f) => Ok(Val::Float(f.sqrt())),
-                _ => Err(format!("sqrt() requires a number, got {:?}", args[0])),
+        // The index is evaluated once, for both the read and the write.
+        let source = r#"
+            let calls = channel()
+            fn pick(calls, i) {
+                send(calls, i)
+                i
             }
-        });
+            let grid = [[0, 0], [0, 0]]
+            grid[pick(calls, 1)][pick(calls, 0)]++
+            [grid, try_recv(calls), try_recv(calls), try_recv(calls)]
+        "#;
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            "[[[0, 0], [1, 0]], 1, 0, null]"
+        );
+    }
 
-        map.insert("len", |args: Vec<Val>| -> Result<Val, String> {
-            if args.len() != 1 {
-                return Err(format!("len() takes 1 argument, got {}", args.len()));
+    #[test]
+    fn test_chained_comparison() {
+        assert_eq!(run("let x = 5\n0 <= x < 10").unwrap(), Val::Bool(true));
+        assert_eq!(run("let x = 10\n0 <= x < 10").unwrap(), Val::Bool(false));
+        assert_eq!(run("1 < 2 < 3 < 2").unwrap(), Val::Bool(false));
+
+        // Each operand runs once, left to right, and only until a
+        // comparison fails.
+        let source = r#"
+            fn pick(calls, i) {
+                send(calls, i)
+                i
             }
-            match &args[0] {
-                Val::Array(arr) => Ok(Val::Int(arr.len() as i64)),
-                Val::Str(s) => Ok(Val::Int(s.chars().count() as i64)),
-                _ => Err(format!(
-                    "len() requires an array or string, got {:?}",
-                    args[0]
-                )),
+            let calls = channel()
+            let a = pick(calls, 1) < pick(calls, 2) < pick(calls, 3)
+            let b = pick(calls, 5) < pick(calls, 4) < pick(calls, 6)
+            let seen = []
+            while let call = try_recv(calls) {
+                seen = seen + [call]
             }
-        });
+            [a, b, seen]
+        "#;
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            "[true, false, [1, 2, 3, 5, 4]]"
+        );
+    }
 
-        map.insert("clear", |args: Vec<Val>| -> Result<Val, String> {
-            if !args.is_empty() {
-                return Err(format!("clear() takes no arguments, got {}", args.len()));
+    #[test]
+    fn test_matrix_builtins() {
+        assert_eq!(
+            run("matrix(2, 3, 0)").unwrap().to_string(),
+            "[[0, 0, 0], [0, 0, 0]]"
+        );
+        assert_eq!(
+            run("transpose([[1, 2, 3], [4, 5, 6]])")
+                .unwrap()
+                .to_string(),
+            "[[1, 4], [2, 5], [3, 6]]"
+        );
+        assert_eq!(
+            run("matmul([[1, 2], [3, 4]], [[5, 6], [7, 8]])")
+                .unwrap()
+                .to_string(),
+            "[[19, 22], [43, 50]]"
+        );
+        assert_eq!(
+            run("dot([1.0, 2.0, 3.0], [4.0, 5.0, 6.5])").unwrap(),
+            Val::Float(33.5)
+        );
+        assert!(run("matmul([[1, 2]], [[1, 2]])").is_err());
+        assert!(run("transpose([[1], [2, 3]])").is_err());
+        assert!(run("dot([1], [1, 2])").is_err());
+
+        let m = run("[[1, 20], [300, 4]]").unwrap();
+        assert_eq!(format!("{:#}", m), "[[  1,  20]\n [300,   4]]");
+        assert_eq!(format!("{:#}", Val::Array(vec![])), "[]");
+    }
+
+    #[test]
+    fn test_string_append_in_place() {
+        let source = r#"
+            let s = ""
+            for i in 0..5 {
+                s = s + "ab"
             }
-            print!("\x1B[2J\x1B[1;1H");
-            Ok(Val::Unit)
-        });
+            s = s + 1
+        "#;
+        assert!(run(source).is_err());
 
-        map.insert("sleep", |args: Vec<Val>| -> Result<Val, String> {
-            if args.len() != 1 {
-                return Err(format!("sleep() takes 1 argument, got {}", args.len()));
+        let source = r#"
+            let s = ""
+            for i in 0..20000 {
+                s = s + "x"
             }
-            match &args[0] {
-                Val::Int(ms) => {
-                    std::thread::sleep(std::time::Duration::from_millis(*ms as u64));
-                    Ok(Val::Unit)
-                }
-                _ => Err(format!(
-                    "sleep() requires an integer (milliseconds), got {:?}",
-                    args[0]
-                )),
+            let t = "a"
+            t = t + t
+            [len(s), t]
+        "#;
+        assert_eq!(run(source).unwrap().to_string(), r#"[20000, "aa"]"#);
+
+        let source = r#"
+            let s = ""
+            for i in 0..3 {
+                s = s + "<" + str(i) + ">"
             }
-        });
+            s
+        "#;
+        assert_eq!(run(source).unwrap().to_string(), "<0><1><2>");
 
-        map
-    }
-}
+        // A failing operand leaves the variable as it was.
+        let mut interpreter = Interpreter::new();
+        let program = parse("let s = \"x\"\ns = s + \"a\" + 1").unwrap();
+        assert!(interpreter.run(&program).is_err());
+        assert_eq!(interpreter.variable("s"), Some(&Val::Str("x".to_string())));
 
-impl Default for Interpreter {
-    fn default() -> Self {
-        Self::new()
+        // Operands that read the variable see it before any appends.
+        let source = r#"
+            let s = "ab"
+            s = s + s + s
+            let t = "xy"
+            t = t + "-" + str(len(t))
+            [s, t]
+        "#;
+        assert_eq!(run(source).unwrap().to_string(), r#"["ababab", "xy-2"]"#);
     }
-}
 
-impl Frame {
-    pub fn new() -> Frame {
-        Frame {
-            local: HashMap::new(),
-            parent: None,
+    #[test]
+    fn test_alloc_profile() {
+        let source = r#"
+            fn pairs(xs) { [[x, x] for x in xs] }
+            let xs = [1, 2, 3]
+            let p = pairs(xs)
+            let n = len(p[0])
+        "#;
+        let mut interpreter = Interpreter::new();
+        assert_eq!(interpreter.alloc_report(), None);
+        interpreter.set_source_name("pairs.ew");
+        interpreter.enable_alloc_profile();
+        interpreter.run(&parse(source).unwrap()).unwrap();
+        let report = interpreter.alloc_report().unwrap();
+        assert!(
+            report.starts_with("Allocations: 12\n  Array           12\n"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("3  array literal at pairs.ew:2:29"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("1  comprehension at pairs.ew:2:28"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("4  copy of p at pairs.ew:5:25"),
+            "{}",
+            report
+        );
+        assert!(
+            report.contains("1  element of p at pairs.ew:5:25"),
+            "{}",
+            report
+        );
+
+        // Without a source name the site is only its line and column.
+        let mut interpreter = Interpreter::new();
+        interpreter.enable_alloc_profile();
+        interpreter
+            .run(&parse("let a = [[1], [2]]").unwrap())
+            .unwrap();
+        let report = interpreter.alloc_report().unwrap();
+        for at in ["1:9", "1:10", "1:15"] {
+            let site = format!("1  array literal at {}\n", at);
+            assert!(report.contains(&site), "{}", report);
         }
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::parser::parse;
+    #[test]
+    fn test_enums_and_match() {
+        let source = r#"
+            enum Color { Red, Green, Custom(r, g, b) }
 
-    fn run(source: &str) -> Result<Val, String> {
-        let program = parse(source)?;
-        let mut interpreter = Interpreter::new();
-        interpreter.run(&program)
+            fn brightness(c) {
+                return match (c) {
+                    Color::Red => 1,
+                    Color::Green => 2,
+                    Color::Custom(r, g, b) => r + g + b,
+                }
+            }
+
+            let c = Color::Custom(1, 2, 3)
+            [brightness(Color::Red), brightness(c), c == Color::Custom(1, 2, 3), c != Color::Red]
+        "#;
+        assert_eq!(run(source).unwrap().to_string(), "[1, 6, true, true]");
+
+        let source = r#"
+            enum Shape { Dot, Line(len) }
+            [Shape::Line(4), match (3) { 1 => "one", _ => "many" }]
+        "#;
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            r#"[Shape::Line(4), "many"]"#
+        );
+
+        let source = r#"
+            fn first_line(shapes) {
+                for i in 0..len(shapes) {
+                    match (shapes[i]) {
+                        Shape::Line(n) => { return n }
+                        _ => 0
+                    }
+                }
+                return -1
+            }
+            enum Shape { Dot, Line(len) }
+            first_line([Shape::Dot, Shape::Line(7)])
+        "#;
+        assert_eq!(run(source).unwrap(), Val::Int(7));
+
+        assert!(run("enum E { A }\nE::B").is_err());
+        assert!(run("enum E { A(x) }\nE::A").is_err());
+        assert!(run("enum E { A }\nmatch (E::A) { 1 => 1 }").is_err());
     }
 
     #[test]
-    fn test_literal() {
-        assert_eq!(run("42").unwrap(), Val::Int(42));
-        assert_eq!(run("true").unwrap(), Val::Bool(true));
-        assert_eq!(run("false").unwrap(), Val::Bool(false));
+    fn test_assign_undeclared() {
+        assert_eq!(
+            run("x = 1"),
+            Err("The variable [x] does not exist; use `let x = ...` to declare it".to_string())
+        );
     }
 
     #[test]
-    fn test_arithmetic() {
-        assert_eq!(run("1 + 2").unwrap(), Val::Int(3));
-        assert_eq!(run("10 - 3").unwrap(), Val::Int(7));
-        assert_eq!(run("4 * 5").unwrap(), Val::Int(20));
-        assert_eq!(run("15 / 3").unwrap(), Val::Int(5));
-        assert_eq!(run("17 % 5").unwrap(), Val::Int(2));
+    fn test_redeclaration_policy() {
+        let source = "let x = 1\nlet x = x + 1\nx";
+        assert!(run(source).is_err());
+
+        let program = parse(source).unwrap();
+        let mut interpreter = Interpreter::new();
+        interpreter.set_redeclaration(Redeclaration::Lenient);
+        assert_eq!(interpreter.run(&program).unwrap(), Val::Int(2));
     }
 
     #[test]
-    fn test_comparison() {
-        assert_eq!(run("1 < 2").unwrap(), Val::Bool(true));
-        assert_eq!(run("2 > 1").unwrap(), Val::Bool(true));
-        assert_eq!(run("1 == 1").unwrap(), Val::Bool(true));
-        assert_eq!(run("1 != 2").unwrap(), Val::Bool(true));
+    fn test_comprehensions() {
+        assert_eq!(
+            run("[x * x for x in 0..10 if x % 2 == 0]")
+                .unwrap()
+                .to_string(),
+            "[0, 4, 16, 36, 64]"
+        );
+        let source = r#"
+            let words = ["apple", "fig", "kiwi"]
+            let n = 3
+            [[w, len(w)] for w in words if len(w) > n]
+        "#;
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            r#"[["apple", 5], ["kiwi", 4]]"#
+        );
+        let source = r#"
+            let m = {w: len(w) for w in ["a", "bb"]}
+            [m, {k: m[k] * 10 for k in keys(m)}, [[i, j] for i in 0..2 if i < 0]]
+        "#;
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            r#"[{"a": 1, "bb": 2}, {"a": 10, "bb": 20}, []]"#
+        );
+        // Nested comprehensions, and the variable doesn't leak.
+        assert_eq!(
+            run("[[i * j for j in 1..4] for i in 1..3]")
+                .unwrap()
+                .to_string(),
+            "[[1, 2, 3], [2, 4, 6]]"
+        );
+        assert!(run("[x for x in 0..3]\nx").is_err());
+        assert!(run("[x for x in 5]").is_err());
+        assert!(run("{x: x for x in 0..3}").is_err());
     }
 
     #[test]
-    fn test_variables() {
-        assert_eq!(run("let x = 42\nx").unwrap(), Val::Int(42));
+    fn test_bind() {
+        let source = r#"
+            fn add(a, b) { a + b }
+            fn add3(a, b, c) { a + b + c }
+            fn clamp(lo, hi, x) { if (x < lo) { lo } else if (x > hi) { hi } else { x } }
+            let inc = bind(add, 1)
+            let unit = bind(clamp, 0, 1)
+            let two = bind(inc, 1)
+            let sum3 = bind(bind(add3, 1), 2)
+            let abs2 = bind(bind(abs))
+            [inc(41), unit(5), unit(-2), two(), abs2(-3), inc, abs2, sum3(3)]
+        "#;
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            "[42, 1, 0, 2, 3, <bound fn add(a, b) with 1>, <bound builtin abs>, 6]"
+        );
+        assert!(run("fn add(a, b) { a + b }\nbind(add, 1)(2, 3)").is_err());
+        assert!(run("bind(1, 2)").is_err());
     }
 
     #[test]
-    fn test_function() {
+    fn test_compose() {
         let source = r#"
-            fn add(a, b) {
-                return a + b
-            }
-            add(3, 4)
+            fn inc(x) { x + 1 }
+            fn double(x) { x * 2 }
+            fn add(a, b) { a + b }
+            let f = compose(inc, double)
+            let g = pipe(add, inc, double)
+            let h = pipe(f, bind(add, 10), abs)
+            [f(5), g(1, 2), h(-10), f]
         "#;
-        assert_eq!(run(source).unwrap(), Val::Int(7));
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            "[11, 8, 9, <fn double(x) |> fn inc(x)>]"
+        );
+        assert!(run("pipe()").is_err());
+        assert!(run("fn inc(x) { x + 1 }\ncompose(inc, 1)").is_err());
+        assert!(run("fn inc(x) { x + 1 }\ncompose(inc)").is_err());
     }
 
     #[test]
-    fn test_conditional() {
+    fn test_curry() {
         let source = r#"
-            if (true) {
-                42
-            } else {
-                0
-            }
+            fn add3(a, b, c) { a + b + c }
+            let add = curry(add3)
+            let add1 = add(1)
+            let add3_ = add1(2)
+            [add(1, 2, 3), add1(2, 3), add3_(3), add, add1]
         "#;
-        assert_eq!(run(source).unwrap(), Val::Int(42));
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            "[6, 6, 6, <curried fn add3(a, b, c) needs a, b, c>, \
+             <curried fn add3(a, b, c) needs b, c>]"
+        );
+        let err =
+            run("fn add(a, b) { a + b }\nlet f = curry(add)\nlet g = f(1)\ng(2, 3)").unwrap_err();
+        assert_eq!(
+            err,
+            "Curried g expects at most 1 more argument(s) (b), got 2"
+        );
+        // Arguments already bound don't have to be given again.
+        assert_eq!(
+            run("fn add(a, b) { a + b }\nlet f = curry(bind(add, 1))\nf(2)").unwrap(),
+            Val::Int(3)
+        );
+        assert!(run("curry(abs)").is_err());
+    }
 
+    #[test]
+    fn test_encodings() {
         let source = r#"
-            if (false) {
-                42
-            } else {
-                0
-            }
+            let s = "key=a b&é"
+            [base64_encode(s), hex_encode("hi"), url_encode(s),
+             deep_eq(base64_decode(base64_encode(s)), s), url_decode("a+b%21"), hex_decode("6869")]
         "#;
-        assert_eq!(run(source).unwrap(), Val::Int(0));
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            r#"["a2V5PWEgYibDqQ==", "6869", "key%3Da%20b%26%C3%A9", true, "a b!", "hi"]"#
+        );
+        assert!(run("base64_decode(\"***\")").is_err());
+        assert!(run("hex_encode(1)").is_err());
     }
 
     #[test]
-    fn test_while_loop() {
+    fn test_wildcards() {
         let source = r#"
-            let x = 0
-            while (x < 5) {
-                x = x + 1
-            }
-            x
+            let names = ["a.txt", "b.rs", "notes.txt", "docs/c.txt"]
+            [[n for n in names if glob_match("*.txt", n)], like("hello world", "h?llo*"),
+             like("hello", "*x*"), glob_match("[!a]?.rs", "b1.rs")]
         "#;
-        assert_eq!(run(source).unwrap(), Val::Int(5));
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            r#"[["a.txt", "notes.txt"], true, false, true]"#
+        );
+        assert!(run("glob_match(\"[a\", \"a\")").is_err());
+        assert!(run("like(\"a\")").is_err());
     }
 
     #[test]
-    fn test_factorial_iterative() {
+    #[cfg(feature = "crypto")]
+    fn test_digests() {
+        assert_eq!(
+            run(r#"[md5("abc"), crc32("abc"), len(sha1("")), len(sha256(""))]"#)
+                .unwrap()
+                .to_string(),
+            r#"["900150983cd24fb0d6963f7d28e17f72", "352441c2", 40, 64]"#
+        );
+        assert!(run("sha256(1)").is_err());
+    }
+
+    #[test]
+    fn test_random_strings() {
         let source = r#"
-            fn factorial(n) {
-                let result = 1
-                while (n > 1) {
-                    result = result * n
-                    n = n - 1
-                }
-                return result
-            }
-            factorial(5)
+            let r = rng(7)
+            let id = uuid(r)
+            [len(id), len(random_hex(12)), len(random_string(5, "ab", r)), random_string(3, "x")]
         "#;
-        assert_eq!(run(source).unwrap(), Val::Int(120));
+        assert_eq!(run(source).unwrap().to_string(), r#"[36, 12, 5, "xxx"]"#);
+
+        // The same seed gives the same strings.
+        let source = r#"
+            let a = rng(42)
+            let b = rng(42)
+            deep_eq([uuid(a), random_hex(8, a)], [uuid(b), random_hex(8, b)])
+        "#;
+        assert_eq!(run(source).unwrap(), Val::Bool(true));
+
+        let Val::Str(id) = run("uuid()").unwrap() else {
+            panic!("uuid() should give a string");
+        };
+        assert_eq!(id.as_bytes()[14], b'4');
+        assert!("89ab".contains(id.as_bytes()[19] as char));
+
+        assert!(run(r#"random_string(3, "")"#).is_err());
+        assert!(run("random_hex(-1)").is_err());
+        assert_eq!(
+            run("random_hex(1048577)").unwrap_err(),
+            "random_hex() can make at most 1048576 characters, got 1048577"
+        );
+        assert_eq!(
+            run(r#"len(random_string(1048576, "a"))"#),
+            Ok(Val::Int(1 << 20))
+        );
     }
 
     #[test]
-    fn test_factorial_recursive() {
+    fn test_csv() {
         let source = r#"
-            fn factorial(n) {
-                if (n <= 1) {
-                    return 1
-                } else {
-                    return n * factorial(n - 1)
-                }
-            }
-            factorial(5)
+            let text = "name,age\nAda,36\n\"Hopper, G\",85\n"
+            let rows = csv_parse(text)
+            let people = csv_parse(text, true)
+            [rows[2], people[1]["name"], people,
+             csv_stringify(people, ";"),
+             csv_stringify([["a", 1, 2.5, true, null]])]
         "#;
-        assert_eq!(run(source).unwrap(), Val::Int(120));
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            r#"[["Hopper, G", "85"], "Hopper, G", [{"age": "36", "name": "Ada"}, {"age": "85", "name": "Hopper, G"}], "age;name\n36;Ada\n85;Hopper, G\n", "a,1,2.5,true,\n"]"#
+        );
+        assert_eq!(
+            run(r#"csv_parse("a;'b;c'", false, ";", "'")"#)
+                .unwrap()
+                .to_string(),
+            r#"[["a", "b;c"]]"#
+        );
+        assert!(run(r#"csv_parse("a,b\n1", true)"#).is_err());
+        assert_eq!(
+            run(r#"csv_parse("a,b,a\n1,2,3", true)"#),
+            Err(r#"CSV header has the column "a" twice"#.to_string())
+        );
+        assert!(run(r#"csv_parse("a,a\n1,2")"#).is_ok());
+        assert!(run(r#"csv_parse("a", false, ";;")"#).is_err());
+        assert!(run(r#"csv_parse("a", ";")"#).is_err());
+        assert!(run("csv_stringify([[1], [[2]]])").is_err());
+
+        // Keys missing from the first map still get a column, and explicit
+        // columns fix the order.
+        let source = r#"
+            let a = {k: 1 for k in ["z", "a"]}
+            let b = {k: 2 for k in ["m"]}
+            [csv_stringify([a, b]), csv_stringify([a, b], ["z", "m", "a"], ";"),
+             csv_stringify([[""], ["x"]], ["h"])]
+        "#;
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            r#"["a,z,m\n1,1,\n,,2\n", "z;m;a\n1;;1\n;2;\n", "h\n\"\"\nx\n"]"#
+        );
+        assert_eq!(
+            run(r#"csv_parse(csv_stringify([[""], ["x"]]))"#)
+                .unwrap()
+                .to_string(),
+            r#"[[""], ["x"]]"#
+        );
+        assert!(run(r#"csv_stringify([{k: 1 for k in ["a", "b"]}], ["a"])"#).is_err());
+        assert!(run(r#"csv_stringify([{k: 1 for k in ["a"]}, [1]])"#).is_err());
+        assert!(run(r#"csv_stringify([["a"]], [1])"#).is_err());
     }
 
     #[test]
-    fn test_fibonacci_recursive() {
+    fn test_filesystem() {
+        let dir = std::env::temp_dir().join(format!("ew-fs-{}", std::process::id()));
+        let dir = dir.to_str().unwrap();
+        let source = format!(
+            r#"
+            let dir = "{dir}"
+            mkdir(dir + "/sub/deeper")
+            copy_file("Cargo.toml", dir + "/a.toml")
+            rename(dir + "/a.toml", dir + "/sub/b.toml")
+            let found = [is_dir(dir + "/sub"), is_file(dir + "/sub"), is_file(dir + "/sub/b.toml"),
+                         file_size(dir + "/sub/b.toml") > 0, modified_time(dir + "/sub/b.toml") > 0.0]
+            remove_file(dir + "/sub/b.toml")
+            remove_dir(dir + "/sub/deeper")
+            remove_dir(dir + "/sub")
+            remove_dir(dir)
+            [found, is_dir(dir)]
+        "#
+        );
+        assert_eq!(
+            run(&source).unwrap().to_string(),
+            "[[true, false, true, true, true], false]"
+        );
+
+        let err = run(r#"remove_file("/nonexistent/ew-missing")"#).unwrap_err();
+        assert!(
+            err.starts_with(r#"remove_file() failed for "/nonexistent/ew-missing": "#),
+            "{}",
+            err
+        );
+        assert!(run("file_size(1)").is_err());
+        assert!(run(r#"rename("a")"#).is_err());
+    }
+
+    #[test]
+    fn test_pipeline() {
         let source = r#"
-            fn fib(n) {
-                if (n < 2) {
-                    return n
-                } else {
-                    return fib(n - 1) + fib(n - 2)
-                }
-            }
-            fib(10)
+            fn add(a, b) { a + b }
+            fn double(x) { x * 2 }
+            fn evens(xs) { [x for x in xs if x % 2 == 0] }
+            [5 |> add(1) |> double, [1, 2, 3, 4] |> evens |> len(), (2 |> add(3)) == 5]
         "#;
-        assert_eq!(run(source).unwrap(), Val::Int(55));
+        assert_eq!(run(source).unwrap().to_string(), "[12, 2, true]");
     }
 
     #[test]
-    fn test_fibonacci_iterative() {
+    fn test_implicit_return() {
+        let cases = [
+            (
+                "fn f(x) { if (x) { 1 } else { 2 } } [f(true), f(false)]",
+                "[1, 2]",
+            ),
+            (
+                "fn f(x) { if (x > 0) { let y = x\ny * 2 } else if (x < 0) { { -1 } } else { 0 } }
+                [f(5), f(-5), f(0)]",
+                "[10, -1, 0]",
+            ),
+            (
+                "fn f(x) { match (x) { 1 => \"one\", _ => { let s = \"many\"\ns } } } [f(1), f(2)]",
+                r#"["one", "many"]"#,
+            ),
+            ("fn f() { { { 9 } } } f()", "9"),
+            ("fn f() { loop { break 7 } } f()", "7"),
+            // Statements without a value give `()`.
+            ("fn f() { let z = 1 } f()", "()"),
+            ("fn f(x) { if (x) { 1 } } f(false)", "()"),
+            ("fn f() { let i = 0\nwhile (i < 3) { i++ } } f()", "()"),
+            ("fn f() {} f()", "()"),
+            // An early `return` still wins over the tail.
+            (
+                "fn f(x) { if (x) { return 1 }\n2 } [f(true), f(false)]",
+                "[1, 2]",
+            ),
+        ];
+        for (source, expected) in cases {
+            assert_eq!(run(source).unwrap().to_string(), expected, "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_return_from_nested_block() {
         let source = r#"
-            fn fib(n) {
-                if (n < 2) {
-                    return n
-                } else {
-                    let a = 0
-                    let b = 1
-                    let i = 2
-                    let temp = 0
-                    while (i <= n) {
-                        temp = a + b
-                        a = b
-                        b = temp
-                        i = i + 1
+            fn first_over(arr, limit) {
+                for i in 0..len(arr) {
+                    if (arr[i] > limit) {
+                        return i
                     }
-                    return b
                 }
+                return -1
             }
-            fib(10)
+            first_over([1, 5, 9], 4)
         "#;
-        assert_eq!(run(source).unwrap(), Val::Int(55));
+        assert_eq!(run(source).unwrap(), Val::Int(1));
     }
 
     #[test]
-    fn test_nested_calls() {
+    fn test_flow_out_of_expressions() {
         let source = r#"
-            fn double(x) {
-                return x * 2
+            let n = 0
+            let last = loop {
+                n = n + 1
+                let y = if (n == 3) { break n * 10 } else { n }
+                y + 1
             }
-            fn quadruple(x) {
-                return double(double(x))
+            [n, last]
+        "#;
+        assert_eq!(run(source).unwrap().to_string(), "[3, 30]");
+
+        let source = r#"
+            fn sign(x) {
+                let s = if (x < 0) { return "-" } else { "+" }
+                s + s
             }
-            quadruple(5)
+            fn kind(x) {
+                let k = match (x) { 0 => { return "zero" }, _ => "some" }
+                k
+            }
+            [sign(-1), sign(1), kind(0), kind(2)]
         "#;
-        assert_eq!(run(source).unwrap(), Val::Int(20));
+        assert_eq!(
+            run(source).unwrap().to_string(),
+            r#"["-", "++", "zero", "some"]"#
+        );
+        assert_eq!(
+            run("let x = { break 1 }").unwrap_err(),
+            "'break' outside of a loop"
+        );
     }
 }
@@ -0,0 +1,215 @@
+/// Keywords reserved by the grammar (see `KEYWORD` in grammar.pest).
+pub const KEYWORDS: &[&str] = &[
+    "return", "while", "break", "match", "else", "enum", "loop", "for", "let", "fn", "if", "in",
+];
+
+/// Directives that start a `Pragma` in grammar.pest, lexed as keywords.
+const DIRECTIVES: &[&str] = &["#pragma", "#feature"];
+
+/// Operators, longest first so that `**` wins over `*` and `..=` over `..`.
+const OPERATORS: &[&str] = &[
+    "...", "..=", "::", "=>", "**", "++", "--", "<=", ">=", "==", "!=", "&&", "|>", "||", "..",
+    "<", ">", "+", "-", "*", "/", "%", "!", "=",
+];
+
+const PUNCTUATION: &[char] = &['(', ')', '{', '}', '[', ']', ',', '@', ':'];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenKind {
+    Int,
+    Float,
+    Str,
+    Bool,
+    Null,
+    Ident,
+    Keyword,
+    Operator,
+    Punctuation,
+    Comment,
+    /// Anything the grammar has no token for, e.g. a stray `$` or an
+    /// unterminated string. Lexing never fails so that half-typed input
+    /// can still be highlighted.
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Token {
+    pub kind: TokenKind,
+    pub text: String,
+    /// Byte range into the source.
+    pub span: (usize, usize),
+}
+
+/// Splits `source` into tokens, skipping whitespace but keeping comments.
+pub fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut pos = 0;
+
+    while let Some(c) = source[pos..].chars().next() {
+        let rest = &source[pos..];
+
+        if c.is_whitespace() {
+            pos += c.len_utf8();
+            continue;
+        }
+
+        let (kind, len) = if rest.starts_with("//") {
+            (TokenKind::Comment, rest.find('\n').unwrap_or(rest.len()))
+        } else if c == '"' {
+            match string_len(rest) {
+                Some(len) => (TokenKind::Str, len),
+                None => (TokenKind::Unknown, rest.len()),
+            }
+        } else if c.is_ascii_digit() {
+            lex_number(rest)
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            let word = &rest[..len];
+            let kind = match word {
+                "true" | "false" => TokenKind::Bool,
+                "null" => TokenKind::Null,
+                w if KEYWORDS.contains(&w) => TokenKind::Keyword,
+                _ => TokenKind::Ident,
+            };
+            (kind, len)
+        } else if let Some(directive) = DIRECTIVES.iter().find(|d| {
+            rest.starts_with(*d)
+                && !rest[d.len()..].starts_with(|c: char| c.is_ascii_alphanumeric() || c == '_')
+        }) {
+            (TokenKind::Keyword, directive.len())
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            (TokenKind::Operator, op.len())
+        } else if PUNCTUATION.contains(&c) {
+            (TokenKind::Punctuation, 1)
+        } else {
+            (TokenKind::Unknown, c.len_utf8())
+        };
+
+        tokens.push(Token {
+            kind,
+            text: rest[..len].to_string(),
+            span: (pos, pos + len),
+        });
+        pos += len;
+    }
+
+    tokens
+}
+
+/// Length of the string literal at the start of `rest`, including quotes
+/// and skipping over backslash escapes, or `None` if it never closes.
+fn string_len(rest: &str) -> Option<usize> {
+    let mut escaped = false;
+    for (i, c) in rest.char_indices().skip(1) {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => return Some(i + 1),
+            _ => {}
+        }
+    }
+    None
+}
+
+fn lex_number(rest: &str) -> (TokenKind, usize) {
+    let digits = |s: &str| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+
+    let int_len = digits(rest);
+    let after = &rest[int_len..];
+    // `1..5` is a range, not the float `1.` followed by `.5`.
+    if after.starts_with('.') && after[1..].starts_with(|c: char| c.is_ascii_digit()) {
+        (TokenKind::Float, int_len + 1 + digits(&after[1..]))
+    } else {
+        (TokenKind::Int, int_len)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds(source: &str) -> Vec<(TokenKind, String)> {
+        tokenize(source)
+            .into_iter()
+            .map(|t| (t.kind, t.text))
+            .collect()
+    }
+
+    #[test]
+    fn test_tokenize_statement() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("let x = f(2 ** 3.5, \"hi\") |> g // note"),
+            vec![
+                (Keyword, "let".to_string()),
+                (Ident, "x".to_string()),
+                (Operator, "=".to_string()),
+                (Ident, "f".to_string()),
+                (Punctuation, "(".to_string()),
+                (Int, "2".to_string()),
+                (Operator, "**".to_string()),
+                (Float, "3.5".to_string()),
+                (Punctuation, ",".to_string()),
+                (Str, "\"hi\"".to_string()),
+                (Punctuation, ")".to_string()),
+                (Operator, "|>".to_string()),
+                (Ident, "g".to_string()),
+                (Comment, "// note".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_directives() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds("#pragma version 0.2\n#feature(strict_types)"),
+            vec![
+                (Keyword, "#pragma".to_string()),
+                (Ident, "version".to_string()),
+                (Float, "0.2".to_string()),
+                (Keyword, "#feature".to_string()),
+                (Punctuation, "(".to_string()),
+                (Ident, "strict_types".to_string()),
+                (Punctuation, ")".to_string()),
+            ]
+        );
+        assert_eq!(
+            kinds("#pragmas"),
+            vec![(Unknown, "#".to_string()), (Ident, "pragmas".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_tokenize_ranges_and_spans() {
+        let tokens = tokenize("for i in 0..=10 {}");
+        let texts: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(texts, vec!["for", "i", "in", "0", "..=", "10", "{", "}"]);
+        assert_eq!(tokens[4].span, (10, 13));
+
+        let texts: Vec<String> = tokenize("xs[i]++\nn--")
+            .into_iter()
+            .map(|t| t.text)
+            .collect();
+        assert_eq!(texts, vec!["xs", "[", "i", "]", "++", "n", "--"]);
+    }
+
+    #[test]
+    fn test_tokenize_string_escapes() {
+        let tokens = tokenize(r#""a \"b\" \\" x"#);
+        assert_eq!(tokens[0].kind, TokenKind::Str);
+        assert_eq!(tokens[0].text, r#""a \"b\" \\""#);
+        assert_eq!(tokens[1].text, "x");
+    }
+
+    #[test]
+    fn test_tokenize_never_fails() {
+        let tokens = tokenize("nulls $ \"open");
+        assert_eq!(tokens[0].kind, TokenKind::Ident);
+        assert_eq!(tokens[1].kind, TokenKind::Unknown);
+        assert_eq!(tokens[2].kind, TokenKind::Unknown);
+        assert_eq!(tokens[2].text, "\"open");
+    }
+}
@@ -1,23 +1,167 @@
-use crate::{
-    interpreter::{Interpreter, Val},
-    parser::parse,
-};
+//! ew: a small scripting language with a tree-walking interpreter.
+//!
+//! The crate root re-exports what a host needs to embed ew: parse source
+//! with [`parse`] or [`parse_all`], then run the [`Program`] with an
+//! [`Interpreter`], configured through [`InterpreterBuilder`] if the
+//! defaults don't fit. The interpreter reports errors as plain `String`s;
+//! the one-call helpers such as [`run`] sort them into an [`EwError`], and
+//! syntax errors from [`parse_all`] are [`ParseError`]s. The compilers are
+//! [`to_c`], [`build_native`], [`to_js`] and [`to_wasm`].
 
+mod alloc_profile;
 pub mod ast;
+mod csv;
+pub mod diagnostics;
+#[cfg(feature = "crypto")]
+mod digest;
+mod encoding;
+pub(crate) mod formatter;
+#[cfg(feature = "graphics")]
+mod graphics;
+#[cfg(feature = "image")]
+mod image;
 pub mod interpreter;
-pub mod parser;
+pub(crate) mod lexer;
+pub(crate) mod literate;
+mod log;
+pub(crate) mod native;
+pub(crate) mod parser;
+pub(crate) mod plugin;
+pub(crate) mod resolver;
+#[cfg(feature = "term")]
+mod term;
+pub(crate) mod transpile;
+mod turtle;
+pub(crate) mod version;
+pub(crate) mod wasm;
+mod wildcard;
 
-extern crate pest;
+use std::{
+    fmt,
+    io::{self, Write},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 
-#[macro_use]
+extern crate pest;
 extern crate pest_derive;
 
-// pub use ast::{Expr, Program, Stmt};
-// pub use interpreter::{Interpreter, Val};
-// pub use parser::parse;
+pub use ast::{Expr, Program, Stmt};
+pub use diagnostics::{Diagnostics, Warning, WarningKind};
+pub use formatter::format_program;
+pub use interpreter::{
+    ArrayGrowth, BuiltinGroup, Division, Interpreter, InterpreterBuilder, LogFormat, LogLevel,
+    Redeclaration, Reloader, Truthiness, TypeChecking, Val,
+};
+pub use lexer::{Token, TokenKind, tokenize};
+pub use literate::{Block as CodeBlock, code_blocks};
+pub use native::{build as build_native, to_c};
+pub use parser::{ParseError, bracket_depth, parse, parse_all};
+pub use plugin::{NativeFn, PLUGIN_ABI_VERSION, Registry};
+pub use resolver::resolve;
+pub use transpile::to_js;
+pub use version::Feature;
+pub use wasm::to_wasm;
+
+/// Why [`run`], [`run_with_timeout`] or [`eval_captured`] failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EwError {
+    /// The source is not valid ew.
+    Parse(String),
+    /// The program stopped with an error.
+    Runtime(String),
+    /// The program was still running when its time ran out.
+    Timeout,
+}
+
+impl fmt::Display for EwError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EwError::Parse(message) | EwError::Runtime(message) => write!(f, "{}", message),
+            EwError::Timeout => write!(f, "{}", interpreter::TIMEOUT),
+        }
+    }
+}
+
+impl std::error::Error for EwError {}
+
+/// Parses and runs `source` in a fresh interpreter.
+pub fn run(source: &str) -> Result<Val, EwError> {
+    let program = parse(source).map_err(EwError::Parse)?;
+    let mut interpreter = Interpreter::new();
+    interpreter.run(&program).map_err(EwError::Runtime)
+}
 
-pub fn run(source: &str) -> Result<Val, String> {
-    let program = parse(source)?;
+/// Like [`run`], but stops the program with [`EwError::Timeout`] once it
+/// has run for `timeout`.
+pub fn run_with_timeout(source: &str, timeout: Duration) -> Result<Val, EwError> {
+    let program = parse(source).map_err(EwError::Parse)?;
     let mut interpreter = Interpreter::new();
-    interpreter.run(&program)
+    match interpreter.run_within(&program, timeout) {
+        Some(res) => res.map_err(EwError::Runtime),
+        None => Err(EwError::Timeout),
+    }
+}
+
+/// Like [`run`], but also returns everything the program printed instead of
+/// writing it to stdout, including what it printed before an error.
+pub fn eval_captured(source: &str) -> Result<(Val, String), (EwError, String)> {
+    let program = parse(source).map_err(|e| (EwError::Parse(e), String::new()))?;
+    let output = Captured::default();
+    let mut interpreter = Interpreter::builder().output(output.clone()).build();
+    let res = interpreter.run(&program);
+    let text = String::from_utf8_lossy(&output.0.lock().unwrap()).into_owned();
+    match res {
+        Ok(value) => Ok((value, text)),
+        Err(e) => Err((EwError::Runtime(e), text)),
+    }
+}
+
+/// A writer whose bytes stay readable after the interpreter takes a clone.
+#[derive(Clone, Default)]
+struct Captured(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Captured {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_eval_captured() {
+        let (value, output) = eval_captured("println(\"hi\", 2)\npprint([1])\n3").unwrap();
+        assert_eq!(value, Val::Int(3));
+        assert_eq!(output, "hi 2\n[1]\n");
+        assert!(matches!(
+            eval_captured("print("),
+            Err((EwError::Parse(_), _))
+        ));
+        assert_eq!(
+            eval_captured("println(1)\nmissing\nprintln(2)"),
+            Err((
+                EwError::Runtime("Undefined Variable: missing".to_string()),
+                "1\n".to_string()
+            ))
+        );
+        assert_eq!(
+            run("1 + \"a\"").err().map(|e| e.to_string()),
+            Some("Cannot apply Add to 1 and \"a\"".to_string())
+        );
+        assert_eq!(
+            run_with_timeout("while (true) {}", Duration::from_millis(50)),
+            Err(EwError::Timeout)
+        );
+        assert_eq!(
+            run_with_timeout("sleep(5000)", Duration::from_millis(50)),
+            Err(EwError::Timeout)
+        );
+    }
 }
@@ -0,0 +1,92 @@
+//! Finds the ew code in a Markdown file, for `ew run --literate`.
+
+/// A fenced code block tagged `ew`.
+#[derive(Debug, PartialEq)]
+pub struct Block {
+    /// The line of the opening fence, from 1.
+    pub line: usize,
+    pub code: String,
+}
+
+/// The ```` ```ew ```` (or `~~~ew`) blocks of `markdown`, in order. Blocks in
+/// other languages are skipped, and an unclosed block runs to the end of the
+/// file, as in CommonMark.
+pub fn code_blocks(markdown: &str) -> Vec<Block> {
+    let mut blocks = Vec::new();
+    // The fence that opened the current block and whether it is ew code.
+    let mut open: Option<(&str, Option<Block>)> = None;
+    for (i, line) in markdown.lines().enumerate() {
+        let trimmed = line.trim_start();
+        let Some((opening, block)) = &mut open else {
+            if let Some(fence) = fence(trimmed) {
+                let info = trimmed[fence.len()..].split_whitespace().next();
+                let block = (info == Some("ew")).then(|| Block {
+                    line: i + 1,
+                    code: String::new(),
+                });
+                open = Some((fence, block));
+            }
+            continue;
+        };
+        let closes = fence(trimmed).is_some_and(|f| {
+            f.starts_with(&opening[..1])
+                && f.len() >= opening.len()
+                && trimmed[f.len()..].trim().is_empty()
+        });
+        if closes {
+            blocks.extend(open.take().and_then(|(_, block)| block));
+        } else if let Some(block) = block {
+            block.code.push_str(line);
+            block.code.push('\n');
+        }
+    }
+    blocks.extend(open.and_then(|(_, block)| block));
+    blocks
+}
+
+/// The run of three or more backticks or tildes starting `line`, if any.
+fn fence(line: &str) -> Option<&str> {
+    let c = line.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let len = line.len() - line.trim_start_matches(c).len();
+    (len >= 3).then(|| &line[..len])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_blocks() {
+        let markdown = "# Tutorial\n\
+                        ```ew\n\
+                        let x = 1\n\
+                        ```\n\
+                        Some text.\n\
+                        ```python\n\
+                        print(1)\n\
+                        ```\n\
+                        ~~~~ ew title\n\
+                        ```\n\
+                        println(x)\n\
+                        ~~~~\n\
+                        ```ew\n\
+                        unclosed()\n";
+        assert_eq!(
+            code_blocks(markdown),
+            [
+                Block {
+                    line: 2,
+                    code: "let x = 1\n".to_string()
+                },
+                Block {
+                    line: 9,
+                    code: "```\nprintln(x)\n".to_string()
+                },
+                Block {
+                    line: 13,
+                    code: "unclosed()\n".to_string()
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,72 @@
+//! The lines `log_debug`, `log_info`, `log_warn` and `log_error` write.
+
+use std::collections::BTreeMap;
+
+use serde_json::{Map, Number, Value};
+
+use crate::interpreter::{LogFormat, LogLevel, Val, quote};
+
+/// One record, without a trailing newline. Text is the level, the message
+/// and `key=value` pairs, quoting strings with spaces and the like; JSON is
+/// an object with `level`, `message` and, if there are any, `fields`.
+pub(crate) fn line(
+    format: LogFormat,
+    level: LogLevel,
+    message: &str,
+    fields: &BTreeMap<String, Val>,
+) -> String {
+    match format {
+        LogFormat::Text => {
+            let mut out = format!("{} {}", level.name().to_uppercase(), message);
+            for (key, value) in fields {
+                out.push_str(&format!(" {}={}", key, text_value(value)));
+            }
+            out
+        }
+        LogFormat::Json => {
+            // Written by hand so `level` and `message` come first.
+            let mut out = format!(
+                "{{\"level\":{},\"message\":{}",
+                Value::from(level.name()),
+                Value::from(message)
+            );
+            if !fields.is_empty() {
+                let fields: Map<_, _> = fields.iter().map(|(k, v)| (k.clone(), json(v))).collect();
+                out.push_str(&format!(",\"fields\":{}", Value::Object(fields)));
+            }
+            out.push('}');
+            out
+        }
+    }
+}
+
+fn text_value(value: &Val) -> String {
+    match value {
+        Val::Str(s)
+            if s.is_empty()
+                || s.chars()
+                    .any(|c| c.is_whitespace() || c.is_control() || c == '=' || c == '"') =>
+        {
+            quote(s)
+        }
+        Val::Frozen { value, .. } => text_value(value),
+        _ => value.to_string(),
+    }
+}
+
+/// `value` as JSON: big integers become strings and values JSON has no
+/// form for, such as functions, become their text.
+fn json(value: &Val) -> Value {
+    match value {
+        Val::Int(n) => Value::from(*n),
+        Val::BigInt(n) => Value::from(n.to_string()),
+        Val::Float(f) => Number::from_f64(*f).map_or(Value::Null, Value::Number),
+        Val::Bool(b) => Value::from(*b),
+        Val::Str(s) => Value::from(s.as_str()),
+        Val::Array(items) => Value::Array(items.iter().map(json).collect()),
+        Val::Map(map) => Value::Object(map.iter().map(|(k, v)| (k.clone(), json(v))).collect()),
+        Val::Frozen { value, .. } => json(value),
+        Val::Null | Val::Unit => Value::Null,
+        other => Value::from(other.to_string()),
+    }
+}
@@ -1,50 +1,505 @@
-use std::{env::args, fs};
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::Path,
+    process,
+    sync::{
+        atomic::Ordering,
+        mpsc::{Receiver, TryRecvError},
+    },
+    thread,
+    time::{Duration, Instant, SystemTime},
+};
 
+use clap::{Args, Parser, Subcommand, ValueEnum};
 use ew::{
-    interpreter::{Interpreter, Val},
-    parser::parse,
+    Diagnostics, Interpreter, Redeclaration, Val, WarningKind, bracket_depth, build_native,
+    code_blocks, parse, parse_all, resolve, to_c, to_js, to_wasm, tokenize,
 };
 use rustyline::{Editor, error::ReadlineError, history::DefaultHistory};
 
-fn main() {
-    let args: Vec<String> = args().collect();
+#[derive(Parser)]
+#[command(name = "ew", version, about)]
+#[command(args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// Script to run; starts the REPL when omitted
+    file: Option<String>,
+
+    /// Start the REPL without running $EW_INIT or ~/.ewrc
+    #[arg(long)]
+    no_init: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Run a script
+    Run {
+        file: String,
+        /// Treat the file as Markdown and run its ```ew code blocks in order
+        #[arg(long)]
+        literate: bool,
+        /// Run again, from a cleared screen, whenever the script or its
+        /// config file is saved
+        #[arg(long)]
+        watch: bool,
+        /// With --watch, swap the saved file's functions into the running
+        /// script instead of starting it again, keeping its variables
+        #[arg(long, requires = "watch", conflicts_with = "literate")]
+        hot: bool,
+        #[command(flatten)]
+        options: RunOptions,
+    },
+    /// Report every syntax error in a script without running it
+    Check {
+        file: String,
+        #[command(flatten)]
+        warnings: WarningOptions,
+    },
+    /// Compile a script to an executable or another language
+    Build {
+        file: String,
+        #[arg(long, value_enum, default_value = "native")]
+        target: Target,
+        /// Where to write the output; defaults to the script's path with
+        /// the target's extension, or none for an executable
+        #[arg(short, long)]
+        output: Option<String>,
+        #[command(flatten)]
+        warnings: WarningOptions,
+    },
+}
+
+#[derive(Args, Default)]
+struct RunOptions {
+    /// JSON file exposed to the script as the read-only `CONFIG`
+    #[arg(long)]
+    config: Option<String>,
+    /// Define a read-only global; the value is read as an int, float or
+    /// bool if it is one, and as a string otherwise
+    #[arg(short = 'D', value_name = "NAME=VALUE", value_parser = parse_define)]
+    define: Vec<(String, Val)>,
+    /// Count the strings, arrays and other values the script creates and
+    /// report where it made the most, on stderr
+    #[arg(long)]
+    alloc_profile: bool,
+    #[command(flatten)]
+    warnings: WarningOptions,
+}
+
+#[derive(Args, Default)]
+struct WarningOptions {
+    /// Control warnings: `none` or `all` switches every kind off or on,
+    /// `no-KIND` and `KIND` one kind (shadowing, undeclared, deprecated or
+    /// conversion), and `error` makes any warning fail the command
+    #[arg(short = 'W', value_name = "WARNING", value_parser = parse_warning_flag)]
+    warnings: Vec<WarningFlag>,
+}
+
+#[derive(Clone)]
+enum WarningFlag {
+    /// Switches one kind, or every kind when `None`, on or off.
+    Set(Option<WarningKind>, bool),
+    Error,
+}
+
+/// A `-W WARNING` argument.
+fn parse_warning_flag(arg: &str) -> Result<WarningFlag, String> {
+    let (name, enabled) = match arg.strip_prefix("no-") {
+        Some(name) => (name, false),
+        None => (arg, true),
+    };
+    match name {
+        "error" if enabled => Ok(WarningFlag::Error),
+        "all" => Ok(WarningFlag::Set(None, enabled)),
+        "none" if enabled => Ok(WarningFlag::Set(None, false)),
+        _ => match WarningKind::from_name(name) {
+            Some(kind) => Ok(WarningFlag::Set(Some(kind), enabled)),
+            None => Err(format!("unknown warning '{}'", arg)),
+        },
+    }
+}
+
+impl WarningOptions {
+    /// Applies the flags to `diagnostics` in order, so later ones win.
+    fn apply(&self, diagnostics: &mut Diagnostics) {
+        for flag in &self.warnings {
+            if let WarningFlag::Set(kind, enabled) = flag {
+                let kinds = kind
+                    .as_ref()
+                    .map_or(&WarningKind::ALL[..], std::slice::from_ref);
+                for kind in kinds {
+                    diagnostics.set_enabled(*kind, *enabled);
+                }
+            }
+        }
+    }
+
+    fn diagnostics(&self) -> Diagnostics {
+        let mut diagnostics = Diagnostics::new();
+        self.apply(&mut diagnostics);
+        diagnostics
+    }
 
-    if args.len() > 1 {
-        let file = &args[1];
-        run(file)
+    fn as_errors(&self) -> bool {
+        self.warnings
+            .iter()
+            .any(|flag| matches!(flag, WarningFlag::Error))
+    }
+}
+
+/// Prints the warnings collected since the last call, each after `prefix`;
+/// false if there were none.
+fn report_warnings(diagnostics: &mut Diagnostics, prefix: &str) -> bool {
+    let warnings = diagnostics.take();
+    for warning in &warnings {
+        eprintln!("{}{}", prefix, warning);
+    }
+    !warnings.is_empty()
+}
+
+/// A `-D NAME=VALUE` argument.
+fn parse_define(arg: &str) -> Result<(String, Val), String> {
+    let (name, value) = arg
+        .split_once('=')
+        .ok_or_else(|| "expected NAME=VALUE".to_string())?;
+    let is_ident = name.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_ident {
+        return Err(format!("'{}' is not a valid name", name));
+    }
+    let value = if let Ok(n) = value.parse() {
+        Val::Int(n)
+    } else if let Ok(f) = value.parse()
+        && value.contains(|c: char| c.is_ascii_digit())
+    {
+        // The digit keeps words like `nan` and `inf` strings.
+        Val::Float(f)
+    } else if let Ok(b) = value.parse() {
+        Val::Bool(b)
     } else {
-        repl();
+        Val::Str(value.to_string())
+    };
+    Ok((name.to_string(), value))
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Target {
+    /// A standalone executable, built with the C compiler named by CC
+    Native,
+    /// C source, for GCC or Clang
+    C,
+    /// JavaScript, for browsers and Node
+    Js,
+    /// A WebAssembly module exporting the script's numeric functions
+    Wasm,
+}
+
+fn main() {
+    let cli = Cli::parse();
+
+    match (cli.command, cli.file) {
+        (
+            Some(Command::Run {
+                file,
+                literate,
+                watch: true,
+                hot,
+                options,
+            }),
+            _,
+        ) => watch(&file, literate, hot, &options),
+        (
+            Some(Command::Run {
+                file,
+                literate,
+                options,
+                ..
+            }),
+            _,
+        ) => run(&file, literate, &options),
+        (None, Some(file)) => run(&file, false, &RunOptions::default()),
+        (Some(Command::Check { file, warnings }), _) => check(&file, &warnings),
+        (
+            Some(Command::Build {
+                file,
+                target,
+                output,
+                warnings,
+            }),
+            _,
+        ) => build(&file, target, output.as_deref(), &warnings),
+        (None, None) => repl(!cli.no_init),
     }
 }
 
-fn run(file: &str) {
-    let source = match fs::read_to_string(file) {
+fn read_source(file: &str) -> String {
+    match fs::read_to_string(file) {
         Ok(s) => s,
         Err(e) => {
             eprintln!("Error reading file '{}': {}", file, e);
             std::process::exit(1);
         }
+    }
+}
+
+fn file_interpreter(file: &str, options: &RunOptions) -> Interpreter {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_source_name(file);
+    if options.alloc_profile {
+        interpreter.enable_alloc_profile();
+    }
+    options.warnings.apply(interpreter.diagnostics_mut());
+    for (name, value) in &options.define {
+        interpreter.define_constant(name, value.clone());
+    }
+    if let Some(config) = &options.config {
+        match Val::from_json(&read_source(config)) {
+            Ok(config) => interpreter.set_config(config),
+            Err(e) => {
+                eprintln!("Error in config '{}': {}", config, e);
+                std::process::exit(1);
+            }
+        }
+    }
+    interpreter
+}
+
+/// Runs the script, or with `literate` the ew blocks of a Markdown file,
+/// exiting with an error status if it fails.
+fn run(file: &str, literate: bool, options: &RunOptions) {
+    let mut interpreter = file_interpreter(file, options);
+    if !execute(file, literate, &options.warnings, &mut interpreter) {
+        process::exit(1);
+    }
+}
+
+/// How often `--watch` looks for changes.
+const WATCH_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Runs the script in a fresh interpreter each time it or its config file
+/// changes, until interrupted. A run still going when a file changes, such
+/// as an animation's loop, is cancelled first, or with `hot` given the
+/// file's new functions.
+fn watch(file: &str, literate: bool, hot: bool, options: &RunOptions) {
+    let files: Vec<&str> = std::iter::once(file)
+        .chain(options.config.as_deref())
+        .collect();
+    let modified = || -> Vec<Option<SystemTime>> {
+        files
+            .iter()
+            .map(|f| fs::metadata(f).and_then(|m| m.modified()).ok())
+            .collect()
     };
+    loop {
+        let mut seen = modified();
+        print!("\x1b[2J\x1b[H");
+        io::stdout().flush().ok();
+        let mut interpreter = file_interpreter(file, options);
+        let cancel = interpreter.cancellation_token();
+        let reloader = interpreter.reloader();
+        thread::scope(|scope| {
+            let running =
+                scope.spawn(|| execute(file, literate, &options.warnings, &mut interpreter));
+            let mut reloads: Vec<Receiver<Result<Vec<String>, String>>> = Vec::new();
+            while !running.is_finished() {
+                thread::sleep(WATCH_INTERVAL);
+                reloads.retain(|outcome| match outcome.try_recv() {
+                    Ok(Ok(changed)) if changed.is_empty() => false,
+                    Ok(Ok(changed)) => {
+                        eprintln!("Reloaded {}", changed.join(", "));
+                        false
+                    }
+                    Ok(Err(e)) => {
+                        eprintln!("Error reloading {}: {}", file, e);
+                        false
+                    }
+                    Err(TryRecvError::Empty) => true,
+                    Err(TryRecvError::Disconnected) => false,
+                });
+                if modified() == seen {
+                    continue;
+                }
+                if !hot {
+                    cancel.store(true, Ordering::Relaxed);
+                    break;
+                }
+                seen = modified();
+                match fs::read_to_string(file)
+                    .map_err(|e| e.to_string())
+                    .and_then(|source| parse(&source))
+                {
+                    Ok(program) => reloads.push(reloader.reload(program)),
+                    Err(e) => eprintln!("Error reloading {}: {}", file, e),
+                }
+            }
+        });
+        if modified() == seen {
+            eprintln!("Waiting for changes to {}...", files.join(" or "));
+        }
+        while modified() == seen {
+            thread::sleep(WATCH_INTERVAL);
+        }
+    }
+}
 
-    match ew::run(&source) {
-        Ok(_) => println!(),
+/// Runs the file in `interpreter`, reporting errors and warnings on
+/// stderr; false if it failed, or warned under `-W error`.
+fn execute(
+    file: &str,
+    literate: bool,
+    warnings: &WarningOptions,
+    interpreter: &mut Interpreter,
+) -> bool {
+    let res = if literate {
+        run_literate(file, interpreter)
+    } else {
+        let source = read_source(file);
+        let res = parse(&source).and_then(|program| {
+            interpreter.check(&program);
+            if report_warnings(interpreter.diagnostics_mut(), "Warning: ") && warnings.as_errors() {
+                return Err("warnings are errors under -W error".to_string());
+            }
+            interpreter.run(&program)
+        });
+        match &res {
+            Ok(_) => println!(),
+            Err(e) => eprintln!("Error: {}", e),
+        }
+        res.is_ok()
+    };
+    let warned = report_warnings(interpreter.diagnostics_mut(), "Warning: ");
+    print_alloc_report(interpreter);
+    res && !(warned && warnings.as_errors())
+}
+
+/// Runs the ew blocks of a Markdown file in one interpreter, echoing each
+/// block followed by what it printed and its value, up to the first error.
+fn run_literate(file: &str, interpreter: &mut Interpreter) -> bool {
+    let markdown = read_source(file);
+
+    for block in code_blocks(&markdown) {
+        println!("```ew\n{}```", block.code);
+        match parse(&block.code).and_then(|program| interpreter.run(&program)) {
+            Ok(Val::Unit) => {}
+            Ok(value) => println!("=> {:#}", value),
+            Err(e) => {
+                eprintln!("Error in the block on line {}: {}", block.line, e);
+                return false;
+            }
+        }
+        println!();
+    }
+    true
+}
+
+/// Prints what `--alloc-profile` counted, if it was given.
+fn print_alloc_report(interpreter: &Interpreter) {
+    if let Some(report) = interpreter.alloc_report() {
+        eprint!("{}", report);
+    }
+}
+
+fn build(file: &str, target: Target, output: Option<&str>, warnings: &WarningOptions) {
+    let source = read_source(file);
+    let extension = match target {
+        Target::Native => "",
+        Target::C => "c",
+        Target::Js => "js",
+        Target::Wasm => "wasm",
+    };
+    let output = match output {
+        Some(output) => output.to_string(),
+        None => std::path::Path::new(file)
+            .with_extension(extension)
+            .display()
+            .to_string(),
+    };
+    let program = parse(&source).and_then(|program| {
+        let mut diagnostics = warnings.diagnostics();
+        diagnostics.extend(resolve(&program));
+        if report_warnings(&mut diagnostics, "Warning: ") && warnings.as_errors() {
+            return Err("warnings are errors under -W error".to_string());
+        }
+        Ok(program)
+    });
+    // None when the compiler wrote the output itself.
+    let compiled = program.and_then(|p| match target {
+        Target::Native => build_native(&p, Some(file), std::path::Path::new(&output)).map(|_| None),
+        Target::C => to_c(&p, Some(file)).map(|c| Some(c.into_bytes())),
+        Target::Js => to_js(&p).map(|js| Some(js.into_bytes())),
+        Target::Wasm => to_wasm(&p).map(|(module, notes)| {
+            for note in notes {
+                eprintln!("Warning: {}", note);
+            }
+            Some(module)
+        }),
+    });
+    let compiled = match compiled {
+        Ok(Some(compiled)) => compiled,
+        Ok(None) => return,
         Err(e) => {
             eprintln!("Error: {}", e);
             std::process::exit(1);
         }
+    };
+    if let Err(e) = fs::write(&output, compiled) {
+        eprintln!("Error writing file '{}': {}", output, e);
+        std::process::exit(1);
     }
 }
 
-fn repl() {
+fn check(file: &str, warnings: &WarningOptions) {
+    let source = read_source(file);
+
+    let errors = match parse_all(&source) {
+        Ok(program) => {
+            let mut diagnostics = warnings.diagnostics();
+            diagnostics.extend(resolve(&program));
+            let prefix = format!("{}: warning: ", file);
+            if report_warnings(&mut diagnostics, &prefix) && warnings.as_errors() {
+                std::process::exit(1);
+            }
+            return;
+        }
+        Err(errors) => errors,
+    };
+
+    for e in &errors {
+        let line = source.lines().nth(e.line - 1).unwrap_or("");
+        eprintln!("{}:{}:{}: error: {}", file, e.line, e.col, e.message);
+        eprintln!("    {}", line);
+        eprintln!("    {}^", " ".repeat(e.col - 1));
+    }
+    eprintln!("{} syntax error(s) found", errors.len());
+    std::process::exit(1);
+}
+
+/// Starts the REPL, first running the startup script unless `init` is
+/// false. The script can define helpers and set `PROMPT`.
+fn repl(init: bool) {
     println!("Lmao v0.0.1");
     println!("Type 'quit' to exit\n");
 
     let mut interpret = Interpreter::new();
+    interpret.set_redeclaration(Redeclaration::Lenient);
+    if init {
+        run_init(&mut interpret);
+    }
     let mut rl = Editor::<(), DefaultHistory>::new().unwrap();
+    // Whole inputs, multi-line ones in one piece, for `:edit`.
+    let mut entries: Vec<String> = Vec::new();
     loop {
         let mut inp = String::new();
 
-        let line = match rl.readline("> ") {
+        // Read each time so inputs can change it.
+        let prompt = match interpret.variable("PROMPT") {
+            Some(Val::Str(prompt)) => prompt.clone(),
+            _ => "> ".to_string(),
+        };
+        let line = match rl.readline(&prompt) {
             Ok(line) => line,
             Err(ReadlineError::Interrupted) => {
                 println!("^C");
@@ -70,6 +525,38 @@ fn repl() {
             break;
         }
 
+        if trim == ":history" {
+            for (i, entry) in entries.iter().enumerate() {
+                println!("{:>4}  {}", i + 1, entry.replace('\n', "\n      "));
+            }
+            continue;
+        }
+
+        if let Some(arg) = trim.strip_prefix(":edit") {
+            let entry = match arg.trim() {
+                "" => entries.last(),
+                n => n
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|n| entries.get(n.wrapping_sub(1))),
+            };
+            let Some(entry) = entry else {
+                eprintln!("No such history entry: {}", arg.trim());
+                continue;
+            };
+            match edit(entry) {
+                Ok(edited) if !edited.trim().is_empty() => {
+                    println!("{}", edited.trim_end());
+                    let edited = edited.trim().to_string();
+                    eval_input(&mut interpret, &edited);
+                    entries.push(edited);
+                }
+                Ok(_) => {}
+                Err(e) => eprintln!("Error: {}", e),
+            }
+            continue;
+        }
+
         rl.add_history_entry(line.as_str()).unwrap();
         inp.push_str(&line);
         inp.push('\n');
@@ -86,39 +573,132 @@ fn repl() {
         }
 
         let inp = inp.trim();
-        match parse(inp) {
-            Ok(program) => match interpret.run(&program) {
-                Ok(value) => {
-                    if value != Val::Unit {
-                        println!("{}", value);
-                    }
-                }
-                Err(e) => eprintln!("Runtime error: {}", e),
-            },
+        eval_input(&mut interpret, inp);
+        entries.push(inp.to_string());
+    }
+}
+
+fn eval_input(interpret: &mut Interpreter, inp: &str) {
+    let rewritten;
+    let inp = if let Some(name) = inp.strip_prefix(":doc ") {
+        rewritten = format!("help({})", name.trim());
+        &rewritten
+    } else if let Some(name) = inp.strip_prefix(":source ") {
+        rewritten = format!("source({})", name.trim());
+        &rewritten
+    } else if let Some(src) = inp.strip_prefix(":tokens ") {
+        for token in tokenize(src) {
+            let kind = format!("{:?}", token.kind);
+            println!(
+                "{:>4}..{:<4} {:<12} {}",
+                token.span.0, token.span.1, kind, token.text
+            );
+        }
+        return;
+    } else if let Some(src) = inp.strip_prefix(":ast ") {
+        match parse(src) {
+            Ok(program) => println!("{:#?}", program),
             Err(e) => eprintln!("Parse error: {}", e),
         }
+        return;
+    } else if let Some(src) = inp.strip_prefix(":bench ") {
+        bench(interpret, src);
+        return;
+    } else {
+        inp
+    };
+    match parse(inp) {
+        Ok(program) => match interpret.run(&program) {
+            Ok(value) => {
+                if value != Val::Unit {
+                    println!("{:#}", value);
+                }
+            }
+            Err(e) => eprintln!("Runtime error: {}", e),
+        },
+        Err(e) => eprintln!("Parse error: {}", e),
     }
+    report_warnings(interpret.diagnostics_mut(), "Warning: ");
 }
 
-fn bracket_depth(s: &str) -> i32 {
-    let mut depth = 0;
-    let mut in_string = false;
-    let mut prev_char = ' ';
+/// How long `:bench` keeps running its input.
+const BENCH_TIME: Duration = Duration::from_secs(1);
 
-    for c in s.chars() {
-        if c == '"' && prev_char != '\\' {
-            in_string = !in_string;
+/// Runs `src` over and over for about `BENCH_TIME`, at least once, and
+/// reports how long the runs took.
+fn bench(interpret: &mut Interpreter, src: &str) {
+    let program = match parse(src) {
+        Ok(program) => program,
+        Err(e) => return eprintln!("Parse error: {}", e),
+    };
+    let (mut runs, mut min, mut max) = (0u32, Duration::MAX, Duration::ZERO);
+    let start = Instant::now();
+    while runs == 0 || start.elapsed() < BENCH_TIME {
+        let run = Instant::now();
+        if let Err(e) = interpret.run(&program) {
+            return eprintln!("Runtime error: {}", e);
         }
+        let elapsed = run.elapsed();
+        min = min.min(elapsed);
+        max = max.max(elapsed);
+        runs += 1;
+    }
+    println!(
+        "{} runs: min {:.3?}, mean {:.3?}, max {:.3?}",
+        runs,
+        min,
+        start.elapsed() / runs,
+        max
+    );
+}
 
-        if !in_string {
-            match c {
-                '{' | '(' | '[' => depth += 1,
-                '}' | ')' | ']' => depth -= 1,
-                _ => {}
-            }
-        }
-        prev_char = c;
+/// Runs the script named by `$EW_INIT`, or `~/.ewrc` if there is one, in
+/// the REPL's interpreter. Errors are reported but don't stop the REPL.
+fn run_init(interpret: &mut Interpreter) {
+    let path = match env::var_os("EW_INIT") {
+        Some(path) => path.into(),
+        None => match env::var_os("HOME") {
+            Some(home) if Path::new(&home).join(".ewrc").exists() => Path::new(&home).join(".ewrc"),
+            _ => return,
+        },
+    };
+    let res = fs::read_to_string(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|source| parse(&source))
+        .and_then(|program| interpret.run(&program));
+    if let Err(e) = res {
+        eprintln!("Error in {}: {}", path.display(), e);
     }
+}
 
-    depth
+/// Opens `$VISUAL` or `$EDITOR` (falling back to vi) on a copy of `entry`
+/// and returns what was saved.
+fn edit(entry: &str) -> Result<String, String> {
+    let editor = env::var("VISUAL")
+        .or_else(|_| env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    // Allow values like `code --wait`.
+    let mut words = editor.split_whitespace();
+    let program = words.next().ok_or("$EDITOR is empty")?;
+
+    // A new file only this user can read, rather than a predictable name
+    // someone else could have put a link at first; removed when dropped.
+    let mut file = tempfile::Builder::new()
+        .prefix("ew-edit-")
+        .suffix(".ew")
+        .tempfile()
+        .map_err(|e| format!("could not create a file to edit: {}", e))?;
+    writeln!(file, "{}", entry).map_err(|e| e.to_string())?;
+    let path = file.path();
+    let status = process::Command::new(program)
+        .args(words)
+        .arg(path)
+        .status()
+        .map_err(|e| format!("could not start '{}': {}", editor, e));
+    let edited = fs::read_to_string(path).map_err(|e| e.to_string());
+
+    match status? {
+        status if status.success() => edited,
+        status => Err(format!("'{}' exited with {}", editor, status)),
+    }
 }
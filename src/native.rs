@@ -0,0 +1,1380 @@
+//! `ew build`: compiles a program to C, and with the system's C compiler to
+//! a standalone executable. The values and builtins come from a runtime,
+//! `native_runtime.c`, placed at the top of the C file, so the executable
+//! needs nothing else to run.
+//!
+//! Every ew function becomes a C function taking its arguments as an array,
+//! so that functions and builtins can be passed around as values. An `if`,
+//! `match` or loop used as a value becomes a GNU C statement expression, so
+//! the output needs GCC or Clang. Operators go through the runtime, which
+//! fails with the interpreter's error messages; as in the JavaScript target,
+//! a variable assigned through an index gets its own copy of the array.
+//!
+//! Big integers aren't supported: an overflowing operation stops the program
+//! with an error instead.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::process::{self, Command};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::{env, fs};
+
+use crate::ast::{
+    AssignmentTarget, BinaryOp, Expr, Iteration, Pattern, Stmt, UnaryOp, Visitor, walk_expr,
+    walk_stmt,
+};
+use crate::interpreter::builtin_doc;
+use crate::resolver::mentions;
+use crate::transpile::{index_assigned, is_statement};
+use crate::version;
+
+const INDENT: &str = "    ";
+
+const RUNTIME: &str = include_str!("native_runtime.c");
+
+/// The builtins `native_runtime.c` implements, with their C names.
+const RUNTIME_BUILTINS: &[(&str, &str)] = &[
+    ("print", "ew_print"),
+    ("println", "ew_println"),
+    ("pprint", "ew_pprint"),
+    ("str", "ew_str_"),
+    ("repr", "ew_repr_"),
+    ("len", "ew_len"),
+    ("keys", "ew_keys"),
+    ("copy", "ew_copy_"),
+    ("deep_copy", "ew_deep_copy"),
+    ("deep_eq", "ew_deep_eq"),
+    ("sort", "ew_sort"),
+    ("sort_by", "ew_sort_by"),
+    ("abs", "ew_abs"),
+    ("sqrt", "ew_sqrt"),
+    ("floor", "ew_floor"),
+    ("sin", "ew_sin"),
+    ("cos", "ew_cos"),
+    ("round", "ew_round"),
+    ("to_fixed", "ew_to_fixed"),
+    ("divmod", "ew_divmod"),
+    ("approx_eq", "ew_approx_eq"),
+    ("is_nan", "ew_is_nan"),
+    ("is_inf", "ew_is_inf"),
+    ("parse_int", "ew_parse_int"),
+    ("parse_float", "ew_parse_float"),
+    ("memoize", "ew_memoize"),
+];
+
+/// C keywords, and the names the generated code uses, which an ew variable
+/// must not shadow; they get a trailing `_`.
+const C_RESERVED: &[&str] = &[
+    "argc", "argv", "auto", "bool", "break", "case", "char", "const", "continue", "default", "do",
+    "double", "else", "enum", "extern", "false", "float", "for", "goto", "if", "inline", "int",
+    "int64_t", "long", "register", "restrict", "return", "short", "signed", "size_t", "sizeof",
+    "static", "struct", "switch", "true", "typedef", "union", "unsigned", "Val", "void",
+    "volatile", "while",
+];
+
+/// What to do with the value of the statement being compiled.
+#[derive(Clone)]
+enum Tail {
+    Discard,
+    Return,
+    /// Assign it to the named C variable.
+    Assign(String),
+}
+
+/// How a call reaches its function.
+enum Callee {
+    /// A C function, called directly.
+    Direct(String),
+    /// A function value, called through `ew_call`.
+    Value(String),
+}
+
+/// What is known about the functions of one name before compiling.
+#[derive(Default)]
+struct Definitions {
+    count: usize,
+    decorated: bool,
+    /// The C function and signature of the first definition.
+    symbol: String,
+    signature: String,
+}
+
+impl Definitions {
+    /// Whether calls can go straight to the C function. Otherwise they go
+    /// through the global the latest definition was stored in.
+    fn direct(&self) -> bool {
+        self.count == 1 && !self.decorated
+    }
+}
+
+/// A whole C program: the runtime followed by `program`. Functions show
+/// where they were defined in `source_name`, as in the interpreter.
+pub fn to_c(program: &[Stmt], source_name: Option<&str>) -> Result<String, String> {
+    let mut compiler = Compiler {
+        source_name: source_name.map(str::to_string),
+        out: String::new(),
+        functions: String::new(),
+        declarations: Vec::new(),
+        definitions: HashMap::new(),
+        defined: HashMap::new(),
+        enums: HashMap::new(),
+        scopes: vec![HashSet::new()],
+        copied: index_assigned(program),
+        loops: Vec::new(),
+        labels: Vec::new(),
+        temps: 0,
+    };
+    compiler.collect(program);
+    compiler.block(program, Tail::Discard)?;
+    compiler.line("return EW_UNIT_VAL;");
+
+    let mut code = compiler.declarations.join("\n");
+    code.push_str("\n\n");
+    code.push_str(&compiler.functions);
+    code.push_str("static Val ew_program(void) {\n");
+    code.push_str(&compiler.out);
+    code.push_str("}\n");
+    Ok(format!("{}\n{}", RUNTIME, reindent(&code)))
+}
+
+/// Compiles `program` to an executable at `output`, with the C compiler
+/// named by `CC`, or `cc`.
+pub fn build(program: &[Stmt], source_name: Option<&str>, output: &Path) -> Result<(), String> {
+    static BUILDS: AtomicUsize = AtomicUsize::new(0);
+    let c = to_c(program, source_name)?;
+    let n = BUILDS.fetch_add(1, Ordering::Relaxed);
+    let source = env::temp_dir().join(format!("ew-build-{}-{}.c", process::id(), n));
+    fs::write(&source, c).map_err(|e| format!("Couldn't write {}: {}", source.display(), e))?;
+
+    let cc = env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let res = Command::new(&cc)
+        .args(["-std=gnu11", "-O2", "-w", "-o"])
+        .arg(output)
+        .arg(&source)
+        .arg("-lm")
+        .output();
+    fs::remove_file(&source).ok();
+    let res = res.map_err(|e| format!("Couldn't run the C compiler '{}': {}", cc, e))?;
+    if !res.status.success() {
+        return Err(format!(
+            "The C compiler failed:\n{}",
+            String::from_utf8_lossy(&res.stderr)
+        ));
+    }
+    Ok(())
+}
+
+struct Compiler {
+    source_name: Option<String>,
+    /// The body of the C function being compiled, one statement per line;
+    /// `reindent` lays it out at the end.
+    out: String,
+    /// The finished C functions.
+    functions: String,
+    /// Prototypes, and the globals holding functions that aren't called
+    /// directly.
+    declarations: Vec<String>,
+    definitions: HashMap<String, Definitions>,
+    /// How many definitions of each name have been compiled so far.
+    defined: HashMap<String, usize>,
+    /// The arity of each variant, by enum.
+    enums: HashMap<String, HashMap<String, usize>>,
+    /// C names declared with `let` or as parameters, innermost scope last.
+    scopes: Vec<HashSet<String>>,
+    /// Variables of the current function that are assigned through an
+    /// index, and so must not share their array with anything else.
+    copied: HashSet<String>,
+    /// What `break value` does in each enclosing loop, innermost last.
+    loops: Vec<Tail>,
+    /// The ew label, C label and tail of each enclosing labeled loop.
+    labels: Vec<(String, String, Tail)>,
+    temps: usize,
+}
+
+impl Compiler {
+    /// Records the functions and enums of the whole program, since a
+    /// function may call one defined after it.
+    fn collect(&mut self, program: &[Stmt]) {
+        struct Collector<'a>(&'a mut Compiler);
+        impl Visitor for Collector<'_> {
+            fn visit_stmt(&mut self, stmt: &Stmt) {
+                match stmt {
+                    Stmt::Function {
+                        name,
+                        params,
+                        decorators,
+                        line,
+                        ..
+                    } => {
+                        let signature = self.0.signature(name, params, *line);
+                        let defs = self.0.definitions.entry(name.clone()).or_default();
+                        defs.count += 1;
+                        defs.decorated |= !decorators.is_empty();
+                        if defs.count == 1 {
+                            defs.symbol = format!("fn_{}", name);
+                            defs.signature = signature;
+                        }
+                    }
+                    Stmt::Enum { name, variants } => {
+                        let arities = variants
+                            .iter()
+                            .map(|v| (v.name.clone(), v.fields.len()))
+                            .collect();
+                        self.0.enums.insert(name.clone(), arities);
+                    }
+                    _ => {}
+                }
+                walk_stmt(self, stmt);
+            }
+        }
+        let mut collector = Collector(self);
+        for stmt in program {
+            collector.visit_stmt(stmt);
+        }
+    }
+
+    /// Where a function starting on `line` was defined, if known.
+    fn location(&self, line: usize) -> Option<String> {
+        match (&self.source_name, line) {
+            (Some(file), 1..) => Some(format!("{}:{}", file, line)),
+            _ => None,
+        }
+    }
+
+    /// What a function value shows after `fn `.
+    fn signature(&self, name: &str, params: &[String], line: usize) -> String {
+        let mut signature = format!("{}({})", name, params.join(", "));
+        if let Some(at) = self.location(line) {
+            signature.push_str(&format!(" at {}", at));
+        }
+        signature
+    }
+
+    fn line(&mut self, text: &str) {
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    fn temp(&mut self, kind: &str) -> String {
+        self.temps += 1;
+        format!("ew_{}{}", kind, self.temps)
+    }
+
+    fn declare(&mut self, name: String) {
+        self.scopes.last_mut().unwrap().insert(name);
+    }
+
+    fn declared(&self, name: &str) -> bool {
+        self.scopes.iter().any(|scope| scope.contains(name))
+    }
+
+    /// Compiles `stmts` inside a new scope, giving the last statement's
+    /// value to `tail`.
+    fn nested(&mut self, stmts: &[Stmt], tail: Tail) -> Result<(), String> {
+        self.scopes.push(HashSet::new());
+        let res = self.block(stmts, tail);
+        self.scopes.pop();
+        res
+    }
+
+    fn block(&mut self, stmts: &[Stmt], tail: Tail) -> Result<(), String> {
+        let Some((last, init)) = stmts.split_last() else {
+            self.tail_unit(tail);
+            return Ok(());
+        };
+        for stmt in init {
+            self.stmt(stmt, Tail::Discard)?;
+        }
+        self.stmt(last, tail)
+    }
+
+    /// Gives `()` to `tail`, for a statement that has no value.
+    fn tail_unit(&mut self, tail: Tail) {
+        match tail {
+            Tail::Discard => {}
+            Tail::Return => self.line("return EW_UNIT_VAL;"),
+            Tail::Assign(name) => self.line(&format!("{} = EW_UNIT_VAL;", name)),
+        }
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, tail: Tail) -> Result<(), String> {
+        match stmt {
+            Stmt::Function {
+                name,
+                params,
+                body,
+                decorators,
+                line,
+            } => {
+                self.function(name, params, body, decorators, *line)?;
+                self.tail_unit(tail);
+            }
+            Stmt::Enum { .. } => self.tail_unit(tail),
+            Stmt::Return(value) => self.expr_stmt(value, Tail::Return)?,
+            Stmt::Break(value) => {
+                let tail = self
+                    .loops
+                    .last()
+                    .cloned()
+                    .ok_or("'break' outside of a loop")?;
+                match value {
+                    Some(value) => self.expr_stmt(value, tail.clone())?,
+                    None => self.tail_unit(tail.clone()),
+                }
+                if !matches!(tail, Tail::Return) {
+                    self.line("break;");
+                }
+            }
+            Stmt::BreakLabel(label) => {
+                let (_, end, tail) = self
+                    .labels
+                    .iter()
+                    .rev()
+                    .find(|(l, ..)| l == label)
+                    .cloned()
+                    .ok_or_else(|| format!("No enclosing loop is labeled {}", label))?;
+                let returns = matches!(tail, Tail::Return);
+                self.tail_unit(tail);
+                if !returns {
+                    self.line(&format!("goto {};", end));
+                }
+            }
+            Stmt::Assignment { name, value } => {
+                let c = ident(name);
+                let redeclared = self.scopes.last().unwrap().contains(&c);
+                if is_statement(value) {
+                    if !redeclared {
+                        self.line(&format!("Val {};", c));
+                    }
+                    self.expr_stmt(value, Tail::Assign(c.clone()))?;
+                    if self.copied.contains(name) {
+                        self.line(&format!("{0} = ew_copy({0});", c));
+                    }
+                } else {
+                    let value = self.bound_value(name, value)?;
+                    let keyword = if redeclared { "" } else { "Val " };
+                    self.line(&format!("{}{} = {};", keyword, c, value));
+                }
+                self.declare(c);
+                self.tail_unit(tail);
+            }
+            Stmt::Reassignment { target, value } => {
+                match target {
+                    AssignmentTarget::Ident(name) => {
+                        let c = ident(name);
+                        if !self.declared(&c) {
+                            let message = format!(
+                                "The variable [{0}] does not exist; use `let {0} = ...` to declare it",
+                                name
+                            );
+                            self.line(&format!("ew_error({});", c_string(&message)));
+                        } else if is_statement(value) {
+                            self.expr_stmt(value, Tail::Assign(c.clone()))?;
+                            if self.copied.contains(name) {
+                                self.line(&format!("{0} = ew_copy({0});", c));
+                            }
+                        } else {
+                            let value = self.bound_value(name, value)?;
+                            self.line(&format!("{} = {};", c, value));
+                        }
+                    }
+                    AssignmentTarget::ArrayAccess { name, indices } => {
+                        self.index_assignment(name, indices, value)?
+                    }
+                }
+                self.tail_unit(tail);
+            }
+            Stmt::Expr(expr) => self.expr_stmt(expr, tail)?,
+            Stmt::Pragma(pragma) => {
+                version::require_default(pragma)?;
+                self.tail_unit(tail);
+            }
+        }
+        Ok(())
+    }
+
+    /// Compiles a function to a C function of its own, and at its place in
+    /// the program stores it in its global when calls go through one.
+    fn function(
+        &mut self,
+        name: &str,
+        params: &[String],
+        body: &[Stmt],
+        decorators: &[String],
+        line: usize,
+    ) -> Result<(), String> {
+        let signature = self.signature(name, params, line);
+        let shown = match self.location(line) {
+            Some(at) => format!("{} (defined at {})", name, at),
+            None => name.to_string(),
+        };
+        let count = self.defined.entry(name.to_string()).or_default();
+        *count += 1;
+        let count = *count;
+        let symbol = match count {
+            1 => format!("fn_{}", name),
+            n => format!("fn_{}_{}", name, n),
+        };
+        self.declarations
+            .push(format!("static Val {}(int argc, Val *argv);", symbol));
+
+        // Functions see only their parameters, never the caller's variables.
+        let outer = (
+            std::mem::take(&mut self.out),
+            std::mem::replace(&mut self.scopes, vec![HashSet::new()]),
+            std::mem::replace(&mut self.copied, index_assigned(body)),
+            std::mem::take(&mut self.loops),
+            std::mem::take(&mut self.labels),
+        );
+        self.line(&format!("static Val {}(int argc, Val *argv) {{", symbol));
+        self.line(&format!(
+            "ew_arity({}, argc, {});",
+            c_string(&shown),
+            params.len()
+        ));
+        for (i, param) in params.iter().enumerate() {
+            let c = ident(param);
+            if self.copied.contains(param) {
+                self.line(&format!("Val {} = ew_copy(argv[{}]);", c, i));
+            } else {
+                self.line(&format!("Val {} = argv[{}];", c, i));
+            }
+            self.declare(c);
+        }
+        let res = self.block(body, Tail::Return);
+        self.line("}");
+        let compiled = std::mem::replace(&mut self.out, outer.0);
+        (self.scopes, self.copied, self.loops, self.labels) = (outer.1, outer.2, outer.3, outer.4);
+        res?;
+        self.functions.push_str(&compiled);
+        self.functions.push('\n');
+
+        if self.definitions[name].direct() {
+            return Ok(());
+        }
+        let global = format!("fv_{}", name);
+        if count == 1 {
+            self.declarations.push(format!("static Val {};", global));
+        }
+        let mut value = format!(
+            "ew_function({}, {}, {})",
+            c_string(name),
+            c_string(&signature),
+            symbol
+        );
+        // The decorator nearest the `fn` is applied first.
+        for decorator in decorators.iter().rev() {
+            let callee = self.callee(decorator)?;
+            value = apply(callee, &[value]);
+        }
+        self.line(&format!("{} = {};", global, value));
+        Ok(())
+    }
+
+    /// `name[i][j] = value`. The value is evaluated first, then the
+    /// indices, as in the interpreter.
+    fn index_assignment(
+        &mut self,
+        name: &str,
+        indices: &[Box<Expr>],
+        value: &Expr,
+    ) -> Result<(), String> {
+        let c = ident(name);
+        if !self.declared(&c) {
+            let message = format!("The variable [{}] does not exist", name);
+            self.line(&format!("ew_error({});", c_string(&message)));
+            return Ok(());
+        }
+        let mut operands: Vec<&Expr> = vec![value];
+        operands.extend(indices.iter().map(|index| &**index));
+        let (prelude, mut compiled) = self.sequence(&operands)?;
+        let mut value_c = compiled.remove(0);
+        // The array now holds the value, so it mustn't share it.
+        if !is_fresh(value) {
+            value_c = format!("ew_copy({})", value_c);
+        }
+        let mut slot = format!("&{}", c);
+        for index in compiled {
+            slot = format!("ew_slot({}, {})", slot, index);
+        }
+        if prelude.is_empty() {
+            self.line(&format!("*{} = {};", slot, value_c));
+        } else {
+            self.line("{");
+            for line in prelude {
+                self.line(&line);
+            }
+            self.line(&format!("*{} = {};", slot, value_c));
+            self.line("}");
+        }
+        Ok(())
+    }
+
+    /// The value `name` is bound to, copied if `name` is changed through an
+    /// index later and the value may be shared.
+    fn bound_value(&mut self, name: &str, value: &Expr) -> Result<String, String> {
+        let c = self.expr(value)?;
+        Ok(if self.copied.contains(name) && !is_fresh(value) {
+            format!("ew_copy({})", c)
+        } else {
+            c
+        })
+    }
+
+    /// Compiles `expr` as C statements whose value goes to `tail`.
+    fn expr_stmt(&mut self, expr: &Expr, tail: Tail) -> Result<(), String> {
+        match expr {
+            Expr::If {
+                cond, then, else_, ..
+            } => {
+                let cond = self.expr(cond)?;
+                self.line(&format!("if (ew_cond({}, \"If\")) {{", cond));
+                self.nested(then, tail.clone())?;
+                self.else_branch(else_, tail)?;
+            }
+            Expr::IfLet {
+                name,
+                value,
+                then,
+                else_,
+            } => {
+                let temp = self.temp("t");
+                let value = self.expr(value)?;
+                self.line("{");
+                self.line(&format!("Val {} = {};", temp, value));
+                self.line(&format!("if ({}.kind != EW_NULL) {{", temp));
+                let c = ident(name);
+                self.line(&format!("Val {} = {};", c, temp));
+                self.scopes.push(HashSet::from([c]));
+                let res = self.block(then, tail.clone());
+                self.scopes.pop();
+                res?;
+                self.else_branch(else_, tail)?;
+                self.line("}");
+            }
+            Expr::While { cond, body, .. } => {
+                self.loop_start(&tail);
+                let cond = self.expr(cond)?;
+                self.line(&format!("while (ew_cond({}, \"While\")) {{", cond));
+                self.loop_body(body, HashSet::new(), tail)?;
+            }
+            Expr::WhileLet { name, value, body } => {
+                self.loop_start(&tail);
+                let c = ident(name);
+                self.line("while (1) {");
+                let value = self.expr(value)?;
+                self.line(&format!("Val {} = {};", c, value));
+                self.line(&format!("if ({}.kind == EW_NULL) break;", c));
+                self.loop_body(body, HashSet::from([c]), tail)?;
+            }
+            Expr::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                self.loop_start(&tail);
+                let c = ident(var);
+                let i = self.temp("i");
+                // Literal bounds need no check; others are checked, once.
+                let simple = matches!((&**start, &**end), (Expr::Int(_), Expr::Int(_)));
+                let (start, end) = match (&**start, &**end) {
+                    (Expr::Int(start), Expr::Int(end)) => (start.to_string(), end.to_string()),
+                    _ => {
+                        let (s, e) = (self.temp("t"), self.temp("t"));
+                        let (prelude, bounds) = self.sequence(&[start, end])?;
+                        self.line("{");
+                        for line in prelude {
+                            self.line(&line);
+                        }
+                        self.line(&format!("Val {} = {};", s, bounds[0]));
+                        self.line(&format!("Val {} = {};", e, bounds[1]));
+                        self.line(&format!("ew_range({}, {}, \"ineteger\");", s, e));
+                        (format!("{}.i", s), format!("{}.i", e))
+                    }
+                };
+                self.line(&format!(
+                    "for (int64_t {0} = {1}; {0} < {2}; {0}++) {{",
+                    i, start, end
+                ));
+                self.line(&format!("Val {} = ew_int({});", c, i));
+                self.loop_body(body, HashSet::from([c]), tail)?;
+                if !simple {
+                    self.line("}");
+                }
+            }
+            Expr::Loop(body) => {
+                self.loop_start(&tail);
+                self.line("while (1) {");
+                self.loop_body(body, HashSet::new(), tail)?;
+            }
+            Expr::Labeled { label, body } => {
+                let end = self.temp("end");
+                self.labels.push((label.clone(), end.clone(), tail.clone()));
+                let res = self.expr_stmt(body, tail);
+                self.labels.pop();
+                res?;
+                self.line(&format!("{}:;", end));
+            }
+            Expr::Match { scrutinee, arms } => {
+                let subject = match &**scrutinee {
+                    Expr::Var(name) if self.declared(&ident(name)) => ident(name),
+                    other => {
+                        let temp = self.temp("subject");
+                        let value = self.expr(other)?;
+                        self.line("{");
+                        self.line(&format!("Val {} = {};", temp, value));
+                        temp
+                    }
+                };
+                let mut keyword = "if";
+                let mut exhaustive = false;
+                for arm in arms {
+                    let (cond, bindings) = match &arm.pattern {
+                        Pattern::Wildcard => (None, &[][..]),
+                        Pattern::Literal(value) => {
+                            let value = self.expr(value)?;
+                            (Some(format!("ew_equal({}, {})", subject, value)), &[][..])
+                        }
+                        Pattern::Variant {
+                            enum_name,
+                            variant,
+                            bindings,
+                        } => (
+                            Some(format!(
+                                "ew_is_variant({}, {}, {}, {})",
+                                subject,
+                                c_string(enum_name),
+                                c_string(variant),
+                                bindings.len()
+                            )),
+                            &bindings[..],
+                        ),
+                    };
+                    match cond {
+                        Some(cond) => self.line(&format!("{} ({}) {{", keyword, cond)),
+                        None if keyword == "if" => self.line("{"),
+                        None => self.line("} else {"),
+                    }
+                    let mut scope = HashSet::new();
+                    for (i, binding) in bindings.iter().enumerate() {
+                        let c = ident(binding);
+                        self.line(&format!("Val {} = {}.v->fields[{}];", c, subject, i));
+                        scope.insert(c);
+                    }
+                    self.scopes.push(scope);
+                    let res = self.block(&arm.body, tail.clone());
+                    self.scopes.pop();
+                    res?;
+                    if matches!(arm.pattern, Pattern::Wildcard) {
+                        exhaustive = true;
+                        break;
+                    }
+                    keyword = "} else if";
+                }
+                if !exhaustive {
+                    if keyword != "if" {
+                        self.line("} else {");
+                    }
+                    self.line(&format!("ew_no_match({});", subject));
+                }
+                if keyword != "if" || exhaustive {
+                    self.line("}");
+                }
+                if !matches!(&**scrutinee, Expr::Var(name) if self.declared(&ident(name))) {
+                    self.line("}");
+                }
+            }
+            Expr::LetIn { name, .. } if matches!(&tail, Tail::Assign(target) if *target == ident(name)) =>
+            {
+                // The binding would shadow the variable the value goes to.
+                let c = self.statement_expr(expr)?;
+                self.line(&format!("{} = {};", ident(name), c));
+            }
+            Expr::LetIn { name, value, body } => {
+                let c = ident(name);
+                // A value that reads the variable being shadowed is worked
+                // out before the new one is declared.
+                let early = mentions(value, name);
+                let bound = if early {
+                    self.temp("bound")
+                } else {
+                    self.line("{");
+                    c.clone()
+                };
+                if is_statement(value) {
+                    self.line(&format!("Val {};", bound));
+                    self.expr_stmt(value, Tail::Assign(bound.clone()))?;
+                    if self.copied.contains(name) {
+                        self.line(&format!("{0} = ew_copy({0});", bound));
+                    }
+                } else {
+                    let value = self.bound_value(name, value)?;
+                    self.line(&format!("Val {} = {};", bound, value));
+                }
+                if early {
+                    self.line("{");
+                    self.line(&format!("Val {} = {};", c, bound));
+                }
+                self.scopes.push(HashSet::from([c]));
+                let res = self.expr_stmt(body, tail);
+                self.scopes.pop();
+                res?;
+                self.line("}");
+            }
+            Expr::Block(stmts) => {
+                self.line("{");
+                self.nested(stmts, tail)?;
+                self.line("}");
+            }
+            expr => {
+                let c = self.expr(expr)?;
+                match tail {
+                    Tail::Discard => self.line(&format!("{};", c)),
+                    Tail::Return => self.line(&format!("return {};", c)),
+                    Tail::Assign(name) => self.line(&format!("{} = {};", name, c)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `} else ...` after an `if` branch, and the closing brace.
+    fn else_branch(&mut self, else_: &[Stmt], tail: Tail) -> Result<(), String> {
+        match else_ {
+            [] if matches!(tail, Tail::Discard) => self.line("}"),
+            [
+                Stmt::Expr(Expr::If {
+                    cond, then, else_, ..
+                }),
+            ] => {
+                let cond = self.expr(cond)?;
+                self.line(&format!("}} else if (ew_cond({}, \"If\")) {{", cond));
+                self.nested(then, tail.clone())?;
+                self.else_branch(else_, tail)?;
+            }
+            stmts => {
+                self.line("} else {");
+                self.nested(stmts, tail)?;
+                self.line("}");
+            }
+        }
+        Ok(())
+    }
+
+    /// A loop is `()` unless left with `break value`.
+    fn loop_start(&mut self, tail: &Tail) {
+        if let Tail::Assign(name) = tail {
+            self.line(&format!("{} = EW_UNIT_VAL;", name));
+        }
+    }
+
+    /// A loop's body, with `scope` bound in it, and its closing brace.
+    fn loop_body(
+        &mut self,
+        body: &[Stmt],
+        scope: HashSet<String>,
+        tail: Tail,
+    ) -> Result<(), String> {
+        self.loops.push(tail.clone());
+        self.scopes.push(scope);
+        let res = self.block(body, Tail::Discard);
+        self.scopes.pop();
+        self.loops.pop();
+        res?;
+        self.line("}");
+        if let Tail::Return = tail {
+            self.line("return EW_UNIT_VAL;");
+        }
+        Ok(())
+    }
+
+    /// Compiles `exprs`, which must run in order. C leaves the order of
+    /// arguments open, so all but the last with side effects are moved to
+    /// temporaries, declared by the returned lines.
+    fn sequence(&mut self, exprs: &[&Expr]) -> Result<(Vec<String>, Vec<String>), String> {
+        let last = exprs.iter().rposition(|e| has_effects(e));
+        let mut prelude = Vec::new();
+        let mut compiled = Vec::new();
+        for (i, expr) in exprs.iter().enumerate() {
+            let c = self.expr(expr)?;
+            if has_effects(expr) && Some(i) != last {
+                let temp = self.temp("t");
+                prelude.push(format!("Val {} = {};", temp, c));
+                compiled.push(temp);
+            } else {
+                compiled.push(c);
+            }
+        }
+        Ok((prelude, compiled))
+    }
+
+    /// `exprs` compiled in order and passed to `build`, which makes an
+    /// expression of them.
+    fn ordered(
+        &mut self,
+        exprs: &[&Expr],
+        build: impl FnOnce(&[String]) -> String,
+    ) -> Result<String, String> {
+        let (prelude, compiled) = self.sequence(exprs)?;
+        let c = build(&compiled);
+        Ok(if prelude.is_empty() {
+            c
+        } else {
+            format!("({{\n{}\n{};\n}})", prelude.join("\n"), c)
+        })
+    }
+
+    fn callee(&self, name: &str) -> Result<Callee, String> {
+        let c = ident(name);
+        if self.declared(&c) {
+            Ok(Callee::Value(c))
+        } else if let Some(defs) = self.definitions.get(name) {
+            Ok(if defs.direct() {
+                Callee::Direct(defs.symbol.clone())
+            } else {
+                Callee::Value(format!("fv_{}", name))
+            })
+        } else if let Some((_, c)) = RUNTIME_BUILTINS.iter().find(|(n, _)| *n == name) {
+            Ok(Callee::Direct(c.to_string()))
+        } else if builtin_doc(name).is_some() {
+            Err(format!("{}() has no C equivalent", name))
+        } else {
+            Ok(Callee::Value(format!(
+                "ew_error({})",
+                c_string(&format!("Undefined Variable: {}", name))
+            )))
+        }
+    }
+
+    fn call(&mut self, name: &str, args: &[Expr]) -> Result<String, String> {
+        let callee = self.callee(name)?;
+        if !args.iter().any(|arg| matches!(arg, Expr::Spread(_))) {
+            let args: Vec<&Expr> = args.iter().collect();
+            return self.ordered(&args, |args| apply(callee, args));
+        }
+        let list = self.temp("args");
+        let mut lines = vec![format!("EwArray *{} = ew_array_new({});", list, args.len())];
+        for arg in args {
+            lines.push(match arg {
+                Expr::Spread(items) => format!("ew_spread({}, {});", list, self.expr(items)?),
+                arg => format!("ew_push({}, {});", list, self.expr(arg)?),
+            });
+        }
+        let argv = format!("(int){0}->len, {0}->items", list);
+        let call = match callee {
+            Callee::Direct(f) => format!("{}({})", f, argv),
+            Callee::Value(f) => format!("ew_call({}, {})", f, argv),
+        };
+        Ok(format!("({{\n{}\n{};\n}})", lines.join("\n"), call))
+    }
+
+    fn var(&self, name: &str) -> Result<String, String> {
+        let c = ident(name);
+        if self.declared(&c) {
+            return Ok(c);
+        }
+        Ok(match name {
+            "NAN" => "ew_float(NAN)".to_string(),
+            "INF" => "ew_float(INFINITY)".to_string(),
+            _ => match self.callee(name)? {
+                Callee::Direct(symbol) => match self.definitions.get(name) {
+                    Some(defs) => format!(
+                        "ew_function({}, {}, {})",
+                        c_string(name),
+                        c_string(&defs.signature),
+                        symbol
+                    ),
+                    None => format!("ew_function({}, NULL, {})", c_string(name), symbol),
+                },
+                Callee::Value(value) => value,
+            },
+        })
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<String, String> {
+        Ok(match expr {
+            Expr::Int(n) => format!("ew_int({})", n),
+            Expr::BigInt(_) => {
+                return Err("Integers too big for 64 bits can't be compiled to C".into());
+            }
+            Expr::Float(f) if f.is_infinite() => {
+                format!("ew_float({}INFINITY)", if *f < 0.0 { "-" } else { "" })
+            }
+            Expr::Float(f) => format!("ew_float({:?})", f),
+            Expr::Bool(b) => format!("ew_bool({})", b),
+            Expr::Str { value: s, .. } => format!("ew_str({}, {})", c_string(s), s.len()),
+            Expr::Null => "EW_NULL_VAL".to_string(),
+            Expr::Array { items, .. } if items.is_empty() => "ew_array(0, NULL)".to_string(),
+            Expr::Array { items, .. } => {
+                let items: Vec<&Expr> = items.iter().collect();
+                let n = items.len();
+                self.ordered(&items, |items| {
+                    format!("ew_array({}, (Val[]){{{}}})", n, items.join(", "))
+                })?
+            }
+            Expr::ArrayAccess { name, indices, .. } => {
+                let var = self.var(name)?;
+                let indices: Vec<&Expr> = indices.iter().map(|index| &**index).collect();
+                self.ordered(&indices, |indices| {
+                    indices
+                        .iter()
+                        .fold(var, |acc, index| format!("ew_index({}, {})", acc, index))
+                })?
+            }
+            Expr::Slice {
+                name,
+                indices,
+                start,
+                end,
+                ..
+            } => {
+                let var = self.var(name)?;
+                let mut exprs: Vec<&Expr> = indices.iter().map(|index| &**index).collect();
+                exprs.extend(start.iter().chain(end).map(|bound| &**bound));
+                self.ordered(&exprs, |exprs| {
+                    let (indices, mut bounds) =
+                        (&exprs[..indices.len()], exprs[indices.len()..].iter());
+                    let value = indices
+                        .iter()
+                        .fold(var, |acc, index| format!("ew_index({}, {})", acc, index));
+                    // A missing bound is null.
+                    let mut bound = |present: bool| match present {
+                        true => bounds.next().unwrap().clone(),
+                        false => "EW_NULL_VAL".to_string(),
+                    };
+                    let (start, end) = (bound(start.is_some()), bound(end.is_some()));
+                    format!("ew_slice({}, {}, {})", value, start, end)
+                })?
+            }
+            Expr::Var(name) => self.var(name)?,
+            Expr::Unary { op, expr, .. } => {
+                let f = match op {
+                    UnaryOp::Neg => "ew_neg",
+                    UnaryOp::Not => "ew_not",
+                };
+                format!("{}({})", f, self.expr(expr)?)
+            }
+            Expr::Binary { op, lhs, rhs, .. } => {
+                let f = match op {
+                    BinaryOp::Add => "ew_add",
+                    BinaryOp::Sub => "ew_sub",
+                    BinaryOp::Mul => "ew_mul",
+                    BinaryOp::Div => "ew_div",
+                    BinaryOp::Mod => "ew_mod",
+                    BinaryOp::Pow => "ew_pow",
+                    BinaryOp::Eq => "ew_eq",
+                    BinaryOp::Ne => "ew_ne",
+                    BinaryOp::Lt => "ew_lt",
+                    BinaryOp::Le => "ew_le",
+                    BinaryOp::Gt => "ew_gt",
+                    BinaryOp::Ge => "ew_ge",
+                    BinaryOp::And => "ew_and",
+                    BinaryOp::Or => "ew_or",
+                };
+                self.ordered(&[lhs, rhs], |operands| {
+                    format!("{}({}, {})", f, operands[0], operands[1])
+                })?
+            }
+            Expr::Call { name, args, .. } => self.call(name, args)?,
+            Expr::Spread(_) => "ew_error(\"'...' is only allowed in call arguments\")".to_string(),
+            Expr::Variant {
+                enum_name,
+                variant,
+                args,
+                ..
+            } => {
+                let arity =
+                    self.enums
+                        .get(enum_name)
+                        .ok_or_else(|| format!("Undefined enum: {}", enum_name))
+                        .and_then(|variants| {
+                            variants.get(variant).copied().ok_or_else(|| {
+                                format!("Enum {} has no variant {}", enum_name, variant)
+                            })
+                        });
+                let message = match arity {
+                    Err(message) => Some(message),
+                    Ok(arity) if arity != args.len() => Some(format!(
+                        "Variant {}::{} expects {} fields, got {}",
+                        enum_name,
+                        variant,
+                        arity,
+                        args.len()
+                    )),
+                    Ok(_) => None,
+                };
+                if let Some(message) = message {
+                    return Ok(format!("ew_error({})", c_string(&message)));
+                }
+                let (enum_name, variant) = (c_string(enum_name), c_string(variant));
+                if args.is_empty() {
+                    format!("ew_variant({}, {}, 0, NULL)", enum_name, variant)
+                } else {
+                    let args: Vec<&Expr> = args.iter().collect();
+                    self.ordered(&args, |fields| {
+                        format!(
+                            "ew_variant({}, {}, {}, (Val[]){{{}}})",
+                            enum_name,
+                            variant,
+                            fields.len(),
+                            fields.join(", ")
+                        )
+                    })?
+                }
+            }
+            Expr::Comprehension {
+                key,
+                value,
+                var,
+                source,
+                cond,
+                ..
+            } => self.comprehension(key.as_deref(), value, var, source, cond.as_deref())?,
+            Expr::If {
+                cond, then, else_, ..
+            } => match (&then[..], &else_[..]) {
+                // Simple conditional values read best as a ternary.
+                ([Stmt::Expr(a)], [Stmt::Expr(b)]) if !is_statement(a) && !is_statement(b) => {
+                    format!(
+                        "(ew_cond({}, \"If\") ? {} : {})",
+                        self.expr(cond)?,
+                        self.expr(a)?,
+                        self.expr(b)?
+                    )
+                }
+                _ => self.statement_expr(expr)?,
+            },
+            _ => self.statement_expr(expr)?,
+        })
+    }
+
+    /// A statement used as a value, as a GNU C statement expression.
+    fn statement_expr(&mut self, expr: &Expr) -> Result<String, String> {
+        let result = self.temp("r");
+        let outer = std::mem::take(&mut self.out);
+        self.line("({");
+        self.line(&format!("Val {};", result));
+        let res = self.expr_stmt(expr, Tail::Assign(result.clone()));
+        self.line(&format!("{};", result));
+        self.out.push_str("})");
+        let c = std::mem::replace(&mut self.out, outer);
+        res?;
+        Ok(c)
+    }
+
+    fn comprehension(
+        &mut self,
+        key: Option<&Expr>,
+        value: &Expr,
+        var: &str,
+        source: &Iteration,
+        cond: Option<&Expr>,
+    ) -> Result<String, String> {
+        let result = self.temp("out");
+        let outer = std::mem::take(&mut self.out);
+        self.line("({");
+        match key {
+            Some(_) => self.line(&format!("EwMap *{} = ew_map_new();", result)),
+            None => self.line(&format!("EwArray *{} = ew_array_new(0);", result)),
+        }
+        let c = ident(var);
+        let i = self.temp("i");
+        let res = (|| -> Result<(), String> {
+            match source {
+                Iteration::Range { start, end } => {
+                    let (s, e) = (self.temp("t"), self.temp("t"));
+                    let (prelude, bounds) = self.sequence(&[start, end])?;
+                    for line in prelude {
+                        self.line(&line);
+                    }
+                    self.line(&format!("Val {} = {};", s, bounds[0]));
+                    self.line(&format!("Val {} = {};", e, bounds[1]));
+                    self.line(&format!("ew_range({}, {}, \"integer\");", s, e));
+                    self.line(&format!(
+                        "for (int64_t {0} = {1}.i; {0} < {2}.i; {0}++) {{",
+                        i, s, e
+                    ));
+                    self.line(&format!("Val {} = ew_int({});", c, i));
+                }
+                Iteration::Each(items) => {
+                    let items_c = self.temp("items");
+                    let source = self.expr(items)?;
+                    self.line(&format!("EwArray *{} = ew_items({});", items_c, source));
+                    self.line(&format!(
+                        "for (size_t {0} = 0; {0} < {1}->len; {0}++) {{",
+                        i, items_c
+                    ));
+                    self.line(&format!("Val {} = {}->items[{}];", c, items_c, i));
+                }
+            }
+            self.scopes.push(HashSet::from([c]));
+            let res = (|| -> Result<(), String> {
+                if let Some(cond) = cond {
+                    let cond = self.expr(cond)?;
+                    self.line(&format!(
+                        "if (!ew_cond({}, \"Comprehension\")) continue;",
+                        cond
+                    ));
+                }
+                match key {
+                    Some(key) => {
+                        let k = self.temp("key");
+                        let key = self.expr(key)?;
+                        self.line(&format!("Val {} = {};", k, key));
+                        let value = self.expr(value)?;
+                        self.line(&format!("ew_map_set({}, {}, {});", result, k, value));
+                    }
+                    None => {
+                        let value = self.expr(value)?;
+                        self.line(&format!("ew_push({}, {});", result, value));
+                    }
+                }
+                Ok(())
+            })();
+            self.scopes.pop();
+            res
+        })();
+        self.line("}");
+        match key {
+            Some(_) => self.line(&format!("ew_map_val({});", result)),
+            None => self.line(&format!("ew_array_val({});", result)),
+        }
+        self.out.push_str("})");
+        let c = std::mem::replace(&mut self.out, outer);
+        res?;
+        Ok(c)
+    }
+}
+
+fn apply(callee: Callee, args: &[String]) -> String {
+    let argv = if args.is_empty() {
+        "0, NULL".to_string()
+    } else {
+        format!("{}, (Val[]){{{}}}", args.len(), args.join(", "))
+    };
+    match callee {
+        Callee::Direct(f) => format!("{}({})", f, argv),
+        Callee::Value(f) => format!("ew_call({}, {})", f, argv),
+    }
+}
+
+/// Whether evaluating `expr` may print or change a variable, which makes
+/// the order it runs in matter.
+fn has_effects(expr: &Expr) -> bool {
+    struct Finder(bool);
+    impl Visitor for Finder {
+        fn visit_stmt(&mut self, stmt: &Stmt) {
+            self.0 |= matches!(stmt, Stmt::Reassignment { .. });
+            walk_stmt(self, stmt);
+        }
+        fn visit_expr(&mut self, expr: &Expr) {
+            self.0 |= matches!(expr, Expr::Call { .. });
+            walk_expr(self, expr);
+        }
+    }
+    let mut finder = Finder(false);
+    finder.visit_expr(expr);
+    finder.0
+}
+
+/// Whether `expr` makes a value that nothing else refers to, so binding it
+/// needs no copy.
+fn is_fresh(expr: &Expr) -> bool {
+    match expr {
+        Expr::Array { items, .. } => items.iter().all(is_fresh),
+        Expr::Int(_)
+        | Expr::Float(_)
+        | Expr::Bool(_)
+        | Expr::Str { .. }
+        | Expr::Null
+        | Expr::Unary { .. }
+        | Expr::Binary {
+            op:
+                BinaryOp::Sub
+                | BinaryOp::Mul
+                | BinaryOp::Div
+                | BinaryOp::Mod
+                | BinaryOp::Pow
+                | BinaryOp::Eq
+                | BinaryOp::Ne
+                | BinaryOp::Lt
+                | BinaryOp::Le
+                | BinaryOp::Gt
+                | BinaryOp::Ge
+                | BinaryOp::And
+                | BinaryOp::Or,
+            ..
+        } => true,
+        _ => false,
+    }
+}
+
+fn ident(name: &str) -> String {
+    let is_macro_like = name.chars().any(|c| c.is_ascii_uppercase())
+        && !name.chars().any(|c| c.is_ascii_lowercase());
+    if C_RESERVED.contains(&name)
+        || is_macro_like
+        || ["ew_", "fn_", "fv_"].iter().any(|p| name.starts_with(p))
+    {
+        format!("{}_", name)
+    } else {
+        name.to_string()
+    }
+}
+
+/// A C string literal for `s`. Anything but printable ASCII is escaped in
+/// octal, which can't run into the characters after it.
+fn c_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for byte in s.bytes() {
+        match byte {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            b'?' => out.push_str("\\?"),
+            b'\n' => out.push_str("\\n"),
+            b' '..=b'~' => out.push(byte as char),
+            _ => out.push_str(&format!("\\{:03o}", byte)),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Indents `code` by its braces: a line starting with `}` closes a level
+/// and one ending with `{` opens one.
+fn reindent(code: &str) -> String {
+    let mut out = String::with_capacity(code.len() * 2);
+    let mut depth = 0usize;
+    for line in code.lines() {
+        let line = line.trim();
+        if line.starts_with('}') {
+            depth = depth.saturating_sub(1);
+        }
+        if !line.is_empty() {
+            out.push_str(&INDENT.repeat(depth));
+        }
+        out.push_str(line);
+        out.push('\n');
+        if line.ends_with('{') {
+            depth += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    /// The program without the runtime in front of it.
+    fn program(source: &str) -> String {
+        let c = to_c(&parse(source).unwrap(), None).unwrap();
+        c[RUNTIME.len() + 1..].to_string()
+    }
+
+    #[test]
+    fn test_functions_and_calls() {
+        assert_eq!(
+            program(
+                "fn sign(x) {\n if (x < 0) { \"-\" } else { let s = \"+\"\n s }\n}\nprintln(sign(-2))"
+            ),
+            "static Val fn_sign(int argc, Val *argv);\n\
+             \n\
+             static Val fn_sign(int argc, Val *argv) {\n\
+             \x20   ew_arity(\"sign\", argc, 1);\n\
+             \x20   Val x = argv[0];\n\
+             \x20   if (ew_cond(ew_lt(x, ew_int(0)), \"If\")) {\n\
+             \x20       return ew_str(\"-\", 1);\n\
+             \x20   } else {\n\
+             \x20       Val s = ew_str(\"+\", 1);\n\
+             \x20       return s;\n\
+             \x20   }\n\
+             }\n\
+             \n\
+             static Val ew_program(void) {\n\
+             \x20   ew_println(1, (Val[]){fn_sign(1, (Val[]){ew_neg(ew_int(2))})});\n\
+             \x20   return EW_UNIT_VAL;\n\
+             }\n"
+        );
+    }
+
+    #[test]
+    fn test_statements_as_values() {
+        let c = program("let found = loop { break 7 }\nlet t = if (found > 1) { 1 } else { 2 }");
+        assert!(
+            c.contains("Val found;\n    found = EW_UNIT_VAL;\n    while (1) {\n        found = ew_int(7);\n        break;\n    }"),
+            "{}",
+            c
+        );
+        assert!(
+            c.contains(
+                "Val t = (ew_cond(ew_gt(found, ew_int(1)), \"If\") ? ew_int(1) : ew_int(2));"
+            ),
+            "{}",
+            c
+        );
+    }
+
+    #[test]
+    fn test_slices() {
+        let c = program("let m = [[1, 2, 3]]\nlet a = m[0][1..]\nlet b = m[..-1]");
+        assert!(
+            c.contains("Val a = ew_slice(ew_index(m, ew_int(0)), ew_int(1), EW_NULL_VAL);"),
+            "{}",
+            c
+        );
+        assert!(
+            c.contains("Val b = ew_slice(m, EW_NULL_VAL, ew_neg(ew_int(1)));"),
+            "{}",
+            c
+        );
+    }
+
+    #[test]
+    fn test_let_in() {
+        let c = program("let x = 5\nlet y = let x = x + 1 in x * 10\nlet z = let z = 2 in z * z");
+        assert!(
+            c.contains("Val ew_bound1 = ew_add(x, ew_int(1));\n    {\n        Val x = ew_bound1;\n        y = ew_mul(x, ew_int(10));\n    }"),
+            "{}",
+            c
+        );
+        assert!(c.contains("Val z;\n    z = ({"), "{}", c);
+    }
+
+    #[test]
+    fn test_evaluation_order_and_copies() {
+        // C may evaluate arguments in any order, so calls are sequenced.
+        let c = program("fn f(x) { println(x)\n x }\nlet n = f(1) + f(2)");
+        assert!(
+            c.contains("Val n = ({\n        Val ew_t1 = fn_f(1, (Val[]){ew_int(1)});\n        ew_add(ew_t1, fn_f(1, (Val[]){ew_int(2)}));\n    });"),
+            "{}",
+            c
+        );
+        let c = program("fn zero(xs) { xs[0] = 0\n xs }\nlet a = [1]\nlet b = a\nb[0] = a");
+        assert!(c.contains("Val xs = ew_copy(argv[0]);"), "{}", c);
+        assert!(c.contains("Val b = ew_copy(a);"), "{}", c);
+        assert!(c.contains("*ew_slot(&b, ew_int(0)) = ew_copy(a);"), "{}", c);
+    }
+
+    #[test]
+    fn test_names() {
+        let c = program("let int = 1\nlet N = int\nlet ew_x = N");
+        assert!(
+            c.contains("Val int_ = ew_int(1);\n    Val N_ = int_;\n    Val ew_x_ = N_;"),
+            "{}",
+            c
+        );
+    }
+
+    #[test]
+    fn test_unsupported_builtins() {
+        assert_eq!(
+            to_c(&parse("sleep(1)").unwrap(), None),
+            Err("sleep() has no C equivalent".to_string())
+        );
+        assert!(to_c(&parse("fn sleep(n) { n }\nsleep(1)").unwrap(), None).is_ok());
+    }
+}
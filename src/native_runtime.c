@@ -0,0 +1,1323 @@
+// The runtime `ew build` puts at the top of every C program: the value type,
+// the operators, and the ew builtins a program may call, each checking its
+// arguments and failing with the interpreter's message.
+//
+// Strings are immutable and share their bytes; arrays are shared until the
+// compiler inserts a copy. Nothing is freed: a compiled script runs to
+// completion and exits.
+#include <limits.h>
+#include <math.h>
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+typedef enum {
+    EW_UNIT,
+    EW_NULL,
+    EW_BOOL,
+    EW_INT,
+    EW_FLOAT,
+    EW_STR,
+    EW_ARRAY,
+    EW_MAP,
+    EW_VARIANT,
+    EW_FN,
+} EwKind;
+
+typedef struct Val Val;
+typedef struct EwArray EwArray;
+typedef struct EwMap EwMap;
+typedef struct EwVariant EwVariant;
+typedef struct EwFn EwFn;
+
+struct Val {
+    EwKind kind;
+    union {
+        bool b;
+        int64_t i;
+        double f;
+        struct {
+            const char *ptr;
+            size_t len;
+        } s;
+        EwArray *a;
+        EwMap *m;
+        EwVariant *v;
+        EwFn *fn;
+    };
+};
+
+struct EwArray {
+    size_t len, cap;
+    Val *items;
+};
+
+// Keys are kept sorted, as in the interpreter's BTreeMap.
+struct EwMap {
+    size_t len, cap;
+    Val *keys, *vals;
+};
+
+struct EwVariant {
+    const char *enum_name, *variant;
+    size_t len;
+    Val fields[];
+};
+
+typedef Val (*EwCode)(int argc, Val *argv);
+
+typedef struct EwMemo EwMemo;
+
+struct EwFn {
+    const char *name;
+    // How the function shows after `fn `, e.g. "f(a, b) at f.ew:3", or
+    // NULL for a builtin.
+    const char *signature;
+    EwCode code;
+    // Set for a memoized function, which calls `inner` on a cache miss.
+    EwFn *inner;
+    EwMemo *memo;
+};
+
+#define EW_UNIT_VAL ((Val){.kind = EW_UNIT})
+#define EW_NULL_VAL ((Val){.kind = EW_NULL})
+
+static Val ew_int(int64_t i) { return (Val){.kind = EW_INT, .i = i}; }
+static Val ew_float(double f) { return (Val){.kind = EW_FLOAT, .f = f}; }
+static Val ew_bool(bool b) { return (Val){.kind = EW_BOOL, .b = b}; }
+static Val ew_str(const char *ptr, size_t len) {
+    return (Val){.kind = EW_STR, .s = {ptr, len}};
+}
+
+static void *ew_alloc(size_t size) {
+    void *p = malloc(size ? size : 1);
+    if (!p) {
+        fputs("Error: out of memory\n", stderr);
+        exit(1);
+    }
+    return p;
+}
+
+// Text being built, e.g. a value's display form.
+typedef struct {
+    char *ptr;
+    size_t len, cap;
+} EwBuf;
+
+static void ew_buf_add(EwBuf *b, const char *s, size_t len) {
+    if (b->len + len + 1 > b->cap) {
+        b->cap = (b->len + len + 1) * 2;
+        char *p = ew_alloc(b->cap);
+        if (b->ptr) memcpy(p, b->ptr, b->len);
+        b->ptr = p;
+    }
+    memcpy(b->ptr + b->len, s, len);
+    b->len += len;
+    b->ptr[b->len] = 0;
+}
+
+static void ew_buf_str(EwBuf *b, const char *s) { ew_buf_add(b, s, strlen(s)); }
+
+static void ew_buf_fmt(EwBuf *b, const char *fmt, ...) {
+    char small[64];
+    va_list ap;
+    va_start(ap, fmt);
+    int n = vsnprintf(small, sizeof small, fmt, ap);
+    va_end(ap);
+    if ((size_t)n < sizeof small) {
+        ew_buf_add(b, small, n);
+        return;
+    }
+    char *big = ew_alloc(n + 1);
+    va_start(ap, fmt);
+    vsnprintf(big, n + 1, fmt, ap);
+    va_end(ap);
+    ew_buf_add(b, big, n);
+}
+
+static Val ew_buf_val(EwBuf *b) { return ew_str(b->ptr ? b->ptr : "", b->len); }
+
+static void ew_show(EwBuf *b, Val v);
+static void ew_debug(EwBuf *b, Val v);
+
+// Stops the program with `Error: message`, as `ew run` does.
+static _Noreturn void ew_fail(const char *fmt, ...) {
+    fflush(stdout);
+    EwBuf b = {0};
+    char small[256];
+    va_list ap;
+    va_start(ap, fmt);
+    vsnprintf(small, sizeof small, fmt, ap);
+    va_end(ap);
+    ew_buf_str(&b, small);
+    fprintf(stderr, "Error: %s\n", b.ptr);
+    exit(1);
+}
+
+// The value's Rust debug form, for error messages.
+static const char *ew_dbg(Val v) {
+    EwBuf b = {0};
+    ew_debug(&b, v);
+    return b.ptr;
+}
+
+static const char *ew_type_name(Val v) {
+    switch (v.kind) {
+    case EW_UNIT: return "Unit";
+    case EW_NULL: return "Null";
+    case EW_BOOL: return "Bool";
+    case EW_INT: return "Int";
+    case EW_FLOAT: return "Float";
+    case EW_STR: return "Str";
+    case EW_ARRAY: return "Array";
+    case EW_MAP: return "Map";
+    case EW_VARIANT: return "Variant";
+    case EW_FN: return "Function";
+    }
+    return "?";
+}
+
+// `ew_fail` where a value is expected.
+static Val ew_error(const char *message) { ew_fail("%s", message); }
+
+static void ew_argc(const char *name, int argc, int n) {
+    if (argc != n) {
+        ew_fail("%s() takes %d argument%s, got %d", name, n, n == 1 ? "" : "s", argc);
+    }
+}
+
+static void ew_arity(const char *name, int argc, int n) {
+    if (argc != n) ew_fail("Function %s expects %d arguments, got %d", name, n, argc);
+}
+
+static _Noreturn void ew_overflow(void) {
+    ew_fail("Integer overflow: compiled programs don't support big integers");
+}
+
+// Arrays.
+
+static EwArray *ew_array_new(size_t cap) {
+    EwArray *a = ew_alloc(sizeof *a);
+    a->len = 0;
+    a->cap = cap;
+    a->items = ew_alloc(cap * sizeof(Val));
+    return a;
+}
+
+static void ew_push(EwArray *a, Val v) {
+    if (a->len == a->cap) {
+        a->cap = a->cap ? a->cap * 2 : 4;
+        Val *items = ew_alloc(a->cap * sizeof(Val));
+        memcpy(items, a->items, a->len * sizeof(Val));
+        a->items = items;
+    }
+    a->items[a->len++] = v;
+}
+
+static Val ew_array_val(EwArray *a) { return (Val){.kind = EW_ARRAY, .a = a}; }
+
+// An array literal of `n` values.
+static Val ew_array(size_t n, Val *items) {
+    EwArray *a = ew_array_new(n);
+    memcpy(a->items, items, n * sizeof(Val));
+    a->len = n;
+    return ew_array_val(a);
+}
+
+// Call arguments, when some are spread from arrays.
+static void ew_spread(EwArray *args, Val v) {
+    if (v.kind != EW_ARRAY) ew_fail("Cannot spread %s into arguments", ew_dbg(v));
+    for (size_t i = 0; i < v.a->len; i++) ew_push(args, v.a->items[i]);
+}
+
+// The items a comprehension runs over.
+static EwArray *ew_items(Val v) {
+    if (v.kind != EW_ARRAY) ew_fail("A comprehension needs a range or an array, got %s", ew_dbg(v));
+    return v.a;
+}
+
+// Maps.
+
+static int ew_str_cmp(Val a, Val b) {
+    size_t n = a.s.len < b.s.len ? a.s.len : b.s.len;
+    int c = memcmp(a.s.ptr, b.s.ptr, n);
+    if (c) return c;
+    return a.s.len < b.s.len ? -1 : a.s.len > b.s.len;
+}
+
+// Where `key` is, or would go, in the sorted keys of `m`.
+static size_t ew_map_find(EwMap *m, Val key, bool *found) {
+    size_t lo = 0, hi = m->len;
+    while (lo < hi) {
+        size_t mid = lo + (hi - lo) / 2;
+        int c = ew_str_cmp(m->keys[mid], key);
+        if (c == 0) {
+            *found = true;
+            return mid;
+        }
+        if (c < 0) lo = mid + 1;
+        else hi = mid;
+    }
+    *found = false;
+    return lo;
+}
+
+static EwMap *ew_map_new(void) {
+    EwMap *m = ew_alloc(sizeof *m);
+    *m = (EwMap){0};
+    return m;
+}
+
+static void ew_map_set(EwMap *m, Val key, Val v) {
+    if (key.kind != EW_STR) ew_fail("Map key must be a string");
+    bool found;
+    size_t i = ew_map_find(m, key, &found);
+    if (found) {
+        m->vals[i] = v;
+        return;
+    }
+    if (m->len == m->cap) {
+        m->cap = m->cap ? m->cap * 2 : 4;
+        Val *keys = ew_alloc(m->cap * sizeof(Val)), *vals = ew_alloc(m->cap * sizeof(Val));
+        memcpy(keys, m->keys, m->len * sizeof(Val));
+        memcpy(vals, m->vals, m->len * sizeof(Val));
+        m->keys = keys;
+        m->vals = vals;
+    }
+    memmove(m->keys + i + 1, m->keys + i, (m->len - i) * sizeof(Val));
+    memmove(m->vals + i + 1, m->vals + i, (m->len - i) * sizeof(Val));
+    m->keys[i] = key;
+    m->vals[i] = v;
+    m->len++;
+}
+
+static Val ew_map_val(EwMap *m) { return (Val){.kind = EW_MAP, .m = m}; }
+
+// Variants and functions.
+
+static Val ew_variant(const char *enum_name, const char *variant, size_t n, Val *fields) {
+    EwVariant *v = ew_alloc(sizeof *v + n * sizeof(Val));
+    v->enum_name = enum_name;
+    v->variant = variant;
+    v->len = n;
+    memcpy(v->fields, fields, n * sizeof(Val));
+    return (Val){.kind = EW_VARIANT, .v = v};
+}
+
+// Whether `v` is `enum_name::variant`, with `n` fields to bind.
+static bool ew_is_variant(Val v, const char *enum_name, const char *variant, size_t n) {
+    if (v.kind != EW_VARIANT || strcmp(v.v->enum_name, enum_name) || strcmp(v.v->variant, variant)) {
+        return false;
+    }
+    if (v.v->len != n) {
+        ew_fail("Pattern %s::%s binds %zu fields, but the variant has %zu", enum_name, variant, n,
+                v.v->len);
+    }
+    return true;
+}
+
+static _Noreturn void ew_no_match(Val v) {
+    EwBuf b = {0};
+    ew_show(&b, v);
+    ew_fail("No match arm for %s", b.ptr);
+}
+
+static Val ew_function(const char *name, const char *signature, EwCode code) {
+    EwFn *fn = ew_alloc(sizeof *fn);
+    *fn = (EwFn){name, signature, code, NULL, NULL};
+    return (Val){.kind = EW_FN, .fn = fn};
+}
+
+static Val ew_call(Val f, int argc, Val *argv);
+
+// Display.
+
+// The shortest decimal digits that read back as `x`, which must be finite
+// and positive, with the exponent of the first digit.
+static int ew_float_digits(double x, char *digits, int *exp10) {
+    char buf[40];
+    for (int p = 1; p <= 17; p++) {
+        snprintf(buf, sizeof buf, "%.*e", p - 1, x);
+        if (strtod(buf, NULL) == x) break;
+    }
+    int n = 0;
+    char *c = buf;
+    for (; *c != 'e'; c++) {
+        if (*c != '.') digits[n++] = *c;
+    }
+    *exp10 = atoi(c + 1);
+    while (n > 1 && digits[n - 1] == '0') n--;
+    digits[n] = 0;
+    return n;
+}
+
+// `x` the way Rust prints an f64: never in exponent form, and without a
+// fraction when it is whole. `debug` adds the `.0`, and switches to
+// exponent form for very large or small numbers, as `{:?}` does.
+static void ew_show_float(EwBuf *b, double x, bool debug) {
+    if (isnan(x)) {
+        ew_buf_str(b, "NaN");
+        return;
+    }
+    if (signbit(x)) ew_buf_str(b, "-");
+    x = fabs(x);
+    if (isinf(x)) {
+        ew_buf_str(b, "inf");
+        return;
+    }
+    if (x == 0) {
+        ew_buf_str(b, debug ? "0.0" : "0");
+        return;
+    }
+    char digits[24];
+    int e;
+    int n = ew_float_digits(x, digits, &e);
+    if (debug && (x < 1e-4 || x >= 1e16)) {
+        ew_buf_add(b, digits, 1);
+        if (n > 1) {
+            ew_buf_str(b, ".");
+            ew_buf_add(b, digits + 1, n - 1);
+        }
+        ew_buf_fmt(b, "e%d", e);
+        return;
+    }
+    if (e >= n - 1) {
+        ew_buf_add(b, digits, n);
+        for (int i = 0; i < e - (n - 1); i++) ew_buf_str(b, "0");
+        if (debug) ew_buf_str(b, ".0");
+    } else if (e >= 0) {
+        ew_buf_add(b, digits, e + 1);
+        ew_buf_str(b, ".");
+        ew_buf_add(b, digits + e + 1, n - e - 1);
+    } else {
+        ew_buf_str(b, "0.");
+        for (int i = 0; i < -e - 1; i++) ew_buf_str(b, "0");
+        ew_buf_add(b, digits, n);
+    }
+}
+
+static void ew_quote(EwBuf *b, Val s) {
+    ew_buf_str(b, "\"");
+    for (size_t i = 0; i < s.s.len; i++) {
+        char c = s.s.ptr[i];
+        switch (c) {
+        case '"': ew_buf_str(b, "\\\""); break;
+        case '\\': ew_buf_str(b, "\\\\"); break;
+        case '\n': ew_buf_str(b, "\\n"); break;
+        case '\t': ew_buf_str(b, "\\t"); break;
+        case '\r': ew_buf_str(b, "\\r"); break;
+        case '\0': ew_buf_str(b, "\\0"); break;
+        default: ew_buf_add(b, &c, 1);
+        }
+    }
+    ew_buf_str(b, "\"");
+}
+
+// How a value shows inside an array or variant: strings are quoted.
+static void ew_nested(EwBuf *b, Val v) {
+    if (v.kind == EW_STR) ew_quote(b, v);
+    else ew_show(b, v);
+}
+
+static void ew_show_fn(EwBuf *b, EwFn *fn) {
+    if (fn->memo) {
+        ew_buf_str(b, "<memoized ");
+        EwBuf inner = {0};
+        ew_show_fn(&inner, fn->inner);
+        ew_buf_add(b, inner.ptr + 1, inner.len - 2);
+        ew_buf_str(b, ">");
+    } else if (fn->signature) {
+        ew_buf_fmt(b, "<fn %s>", fn->signature);
+    } else {
+        ew_buf_fmt(b, "<builtin %s>", fn->name);
+    }
+}
+
+// A value as `str` and `println` show it.
+static void ew_show(EwBuf *b, Val v) {
+    switch (v.kind) {
+    case EW_UNIT: ew_buf_str(b, "()"); break;
+    case EW_NULL: ew_buf_str(b, "null"); break;
+    case EW_BOOL: ew_buf_str(b, v.b ? "true" : "false"); break;
+    case EW_INT: ew_buf_fmt(b, "%lld", (long long)v.i); break;
+    case EW_FLOAT: ew_show_float(b, v.f, false); break;
+    case EW_STR: ew_buf_add(b, v.s.ptr, v.s.len); break;
+    case EW_ARRAY:
+        ew_buf_str(b, "[");
+        for (size_t i = 0; i < v.a->len; i++) {
+            if (i) ew_buf_str(b, ", ");
+            ew_nested(b, v.a->items[i]);
+        }
+        ew_buf_str(b, "]");
+        break;
+    case EW_MAP:
+        ew_buf_str(b, "{");
+        for (size_t i = 0; i < v.m->len; i++) {
+            if (i) ew_buf_str(b, ", ");
+            ew_quote(b, v.m->keys[i]);
+            ew_buf_str(b, ": ");
+            ew_nested(b, v.m->vals[i]);
+        }
+        ew_buf_str(b, "}");
+        break;
+    case EW_VARIANT:
+        ew_buf_fmt(b, "%s::%s", v.v->enum_name, v.v->variant);
+        if (v.v->len) {
+            ew_buf_str(b, "(");
+            for (size_t i = 0; i < v.v->len; i++) {
+                if (i) ew_buf_str(b, ", ");
+                ew_nested(b, v.v->fields[i]);
+            }
+            ew_buf_str(b, ")");
+        }
+        break;
+    case EW_FN: ew_show_fn(b, v.fn); break;
+    }
+}
+
+// Source syntax for the value, for `repr`.
+static void ew_repr(EwBuf *b, Val v) {
+    switch (v.kind) {
+    case EW_STR: ew_quote(b, v); break;
+    case EW_FLOAT:
+        if (isnan(v.f)) ew_buf_str(b, "NAN");
+        else if (isinf(v.f)) ew_buf_str(b, v.f > 0 ? "INF" : "-INF");
+        else {
+            size_t start = b->len;
+            ew_show_float(b, v.f, false);
+            if (!memchr(b->ptr + start, '.', b->len - start)) ew_buf_str(b, ".0");
+        }
+        break;
+    case EW_ARRAY:
+        ew_buf_str(b, "[");
+        for (size_t i = 0; i < v.a->len; i++) {
+            if (i) ew_buf_str(b, ", ");
+            ew_repr(b, v.a->items[i]);
+        }
+        ew_buf_str(b, "]");
+        break;
+    case EW_VARIANT:
+        if (v.v->len) {
+            ew_buf_fmt(b, "%s::%s(", v.v->enum_name, v.v->variant);
+            for (size_t i = 0; i < v.v->len; i++) {
+                if (i) ew_buf_str(b, ", ");
+                ew_repr(b, v.v->fields[i]);
+            }
+            ew_buf_str(b, ")");
+            break;
+        }
+        ew_show(b, v);
+        break;
+    default: ew_show(b, v);
+    }
+}
+
+static void ew_debug_list(EwBuf *b, size_t n, Val *items) {
+    ew_buf_str(b, "[");
+    for (size_t i = 0; i < n; i++) {
+        if (i) ew_buf_str(b, ", ");
+        ew_debug(b, items[i]);
+    }
+    ew_buf_str(b, "]");
+}
+
+static void ew_debug(EwBuf *b, Val v) {
+    switch (v.kind) {
+    case EW_UNIT: ew_buf_str(b, "Unit"); break;
+    case EW_NULL: ew_buf_str(b, "Null"); break;
+    case EW_BOOL: ew_buf_str(b, v.b ? "Bool(true)" : "Bool(false)"); break;
+    case EW_INT: ew_buf_fmt(b, "Int(%lld)", (long long)v.i); break;
+    case EW_FLOAT:
+        ew_buf_str(b, "Float(");
+        ew_show_float(b, v.f, true);
+        ew_buf_str(b, ")");
+        break;
+    case EW_STR:
+        ew_buf_str(b, "Str(");
+        ew_quote(b, v);
+        ew_buf_str(b, ")");
+        break;
+    case EW_ARRAY:
+        ew_buf_str(b, "Array(");
+        ew_debug_list(b, v.a->len, v.a->items);
+        ew_buf_str(b, ")");
+        break;
+    case EW_MAP:
+        ew_buf_str(b, "Map({");
+        for (size_t i = 0; i < v.m->len; i++) {
+            if (i) ew_buf_str(b, ", ");
+            ew_quote(b, v.m->keys[i]);
+            ew_buf_str(b, ": ");
+            ew_debug(b, v.m->vals[i]);
+        }
+        ew_buf_str(b, "})");
+        break;
+    case EW_VARIANT:
+        ew_buf_fmt(b, "Variant { enum_name: \"%s\", variant: \"%s\", fields: ", v.v->enum_name,
+                   v.v->variant);
+        ew_debug_list(b, v.v->len, v.v->fields);
+        ew_buf_str(b, " }");
+        break;
+    case EW_FN:
+        if (v.fn->signature) ew_buf_fmt(b, "Function { name: \"%s\", .. }", v.fn->name);
+        else ew_buf_fmt(b, "Builtin(\"%s\")", v.fn->name);
+        break;
+    }
+}
+
+static void ew_pretty(EwBuf *b, Val v, size_t indent) {
+    size_t n;
+    Val *items;
+    EwBuf open = {0};
+    const char *close;
+    if (v.kind == EW_ARRAY) {
+        ew_buf_str(&open, "[");
+        close = "]";
+        n = v.a->len;
+        items = v.a->items;
+    } else if (v.kind == EW_VARIANT && v.v->len) {
+        ew_buf_fmt(&open, "%s::%s(", v.v->enum_name, v.v->variant);
+        close = ")";
+        n = v.v->len;
+        items = v.v->fields;
+    } else {
+        ew_nested(b, v);
+        return;
+    }
+    EwBuf flat = {0};
+    ew_nested(&flat, v);
+    if (indent + flat.len <= 60 || n == 0) {
+        ew_buf_add(b, flat.ptr, flat.len);
+        return;
+    }
+    ew_buf_add(b, open.ptr, open.len);
+    for (size_t i = 0; i < n; i++) {
+        ew_buf_str(b, "\n");
+        for (size_t j = 0; j <= indent; j++) ew_buf_str(b, "  ");
+        ew_pretty(b, items[i], indent + 1);
+        ew_buf_str(b, ",");
+    }
+    ew_buf_str(b, "\n");
+    for (size_t j = 0; j < indent; j++) ew_buf_str(b, "  ");
+    ew_buf_str(b, close);
+}
+
+// Equality and copies.
+
+// Structural equality, as the interpreter's `==` on values of any type.
+static bool ew_equal(Val a, Val b) {
+    if (a.kind != b.kind) return false;
+    switch (a.kind) {
+    case EW_UNIT:
+    case EW_NULL: return true;
+    case EW_BOOL: return a.b == b.b;
+    case EW_INT: return a.i == b.i;
+    case EW_FLOAT: return a.f == b.f;
+    case EW_STR: return a.s.len == b.s.len && !memcmp(a.s.ptr, b.s.ptr, a.s.len);
+    case EW_ARRAY:
+        if (a.a->len != b.a->len) return false;
+        for (size_t i = 0; i < a.a->len; i++) {
+            if (!ew_equal(a.a->items[i], b.a->items[i])) return false;
+        }
+        return true;
+    case EW_MAP:
+        if (a.m->len != b.m->len) return false;
+        for (size_t i = 0; i < a.m->len; i++) {
+            if (!ew_equal(a.m->keys[i], b.m->keys[i]) || !ew_equal(a.m->vals[i], b.m->vals[i])) {
+                return false;
+            }
+        }
+        return true;
+    case EW_VARIANT:
+        if (strcmp(a.v->enum_name, b.v->enum_name) || strcmp(a.v->variant, b.v->variant) ||
+            a.v->len != b.v->len) {
+            return false;
+        }
+        for (size_t i = 0; i < a.v->len; i++) {
+            if (!ew_equal(a.v->fields[i], b.v->fields[i])) return false;
+        }
+        return true;
+    case EW_FN: return a.fn == b.fn;
+    }
+    return false;
+}
+
+// A copy of `v` sharing no arrays with it, for a variable that is about to
+// be changed through an index.
+static Val ew_copy(Val v) {
+    switch (v.kind) {
+    case EW_ARRAY: {
+        EwArray *a = ew_array_new(v.a->len);
+        for (size_t i = 0; i < v.a->len; i++) a->items[i] = ew_copy(v.a->items[i]);
+        a->len = v.a->len;
+        return ew_array_val(a);
+    }
+    case EW_MAP: {
+        EwMap *m = ew_map_new();
+        for (size_t i = 0; i < v.m->len; i++) ew_map_set(m, v.m->keys[i], ew_copy(v.m->vals[i]));
+        return ew_map_val(m);
+    }
+    case EW_VARIANT: {
+        Val c = ew_variant(v.v->enum_name, v.v->variant, v.v->len, v.v->fields);
+        for (size_t i = 0; i < v.v->len; i++) c.v->fields[i] = ew_copy(v.v->fields[i]);
+        return c;
+    }
+    default: return v;
+    }
+}
+
+// Operators.
+
+static const char *const EW_OP_NAMES[] = {"Add", "Sub", "Mul", "Div", "Mod", "Pow", "Eq",
+                                          "Ne",  "Lt",  "Le",  "Gt",  "Ge",  "And", "Or"};
+
+enum { EW_ADD, EW_SUB, EW_MUL, EW_DIV, EW_MOD, EW_POW, EW_EQ, EW_NE, EW_LT, EW_LE, EW_GT, EW_GE, EW_AND, EW_OR };
+
+static _Noreturn void ew_bad_operands(int op, Val a, Val b) {
+    ew_fail("Cannot apply %s to %s and %s", EW_OP_NAMES[op], ew_dbg(a), ew_dbg(b));
+}
+
+static Val ew_concat(Val a, Val b) {
+    char *p = ew_alloc(a.s.len + b.s.len);
+    memcpy(p, a.s.ptr, a.s.len);
+    memcpy(p + a.s.len, b.s.ptr, b.s.len);
+    return ew_str(p, a.s.len + b.s.len);
+}
+
+static Val ew_add(Val a, Val b) {
+    int64_t n;
+    if (a.kind == EW_INT && b.kind == EW_INT) {
+        if (__builtin_add_overflow(a.i, b.i, &n)) ew_overflow();
+        return ew_int(n);
+    }
+    if (a.kind == EW_FLOAT && b.kind == EW_FLOAT) return ew_float(a.f + b.f);
+    if (a.kind == EW_STR && b.kind == EW_STR) return ew_concat(a, b);
+    if (a.kind == EW_ARRAY && b.kind == EW_ARRAY) {
+        EwArray *r = ew_array_new(a.a->len + b.a->len);
+        memcpy(r->items, a.a->items, a.a->len * sizeof(Val));
+        memcpy(r->items + a.a->len, b.a->items, b.a->len * sizeof(Val));
+        r->len = a.a->len + b.a->len;
+        return ew_array_val(r);
+    }
+    ew_bad_operands(EW_ADD, a, b);
+}
+
+static Val ew_sub(Val a, Val b) {
+    int64_t n;
+    if (a.kind == EW_INT && b.kind == EW_INT) {
+        if (__builtin_sub_overflow(a.i, b.i, &n)) ew_overflow();
+        return ew_int(n);
+    }
+    if (a.kind == EW_FLOAT && b.kind == EW_FLOAT) return ew_float(a.f - b.f);
+    ew_bad_operands(EW_SUB, a, b);
+}
+
+static Val ew_mul(Val a, Val b) {
+    int64_t n;
+    if (a.kind == EW_INT && b.kind == EW_INT) {
+        if (__builtin_mul_overflow(a.i, b.i, &n)) ew_overflow();
+        return ew_int(n);
+    }
+    if (a.kind == EW_FLOAT && b.kind == EW_FLOAT) return ew_float(a.f * b.f);
+    if (a.kind == EW_STR && b.kind == EW_INT) {
+        if (b.i < 0) ew_fail("Cannot repeat a string %lld times", (long long)b.i);
+        size_t len = a.s.len * (size_t)b.i;
+        char *p = ew_alloc(len);
+        for (int64_t i = 0; i < b.i; i++) memcpy(p + i * a.s.len, a.s.ptr, a.s.len);
+        return ew_str(p, len);
+    }
+    ew_bad_operands(EW_MUL, a, b);
+}
+
+static Val ew_div(Val a, Val b) {
+    if (a.kind == EW_INT && b.kind == EW_INT) {
+        if (b.i == 0) ew_fail("Division by zero");
+        if (a.i == INT64_MIN && b.i == -1) ew_overflow();
+        return ew_int(a.i / b.i);
+    }
+    if (a.kind == EW_FLOAT && b.kind == EW_FLOAT) {
+        if (b.f == 0) ew_fail("Division by zero");
+        return ew_float(a.f / b.f);
+    }
+    ew_bad_operands(EW_DIV, a, b);
+}
+
+static Val ew_mod(Val a, Val b) {
+    if (a.kind == EW_INT && b.kind == EW_INT) {
+        if (b.i == 0) ew_fail("Modulo by zero");
+        return ew_int(b.i == -1 ? 0 : a.i % b.i);
+    }
+    if (a.kind == EW_FLOAT && b.kind == EW_FLOAT) {
+        if (b.f == 0) ew_fail("Modulo by zero");
+        return ew_float(fmod(a.f, b.f));
+    }
+    ew_bad_operands(EW_MOD, a, b);
+}
+
+static Val ew_pow(Val a, Val b) {
+    if (a.kind == EW_INT && b.kind == EW_INT) {
+        if (b.i < 0) return ew_float(pow((double)a.i, (double)b.i));
+        if (b.i > UINT32_MAX) ew_fail("Exponent too large: %lld", (long long)b.i);
+        int64_t r = 1, base = a.i;
+        for (int64_t e = b.i; e > 0; e >>= 1) {
+            if ((e & 1) && __builtin_mul_overflow(r, base, &r)) ew_overflow();
+            if (e > 1 && __builtin_mul_overflow(base, base, &base)) ew_overflow();
+        }
+        return ew_int(r);
+    }
+    if (a.kind == EW_FLOAT && b.kind == EW_FLOAT) return ew_float(pow(a.f, b.f));
+    if (a.kind == EW_FLOAT && b.kind == EW_INT) return ew_float(pow(a.f, (double)b.i));
+    ew_bad_operands(EW_POW, a, b);
+}
+
+// `==` and `!=` compare numbers of one kind, Bools, variants, and anything
+// with null.
+static Val ew_eq(Val a, Val b) {
+    if ((a.kind == b.kind && (a.kind == EW_INT || a.kind == EW_FLOAT || a.kind == EW_BOOL ||
+                              a.kind == EW_VARIANT)) ||
+        a.kind == EW_NULL || b.kind == EW_NULL) {
+        return ew_bool(ew_equal(a, b));
+    }
+    ew_bad_operands(EW_EQ, a, b);
+}
+
+static Val ew_ne(Val a, Val b) {
+    if ((a.kind == b.kind && (a.kind == EW_INT || a.kind == EW_FLOAT || a.kind == EW_BOOL ||
+                              a.kind == EW_VARIANT)) ||
+        a.kind == EW_NULL || b.kind == EW_NULL) {
+        return ew_bool(!ew_equal(a, b));
+    }
+    ew_bad_operands(EW_NE, a, b);
+}
+
+static Val ew_order(int op, Val a, Val b) {
+    int c;
+    if (a.kind == EW_INT && b.kind == EW_INT) {
+        c = (a.i > b.i) - (a.i < b.i);
+    } else if (a.kind == EW_FLOAT && b.kind == EW_FLOAT) {
+        // Every ordering with NaN is false.
+        if (isnan(a.f) || isnan(b.f)) return ew_bool(false);
+        c = (a.f > b.f) - (a.f < b.f);
+    } else {
+        ew_bad_operands(op, a, b);
+    }
+    switch (op) {
+    case EW_LT: return ew_bool(c < 0);
+    case EW_LE: return ew_bool(c <= 0);
+    case EW_GT: return ew_bool(c > 0);
+    default: return ew_bool(c >= 0);
+    }
+}
+
+static Val ew_lt(Val a, Val b) { return ew_order(EW_LT, a, b); }
+static Val ew_le(Val a, Val b) { return ew_order(EW_LE, a, b); }
+static Val ew_gt(Val a, Val b) { return ew_order(EW_GT, a, b); }
+static Val ew_ge(Val a, Val b) { return ew_order(EW_GE, a, b); }
+
+// Both sides are evaluated, as in the interpreter.
+static Val ew_and(Val a, Val b) {
+    if (a.kind == EW_BOOL && b.kind == EW_BOOL) return ew_bool(a.b && b.b);
+    ew_bad_operands(EW_AND, a, b);
+}
+
+static Val ew_or(Val a, Val b) {
+    if (a.kind == EW_BOOL && b.kind == EW_BOOL) return ew_bool(a.b || b.b);
+    ew_bad_operands(EW_OR, a, b);
+}
+
+static Val ew_neg(Val v) {
+    if (v.kind == EW_INT) {
+        if (v.i == INT64_MIN) ew_overflow();
+        return ew_int(-v.i);
+    }
+    if (v.kind == EW_FLOAT) return ew_float(-v.f);
+    ew_fail("Cannot apply Neg to %s", ew_dbg(v));
+}
+
+static Val ew_not(Val v) {
+    if (v.kind == EW_BOOL) return ew_bool(!v.b);
+    ew_fail("Cannot apply Not to %s", ew_dbg(v));
+}
+
+// The condition of an `if`, `while` or comprehension, which must be a Bool.
+static bool ew_cond(Val v, const char *kind) {
+    if (v.kind != EW_BOOL) {
+        ew_fail("%s condition must be a Boolean, got %s (use bool(...) to test truthiness)", kind,
+                ew_dbg(v));
+    }
+    return v.b;
+}
+
+// Checks the bounds of a `for` loop.
+static void ew_range(Val start, Val end, const char *typo) {
+    if (start.kind != EW_INT || end.kind != EW_INT) {
+        EwBuf b = {0};
+        ew_show(&b, start);
+        ew_buf_str(&b, "..");
+        ew_show(&b, end);
+        ew_fail("The range must evaluate to %s bounds, got %s", typo, b.ptr);
+    }
+}
+
+// The `i`th character of a string, which is UTF-8.
+static bool ew_char_at(Val s, size_t i, size_t *start, size_t *len) {
+    size_t pos = 0;
+    for (size_t n = 0; pos < s.s.len; n++) {
+        size_t l = 1;
+        while (pos + l < s.s.len && (s.s.ptr[pos + l] & 0xc0) == 0x80) l++;
+        if (n == i) {
+            *start = pos;
+            *len = l;
+            return true;
+        }
+        pos += l;
+    }
+    return false;
+}
+
+static size_t ew_char_count(Val s) {
+    size_t n = 0;
+    for (size_t i = 0; i < s.s.len; i++) n += (s.s.ptr[i] & 0xc0) != 0x80;
+    return n;
+}
+
+// `index` as a position in something `len` long, counting negative ones
+// from the end. Anything out of bounds is `len` or more.
+static uint64_t ew_position(int64_t index, size_t len) {
+    return (uint64_t)(index < 0 ? index + (int64_t)len : index);
+}
+
+static _Noreturn void ew_out_of_bounds(const char *kind, long long index, size_t len) {
+    ew_fail("%s index %lld is out of bounds for length %zu", kind, index, len);
+}
+
+// `v[index]`.
+static Val ew_index(Val v, Val index) {
+    if (v.kind == EW_MAP) {
+        if (index.kind != EW_STR) ew_fail("Map key must be a string, got %s", ew_dbg(index));
+        bool found;
+        size_t i = ew_map_find(v.m, index, &found);
+        if (!found) {
+            EwBuf b = {0};
+            ew_quote(&b, index);
+            ew_fail("Map has no key %s", b.ptr);
+        }
+        return v.m->vals[i];
+    }
+    if (index.kind != EW_INT) ew_fail("Array index must be an integer, got %s", ew_dbg(index));
+    if (v.kind == EW_ARRAY) {
+        uint64_t i = ew_position(index.i, v.a->len);
+        if (i >= v.a->len) ew_out_of_bounds("Array", index.i, v.a->len);
+        return v.a->items[i];
+    }
+    if (v.kind == EW_STR) {
+        uint64_t i = index.i < 0 ? ew_position(index.i, ew_char_count(v)) : (uint64_t)index.i;
+        size_t start, len;
+        if (!ew_char_at(v, i, &start, &len)) {
+            ew_out_of_bounds("String", index.i, ew_char_count(v));
+        }
+        return ew_str(v.s.ptr + start, len);
+    }
+    ew_fail("Cannot index into %s", ew_dbg(v));
+}
+
+// Where `bound` puts one end of a slice of something `len` long.
+static size_t ew_slice_bound(Val bound, size_t len, size_t missing) {
+    if (bound.kind == EW_NULL) return missing;
+    if (bound.kind != EW_INT) ew_fail("Slice bound must be an integer, got %s", ew_dbg(bound));
+    int64_t i = bound.i < 0 ? bound.i + (int64_t)len : bound.i;
+    return i < 0 ? 0 : (uint64_t)i > len ? len : (size_t)i;
+}
+
+// The byte offset of the character `i` of `s`, or its length past the end.
+static size_t ew_char_offset(Val s, size_t i) {
+    size_t start, len;
+    return ew_char_at(s, i, &start, &len) ? start : s.s.len;
+}
+
+// `v[start..end]`, where a missing bound is null.
+static Val ew_slice(Val v, Val start, Val end) {
+    if (v.kind != EW_ARRAY && v.kind != EW_STR) ew_fail("Cannot slice %s", ew_dbg(v));
+    size_t len = v.kind == EW_ARRAY ? v.a->len : ew_char_count(v);
+    size_t from = ew_slice_bound(start, len, 0), to = ew_slice_bound(end, len, len);
+    if (to < from) to = from;
+    if (v.kind == EW_ARRAY) return ew_array(to - from, v.a->items + from);
+    size_t a = ew_char_offset(v, from), b = ew_char_offset(v, to);
+    return ew_str(v.s.ptr + a, b - a);
+}
+
+// The array element `v[index]` refers to, for assigning through it.
+static Val *ew_slot(Val *v, Val index) {
+    if (index.kind != EW_INT) ew_fail("Array index must be an integer, got %s", ew_dbg(index));
+    if (v->kind != EW_ARRAY) ew_fail("Cannot index into %s", ew_dbg(*v));
+    uint64_t i = ew_position(index.i, v->a->len);
+    if (i >= v->a->len) ew_out_of_bounds("Array", index.i, v->a->len);
+    return &v->a->items[i];
+}
+
+// Calls.
+
+struct EwMemo {
+    size_t len, cap;
+    Val *keys, *vals;
+};
+
+static uint64_t ew_hash(Val key) {
+    uint64_t h = 14695981039346656037u;
+    for (size_t i = 0; i < key.s.len; i++) h = (h ^ (unsigned char)key.s.ptr[i]) * 1099511628211u;
+    return h;
+}
+
+static Val *ew_memo_slot(EwMemo *memo, Val key) {
+    for (size_t i = ew_hash(key) & (memo->cap - 1);; i = (i + 1) & (memo->cap - 1)) {
+        if (memo->keys[i].kind == EW_UNIT || ew_equal(memo->keys[i], key)) return &memo->keys[i];
+    }
+}
+
+// Calls a memoized function, caching results by the arguments' debug form
+// as the interpreter does.
+static Val ew_call_memo(EwFn *fn, int argc, Val *argv) {
+    EwMemo *memo = fn->memo;
+    EwBuf b = {0};
+    ew_debug_list(&b, argc, argv);
+    Val key = ew_buf_val(&b);
+    Val *slot = ew_memo_slot(memo, key);
+    if (slot->kind != EW_UNIT) return memo->vals[slot - memo->keys];
+    Val res = ew_call((Val){.kind = EW_FN, .fn = fn->inner}, argc, argv);
+    if (2 * (memo->len + 1) > memo->cap) {
+        EwMemo grown = {memo->len, memo->cap * 2, NULL, NULL};
+        grown.keys = calloc(grown.cap, sizeof(Val));
+        grown.vals = ew_alloc(grown.cap * sizeof(Val));
+        for (size_t i = 0; i < memo->cap; i++) {
+            if (memo->keys[i].kind == EW_UNIT) continue;
+            Val *s = ew_memo_slot(&grown, memo->keys[i]);
+            *s = memo->keys[i];
+            grown.vals[s - grown.keys] = memo->vals[i];
+        }
+        *memo = grown;
+        slot = ew_memo_slot(memo, key);
+    }
+    *slot = key;
+    memo->vals[slot - memo->keys] = res;
+    memo->len++;
+    return res;
+}
+
+static Val ew_call(Val f, int argc, Val *argv) {
+    if (f.kind != EW_FN) {
+        EwBuf b = {0};
+        ew_show(&b, f);
+        ew_fail("'%s' is not a function", b.ptr);
+    }
+    if (f.fn->memo) return ew_call_memo(f.fn, argc, argv);
+    return f.fn->code(argc, argv);
+}
+
+// Builtins.
+
+static Val ew_write(Val text) {
+    fwrite(text.s.ptr, 1, text.s.len, stdout);
+    return EW_UNIT_VAL;
+}
+
+static Val ew_print(int argc, Val *argv) {
+    EwBuf b = {0};
+    for (int i = 0; i < argc; i++) ew_show(&b, argv[i]);
+    return ew_write(ew_buf_val(&b));
+}
+
+static Val ew_println(int argc, Val *argv) {
+    EwBuf b = {0};
+    for (int i = 0; i < argc; i++) {
+        if (i) ew_buf_str(&b, " ");
+        ew_show(&b, argv[i]);
+    }
+    ew_buf_str(&b, "\n");
+    return ew_write(ew_buf_val(&b));
+}
+
+static Val ew_pprint(int argc, Val *argv) {
+    ew_argc("pprint", argc, 1);
+    EwBuf b = {0};
+    ew_pretty(&b, argv[0], 0);
+    ew_buf_str(&b, "\n");
+    return ew_write(ew_buf_val(&b));
+}
+
+static Val ew_str_(int argc, Val *argv) {
+    ew_argc("str", argc, 1);
+    EwBuf b = {0};
+    ew_show(&b, argv[0]);
+    return ew_buf_val(&b);
+}
+
+static Val ew_repr_(int argc, Val *argv) {
+    ew_argc("repr", argc, 1);
+    EwBuf b = {0};
+    ew_repr(&b, argv[0]);
+    return ew_buf_val(&b);
+}
+
+static Val ew_len(int argc, Val *argv) {
+    ew_argc("len", argc, 1);
+    switch (argv[0].kind) {
+    case EW_ARRAY: return ew_int(argv[0].a->len);
+    case EW_MAP: return ew_int(argv[0].m->len);
+    case EW_STR: return ew_int(ew_char_count(argv[0]));
+    default: ew_fail("len() requires an array or string, got %s", ew_dbg(argv[0]));
+    }
+}
+
+static Val ew_keys(int argc, Val *argv) {
+    ew_argc("keys", argc, 1);
+    if (argv[0].kind != EW_MAP) ew_fail("keys() requires a map, got %s", ew_dbg(argv[0]));
+    return ew_array(argv[0].m->len, argv[0].m->keys);
+}
+
+static Val ew_copy_(int argc, Val *argv) {
+    ew_argc("copy", argc, 1);
+    return ew_copy(argv[0]);
+}
+
+static Val ew_deep_copy(int argc, Val *argv) {
+    ew_argc("deep_copy", argc, 1);
+    return ew_copy(argv[0]);
+}
+
+static Val ew_deep_eq(int argc, Val *argv) {
+    ew_argc("deep_eq", argc, 2);
+    return ew_bool(ew_equal(argv[0], argv[1]));
+}
+
+typedef int (*EwCompare)(Val a, Val b, void *data);
+
+// Stable merge sort of `n` items into `out`, using `tmp` as scratch space.
+static void ew_merge_sort(Val *items, size_t n, Val *tmp, EwCompare cmp, void *data) {
+    if (n <= 1) return;
+    size_t half = n / 2;
+    ew_merge_sort(items, half, tmp, cmp, data);
+    ew_merge_sort(items + half, n - half, tmp, cmp, data);
+    size_t l = 0, r = half, k = 0;
+    while (l < half && r < n) {
+        // Taking from the left on ties keeps equal elements in order.
+        if (cmp(items[l], items[r], data) > 0) tmp[k++] = items[r++];
+        else tmp[k++] = items[l++];
+    }
+    while (l < half) tmp[k++] = items[l++];
+    while (r < n) tmp[k++] = items[r++];
+    memcpy(items, tmp, n * sizeof(Val));
+}
+
+static int ew_natural(Val a, Val b, void *data) {
+    if (a.kind == EW_STR && b.kind == EW_STR) {
+        int c = ew_str_cmp(a, b);
+        return (c > 0) - (c < 0);
+    }
+    if (a.kind == EW_INT && b.kind == EW_INT) return (a.i > b.i) - (a.i < b.i);
+    if (a.kind == EW_FLOAT && b.kind == EW_FLOAT) {
+        if (isnan(a.f) || isnan(b.f)) ew_fail("%s() cannot order NaN", (const char *)data);
+        return (a.f > b.f) - (a.f < b.f);
+    }
+    ew_fail("%s() cannot compare %s with %s", (const char *)data, ew_type_name(a),
+            ew_type_name(b));
+}
+
+static int ew_user_order(Val a, Val b, void *data) {
+    Val res = ew_call(*(Val *)data, 2, (Val[]){a, b});
+    if (res.kind != EW_INT) {
+        ew_fail("sort_by() comparison must return -1, 0 or 1, got %s", ew_dbg(res));
+    }
+    return (res.i > 0) - (res.i < 0);
+}
+
+static Val ew_sorted(Val v, EwCompare cmp, void *data) {
+    Val copy = ew_array(v.a->len, v.a->items);
+    ew_merge_sort(copy.a->items, copy.a->len, ew_alloc(v.a->len * sizeof(Val)), cmp, data);
+    return copy;
+}
+
+static Val ew_sort(int argc, Val *argv) {
+    ew_argc("sort", argc, 1);
+    if (argv[0].kind != EW_ARRAY) ew_fail("sort() requires an array");
+    return ew_sorted(argv[0], ew_natural, "sort");
+}
+
+static Val ew_sort_by(int argc, Val *argv) {
+    ew_argc("sort_by", argc, 2);
+    if (argv[0].kind != EW_ARRAY || argv[1].kind != EW_FN) {
+        ew_fail("sort_by() requires an array and a comparison function");
+    }
+    return ew_sorted(argv[0], ew_user_order, &argv[1]);
+}
+
+// A numeric argument as a double, or NULL's worth of failure.
+static bool ew_as_f64(Val v, double *f) {
+    if (v.kind == EW_INT) *f = (double)v.i;
+    else if (v.kind == EW_FLOAT) *f = v.f;
+    else return false;
+    return true;
+}
+
+static double ew_number(const char *name, int argc, Val *argv) {
+    ew_argc(name, argc, 1);
+    double f;
+    if (!ew_as_f64(argv[0], &f)) ew_fail("%s() requires a number, got %s", name, ew_dbg(argv[0]));
+    return f;
+}
+
+static Val ew_abs(int argc, Val *argv) {
+    double f = ew_number("abs", argc, argv);
+    if (argv[0].kind == EW_INT) {
+        if (argv[0].i == INT64_MIN) ew_overflow();
+        return ew_int(argv[0].i < 0 ? -argv[0].i : argv[0].i);
+    }
+    return ew_float(fabs(f));
+}
+
+static Val ew_sqrt(int argc, Val *argv) { return ew_float(sqrt(ew_number("sqrt", argc, argv))); }
+static Val ew_sin(int argc, Val *argv) { return ew_float(sin(ew_number("sin", argc, argv))); }
+static Val ew_cos(int argc, Val *argv) { return ew_float(cos(ew_number("cos", argc, argv))); }
+
+static Val ew_floor(int argc, Val *argv) {
+    double f = ew_number("floor", argc, argv);
+    if (argv[0].kind == EW_INT) return argv[0];
+    f = floor(f);
+    // Saturates like Rust's `as i64`.
+    if (isnan(f)) return ew_int(0);
+    if (f >= 9223372036854775807.0) return ew_int(INT64_MAX);
+    if (f <= -9223372036854775808.0) return ew_int(INT64_MIN);
+    return ew_int((int64_t)f);
+}
+
+static Val ew_round(int argc, Val *argv) {
+    ew_argc("round", argc, 2);
+    if (argv[1].kind != EW_INT) {
+        ew_fail("round() requires an integer digit count, got %s", ew_dbg(argv[1]));
+    }
+    int64_t digits = argv[1].i;
+    double x;
+    if (!ew_as_f64(argv[0], &x)) ew_fail("round() requires a number, got %s", ew_dbg(argv[0]));
+    if (argv[0].kind == EW_INT && digits >= 0) return argv[0];
+    double scale = pow(10, digits < -308 ? -308 : digits > 308 ? 308 : digits);
+    return ew_float(round(x * scale) / scale);
+}
+
+static Val ew_to_fixed(int argc, Val *argv) {
+    ew_argc("to_fixed", argc, 2);
+    if (argv[1].kind != EW_INT || argv[1].i < 0) {
+        ew_fail("to_fixed() requires a non-negative digit count, got %s", ew_dbg(argv[1]));
+    }
+    if (argv[1].i > 100) {
+        ew_fail("to_fixed() can show at most 100 digits, got %lld", (long long)argv[1].i);
+    }
+    double x;
+    if (!ew_as_f64(argv[0], &x)) ew_fail("to_fixed() requires a number, got %s", ew_dbg(argv[0]));
+    EwBuf b = {0};
+    if (isnan(x)) ew_buf_str(&b, "NaN");
+    else if (isinf(x)) ew_buf_str(&b, x > 0 ? "inf" : "-inf");
+    else {
+        // printf takes halves to even; the float just past one goes away
+        // from zero, as in round(). A tie has exactly one more decimal than
+        // asked for, which is where its lowest set bit puts it.
+        int exp;
+        double m = frexp(fabs(x), &exp);
+        uint64_t bits = (uint64_t)ldexp(m, 53);
+        int lowest = exp - 53 + (bits ? __builtin_ctzll(bits) : 0);
+        if (x != 0 && -lowest == argv[1].i + 1) x = nextafter(x, x > 0 ? INFINITY : -INFINITY);
+        ew_buf_fmt(&b, "%.*f", (int)argv[1].i, x);
+    }
+    return ew_buf_val(&b);
+}
+
+static Val ew_divmod(int argc, Val *argv) {
+    ew_argc("divmod", argc, 2);
+    Val a = argv[0], b = argv[1];
+    if (a.kind == EW_INT && b.kind == EW_INT) {
+        if (b.i == 0) ew_fail("Division by zero");
+        if (a.i == INT64_MIN && b.i == -1) ew_overflow();
+        int64_t q = a.i / b.i, r = a.i % b.i;
+        if (r != 0 && (r < 0) != (b.i < 0)) {
+            q -= 1;
+            r += b.i;
+        }
+        return ew_array(2, (Val[]){ew_int(q), ew_int(r)});
+    }
+    if (a.kind == EW_FLOAT && b.kind == EW_FLOAT) {
+        double q = floor(a.f / b.f);
+        return ew_array(2, (Val[]){ew_float(q), ew_float(a.f - b.f * q)});
+    }
+    ew_fail("divmod() requires two integers or two floats, got %s and %s", ew_dbg(a), ew_dbg(b));
+}
+
+static Val ew_approx_eq(int argc, Val *argv) {
+    ew_argc("approx_eq", argc, 3);
+    double a, b, eps;
+    if (!ew_as_f64(argv[0], &a) || !ew_as_f64(argv[1], &b) || !ew_as_f64(argv[2], &eps)) {
+        ew_fail("approx_eq() requires numbers, got %s, %s and %s", ew_dbg(argv[0]),
+                ew_dbg(argv[1]), ew_dbg(argv[2]));
+    }
+    return ew_bool(a == b || fabs(a - b) <= eps);
+}
+
+static Val ew_is_nan(int argc, Val *argv) { return ew_bool(isnan(ew_number("is_nan", argc, argv))); }
+
+static Val ew_is_inf(int argc, Val *argv) { return ew_bool(isinf(ew_number("is_inf", argc, argv))); }
+
+// The string argument of a parser, trimmed and NUL-terminated.
+static char *ew_trimmed(const char *name, int argc, Val *argv) {
+    ew_argc(name, argc, 1);
+    if (argv[0].kind != EW_STR) ew_fail("%s() requires a string, got %s", name, ew_dbg(argv[0]));
+    const char *s = argv[0].s.ptr, *end = s + argv[0].s.len;
+    while (s < end && strchr(" \t\n\r\f\v", *s)) s++;
+    while (end > s && strchr(" \t\n\r\f\v", end[-1])) end--;
+    char *t = ew_alloc(end - s + 1);
+    memcpy(t, s, end - s);
+    t[end - s] = 0;
+    return t;
+}
+
+static Val ew_parse_int(int argc, Val *argv) {
+    char *s = ew_trimmed("parse_int", argc, argv);
+    const char *digits = s + (*s == '+' || *s == '-');
+    if (!*digits || strspn(digits, "0123456789") != strlen(digits)) return EW_NULL_VAL;
+    char *end;
+    long long n = strtoll(s, &end, 10);
+    if (n == LLONG_MAX || n == LLONG_MIN) {
+        // Either exactly the bound or out of range; only the first fits.
+        char bound[24];
+        snprintf(bound, sizeof bound, "%lld", n);
+        if (strcmp(bound, s + (*s == '+')) != 0) ew_overflow();
+    }
+    return ew_int(n);
+}
+
+static Val ew_parse_float(int argc, Val *argv) {
+    char *s = ew_trimmed("parse_float", argc, argv);
+    const char *body = s + (*s == '+' || *s == '-');
+    // strtod also reads hex and `nan(...)`, which Rust doesn't.
+    if (!*body || strpbrk(body, "xX()") || strchr("+-", *body)) return EW_NULL_VAL;
+    char *end;
+    double f = strtod(s, &end);
+    return *end ? EW_NULL_VAL : ew_float(f);
+}
+
+static Val ew_memoize(int argc, Val *argv) {
+    ew_argc("memoize", argc, 1);
+    if (argv[0].kind != EW_FN) ew_fail("memoize() requires a function, got %s", ew_dbg(argv[0]));
+    EwFn *fn = ew_alloc(sizeof *fn);
+    EwMemo *memo = ew_alloc(sizeof *memo);
+    *memo = (EwMemo){0, 16, calloc(16, sizeof(Val)), ew_alloc(16 * sizeof(Val))};
+    *fn = (EwFn){argv[0].fn->name, argv[0].fn->signature, NULL, argv[0].fn, memo};
+    return (Val){.kind = EW_FN, .fn = fn};
+}
+
+static Val ew_program(void);
+
+int main(void) {
+    ew_program();
+    fflush(stdout);
+    return 0;
+}
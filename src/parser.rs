@@ -1,22 +1,30 @@
 #![allow(unused)]
 use std::fmt::format;
 
-use pest::{Parser, iterators::Pair};
+use pest::{Parser, error::InputLocation, iterators::Pair};
 
-use crate::ast::{AssignmentTarget, BinaryOp, Expr, Stmt, UnaryOp};
+use crate::ast::{
+    AssignmentTarget, BinaryOp, Expr, Iteration, MatchArm, Pattern, Pragma, Program, Span, Stmt,
+    UnaryOp, Variant, VisitorMut, walk_expr_mut, walk_stmt_mut,
+};
+use crate::version::{self, Feature};
 
 #[derive(pest_derive::Parser)]
 #[grammar = "./grammar.pest"]
 struct LangParser;
 
-pub fn parse(source: &str) -> Result<Vec<Stmt>, String> {
+pub fn parse(source: &str) -> Result<Program, String> {
     let pairs =
         LangParser::parse(Rule::Program, source).map_err(|e| format!("Parsing error {}", e))?;
 
     let mut program = Vec::new();
     for pair in pairs {
         match pair.as_rule() {
-            Rule::Stmt => program.push(parse_stmt(pair)?),
+            Rule::Stmt => {
+                let stmt = parse_stmt(pair)?;
+                check_pragma_position(&program, &stmt)?;
+                program.push(stmt);
+            }
             Rule::EOI => {}
             _ => {}
         }
@@ -24,15 +32,239 @@ pub fn parse(source: &str) -> Result<Vec<Stmt>, String> {
     Ok(program)
 }
 
+const MISPLACED_PRAGMA: &str = "#pragma and #feature must come before any other statement";
+
+/// Fails if `stmt` is a pragma and something in `before` isn't.
+fn check_pragma_position(before: &[Stmt], stmt: &Stmt) -> Result<(), String> {
+    if matches!(stmt, Stmt::Pragma(_)) && before.iter().any(|s| !matches!(s, Stmt::Pragma(_))) {
+        return Err(MISPLACED_PRAGMA.to_string());
+    }
+    Ok(())
+}
+
+/// A syntax error located in the original source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub message: String,
+    /// 1-based line and column of the error.
+    pub line: usize,
+    pub col: usize,
+    /// Byte range into the source.
+    pub span: (usize, usize),
+}
+
+impl ParseError {
+    fn new(source: &str, span: (usize, usize), message: String) -> ParseError {
+        let before = &source[..span.0];
+        let line = before.matches('\n').count() + 1;
+        let col = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+        ParseError {
+            message,
+            line,
+            col,
+            span,
+        }
+    }
+}
+
+impl std::fmt::Display for ParseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.col, self.message)
+    }
+}
+
+/// Parses `source` statement by statement, collecting every syntax error
+/// instead of stopping at the first one.
+///
+/// After an error the parser skips ahead to the next line where the brackets
+/// opened by the broken statement are balanced again, and carries on from
+/// there.
+pub fn parse_all(source: &str) -> Result<Program, Vec<ParseError>> {
+    collect_units(source, parse_units(source, 0))
+}
+
+/// One top-level statement as seen by the recovering parser, together with
+/// the byte range it was parsed from. For a syntax error the range runs up
+/// to the point where parsing resumed.
+#[derive(Debug, Clone)]
+struct Unit {
+    span: (usize, usize),
+    result: Result<Stmt, ParseError>,
+}
+
+fn parse_units(source: &str, mut pos: usize) -> Vec<Unit> {
+    let mut units = Vec::new();
+    pos = skip_trivia(source, pos);
+
+    while pos < source.len() {
+        match LangParser::parse(Rule::Stmt, &source[pos..]) {
+            Ok(mut pairs) => {
+                let pair = pairs.next().unwrap();
+                let span = (pos + pair.as_span().start(), pos + pair.as_span().end());
+                let result = parse_stmt(pair).map_err(|m| ParseError::new(source, span, m));
+                units.push(Unit { span, result });
+                pos = span.1;
+            }
+            Err(e) => {
+                let span = match e.location {
+                    InputLocation::Pos(p) => (pos + p, pos + p),
+                    InputLocation::Span((start, end)) => (pos + start, pos + end),
+                };
+                let error = ParseError::new(source, span, e.variant.message().into_owned());
+                let resume = synchronize(source, pos, span.0);
+                units.push(Unit {
+                    span: (pos, resume),
+                    result: Err(error),
+                });
+                pos = resume;
+            }
+        }
+        pos = skip_trivia(source, pos);
+    }
+
+    units
+}
+
+fn collect_units(source: &str, units: Vec<Unit>) -> Result<Program, Vec<ParseError>> {
+    let mut program = Vec::new();
+    let mut errors = Vec::new();
+    for unit in units {
+        match unit.result {
+            Ok(stmt) => match check_pragma_position(&program, &stmt) {
+                Ok(()) => program.push(stmt),
+                Err(m) => errors.push(ParseError::new(source, unit.span, m)),
+            },
+            Err(e) => errors.push(e),
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(program)
+    } else {
+        Err(errors)
+    }
+}
+
+/// How many brackets `s` leaves open, ignoring those inside strings. The
+/// REPL keeps reading lines while this is positive.
+pub fn bracket_depth(s: &str) -> i32 {
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut prev_char = ' ';
+
+    for c in s.chars() {
+        if c == '"' && prev_char != '\\' {
+            in_string = !in_string;
+        }
+
+        if !in_string {
+            match c {
+                '{' | '(' | '[' => depth += 1,
+                '}' | ')' | ']' => depth -= 1,
+                _ => {}
+            }
+        }
+        prev_char = c;
+    }
+
+    depth
+}
+
+/// A recovering parser that remembers its last parse, for editors and the
+/// REPL re-parsing a buffer on every keystroke.
+///
+/// On [`IncrementalParser::update`], statements that lie entirely before the
+/// first changed byte are reused and parsing restarts just after them. A
+/// statement is only reused if the statement following it is unchanged as
+/// well, since appending text right after a statement (`x` becoming
+/// `x + 1`) can change where it ends.
+#[derive(Debug, Default)]
+pub struct IncrementalParser {
+    source: String,
+    units: Vec<Unit>,
+    reused: usize,
+}
+
+impl IncrementalParser {
+    pub fn new() -> IncrementalParser {
+        IncrementalParser::default()
+    }
+
+    pub fn update(&mut self, source: &str) -> Result<Program, Vec<ParseError>> {
+        let changed_at = self
+            .source
+            .bytes()
+            .zip(source.bytes())
+            .position(|(a, b)| a != b)
+            .unwrap_or(self.source.len().min(source.len()));
+
+        let mut keep = 0;
+        while keep + 1 < self.units.len() && self.units[keep + 1].span.1 <= changed_at {
+            keep += 1;
+        }
+
+        let resume = self.units.get(keep).map_or(0, |u| u.span.0);
+        self.units.truncate(keep);
+        self.units.extend(parse_units(source, resume));
+        self.source = source.to_string();
+        self.reused = keep;
+
+        collect_units(source, self.units.clone())
+    }
+
+    /// How many statements the last `update` took from the previous parse.
+    pub fn reused(&self) -> usize {
+        self.reused
+    }
+}
+
+/// Skips whitespace and `//` comments starting at `pos`.
+fn skip_trivia(source: &str, mut pos: usize) -> usize {
+    loop {
+        let rest = &source[pos..];
+        let trimmed = rest.trim_start();
+        pos += rest.len() - trimmed.len();
+        if trimmed.starts_with("//") {
+            pos += trimmed.find('\n').unwrap_or(trimmed.len());
+        } else {
+            return pos;
+        }
+    }
+}
+
+/// Finds where to resume after a statement starting at `start` failed at
+/// `error`: the first line break past the error at which every bracket the
+/// statement opened has been closed.
+fn synchronize(source: &str, start: usize, error: usize) -> usize {
+    let mut depth = 0;
+    let mut in_string = false;
+
+    for (i, c) in source[start..].char_indices() {
+        let i = start + i;
+        match c {
+            '"' => in_string = !in_string,
+            '{' | '(' | '[' if !in_string => depth += 1,
+            '}' | ')' | ']' if !in_string => depth -= 1,
+            '\n' if i >= error && depth <= 0 => return i + 1,
+            _ => {}
+        }
+    }
+    source.len()
+}
+
 fn parse_stmt(pair: Pair<Rule>) -> Result<Stmt, String> {
     let inner = pair.into_inner().next().unwrap();
     match inner.as_rule() {
         Rule::Function => parse_func(inner),
+        Rule::Enum => parse_enum(inner),
         Rule::Return => parse_ret(inner),
+        Rule::Break => parse_break(inner),
         Rule::Assignment => parse_ass(inner),
         Rule::Reassignment => parse_reass(inner),
+        Rule::Increment => parse_increment(inner),
+        Rule::Pragma => parse_pragma(inner),
         Rule::Expr => Ok(Stmt::Expr(parse_expr(inner)?)),
-        Rule::Conditional | Rule::WhileLoop | Rule::Comp | Rule::ForLoop => {
+        Rule::Conditional | Rule::WhileLoop | Rule::Or | Rule::ForLoop => {
             Ok(Stmt::Expr(parse_expr(inner)?))
         }
         r => Err(format!("Unexpected statement rule: {:#?}", r)),
@@ -41,18 +273,45 @@ fn parse_stmt(pair: Pair<Rule>) -> Result<Stmt, String> {
 
 fn parse_expr(pair: Pair<Rule>) -> Result<Expr, String> {
     match pair.as_rule() {
-        Rule::Expr => {
+        Rule::Expr | Rule::Group => {
             let inner = pair.into_inner().next().unwrap();
             parse_expr(inner)
         }
         Rule::Conditional => parse_conditional(pair),
-        Rule::Comp => parse_binary(pair),
+        Rule::LetIn => {
+            let mut inner = pair.into_inner();
+            let (name, value) = parse_let_binding(inner.next().unwrap())?;
+            let body = Box::new(parse_expr(inner.nth(1).unwrap())?);
+            Ok(Expr::LetIn { name, value, body })
+        }
+        Rule::Pipe => parse_pipe(pair),
+        Rule::Or => parse_binary(pair),
+        Rule::And => parse_binary(pair),
+        Rule::Comp => parse_comparison(pair),
         Rule::Unary => parse_unary(pair),
+        Rule::Spread => Ok(Expr::Spread(Box::new(parse_expr(
+            pair.into_inner().next().unwrap(),
+        )?))),
         Rule::WhileLoop => parse_while(pair),
+        Rule::Match => parse_match(pair),
+        Rule::VariantPath => {
+            let span = span(&pair);
+            let mut inner = pair.into_inner();
+            Ok(Expr::Variant {
+                enum_name: inner.next().unwrap().as_str().to_string(),
+                variant: inner.next().unwrap().as_str().to_string(),
+                args: vec![],
+                span,
+            })
+        }
+        Rule::Labeled => parse_labeled(pair),
+        Rule::Comprehension | Rule::MapComprehension => parse_comprehension(pair),
+        Rule::Loop => Ok(Expr::Loop(parse_block(pair.into_inner().next().unwrap())?)),
         Rule::ForLoop => parse_for(pair),
         Rule::Range => parse_for(pair),
         Rule::Additive => parse_binary(pair),
         Rule::Multiplicative => parse_binary(pair),
+        Rule::Power => parse_binary(pair),
         Rule::Call => parse_call(pair),
         Rule::Literal => parse_literal(pair),
         Rule::Ident => Ok(Expr::Var(pair.as_str().to_string())),
@@ -67,20 +326,75 @@ fn parse_expr(pair: Pair<Rule>) -> Result<Expr, String> {
 
 fn parse_conditional(pair: Pair<Rule>) -> Result<Expr, String> {
     let mut inner = pair.into_inner();
-    let cond = Box::new(parse_expr(inner.next().unwrap())?);
+    let cond = inner.next().unwrap();
     let then = parse_block(inner.next().unwrap())?;
-    let else_ = if let Some(else_branch) = inner.next() {
-        parse_block(else_branch)?
-    } else {
-        vec![]
+    // `else if` desugars to an else block holding just the nested `if`.
+    let else_ = match inner.next() {
+        Some(branch) if branch.as_rule() == Rule::Conditional => {
+            vec![Stmt::Expr(parse_conditional(branch)?)]
+        }
+        Some(branch) => parse_block(branch)?,
+        None => vec![],
     };
 
-    Ok(Expr::If { cond, then, else_ })
+    if cond.as_rule() == Rule::LetBinding {
+        let (name, value) = parse_let_binding(cond)?;
+        return Ok(Expr::IfLet {
+            name,
+            value,
+            then,
+            else_,
+        });
+    }
+
+    let span = span(&cond);
+    let cond = Box::new(parse_expr(cond)?);
+    Ok(Expr::If {
+        cond,
+        then,
+        else_,
+        span,
+    })
+}
+
+fn parse_let_binding(pair: Pair<Rule>) -> Result<(String, Box<Expr>), String> {
+    let mut inner = pair.into_inner();
+    let name = inner.next().unwrap().as_str().to_string();
+    let value = Box::new(parse_expr(inner.next().unwrap())?);
+    Ok((name, value))
+}
+
+/// `x |> f(a) |> g` becomes `g(f(x, a))`: each stage gets the value so far
+/// as its first argument.
+fn parse_pipe(pair: Pair<Rule>) -> Result<Expr, String> {
+    let mut inner = pair.into_inner();
+    let mut value = parse_expr(inner.next().unwrap())?;
+    for stage in inner {
+        value = match parse_expr(stage)? {
+            Expr::Var(name) => Expr::Call {
+                name,
+                args: vec![value],
+                span: Span::default(),
+            },
+            Expr::Call {
+                name,
+                mut args,
+                span,
+            } => {
+                args.insert(0, value);
+                Expr::Call { name, args, span }
+            }
+            _ => return Err("expected a function call after |>".to_string()),
+        };
+    }
+    Ok(value)
 }
 
 fn parse_binary(pair: Pair<Rule>) -> Result<Expr, String> {
     let mut inner = pair.into_inner();
-    let mut lhs = parse_expr(inner.next().unwrap())?;
+    let first = inner.next().unwrap();
+    let start = span(&first).start;
+    let mut lhs = parse_expr(first)?;
     while let Some(op_pair) = inner.next() {
         let op = match op_pair.as_str() {
             "+" => BinaryOp::Add,
@@ -88,6 +402,7 @@ fn parse_binary(pair: Pair<Rule>) -> Result<Expr, String> {
             "*" => BinaryOp::Mul,
             "/" => BinaryOp::Div,
             "%" => BinaryOp::Mod,
+            "**" => BinaryOp::Pow,
             "==" => BinaryOp::Eq,
             "!=" => BinaryOp::Ne,
             "<" => BinaryOp::Lt,
@@ -99,19 +414,94 @@ fn parse_binary(pair: Pair<Rule>) -> Result<Expr, String> {
             e => return Err(format!("Unexprected Operator: {}", e)),
         };
 
-        let rhs = parse_expr(inner.next().unwrap())?;
+        let rhs = inner.next().unwrap();
+        let span = Span {
+            start,
+            end: span(&rhs).end,
+        };
+        let rhs = parse_expr(rhs)?;
 
         lhs = Expr::Binary {
-            op: op,
+            op,
             lhs: Box::new(lhs),
             rhs: Box::new(rhs),
+            span,
         };
     }
 
     Ok(lhs)
 }
 
+/// Parses `a < b < c` like mathematics does, as `a < b && b < c` except
+/// that each operand is evaluated once, left to right, and the chain stops
+/// at the first comparison that fails. Operands that would otherwise be
+/// evaluated twice, or out of order, are bound to temporaries.
+fn parse_comparison(pair: Pair<Rule>) -> Result<Expr, String> {
+    // A name none of the operands can refer to.
+    let mut temp = "cmp".to_string();
+    while pair.as_str().contains(&temp) {
+        temp.push('_');
+    }
+    let mut inner = pair.into_inner();
+    let mut operands = vec![parse_expr(inner.next().unwrap())?];
+    let mut ops = Vec::new();
+
+    while let Some(op_pair) = inner.next() {
+        ops.push(match op_pair.as_str() {
+            "==" => BinaryOp::Eq,
+            "!=" => BinaryOp::Ne,
+            "<" => BinaryOp::Lt,
+            "<=" => BinaryOp::Le,
+            ">=" => BinaryOp::Ge,
+            ">" => BinaryOp::Gt,
+            e => return Err(format!("Unexprected Operator: {}", e)),
+        });
+        operands.push(parse_expr(inner.next().unwrap())?);
+    }
+
+    let last = operands.pop().unwrap();
+    let Some(&last_op) = ops.last() else {
+        return Ok(last);
+    };
+    let (bound, operands): (Vec<_>, Vec<_>) = operands
+        .into_iter()
+        .enumerate()
+        .map(|(i, operand)| match operand {
+            Expr::Int(_) | Expr::Float(_) | Expr::Str { .. } | Expr::Bool(_) | Expr::Var(_) => {
+                (None, operand)
+            }
+            _ if ops.len() == 1 => (None, operand),
+            _ => {
+                let name = format!("{}{}", temp, i);
+                (Some((name.clone(), operand)), Expr::Var(name))
+            }
+        })
+        .unzip();
+    let bind = |i: usize, body: Expr| match &bound[i] {
+        Some((name, value)) => Expr::LetIn {
+            name: name.clone(),
+            value: Box::new(value.clone()),
+            body: Box::new(body),
+        },
+        None => body,
+    };
+
+    // From the last comparison back, each run only if the one before it
+    // held, with the operand it shares bound just before that one.
+    let n = ops.len();
+    let mut chain = Expr::binary(last_op, operands[n - 1].clone(), last);
+    for i in (0..n - 1).rev() {
+        let cmp = Expr::binary(ops[i], operands[i].clone(), operands[i + 1].clone());
+        chain = bind(
+            i + 1,
+            Expr::if_else(cmp, [Stmt::Expr(chain)], [Stmt::Expr(Expr::Bool(false))]),
+        );
+    }
+    Ok(bind(0, chain))
+}
+
 fn parse_unary(pair: Pair<Rule>) -> Result<Expr, String> {
+    let span = span(&pair);
     let mut inner = pair.into_inner();
     let first = inner.next().unwrap();
 
@@ -127,6 +517,7 @@ fn parse_unary(pair: Pair<Rule>) -> Result<Expr, String> {
             Ok(Expr::Unary {
                 op,
                 expr: Box::new(expr),
+                span,
             })
         }
         _ => parse_expr(first),
@@ -135,10 +526,25 @@ fn parse_unary(pair: Pair<Rule>) -> Result<Expr, String> {
 
 fn parse_while(pair: Pair<Rule>) -> Result<Expr, String> {
     let mut inner = pair.into_inner();
-    let cond = Box::new(parse_expr(inner.next().unwrap())?);
+    let cond = inner.next().unwrap();
     let body = parse_block(inner.next().unwrap())?;
 
-    Ok(Expr::While { cond, body })
+    if cond.as_rule() == Rule::LetBinding {
+        let (name, value) = parse_let_binding(cond)?;
+        return Ok(Expr::WhileLet { name, value, body });
+    }
+
+    let span = span(&cond);
+    let cond = Box::new(parse_expr(cond)?);
+    Ok(Expr::While { cond, body, span })
+}
+
+fn span(pair: &Pair<Rule>) -> Span {
+    let span = pair.as_span();
+    Span {
+        start: span.start_pos().line_col(),
+        end: span.end_pos().line_col(),
+    }
 }
 
 fn parse_for(pair: Pair<Rule>) -> Result<Expr, String> {
@@ -156,6 +562,45 @@ fn parse_for(pair: Pair<Rule>) -> Result<Expr, String> {
     })
 }
 
+fn parse_comprehension(pair: Pair<Rule>) -> Result<Expr, String> {
+    let is_map = pair.as_rule() == Rule::MapComprehension;
+    let whole = span(&pair);
+    let mut inner = pair.into_inner();
+
+    let key = match is_map {
+        true => Some(Box::new(parse_expr(inner.next().unwrap())?)),
+        false => None,
+    };
+    let value = Box::new(parse_expr(inner.next().unwrap())?);
+    let var = inner.next().unwrap().as_str().to_string();
+    let source = inner.next().unwrap();
+    let source = match source.as_rule() {
+        Rule::Range => {
+            let (start, end) = parse_range(source)?;
+            Iteration::Range { start, end }
+        }
+        _ => Iteration::Each(Box::new(parse_expr(source)?)),
+    };
+    let (cond, cond_span) = match inner.next() {
+        Some(cond) => {
+            let cond = cond.into_inner().next().unwrap();
+            let span = span(&cond);
+            (Some(Box::new(parse_expr(cond)?)), span)
+        }
+        None => (None, Span::default()),
+    };
+
+    Ok(Expr::Comprehension {
+        key,
+        value,
+        var,
+        source,
+        cond,
+        cond_span,
+        span: whole,
+    })
+}
+
 fn parse_range(pair: Pair<Rule>) -> Result<(Box<Expr>, Box<Expr>), String> {
     if pair.as_rule() != Rule::Range {
         return Err(format!("Expected Range, got {:?}", pair.as_rule()));
@@ -171,6 +616,7 @@ fn parse_range(pair: Pair<Rule>) -> Result<(Box<Expr>, Box<Expr>), String> {
 }
 
 fn parse_call(pair: Pair<Rule>) -> Result<Expr, String> {
+    let span = span(&pair);
     let mut inner = pair.into_inner();
     let first = inner.next().unwrap();
 
@@ -183,11 +629,21 @@ fn parse_call(pair: Pair<Rule>) -> Result<Expr, String> {
                 .map(|p| parse_expr(p))
                 .collect::<Result<_, _>>()?;
 
-            if let Expr::Var(name) = expr {
-                expr = Expr::Call { name, args }
-            } else {
-                return Err("expected a named function to be called".to_string());
-            }
+            expr = match expr {
+                Expr::Var(name) => Expr::Call { name, args, span },
+                Expr::Variant {
+                    enum_name,
+                    variant,
+                    args: prev,
+                    ..
+                } if prev.is_empty() => Expr::Variant {
+                    enum_name,
+                    variant,
+                    args,
+                    span,
+                },
+                _ => return Err("expected a named function to be called".to_string()),
+            };
         }
     }
 
@@ -197,26 +653,34 @@ fn parse_call(pair: Pair<Rule>) -> Result<Expr, String> {
 fn parse_literal(pair: Pair<Rule>) -> Result<Expr, String> {
     let inner = pair.into_inner().next().unwrap();
     match inner.as_rule() {
-        Rule::Int => Ok(Expr::Int(inner.as_str().parse().unwrap())),
+        Rule::Int => match inner.as_str().parse() {
+            Ok(n) => Ok(Expr::Int(n)),
+            Err(_) => Ok(Expr::BigInt(inner.as_str().parse().unwrap())),
+        },
         Rule::Bool => Ok(Expr::Bool(inner.as_str() == "true")),
+        Rule::Null => Ok(Expr::Null),
         Rule::Float => Ok(Expr::Float(inner.as_str().parse().unwrap())),
         Rule::String => {
-            let s = inner.as_str().to_string();
-            let s = &s[1..s.len() - 1];
-            Ok(Expr::Str(s.to_string()))
+            let s = inner.as_str();
+            Ok(Expr::Str {
+                value: unescape(&s[1..s.len() - 1])?,
+                span: span(&inner),
+            })
         }
         Rule::Array => {
-            let elements = inner
+            let span = span(&inner);
+            let items = inner
                 .into_inner()
                 .map(|p| parse_expr(p))
                 .collect::<Result<_, _>>()?;
-            Ok(Expr::Array(elements))
+            Ok(Expr::Array { items, span })
         }
         e => Err(format!("Expected a Literal: {:?}", e)),
     }
 }
 
 fn parse_access(pair: Pair<Rule>) -> Result<Expr, String> {
+    let span = span(&pair);
     let mut inner = pair.into_inner();
     let name = inner.next().unwrap().as_str().to_string();
 
@@ -224,11 +688,36 @@ fn parse_access(pair: Pair<Rule>) -> Result<Expr, String> {
     // We first map eval to flesh out all indices
     // And then Box then with the second map
     // ::<> is called the 'turbofish' for some reason
-    let indices = inner
-        .map(|e| parse_expr(e).map(Box::new))
-        .collect::<Result<Vec<Box<Expr>>, String>>()?;
+    let mut indices = Vec::new();
+    for pair in inner {
+        if pair.as_rule() != Rule::Slice {
+            indices.push(Box::new(parse_expr(pair)?));
+            continue;
+        }
+        let (mut start, mut end) = (None, None);
+        for bound in pair.into_inner() {
+            let expr = Some(Box::new(parse_expr(
+                bound.clone().into_inner().next().unwrap(),
+            )?));
+            match bound.as_rule() {
+                Rule::SliceStart => start = expr,
+                _ => end = expr,
+            }
+        }
+        return Ok(Expr::Slice {
+            name,
+            indices,
+            start,
+            end,
+            span,
+        });
+    }
 
-    Ok(Expr::ArrayAccess { name, indices })
+    Ok(Expr::ArrayAccess {
+        name,
+        indices,
+        span,
+    })
 }
 
 fn parse_ret(pair: Pair<Rule>) -> Result<Stmt, String> {
@@ -236,8 +725,141 @@ fn parse_ret(pair: Pair<Rule>) -> Result<Stmt, String> {
     Ok(Stmt::Return(parse_expr(expr)?))
 }
 
-fn parse_func(pair: Pair<Rule>) -> Result<Stmt, String> {
+fn parse_enum(pair: Pair<Rule>) -> Result<Stmt, String> {
     let mut inner = pair.into_inner();
+    let name = inner.next().unwrap().as_str().to_string();
+    let variants = inner
+        .map(|v| {
+            let mut parts = v.into_inner();
+            Variant {
+                name: parts.next().unwrap().as_str().to_string(),
+                fields: parts.map(|p| p.as_str().to_string()).collect(),
+            }
+        })
+        .collect();
+
+    Ok(Stmt::Enum { name, variants })
+}
+
+fn parse_match(pair: Pair<Rule>) -> Result<Expr, String> {
+    let mut inner = pair.into_inner();
+    let scrutinee = Box::new(parse_expr(inner.next().unwrap())?);
+
+    let mut arms = Vec::new();
+    for arm in inner {
+        let mut parts = arm.into_inner();
+        let pattern = parse_pattern(parts.next().unwrap())?;
+        let body = parts.next().unwrap();
+        let body = match body.as_rule() {
+            Rule::Block => parse_block(body)?,
+            _ => vec![Stmt::Expr(parse_expr(body)?)],
+        };
+        arms.push(MatchArm { pattern, body });
+    }
+
+    Ok(Expr::Match { scrutinee, arms })
+}
+
+fn parse_pattern(pair: Pair<Rule>) -> Result<Pattern, String> {
+    let inner = pair.into_inner().next().unwrap();
+    match inner.as_rule() {
+        Rule::Wildcard => Ok(Pattern::Wildcard),
+        Rule::Literal => Ok(Pattern::Literal(parse_literal(inner)?)),
+        Rule::VariantPattern => {
+            let mut parts = inner.into_inner();
+            Ok(Pattern::Variant {
+                enum_name: parts.next().unwrap().as_str().to_string(),
+                variant: parts.next().unwrap().as_str().to_string(),
+                bindings: parts.map(|p| p.as_str().to_string()).collect(),
+            })
+        }
+        r => Err(format!("Unexpected pattern rule: {:?}", r)),
+    }
+}
+
+/// Resolves `\n`, `\t`, `\r`, `\0`, `\\` and `\"` in a string literal.
+fn unescape(raw: &str) -> Result<String, String> {
+    let mut out = String::with_capacity(raw.len());
+    let mut chars = raw.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('r') => out.push('\r'),
+            Some('0') => out.push('\0'),
+            Some('\\') => out.push('\\'),
+            Some('"') => out.push('"'),
+            Some(other) => return Err(format!("Unknown escape sequence: \\{}", other)),
+            None => return Err("Unterminated escape sequence".to_string()),
+        }
+    }
+    Ok(out)
+}
+
+fn parse_labeled(pair: Pair<Rule>) -> Result<Expr, String> {
+    let mut inner = pair.into_inner();
+    let label = inner.next().unwrap().into_inner().next().unwrap().as_str();
+    let mut body = parse_expr(inner.next().unwrap())?;
+
+    walk_expr_mut(&mut LabelResolver { label }, &mut body);
+
+    Ok(Expr::Labeled {
+        label: label.to_string(),
+        body: Box::new(body),
+    })
+}
+
+/// `break outer` parses like a break with the value `outer`; inside a loop
+/// labeled `outer` it is turned into a `BreakLabel` instead. Function bodies
+/// and inner loops reusing the same label are left alone.
+struct LabelResolver<'a> {
+    label: &'a str,
+}
+
+impl VisitorMut for LabelResolver<'_> {
+    fn visit_stmt_mut(&mut self, stmt: &mut Stmt) {
+        match stmt {
+            Stmt::Break(Some(Expr::Var(name))) if name == self.label => {
+                *stmt = Stmt::BreakLabel(name.clone());
+            }
+            Stmt::Function { .. } => {}
+            _ => walk_stmt_mut(self, stmt),
+        }
+    }
+
+    fn visit_expr_mut(&mut self, expr: &mut Expr) {
+        match expr {
+            Expr::Labeled { label, .. } if label == self.label => {}
+            _ => walk_expr_mut(self, expr),
+        }
+    }
+}
+
+fn parse_break(pair: Pair<Rule>) -> Result<Stmt, String> {
+    match pair.into_inner().next() {
+        Some(value) => Ok(Stmt::Break(Some(parse_expr(
+            value.into_inner().next().unwrap(),
+        )?))),
+        None => Ok(Stmt::Break(None)),
+    }
+}
+
+fn parse_func(pair: Pair<Rule>) -> Result<Stmt, String> {
+    let line = pair.as_span().start_pos().line_col().0;
+    let mut inner = pair.into_inner().peekable();
+
+    let mut decorators = Vec::new();
+    while let Some(item) = inner.next_if(|p| p.as_rule() == Rule::Decorator) {
+        decorators.push(item.into_inner().next().unwrap().as_str().into());
+    }
+    if inner.next_if(|p| p.as_rule() == Rule::Memo).is_some() {
+        decorators.push("memoize".into());
+    }
+
     let name = inner.next().unwrap().as_str().into();
 
     let mut params = Vec::new();
@@ -250,7 +872,13 @@ fn parse_func(pair: Pair<Rule>) -> Result<Stmt, String> {
             _ => {}
         }
     }
-    Ok(Stmt::Function { name, params, body })
+    Ok(Stmt::Function {
+        name,
+        params,
+        body,
+        decorators,
+        line,
+    })
 }
 
 fn parse_block(pair: Pair<Rule>) -> Result<Vec<Stmt>, String> {
@@ -259,12 +887,27 @@ fn parse_block(pair: Pair<Rule>) -> Result<Vec<Stmt>, String> {
 
     for item in inner {
         if item.as_rule() == Rule::Stmt {
-            body.push(parse_stmt(item)?);
+            match parse_stmt(item)? {
+                Stmt::Pragma(_) => return Err(MISPLACED_PRAGMA.to_string()),
+                stmt => body.push(stmt),
+            }
         }
     }
     Ok(body)
 }
 
+fn parse_pragma(pair: Pair<Rule>) -> Result<Stmt, String> {
+    let item = pair.into_inner().next().unwrap();
+    let pragma = match item.as_rule() {
+        Rule::Version => {
+            version::features(item.as_str())?;
+            Pragma::Version(item.as_str().to_string())
+        }
+        _ => Pragma::Feature(Feature::from_name(item.as_str())?),
+    };
+    Ok(Stmt::Pragma(pragma))
+}
+
 fn parse_ass(pair: Pair<Rule>) -> Result<Stmt, String> {
     let mut inner = pair.into_inner();
     let name = inner.next().unwrap().as_str().to_string();
@@ -276,30 +919,87 @@ fn parse_ass(pair: Pair<Rule>) -> Result<Stmt, String> {
 fn parse_reass(pair: Pair<Rule>) -> Result<Stmt, String> {
     let mut inner = pair.into_inner();
 
-    let target_pair = inner.next().unwrap();
+    let target = parse_target(inner.next().unwrap())?;
     let value = parse_expr(inner.next().unwrap())?;
 
-    let target = match target_pair.as_rule() {
-        Rule::Ident => AssignmentTarget::Ident(target_pair.as_str().to_string()),
-        Rule::ArrayAccess => {
-            let mut inner = target_pair.into_inner();
-            let name = inner.next().unwrap().as_str().to_string();
+    Ok(Stmt::Reassignment { target, value })
+}
 
-            let indices = inner
-                .map(|e| parse_expr(e).map(Box::new))
-                .collect::<Result<_, _>>()?;
+/// Desugars `x++` / `xs[i]--` into `x = x + 1` / `xs[i] = xs[i] - 1`. An
+/// index that isn't a literal or variable is bound first, as in
+/// `let xs_index0 = f() in { xs[xs_index0] = xs[xs_index0] + 1 }`, so it is
+/// evaluated once.
+fn parse_increment(pair: Pair<Rule>) -> Result<Stmt, String> {
+    let mut inner = pair.into_inner();
 
-            AssignmentTarget::ArrayAccess { name, indices }
-        }
-        _ => {
-            return Err(format!(
-                "Unexpected Assignment Target: {:?}",
-                target_pair.as_rule()
-            ));
+    let target = parse_target(inner.next().unwrap())?;
+    let op = match inner.next().unwrap().as_str() {
+        "++" => BinaryOp::Add,
+        _ => BinaryOp::Sub,
+    };
+    let (target, bound) = match target {
+        AssignmentTarget::ArrayAccess { name, indices } => {
+            let mut bound = Vec::new();
+            let indices = indices
+                .into_iter()
+                .enumerate()
+                .map(|(i, index)| match *index {
+                    Expr::Int(_) | Expr::Str { .. } | Expr::Var(_) => index,
+                    _ => {
+                        // Longer than `name`, so it can't hide the array.
+                        let temp = format!("{}_index{}", name, i);
+                        bound.push((temp.clone(), index));
+                        Box::new(Expr::Var(temp))
+                    }
+                })
+                .collect();
+            (AssignmentTarget::ArrayAccess { name, indices }, bound)
         }
+        target => (target, Vec::new()),
+    };
+    let current = match &target {
+        AssignmentTarget::Ident(name) => Expr::Var(name.clone()),
+        AssignmentTarget::ArrayAccess { name, indices } => Expr::ArrayAccess {
+            name: name.clone(),
+            indices: indices.clone(),
+            span: Span::default(),
+        },
     };
 
-    Ok(Stmt::Reassignment { target, value })
+    let update = Stmt::Reassignment {
+        target,
+        value: Expr::binary(op, current, Expr::Int(1)),
+    };
+    if bound.is_empty() {
+        return Ok(update);
+    }
+    let body = bound
+        .into_iter()
+        .rev()
+        .fold(Expr::Block(vec![update]), |body, (name, value)| {
+            Expr::LetIn {
+                name,
+                value,
+                body: Box::new(body),
+            }
+        });
+    Ok(Stmt::Expr(body))
+}
+
+fn parse_target(pair: Pair<Rule>) -> Result<AssignmentTarget, String> {
+    match pair.as_rule() {
+        Rule::Ident => Ok(AssignmentTarget::Ident(pair.as_str().to_string())),
+        Rule::ArrayAccess => match parse_access(pair)? {
+            Expr::ArrayAccess { name, indices, .. } => {
+                Ok(AssignmentTarget::ArrayAccess { name, indices })
+            }
+            _ => Err("Cannot assign to a slice".to_string()),
+        },
+        _ => Err(format!(
+            "Unexpected Assignment Target: {:?}",
+            pair.as_rule()
+        )),
+    }
 }
 
 #[cfg(test)]
@@ -320,6 +1020,15 @@ mod tests {
         assert_eq!(program, vec![Stmt::Expr(Expr::Bool(true))]);
     }
 
+    #[test]
+    fn test_parse_null() {
+        let program = parse("null").unwrap();
+        assert_eq!(program, vec![Stmt::Expr(Expr::Null)]);
+
+        let program = parse("nullable").unwrap();
+        assert_eq!(program, vec![Stmt::Expr(Expr::Var("nullable".to_string()))]);
+    }
+
     #[test]
     fn test_parse_binary() {
         let program = parse("1 + 2").unwrap();
@@ -329,10 +1038,618 @@ mod tests {
                 op: BinaryOp::Add,
                 lhs: Box::new(Expr::Int(1)),
                 rhs: Box::new(Expr::Int(2)),
+                span: Span::default()
             })]
         );
     }
 
+    fn int(n: i64) -> Box<Expr> {
+        Box::new(Expr::Int(n))
+    }
+
+    fn var(name: &str) -> Box<Expr> {
+        Box::new(Expr::Var(name.to_string()))
+    }
+
+    fn bin(op: BinaryOp, lhs: Box<Expr>, rhs: Box<Expr>) -> Box<Expr> {
+        Box::new(Expr::Binary {
+            op,
+            lhs,
+            rhs,
+            span: Span::default(),
+        })
+    }
+
+    fn unary(op: UnaryOp, expr: Box<Expr>) -> Box<Expr> {
+        Box::new(Expr::Unary {
+            op,
+            expr,
+            span: Span::default(),
+        })
+    }
+
+    fn parse_one(source: &str) -> Expr {
+        match parse(source).unwrap().as_slice() {
+            [Stmt::Expr(e)] => e.clone(),
+            other => panic!("expected a single expression, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_unary_chains() {
+        assert_eq!(
+            parse_one("--x"),
+            *unary(UnaryOp::Neg, unary(UnaryOp::Neg, var("x")))
+        );
+        assert_eq!(
+            parse_one("!(a && b)"),
+            *unary(UnaryOp::Not, bin(BinaryOp::And, var("a"), var("b")))
+        );
+        assert_eq!(
+            parse_one("-f(x)"),
+            *unary(
+                UnaryOp::Neg,
+                Box::new(Expr::Call {
+                    name: "f".to_string(),
+                    args: vec![Expr::Var("x".to_string())],
+                    span: Span::default()
+                })
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_negation_in_every_position() {
+        let x = || Expr::var("x");
+        let neg_item = || -Expr::index("arr", [Expr::var("i")]);
+        let neg_call = || -Expr::call("f", [Expr::int(1)]);
+
+        assert_eq!(parse_one("-arr[i]"), neg_item());
+        assert_eq!(parse_one("a - -b"), Expr::var("a") - -Expr::var("b"));
+        assert_eq!(parse_one("a--b"), Expr::var("a") - -Expr::var("b"));
+        assert_eq!(
+            parse_one("[-x, -arr[i], -f(1)]"),
+            Expr::array([-x(), neg_item(), neg_call()])
+        );
+        assert_eq!(
+            parse_one("arr[-x][-f(1) + 1]"),
+            Expr::index("arr", [-x(), neg_call() + Expr::int(1)])
+        );
+        assert_eq!(
+            parse_one("g(-x, -arr[i], -f(1))"),
+            Expr::call("g", [-x(), neg_item(), neg_call()])
+        );
+        assert_eq!(
+            parse_one("for i in -x..-f(1) {}"),
+            Expr::for_range("i", -x(), neg_call(), [])
+        );
+        assert_eq!(
+            parse("arr[-x] = -arr[i]").unwrap(),
+            vec![Stmt::reassign_index("arr", [-x()], neg_item())]
+        );
+        assert_eq!(parse("return -f(1)").unwrap(), vec![Stmt::ret(neg_call())]);
+    }
+
+    #[test]
+    fn test_parse_pipeline() {
+        let data = || Expr::var("data");
+        assert_eq!(
+            parse_one("data |> filter(is_even) |> map(double) |> sum()"),
+            Expr::call(
+                "sum",
+                [Expr::call(
+                    "map",
+                    [
+                        Expr::call("filter", [data(), Expr::var("is_even")]),
+                        Expr::var("double"),
+                    ]
+                )]
+            )
+        );
+        // Binds looser than everything else, and a bare name is a call too.
+        assert_eq!(
+            parse_one("a + 1 |> f"),
+            Expr::call("f", [Expr::var("a") + Expr::int(1)])
+        );
+        assert!(parse("x |> [1]").is_err());
+    }
+
+    #[test]
+    fn test_parse_precedence_table() {
+        // unary binds tighter than **
+        assert_eq!(
+            parse_one("-2 ** 2"),
+            *bin(BinaryOp::Pow, unary(UnaryOp::Neg, int(2)), int(2))
+        );
+        // ** binds tighter than * and is right associative
+        assert_eq!(
+            parse_one("2 * 3 ** 2 ** 1"),
+            *bin(
+                BinaryOp::Mul,
+                int(2),
+                bin(BinaryOp::Pow, int(3), bin(BinaryOp::Pow, int(2), int(1)))
+            )
+        );
+        // * / % bind tighter than + - and are left associative
+        assert_eq!(
+            parse_one("1 - 2 * 3 % 4"),
+            *bin(
+                BinaryOp::Sub,
+                int(1),
+                bin(BinaryOp::Mod, bin(BinaryOp::Mul, int(2), int(3)), int(4))
+            )
+        );
+        assert_eq!(
+            parse_one("1 - 2 - 3"),
+            *bin(BinaryOp::Sub, bin(BinaryOp::Sub, int(1), int(2)), int(3))
+        );
+        // + - bind tighter than comparisons
+        assert_eq!(
+            parse_one("a + 1 < b"),
+            *bin(BinaryOp::Lt, bin(BinaryOp::Add, var("a"), int(1)), var("b"))
+        );
+        // comparisons bind tighter than &&, which binds tighter than ||
+        assert_eq!(
+            parse_one("a < b || c == d && e"),
+            *bin(
+                BinaryOp::Or,
+                bin(BinaryOp::Lt, var("a"), var("b")),
+                bin(
+                    BinaryOp::And,
+                    bin(BinaryOp::Eq, var("c"), var("d")),
+                    var("e")
+                )
+            )
+        );
+        assert_eq!(
+            parse_one("a || b || c"),
+            *bin(
+                BinaryOp::Or,
+                bin(BinaryOp::Or, var("a"), var("b")),
+                var("c")
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_grouping() {
+        assert_eq!(
+            parse_one("(a + b) * (c - d)"),
+            *bin(
+                BinaryOp::Mul,
+                bin(BinaryOp::Add, var("a"), var("b")),
+                bin(BinaryOp::Sub, var("c"), var("d"))
+            )
+        );
+        assert_eq!(parse_one("((x))"), *var("x"));
+        assert_eq!(
+            parse_one("-(1 + 2) ** 2"),
+            *bin(
+                BinaryOp::Pow,
+                unary(UnaryOp::Neg, bin(BinaryOp::Add, int(1), int(2))),
+                int(2)
+            )
+        );
+        assert_eq!(
+            parse_one("(a || b) && c"),
+            *bin(
+                BinaryOp::And,
+                bin(BinaryOp::Or, var("a"), var("b")),
+                var("c")
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_grouping_in_every_position() {
+        assert_eq!(
+            parse_one("f((a + b) * 2, (c))"),
+            Expr::Call {
+                name: "f".to_string(),
+                args: vec![
+                    *bin(
+                        BinaryOp::Mul,
+                        bin(BinaryOp::Add, var("a"), var("b")),
+                        int(2)
+                    ),
+                    *var("c"),
+                ],
+                span: Span::default()
+            }
+        );
+        assert_eq!(
+            parse_one("arr[(i + 1) * 2]"),
+            Expr::ArrayAccess {
+                name: "arr".to_string(),
+                indices: vec![bin(
+                    BinaryOp::Mul,
+                    bin(BinaryOp::Add, var("i"), int(1)),
+                    int(2)
+                )],
+                span: Span::default()
+            }
+        );
+        assert_eq!(
+            parse_one("for i in (a)..(b + 1) { i }"),
+            Expr::For {
+                var: "i".to_string(),
+                start: var("a"),
+                end: bin(BinaryOp::Add, var("b"), int(1)),
+                body: vec![Stmt::Expr(*var("i"))],
+            }
+        );
+        assert_eq!(
+            parse_one("if (((a))) { 1 }"),
+            Expr::If {
+                cond: var("a"),
+                then: vec![Stmt::Expr(Expr::Int(1))],
+                else_: vec![],
+                span: Span::default(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_all_reports_every_error() {
+        let source = "let a = 1\nlet = 2\nlet b = a +\nfn f( { }\nlet c = 3\n";
+        let errors = parse_all(source).unwrap_err();
+        let lines: Vec<usize> = errors.iter().map(|e| e.line).collect();
+        assert_eq!(lines, vec![2, 3, 4]);
+        assert_eq!(errors[0].col, 5);
+    }
+
+    #[test]
+    fn test_parse_all_resyncs_after_blocks() {
+        let source = r#"
+            fn broken(a) {
+                let x = = a
+            }
+            fn fine(a) {
+                return a
+            }
+            fine(1)
+        "#;
+        let errors = parse_all(source).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 3);
+
+        let program = parse_all("fn f(a) {\n  return a\n}\n// done\nf(1)").unwrap();
+        assert_eq!(program.len(), 2);
+    }
+
+    #[test]
+    fn test_incremental_reuses_unchanged_prefix() {
+        let mut parser = IncrementalParser::new();
+        let source = "let a = 1\nlet b = 2\nlet c = 3\n";
+        parser.update(source).unwrap();
+        assert_eq!(parser.reused(), 0);
+
+        let edited = "let a = 1\nlet b = 2\nlet c = 3 + a\n";
+        assert_eq!(parser.update(edited).unwrap(), parse(edited).unwrap());
+        assert_eq!(parser.reused(), 1);
+
+        let edited = "let a = 1\nlet b = 2\nlet c = 3 + a\nprintln(c)";
+        assert_eq!(parser.update(edited).unwrap(), parse(edited).unwrap());
+        assert_eq!(parser.reused(), 2);
+
+        let edited = "let a = 5\nlet b = 2\n";
+        assert_eq!(parser.update(edited).unwrap(), parse(edited).unwrap());
+        assert_eq!(parser.reused(), 0);
+    }
+
+    #[test]
+    fn test_incremental_handles_statement_growth() {
+        let mut parser = IncrementalParser::new();
+        parser.update("let a = 1\nx").unwrap();
+        let edited = "let a = 1\nx = 2\n";
+        assert_eq!(parser.update(edited).unwrap(), parse(edited).unwrap());
+
+        assert!(parser.update("let a = 1\nx = = 2\n").is_err());
+        assert_eq!(
+            parser.update("let a = 1\nx = 3\n").unwrap(),
+            parse("let a = 1\nx = 3").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_loop_and_break() {
+        assert_eq!(
+            parse_one("loop { break 1 + 2 }"),
+            Expr::Loop(vec![Stmt::Break(Some(*bin(BinaryOp::Add, int(1), int(2))))])
+        );
+        assert_eq!(
+            parse_one("loop {\n  break\n  x = 1\n}"),
+            Expr::Loop(vec![
+                Stmt::Break(None),
+                Stmt::Reassignment {
+                    target: AssignmentTarget::Ident("x".to_string()),
+                    value: Expr::Int(1),
+                },
+            ])
+        );
+        assert_eq!(parse_one("breaking"), *var("breaking"));
+    }
+
+    #[test]
+    fn test_parse_labeled_loop() {
+        let expr = parse_one("outer: loop { loop { break outer } \n break x }");
+        assert_eq!(
+            expr,
+            Expr::Labeled {
+                label: "outer".to_string(),
+                body: Box::new(Expr::Loop(vec![
+                    Stmt::expr(Expr::Loop(vec![Stmt::BreakLabel("outer".to_string())])),
+                    Stmt::Break(Some(Expr::var("x"))),
+                ])),
+            }
+        );
+        // Variant paths still parse.
+        assert!(parse("E::A").is_ok());
+    }
+
+    #[test]
+    fn test_parse_if_let() {
+        assert_eq!(
+            parse_one("if let n = parse_int(s) { n } else { 0 }"),
+            Expr::IfLet {
+                name: "n".to_string(),
+                value: Box::new(Expr::call("parse_int", vec![Expr::var("s")])),
+                then: vec![Stmt::expr(Expr::var("n"))],
+                else_: vec![Stmt::expr(Expr::int(0))],
+            }
+        );
+        assert_eq!(
+            parse_one("while let x = next() { x }"),
+            Expr::WhileLet {
+                name: "x".to_string(),
+                value: Box::new(Expr::call("next", vec![])),
+                body: vec![Stmt::expr(Expr::var("x"))],
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_slice() {
+        assert_eq!(
+            parse_one("m[0][1..-1]"),
+            Expr::Slice {
+                name: "m".to_string(),
+                indices: vec![Box::new(Expr::int(0))],
+                start: Some(Box::new(Expr::int(1))),
+                end: Some(Box::new(Expr::Unary {
+                    op: UnaryOp::Neg,
+                    expr: Box::new(Expr::int(1)),
+                    span: Span::default()
+                })),
+                span: Span::default()
+            }
+        );
+        assert_eq!(
+            parse_one("s[..n]"),
+            Expr::Slice {
+                name: "s".to_string(),
+                indices: vec![],
+                start: None,
+                end: Some(Box::new(Expr::var("n"))),
+                span: Span::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_pragmas() {
+        assert_eq!(
+            parse("#pragma version 0.2\n#feature( strict_types )\n1").unwrap(),
+            vec![
+                Stmt::Pragma(Pragma::Version("0.2".to_string())),
+                Stmt::Pragma(Pragma::Feature(Feature::StrictTypes)),
+                Stmt::Expr(Expr::int(1)),
+            ]
+        );
+        let misplaced = "#pragma and #feature must come before any other statement";
+        assert_eq!(parse("1\n#pragma version 0.1"), Err(misplaced.to_string()));
+        assert_eq!(
+            parse("while (true) { #feature(strict_types) }"),
+            Err(misplaced.to_string())
+        );
+        let errors = parse_all("1\n#feature(float_division)").unwrap_err();
+        assert_eq!((errors[0].line, errors[0].message.as_str()), (2, misplaced));
+        assert!(
+            parse("#pragma version 9.9")
+                .unwrap_err()
+                .contains("0.1, 0.2")
+        );
+        assert!(
+            parse("#feature(nope)")
+                .unwrap_err()
+                .contains("strict_types")
+        );
+    }
+
+    #[test]
+    fn test_parse_let_in() {
+        assert_eq!(
+            parse_one("let x = 10 in x * x"),
+            Expr::LetIn {
+                name: "x".to_string(),
+                value: Box::new(Expr::int(10)),
+                body: Box::new(Expr::binary(BinaryOp::Mul, Expr::var("x"), Expr::var("x"))),
+            }
+        );
+        // `in` has to be a word of its own.
+        let program = parse("let x = 1\ninner(x)").unwrap();
+        assert!(matches!(program[0], Stmt::Assignment { .. }));
+    }
+
+    #[test]
+    fn test_parse_string_escapes() {
+        assert_eq!(
+            parse_one(r#""say \"hi\"\n\tC:\\dir""#),
+            Expr::str("say \"hi\"\n\tC:\\dir")
+        );
+        assert!(parse(r#""\q""#).is_err());
+    }
+
+    #[test]
+    fn test_parse_decorators() {
+        let program = parse("@timed\n@memoize\nfn f(n) { n }").unwrap();
+        let [Stmt::Function { decorators, .. }] = program.as_slice() else {
+            panic!("expected a function, got {:?}", program);
+        };
+        assert_eq!(decorators, &["timed".to_string(), "memoize".to_string()]);
+
+        assert_eq!(
+            parse("@timed\nmemo fn f(n) { n }").unwrap(),
+            parse("@timed\n@memoize\nfn f(n) { n }").unwrap()
+        );
+        // `memo` is still an ordinary name.
+        assert_eq!(parse_one("memo"), Expr::var("memo"));
+    }
+
+    #[test]
+    fn test_parse_spread_args() {
+        assert_eq!(
+            parse_one("f(1, ...xs)"),
+            Expr::call(
+                "f",
+                vec![Expr::int(1), Expr::Spread(Box::new(Expr::var("xs")))]
+            )
+        );
+        assert!(parse("...xs").is_err());
+    }
+
+    #[test]
+    fn test_parse_increment() {
+        assert_eq!(
+            parse("i++\ncounts[k]--").unwrap(),
+            vec![
+                Stmt::reassign("i", Expr::var("i") + Expr::int(1)),
+                Stmt::reassign_index(
+                    "counts",
+                    vec![Expr::var("k")],
+                    Expr::index("counts", vec![Expr::var("k")]) - Expr::int(1)
+                ),
+            ]
+        );
+        // Still a subtraction of a negation, not a decrement.
+        assert_eq!(
+            parse_one("a --b"),
+            *bin(BinaryOp::Sub, var("a"), unary(UnaryOp::Neg, var("b")))
+        );
+    }
+
+    #[test]
+    fn test_chained_comparison() {
+        let or_false = |cmp: Box<Expr>, rest: Box<Expr>| {
+            Expr::if_else(*cmp, [Stmt::Expr(*rest)], [Stmt::Expr(Expr::Bool(false))])
+        };
+        assert_eq!(
+            parse_one("0 <= x < 10"),
+            or_false(
+                bin(BinaryOp::Le, int(0), var("x")),
+                bin(BinaryOp::Lt, var("x"), int(10))
+            )
+        );
+        assert_eq!(
+            parse_one("a == b != c"),
+            or_false(
+                bin(BinaryOp::Eq, var("a"), var("b")),
+                bin(BinaryOp::Ne, var("b"), var("c"))
+            )
+        );
+
+        // `f()` is evaluated once, into a name the chain doesn't use.
+        assert_eq!(
+            parse_one("cmp1 < f() < 2"),
+            Expr::LetIn {
+                name: "cmp_1".to_string(),
+                value: Box::new(Expr::Call {
+                    name: "f".to_string(),
+                    args: vec![],
+                    span: Span::default()
+                }),
+                body: Box::new(or_false(
+                    bin(BinaryOp::Lt, var("cmp1"), var("cmp_1")),
+                    bin(BinaryOp::Lt, var("cmp_1"), int(2))
+                )),
+            }
+        );
+        assert_eq!(
+            parse_one("x < f()"),
+            *bin(
+                BinaryOp::Lt,
+                var("x"),
+                Box::new(Expr::Call {
+                    name: "f".to_string(),
+                    args: vec![],
+                    span: Span::default()
+                })
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_enum_and_match() {
+        let program = parse(
+            r#"
+            enum Color { Red, Custom(r, g, b), }
+            match (c) {
+                Color::Red => 1,
+                Color::Custom(r, _, b) => { r + b }
+                "x" => 2
+                _ => 3
+            }
+        "#,
+        )
+        .unwrap();
+        assert_eq!(
+            program[0],
+            Stmt::Enum {
+                name: "Color".to_string(),
+                variants: vec![
+                    Variant {
+                        name: "Red".to_string(),
+                        fields: vec![],
+                    },
+                    Variant {
+                        name: "Custom".to_string(),
+                        fields: vec!["r".to_string(), "g".to_string(), "b".to_string()],
+                    },
+                ],
+            }
+        );
+        let Stmt::Expr(Expr::Match { arms, .. }) = &program[1] else {
+            panic!("expected a match, got {:?}", program[1]);
+        };
+        let patterns: Vec<&Pattern> = arms.iter().map(|a| &a.pattern).collect();
+        assert_eq!(
+            patterns,
+            vec![
+                &Pattern::Variant {
+                    enum_name: "Color".to_string(),
+                    variant: "Red".to_string(),
+                    bindings: vec![],
+                },
+                &Pattern::Variant {
+                    enum_name: "Color".to_string(),
+                    variant: "Custom".to_string(),
+                    bindings: vec!["r".to_string(), "_".to_string(), "b".to_string()],
+                },
+                &Pattern::Literal(Expr::str("x")),
+                &Pattern::Wildcard,
+            ]
+        );
+        assert_eq!(
+            parse_one("Color::Custom(1, 2, 3)"),
+            Expr::Variant {
+                enum_name: "Color".to_string(),
+                variant: "Custom".to_string(),
+                args: vec![Expr::Int(1), Expr::Int(2), Expr::Int(3)],
+                span: Span::default()
+            }
+        );
+    }
+
     #[test]
     fn test_parse_assignment() {
         let program = parse("let x = 42").unwrap();
@@ -357,7 +1674,10 @@ mod tests {
                     op: BinaryOp::Add,
                     lhs: Box::new(Expr::Var("a".to_string())),
                     rhs: Box::new(Expr::Var("b".to_string())),
+                    span: Span::default()
                 })],
+                decorators: vec![],
+                line: 1,
             }]
         );
     }
@@ -370,6 +1690,7 @@ mod tests {
             vec![Stmt::Expr(Expr::Call {
                 name: "add".to_string(),
                 args: vec![Expr::Int(1), Expr::Int(2),],
+                span: Span::default()
             })]
         );
     }
@@ -384,9 +1705,30 @@ mod tests {
                     op: BinaryOp::Lt,
                     lhs: Box::new(Expr::Var("x".to_string())),
                     rhs: Box::new(Expr::Int(10)),
+                    span: Span::default()
                 }),
                 then: vec![Stmt::Expr(Expr::Int(1))],
                 else_: vec![Stmt::Expr(Expr::Int(2))],
+                span: Span::default(),
+            })]
+        );
+    }
+
+    #[test]
+    fn test_parse_else_if_chain() {
+        let program = parse("if (a) { 1 } else if (b) { 2 } else { 3 }").unwrap();
+        assert_eq!(
+            program,
+            vec![Stmt::Expr(Expr::If {
+                cond: var("a"),
+                then: vec![Stmt::Expr(Expr::Int(1))],
+                else_: vec![Stmt::Expr(Expr::If {
+                    cond: var("b"),
+                    then: vec![Stmt::Expr(Expr::Int(2))],
+                    else_: vec![Stmt::Expr(Expr::Int(3))],
+                    span: Span::default(),
+                })],
+                span: Span::default(),
             })]
         );
     }
@@ -401,6 +1743,7 @@ mod tests {
                     op: BinaryOp::Lt,
                     lhs: Box::new(Expr::Var("x".to_string())),
                     rhs: Box::new(Expr::Int(10)),
+                    span: Span::default()
                 }),
                 body: vec![Stmt::Assignment {
                     name: "x".to_string(),
@@ -408,10 +1751,16 @@ mod tests {
                         op: BinaryOp::Add,
                         lhs: Box::new(Expr::Var("x".to_string())),
                         rhs: Box::new(Expr::Int(1)),
+                        span: Span::default()
                     },
                 }],
+                span: Span::default(),
             })]
         );
+        let Stmt::Expr(Expr::While { span, .. }) = &program[0] else {
+            unreachable!()
+        };
+        assert_eq!((span.start, span.end), ((1, 8), (1, 14)));
     }
 
     #[test]
@@ -440,6 +1789,7 @@ mod tests {
                             op: BinaryOp::Lt,
                             lhs: Box::new(Expr::Var("n".to_string())),
                             rhs: Box::new(Expr::Int(2)),
+                            span: Span::default()
                         }),
                         then: vec![Stmt::Return(Expr::Var("n".to_string()))],
                         else_: vec![Stmt::Return(Expr::Binary {
@@ -450,7 +1800,9 @@ mod tests {
                                     op: BinaryOp::Sub,
                                     lhs: Box::new(Expr::Var("n".to_string())),
                                     rhs: Box::new(Expr::Int(1)),
+                                    span: Span::default()
                                 }],
+                                span: Span::default()
                             }),
                             rhs: Box::new(Expr::Call {
                                 name: "fib".to_string(),
@@ -458,14 +1810,21 @@ mod tests {
                                     op: BinaryOp::Sub,
                                     lhs: Box::new(Expr::Var("n".to_string())),
                                     rhs: Box::new(Expr::Int(2)),
+                                    span: Span::default()
                                 }],
+                                span: Span::default()
                             }),
+                            span: Span::default()
                         })],
+                        span: Span::default(),
                     })],
+                    decorators: vec![],
+                    line: 2,
                 },
                 Stmt::Expr(Expr::Call {
                     name: "fib".to_string(),
                     args: vec![Expr::Int(10)],
+                    span: Span::default()
                 })
             ]
         );
@@ -0,0 +1,174 @@
+//! Native builtins from outside the interpreter: added by the host with
+//! `Interpreter::register`, or by a script with `load_plugin`, which needs
+//! the `plugins` feature.
+//!
+//! A plugin is a shared library, built as a `cdylib` against the same
+//! version of ew and with the same compiler as the interpreter loading it,
+//! that exports two C functions: `ew_abi_version`, which the interpreter
+//! checks against its own `PLUGIN_ABI_VERSION` before calling anything
+//! else, and `ew_register`, which is given the registry to add to.
+//!
+//! ```
+//! #[unsafe(no_mangle)]
+//! pub extern "C" fn ew_abi_version() -> u32 {
+//!     ew::PLUGIN_ABI_VERSION
+//! }
+//!
+//! /// # Safety
+//! ///
+//! /// `registry` must point to a live `Registry`.
+//! #[unsafe(no_mangle)]
+//! pub unsafe extern "C" fn ew_register(registry: *mut ew::Registry) {
+//!     // SAFETY: the interpreter passes its registry, alive for the call.
+//!     let registry = unsafe { &mut *registry };
+//!     registry.add("double", |args| match args.as_slice() {
+//!         [ew::Val::Int(n)] => Ok(ew::Val::Int(n * 2)),
+//!         _ => Err("double() takes an integer".to_string()),
+//!     });
+//! }
+//! ```
+
+use std::sync::Arc;
+
+use crate::interpreter::Val;
+
+/// The version of the interface between the interpreter and plugins,
+/// which a plugin's `ew_abi_version` returns. It changes whenever
+/// `Registry` or the functions a plugin exports do, so a plugin built
+/// against another version is refused rather than called.
+pub const PLUGIN_ABI_VERSION: u32 = 1;
+
+/// A native builtin: the arguments in, a value or an error message out.
+pub type NativeFn = Arc<dyn Fn(Vec<Val>) -> Result<Val, String> + Send + Sync>;
+
+/// The builtins a plugin or host adds, in the order they were added.
+#[derive(Default)]
+pub struct Registry {
+    pub(crate) builtins: Vec<(String, NativeFn)>,
+}
+
+impl Registry {
+    pub fn new() -> Registry {
+        Registry::default()
+    }
+
+    /// Adds a builtin called `name`. Registering it fails if ew already has
+    /// a builtin of that name.
+    pub fn add(
+        &mut self,
+        name: &str,
+        f: impl Fn(Vec<Val>) -> Result<Val, String> + Send + Sync + 'static,
+    ) {
+        self.builtins.push((name.to_string(), Arc::new(f)));
+    }
+}
+
+/// The signature `ew_abi_version` must have.
+#[cfg(all(feature = "plugins", unix))]
+type AbiVersion = extern "C" fn() -> u32;
+
+/// The signature `ew_register` must have.
+#[cfg(all(feature = "plugins", unix))]
+type Register = unsafe extern "C" fn(*mut Registry);
+
+/// Loads the library at `path` and collects what its `ew_register` adds.
+/// A bare file name in the current directory is loaded from there rather
+/// than searched for on the library path. The library is never unloaded,
+/// since the builtins' code lives in it.
+#[cfg(all(feature = "plugins", unix))]
+pub(crate) fn load(path: &str) -> Result<Registry, String> {
+    use std::ffi::CString;
+
+    let local = !path.contains('/') && std::path::Path::new(path).is_file();
+    let file = if local {
+        format!("./{}", path)
+    } else {
+        path.to_string()
+    };
+    let c_path =
+        CString::new(file).map_err(|_| format!("Plugin path {:?} contains a NUL byte", path))?;
+    // Clears any error left from an earlier call, so the one reported is
+    // this dlopen's.
+    // SAFETY: dlerror has no preconditions.
+    unsafe { libc::dlerror() };
+    // SAFETY: dlopen runs the library's initializers, which is what loading
+    // a plugin asks for; the path is a valid C string.
+    let handle = unsafe { libc::dlopen(c_path.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+    if handle.is_null() {
+        return Err(format!("Cannot load plugin {}: {}", path, dl_error()));
+    }
+    let refuse = |message: String| {
+        // SAFETY: nothing from the library is in use.
+        unsafe { libc::dlclose(handle) };
+        Err(message)
+    };
+    // SAFETY: the handle came from a successful dlopen.
+    let symbol = unsafe { libc::dlsym(handle, c"ew_abi_version".as_ptr()) };
+    if symbol.is_null() {
+        return refuse(format!("Plugin {} has no ew_abi_version function", path));
+    }
+    // SAFETY: plugins export `ew_abi_version` with the `AbiVersion`
+    // signature, which hasn't changed between versions.
+    let abi_version = unsafe { std::mem::transmute::<*mut libc::c_void, AbiVersion>(symbol) };
+    let version = abi_version();
+    if version != PLUGIN_ABI_VERSION {
+        return refuse(format!(
+            "Plugin {} was built for plugin ABI version {}, but this ew uses {}",
+            path, version, PLUGIN_ABI_VERSION
+        ));
+    }
+    // SAFETY: the handle came from a successful dlopen.
+    let symbol = unsafe { libc::dlsym(handle, c"ew_register".as_ptr()) };
+    if symbol.is_null() {
+        return refuse(format!("Plugin {} has no ew_register function", path));
+    }
+    // SAFETY: a plugin of this ABI version exports `ew_register` with the
+    // `Register` signature.
+    let register = unsafe { std::mem::transmute::<*mut libc::c_void, Register>(symbol) };
+    let mut registry = Registry::new();
+    // SAFETY: the registry is alive for the call and nothing else uses it.
+    unsafe { register(&mut registry) };
+    Ok(registry)
+}
+
+#[cfg(all(feature = "plugins", not(unix)))]
+pub(crate) fn load(path: &str) -> Result<Registry, String> {
+    Err(format!(
+        "Cannot load plugin {}: plugins are only supported on Unix",
+        path
+    ))
+}
+
+/// The reason the last `dlopen` failed.
+#[cfg(all(feature = "plugins", unix))]
+fn dl_error() -> String {
+    // SAFETY: dlerror returns null or a C string valid until the next call.
+    let error = unsafe { libc::dlerror() };
+    if error.is_null() {
+        return "unknown error".to_string();
+    }
+    // SAFETY: checked for null above.
+    unsafe { std::ffi::CStr::from_ptr(error) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[cfg(all(test, feature = "plugins", unix))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_errors() {
+        let err = load("/nonexistent/libnothing.so").err().unwrap();
+        assert!(
+            err.starts_with("Cannot load plugin /nonexistent/libnothing.so: "),
+            "{}",
+            err
+        );
+        assert!(load("a\0b").is_err());
+        assert_eq!(
+            load("libc.so.6").err().unwrap(),
+            "Plugin libc.so.6 has no ew_abi_version function"
+        );
+    }
+}
@@ -0,0 +1,340 @@
+use std::collections::HashSet;
+
+use crate::ast::{AssignmentTarget, Expr, Iteration, Pattern, Stmt, Visitor, walk_expr, walk_stmt};
+use crate::diagnostics::{Warning, WarningKind};
+
+/// Finds the places where a program most likely meant a declaration when it
+/// wrote an assignment, or the other way round:
+///
+/// - `let x = x + 1` in a loop body declares a fresh `x` on every iteration
+///   instead of updating the `x` outside the loop, which usually means the
+///   loop never ends.
+/// - `x = 1` with no `x` in scope fails at runtime; it needs a `let`.
+///
+/// Scopes follow the interpreter: every block gets its own, and a function
+/// body sees its parameters and the program's functions but not the
+/// variables around it.
+pub fn resolve(program: &[Stmt]) -> Vec<Warning> {
+    resolve_with_globals(program, &[])
+}
+
+/// `resolve` for a program run by a host that provides `globals`, such as
+/// `CONFIG`, which are visible everywhere like the program's functions.
+pub(crate) fn resolve_with_globals(program: &[Stmt], globals: &[&str]) -> Vec<Warning> {
+    let functions = program.iter().filter_map(|stmt| match stmt {
+        Stmt::Function { name, .. } => Some(name.clone()),
+        _ => None,
+    });
+    let mut resolver = Resolver {
+        globals: globals
+            .iter()
+            .map(|name| name.to_string())
+            .chain(functions)
+            .collect(),
+        scopes: vec![Scope::default()],
+        warnings: Vec::new(),
+    };
+    program.iter().for_each(|stmt| resolver.visit_stmt(stmt));
+    resolver.warnings
+}
+
+#[derive(Default)]
+struct Scope {
+    names: HashSet<String>,
+    /// A loop body, run afresh on every iteration.
+    is_loop: bool,
+    /// A function body; lookups stop here.
+    is_function: bool,
+}
+
+struct Resolver {
+    /// The program's functions and the host's globals.
+    globals: HashSet<String>,
+    scopes: Vec<Scope>,
+    warnings: Vec<Warning>,
+}
+
+impl Resolver {
+    fn warn(&mut self, kind: WarningKind, message: String) {
+        self.warnings.push(Warning { kind, message });
+    }
+
+    /// Index of the scope declaring `name`, if the current code can see it.
+    fn lookup(&self, name: &str) -> Option<usize> {
+        for (i, scope) in self.scopes.iter().enumerate().rev() {
+            if scope.names.contains(name) {
+                return Some(i);
+            }
+            if scope.is_function {
+                break;
+            }
+        }
+        None
+    }
+
+    fn declare(&mut self, name: &str) {
+        let scope = self.scopes.last_mut().unwrap();
+        scope.names.insert(name.to_string());
+    }
+
+    fn scoped<'a>(
+        &mut self,
+        scope: Scope,
+        bindings: impl IntoIterator<Item = &'a String>,
+        stmts: &[Stmt],
+    ) {
+        self.scopes.push(scope);
+        bindings.into_iter().for_each(|name| self.declare(name));
+        stmts.iter().for_each(|stmt| self.visit_stmt(stmt));
+        self.scopes.pop();
+    }
+
+    fn block(&mut self, stmts: &[Stmt]) {
+        self.scoped(Scope::default(), [], stmts);
+    }
+
+    fn loop_body<'a>(&mut self, bindings: impl IntoIterator<Item = &'a String>, stmts: &[Stmt]) {
+        let scope = Scope {
+            is_loop: true,
+            ..Scope::default()
+        };
+        self.scoped(scope, bindings, stmts);
+    }
+
+    /// Whether `let name = ...` here would hide a variable declared outside
+    /// the innermost loop, from within that loop.
+    fn shadows_across_loop(&self, name: &str) -> bool {
+        let Some(declared) = self.lookup(name) else {
+            return false;
+        };
+        let innermost_loop = self.scopes.iter().rposition(|s| s.is_loop);
+        let function = self.scopes.iter().rposition(|s| s.is_function);
+        matches!(innermost_loop, Some(l) if declared < l && function.is_none_or(|f| l > f))
+    }
+}
+
+/// Whether `expr` reads the variable `name`.
+pub(crate) fn mentions(expr: &Expr, name: &str) -> bool {
+    struct Mentions<'a>(&'a str, bool);
+
+    impl Visitor for Mentions<'_> {
+        fn visit_expr(&mut self, expr: &Expr) {
+            match expr {
+                Expr::Var(n) | Expr::ArrayAccess { name: n, .. } | Expr::Slice { name: n, .. }
+                    if n == self.0 =>
+                {
+                    self.1 = true
+                }
+                _ => {}
+            }
+            walk_expr(self, expr);
+        }
+    }
+
+    let mut v = Mentions(name, false);
+    v.visit_expr(expr);
+    v.1
+}
+
+impl Visitor for Resolver {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Function {
+                name, params, body, ..
+            } => {
+                self.globals.insert(name.clone());
+                let scope = Scope {
+                    is_function: true,
+                    ..Scope::default()
+                };
+                self.scoped(scope, params, body);
+            }
+            Stmt::Assignment { name, value } => {
+                self.visit_expr(value);
+                if mentions(value, name) && self.shadows_across_loop(name) {
+                    self.warn(
+                        WarningKind::Shadowing,
+                        format!(
+                            "`let {0} = ...` inside a loop declares a new {0} on every \
+                             iteration and leaves the outer {0} unchanged; write `{0} = ...` \
+                             to update it",
+                            name
+                        ),
+                    );
+                }
+                self.declare(name);
+            }
+            Stmt::Reassignment { target, value } => {
+                let (AssignmentTarget::Ident(name) | AssignmentTarget::ArrayAccess { name, .. }) =
+                    target;
+                if let AssignmentTarget::ArrayAccess { indices, .. } = target {
+                    indices.iter().for_each(|e| self.visit_expr(e));
+                }
+                self.visit_expr(value);
+                if self.lookup(name).is_none() && !self.globals.contains(name) {
+                    self.warn(
+                        WarningKind::Undeclared,
+                        format!(
+                            "`{0} = ...` assigns to {0}, which is not declared here; \
+                             write `let {0} = ...` to declare it",
+                            name
+                        ),
+                    );
+                }
+            }
+            Stmt::Enum { .. }
+            | Stmt::Return(_)
+            | Stmt::Break(_)
+            | Stmt::BreakLabel(_)
+            | Stmt::Expr(_)
+            | Stmt::Pragma(_) => walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Match { scrutinee, arms } => {
+                self.visit_expr(scrutinee);
+                for arm in arms {
+                    let bindings = match &arm.pattern {
+                        Pattern::Variant { bindings, .. } => bindings.as_slice(),
+                        _ => &[],
+                    };
+                    self.scoped(Scope::default(), bindings, &arm.body);
+                }
+            }
+            Expr::If {
+                cond, then, else_, ..
+            } => {
+                self.visit_expr(cond);
+                self.block(then);
+                self.block(else_);
+            }
+            Expr::IfLet {
+                name,
+                value,
+                then,
+                else_,
+            } => {
+                self.visit_expr(value);
+                self.scoped(Scope::default(), [name], then);
+                self.block(else_);
+            }
+            Expr::LetIn { name, value, body } => {
+                self.visit_expr(value);
+                self.scopes.push(Scope::default());
+                self.declare(name);
+                self.visit_expr(body);
+                self.scopes.pop();
+            }
+            Expr::While { cond, body, .. } => {
+                self.visit_expr(cond);
+                self.loop_body([], body);
+            }
+            Expr::WhileLet { name, value, body } => {
+                self.visit_expr(value);
+                self.loop_body([name], body);
+            }
+            Expr::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                self.visit_expr(start);
+                self.visit_expr(end);
+                self.loop_body([var], body);
+            }
+            Expr::Loop(body) => self.loop_body([], body),
+            Expr::Comprehension {
+                key,
+                value,
+                var,
+                source,
+                cond,
+                ..
+            } => {
+                match source {
+                    Iteration::Range { start, end } => {
+                        self.visit_expr(start);
+                        self.visit_expr(end);
+                    }
+                    Iteration::Each(items) => self.visit_expr(items),
+                }
+                self.scopes.push(Scope::default());
+                self.declare(var);
+                let parts = [cond.as_deref(), key.as_deref(), Some(value.as_ref())];
+                parts.into_iter().flatten().for_each(|e| self.visit_expr(e));
+                self.scopes.pop();
+            }
+            Expr::Block(stmts) => self.block(stmts),
+            _ => walk_expr(self, expr),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn warnings(source: &str) -> Vec<String> {
+        resolve(&parse(source).unwrap())
+            .into_iter()
+            .map(|warning| warning.message)
+            .collect()
+    }
+
+    #[test]
+    fn test_let_in_loop_shadowing_outer() {
+        let found = warnings("let x = 0\nwhile (x < 10) { let x = x + 1 }");
+        assert_eq!(found.len(), 1);
+        assert!(
+            found[0].starts_with("`let x = ...` inside a loop"),
+            "{}",
+            found[0]
+        );
+
+        assert_eq!(
+            warnings("let x = 0\nfor i in 0..3 { if (true) { let x = [x] } }").len(),
+            1
+        );
+
+        // Fresh names, plain updates and shadowing outside loops are fine.
+        assert!(warnings("let x = 0\nwhile (x < 10) { x = x + 1\nlet y = x }").is_empty());
+        assert!(warnings("let x = 1\n{ let x = x + 1 }").is_empty());
+        assert!(warnings("for i in 0..3 { let x = 1\nlet x = x + 1 }").is_empty());
+        assert!(warnings("let x = 1\nwhile (true) { fn f(x) { let x = x + 1 } }").is_empty());
+    }
+
+    #[test]
+    fn test_assignment_without_declaration() {
+        let found = warnings("y = 1");
+        assert_eq!(
+            found,
+            vec![
+                "`y = ...` assigns to y, which is not declared here; write `let y = ...` to declare it"
+            ]
+        );
+
+        assert_eq!(
+            resolve(&parse("y = 1").unwrap())[0].kind,
+            WarningKind::Undeclared
+        );
+
+        // Function bodies can't see the variables around them.
+        assert_eq!(warnings("let n = 0\nfn bump() { n++ }").len(), 1);
+
+        assert!(warnings("let n = 0\nn++\nfor i in 0..3 { n = i }").is_empty());
+        assert!(warnings("fn f(a) { a = 1 }\nif let v = f(1) { v = 2 }").is_empty());
+        assert!(warnings("enum E { A(x) }\nmatch (E::A(1)) { E::A(x) => { x = 2 } }").is_empty());
+        assert!(warnings("while let line = next() { line = 1 }").is_empty());
+    }
+
+    #[test]
+    fn test_host_globals() {
+        let program = parse("CONFIG[\"a\"] = 2\nfn f() { CONFIG = 1 }").unwrap();
+        assert!(resolve_with_globals(&program, &["CONFIG"]).is_empty());
+        assert_eq!(resolve(&program).len(), 2);
+    }
+}
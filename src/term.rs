@@ -0,0 +1,143 @@
+//! Raw-mode keyboard polling behind `key_pressed` and `read_key`, built with
+//! the `term` feature. Raw mode is only held for the duration of a call, so
+//! `print` output between calls behaves as usual.
+
+use std::time::Duration;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+
+use crate::interpreter::INTERRUPTED;
+
+/// Runs `f` with the terminal in raw mode, restoring it afterwards.
+fn raw<T>(f: impl FnOnce() -> Result<T, String>) -> Result<T, String> {
+    terminal::enable_raw_mode().map_err(cannot_read)?;
+    let res = f();
+    let _ = terminal::disable_raw_mode();
+    res
+}
+
+fn cannot_read(e: std::io::Error) -> String {
+    format!("Cannot read keys: {}", e)
+}
+
+/// Whether a key press is waiting, without consuming it.
+pub fn key_pressed() -> Result<bool, String> {
+    raw(|| event::poll(Duration::ZERO).map_err(cannot_read))
+}
+
+/// The name of the next key pressed within `timeout`, or `None` if there
+/// was none. Other terminal events are skipped.
+pub fn read_key(timeout: Duration) -> Result<Option<String>, String> {
+    raw(|| {
+        let deadline = std::time::Instant::now() + timeout;
+        loop {
+            let left = deadline.saturating_duration_since(std::time::Instant::now());
+            if !event::poll(left).map_err(cannot_read)? {
+                return Ok(None);
+            }
+            if let Event::Key(key) = event::read().map_err(cannot_read)?
+                && key.kind != KeyEventKind::Release
+                && let Some(name) = key_name(key)?
+            {
+                return Ok(Some(name));
+            }
+        }
+    })
+}
+
+/// The names of the keys pressed since the last call, without waiting.
+pub fn pending_keys() -> Result<Vec<String>, String> {
+    raw(|| {
+        let mut keys = Vec::new();
+        while event::poll(Duration::ZERO).map_err(cannot_read)? {
+            if let Event::Key(key) = event::read().map_err(cannot_read)?
+                && key.kind != KeyEventKind::Release
+                && let Some(name) = key_name(key)?
+            {
+                keys.push(name);
+            }
+        }
+        Ok(keys)
+    })
+}
+
+/// A character key as itself, other keys by name, e.g. `"Up"` or `"Esc"`,
+/// after any modifiers held with it, e.g. `"ctrl+x"` or `"alt+Up"`. Shift
+/// only shows for keys it doesn't change the character of. Raw mode keeps
+/// Ctrl-C from interrupting the program, so it stops the program here.
+fn key_name(key: KeyEvent) -> Result<Option<String>, String> {
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+    if ctrl && matches!(key.code, KeyCode::Char('c' | 'C')) {
+        return Err(INTERRUPTED.to_string());
+    }
+    let Some(name) = code_name(key.code) else {
+        return Ok(None);
+    };
+    let mut chord = String::new();
+    if ctrl {
+        chord.push_str("ctrl+");
+    }
+    if key.modifiers.contains(KeyModifiers::ALT) {
+        chord.push_str("alt+");
+    }
+    if key.modifiers.contains(KeyModifiers::SHIFT) && !matches!(key.code, KeyCode::Char(_)) {
+        chord.push_str("shift+");
+    }
+    chord.push_str(&name);
+    Ok(Some(chord))
+}
+
+fn code_name(code: KeyCode) -> Option<String> {
+    Some(match code {
+        KeyCode::Char(c) => c.to_string(),
+        KeyCode::F(n) => format!("F{}", n),
+        KeyCode::Enter => "Enter".to_string(),
+        KeyCode::Esc => "Esc".to_string(),
+        KeyCode::Backspace => "Backspace".to_string(),
+        KeyCode::Tab => "Tab".to_string(),
+        KeyCode::Delete => "Delete".to_string(),
+        KeyCode::Up => "Up".to_string(),
+        KeyCode::Down => "Down".to_string(),
+        KeyCode::Left => "Left".to_string(),
+        KeyCode::Right => "Right".to_string(),
+        KeyCode::Home => "Home".to_string(),
+        KeyCode::End => "End".to_string(),
+        KeyCode::PageUp => "PageUp".to_string(),
+        KeyCode::PageDown => "PageDown".to_string(),
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_key_names() {
+        let name = |code, modifiers| key_name(KeyEvent::new(code, modifiers));
+        let none = KeyModifiers::NONE;
+        assert_eq!(name(KeyCode::Char('w'), none), Ok(Some("w".to_string())));
+        assert_eq!(name(KeyCode::Char(' '), none), Ok(Some(" ".to_string())));
+        assert_eq!(name(KeyCode::Up, none), Ok(Some("Up".to_string())));
+        assert_eq!(name(KeyCode::F(5), none), Ok(Some("F5".to_string())));
+        assert_eq!(name(KeyCode::CapsLock, none), Ok(None));
+
+        assert_eq!(
+            name(KeyCode::Char('x'), KeyModifiers::CONTROL),
+            Ok(Some("ctrl+x".to_string()))
+        );
+        assert_eq!(
+            name(KeyCode::Char('W'), KeyModifiers::SHIFT),
+            Ok(Some("W".to_string()))
+        );
+        assert_eq!(
+            name(KeyCode::Up, KeyModifiers::ALT | KeyModifiers::SHIFT),
+            Ok(Some("alt+shift+Up".to_string()))
+        );
+        assert_eq!(
+            name(KeyCode::Char('c'), KeyModifiers::CONTROL),
+            Err(INTERRUPTED.to_string())
+        );
+    }
+}
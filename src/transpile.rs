@@ -0,0 +1,1078 @@
+//! `ew build --target js`: turns a program into JavaScript that runs without
+//! the interpreter. The builtins it calls come from a small runtime,
+//! `transpile_runtime.js`, placed at the top of the output.
+//!
+//! Statements become the matching JavaScript statements. Where ew uses an
+//! `if`, `match` or loop as a value, it is compiled in tail position instead,
+//! ending each branch with a `return` or an assignment, so the output reads
+//! like hand-written code. Arrays keep ew's value semantics: a variable that
+//! is assigned through an index gets a copy of the array it is bound to.
+//!
+//! JavaScript has one number type, so the runtime boxes Floats to tell them
+//! from Ints, and arithmetic goes through it to keep ew's meaning. Ints are
+//! exact only up to 2^53: a larger literal doesn't compile, and a result
+//! past it is an overflow error, as in native builds.
+
+use std::collections::HashSet;
+
+use crate::ast::{AssignmentTarget, BinaryOp, Expr, Iteration, Pattern, Stmt, UnaryOp};
+use crate::interpreter::builtin_doc;
+use crate::resolver::mentions;
+use crate::version;
+
+const INDENT: &str = "    ";
+
+/// The largest integer a JavaScript number holds exactly, 2^53 - 1.
+const MAX_SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+const RUNTIME: &str = include_str!("transpile_runtime.js");
+
+/// The builtins `transpile_runtime.js` implements, called as `$ew.name`.
+const RUNTIME_BUILTINS: &[&str] = &[
+    "print",
+    "println",
+    "pprint",
+    "str",
+    "repr",
+    "len",
+    "keys",
+    "copy",
+    "deep_copy",
+    "deep_eq",
+    "sort",
+    "sort_by",
+    "abs",
+    "sqrt",
+    "floor",
+    "sin",
+    "cos",
+    "round",
+    "to_fixed",
+    "divmod",
+    "approx_eq",
+    "is_nan",
+    "is_inf",
+    "parse_int",
+    "parse_float",
+    "memoize",
+];
+
+/// Names ew allows that JavaScript reserves, or that would shadow globals
+/// the runtime relies on; they get a trailing `_`.
+const JS_RESERVED: &[&str] = &[
+    "arguments",
+    "Array",
+    "await",
+    "case",
+    "catch",
+    "class",
+    "console",
+    "const",
+    "continue",
+    "debugger",
+    "default",
+    "delete",
+    "do",
+    "Error",
+    "eval",
+    "export",
+    "extends",
+    "finally",
+    "function",
+    "implements",
+    "import",
+    "Infinity",
+    "instanceof",
+    "interface",
+    "JSON",
+    "Map",
+    "Math",
+    "NaN",
+    "new",
+    "Number",
+    "Object",
+    "package",
+    "private",
+    "protected",
+    "public",
+    "static",
+    "String",
+    "super",
+    "switch",
+    "this",
+    "throw",
+    "try",
+    "typeof",
+    "undefined",
+    "var",
+    "void",
+    "with",
+    "yield",
+];
+
+/// What to do with the value of the statement being compiled.
+#[derive(Clone)]
+enum Tail {
+    Discard,
+    Return,
+    /// Assign it to the named JavaScript variable.
+    Assign(String),
+}
+
+/// A whole script: the runtime followed by `program`.
+pub fn to_js(program: &[Stmt]) -> Result<String, String> {
+    let mut transpiler = Transpiler {
+        out: String::new(),
+        depth: 0,
+        functions: HashSet::new(),
+        scopes: vec![HashSet::new()],
+        copied: js_names(index_assigned(program)),
+        loops: Vec::new(),
+        temps: 0,
+    };
+    collect_functions(program, &mut transpiler.functions);
+    transpiler.block(program, Tail::Discard)?;
+    Ok(format!("{}\n{}$ew.flush();\n", RUNTIME, transpiler.out))
+}
+
+struct Transpiler {
+    out: String,
+    depth: usize,
+    /// Every function the program defines, at any depth.
+    functions: HashSet<String>,
+    /// Names declared with `let` or as parameters, innermost scope last.
+    scopes: Vec<HashSet<String>>,
+    /// Variables of the current function that are assigned through an
+    /// index, and so must not share their array with anything else.
+    copied: HashSet<String>,
+    /// What `break value` does in each enclosing loop, innermost last.
+    loops: Vec<Tail>,
+    temps: usize,
+}
+
+impl Transpiler {
+    fn line(&mut self, text: &str) {
+        self.out.push_str(&INDENT.repeat(self.depth));
+        self.out.push_str(text);
+        self.out.push('\n');
+    }
+
+    /// Compiles `stmts` inside a new scope, one level deeper, giving the
+    /// last statement's value to `tail`.
+    fn nested(&mut self, stmts: &[Stmt], tail: Tail) -> Result<(), String> {
+        self.depth += 1;
+        self.scopes.push(HashSet::new());
+        let res = self.block(stmts, tail);
+        self.scopes.pop();
+        self.depth -= 1;
+        res
+    }
+
+    fn block(&mut self, stmts: &[Stmt], tail: Tail) -> Result<(), String> {
+        for (i, stmt) in stmts.iter().enumerate() {
+            if i + 1 == stmts.len() {
+                self.stmt(stmt, tail.clone())?;
+            } else {
+                self.stmt(stmt, Tail::Discard)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn declared(&self, name: &str) -> bool {
+        self.scopes.iter().any(|scope| scope.contains(name))
+    }
+
+    fn stmt(&mut self, stmt: &Stmt, tail: Tail) -> Result<(), String> {
+        match stmt {
+            Stmt::Function {
+                name,
+                params,
+                body,
+                decorators,
+                ..
+            } => {
+                let params: Vec<String> = params.iter().map(|p| ident(p)).collect();
+                let outer_copied =
+                    std::mem::replace(&mut self.copied, js_names(index_assigned(body)));
+                let outer_loops = std::mem::take(&mut self.loops);
+                let header = if decorators.is_empty() {
+                    format!("function {}({}) {{", ident(name), params.join(", "))
+                } else {
+                    // Recursive calls go through the decorated function.
+                    let wrappers: Vec<String> = decorators
+                        .iter()
+                        .map(|d| self.callee(d))
+                        .collect::<Result<_, _>>()?;
+                    format!(
+                        "const {} = {}(function ({}) {{",
+                        ident(name),
+                        wrappers.join("("),
+                        params.join(", ")
+                    )
+                };
+                self.line(&header);
+                self.depth += 1;
+                self.scopes.push(params.iter().cloned().collect());
+                for param in &params {
+                    if self.copied.contains(param) {
+                        self.line(&format!("{0} = $ew.copy({0});", param));
+                    }
+                }
+                let res = self.block(body, Tail::Return);
+                self.scopes.pop();
+                self.depth -= 1;
+                self.copied = outer_copied;
+                self.loops = outer_loops;
+                res?;
+                if decorators.is_empty() {
+                    self.line("}");
+                } else {
+                    self.line(&format!("}}{};", ")".repeat(decorators.len())));
+                }
+            }
+            // Variants carry their enum's name, so the enum itself needs no
+            // code.
+            Stmt::Enum { .. } => {}
+            Stmt::Return(value) => self.expr_stmt(value, Tail::Return)?,
+            Stmt::Break(value) => {
+                let tail = self.loops.last().cloned().unwrap_or(Tail::Discard);
+                match (value, tail) {
+                    (Some(value), Tail::Return) => self.expr_stmt(value, Tail::Return)?,
+                    (Some(value), tail) => {
+                        self.expr_stmt(value, tail)?;
+                        self.line("break;");
+                    }
+                    (None, _) => self.line("break;"),
+                }
+            }
+            Stmt::BreakLabel(label) => self.line(&format!("break {};", ident(label))),
+            Stmt::Assignment { name, value } => {
+                let js = ident(name);
+                let redeclared = self.scopes.last().unwrap().contains(&js);
+                let keyword = if redeclared { "" } else { "let " };
+                if is_statement(value) {
+                    if !redeclared {
+                        self.line(&format!("let {};", js));
+                    }
+                    self.expr_stmt(value, Tail::Assign(js.clone()))?;
+                } else {
+                    let value = self.bound_value(&js, value)?;
+                    self.line(&format!("{}{} = {};", keyword, js, value));
+                }
+                self.scopes.last_mut().unwrap().insert(js);
+                self.tail_unit(tail);
+            }
+            Stmt::Reassignment { target, value } => {
+                match target {
+                    AssignmentTarget::Ident(name) => {
+                        let js = ident(name);
+                        if is_statement(value) {
+                            self.expr_stmt(value, Tail::Assign(js))?;
+                        } else {
+                            let value = self.bound_value(&js, value)?;
+                            self.line(&format!("{} = {};", js, value));
+                        }
+                    }
+                    AssignmentTarget::ArrayAccess { name, indices } => {
+                        let (last, init) = indices.split_last().unwrap();
+                        let target = self.access(name, init)?;
+                        let index = self.expr(last)?;
+                        let value = self.expr(value)?;
+                        match plain_index(last) {
+                            true => self.line(&format!("{}[{}] = {};", target, index, value)),
+                            false => {
+                                self.line(&format!("$ew.set({}, {}, {});", target, index, value))
+                            }
+                        }
+                    }
+                }
+                self.tail_unit(tail);
+            }
+            Stmt::Expr(expr) => self.expr_stmt(expr, tail)?,
+            Stmt::Pragma(pragma) => {
+                version::require_default(pragma)?;
+                self.tail_unit(tail);
+            }
+        }
+        Ok(())
+    }
+
+    /// A statement's value is unit when it isn't an expression; a function
+    /// ending in one returns `undefined`, which the runtime shows as `()`.
+    fn tail_unit(&mut self, tail: Tail) {
+        if let Tail::Assign(name) = tail {
+            self.line(&format!("{} = undefined;", name));
+        }
+    }
+
+    /// The value `name` is bound to, copied if it may be shared and `name`
+    /// is changed through an index later.
+    fn bound_value(&mut self, name: &str, value: &Expr) -> Result<String, String> {
+        let js = self.expr(value)?;
+        let shared = match value {
+            Expr::Var(_) | Expr::ArrayAccess { .. } | Expr::Slice { .. } => true,
+            Expr::Call { name, .. } => !self.is_runtime_call(name),
+            _ => false,
+        };
+        Ok(if shared && self.copied.contains(name) {
+            format!("$ew.copy({})", js)
+        } else {
+            js
+        })
+    }
+
+    /// Compiles `expr` as a statement whose value goes to `tail`.
+    fn expr_stmt(&mut self, expr: &Expr, tail: Tail) -> Result<(), String> {
+        match expr {
+            Expr::If {
+                cond, then, else_, ..
+            } => {
+                let cond = self.expr(cond)?;
+                self.line(&format!("if ({}) {{", cond));
+                self.nested(then, tail.clone())?;
+                self.else_branch(else_, tail)?;
+            }
+            Expr::IfLet {
+                name,
+                value,
+                then,
+                else_,
+            } => {
+                let js = ident(name);
+                let value = self.expr(value)?;
+                self.line("{");
+                self.depth += 1;
+                self.line(&format!("const {} = {};", js, value));
+                self.line(&format!("if ({} !== null) {{", js));
+                self.scopes.push(HashSet::from([js]));
+                let res = self.nested(then, tail.clone());
+                self.scopes.pop();
+                res?;
+                self.else_branch(else_, tail)?;
+                self.depth -= 1;
+                self.line("}");
+            }
+            Expr::LetIn { name, .. } if matches!(&tail, Tail::Assign(target) if *target == ident(name)) =>
+            {
+                // The binding would shadow the variable the value goes to.
+                let js = self.immediate(expr)?;
+                self.line(&format!("{} = {};", ident(name), js));
+            }
+            Expr::LetIn { name, value, body } => {
+                let js = ident(name);
+                // A value that reads the variable being shadowed is worked
+                // out before the new one is declared.
+                let early = mentions(value, name);
+                let bound = if early {
+                    self.temps += 1;
+                    format!("bound{}", self.temps)
+                } else {
+                    self.line("{");
+                    self.depth += 1;
+                    js.clone()
+                };
+                if is_statement(value) {
+                    self.line(&format!("let {};", bound));
+                    self.expr_stmt(value, Tail::Assign(bound.clone()))?;
+                } else {
+                    let value = self.bound_value(&js, value)?;
+                    self.line(&format!("let {} = {};", bound, value));
+                }
+                if early {
+                    self.line("{");
+                    self.depth += 1;
+                    self.line(&format!("let {} = {};", js, bound));
+                }
+                self.scopes.push(HashSet::from([js]));
+                let res = self.expr_stmt(body, tail);
+                self.scopes.pop();
+                res?;
+                self.depth -= 1;
+                self.line("}");
+            }
+            Expr::While { cond, body, .. } => {
+                let cond = self.expr(cond)?;
+                self.line(&format!("while ({}) {{", cond));
+                self.loop_body(body, tail)?;
+            }
+            Expr::WhileLet { name, value, body } => {
+                let js = ident(name);
+                let value = self.expr(value)?;
+                self.line("while (true) {");
+                self.depth += 1;
+                self.line(&format!("const {} = {};", js, value));
+                self.line(&format!("if ({} === null) break;", js));
+                self.scopes.push(HashSet::from([js]));
+                self.depth -= 1;
+                let res = self.loop_body(body, tail);
+                self.scopes.pop();
+                res?;
+            }
+            Expr::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                let js = ident(var);
+                let start = self.expr(start)?;
+                let end_js = self.expr(end)?;
+                // ew evaluates the end once; only simple ends are safe to
+                // re-evaluate on every pass.
+                let header = if matches!(**end, Expr::Int(_) | Expr::Var(_)) {
+                    format!(
+                        "for (let {0} = {1}; {0} < {2}; {0}++) {{",
+                        js, start, end_js
+                    )
+                } else {
+                    format!(
+                        "for (let {0} = {1}, end = {2}; {0} < end; {0}++) {{",
+                        js, start, end_js
+                    )
+                };
+                self.line(&header);
+                self.scopes.push(HashSet::from([js]));
+                let res = self.loop_body(body, tail);
+                self.scopes.pop();
+                res?;
+            }
+            Expr::Loop(body) => {
+                self.line("while (true) {");
+                self.loop_body(body, tail)?;
+            }
+            Expr::Labeled { label, body } => {
+                self.line(&format!("{}:", ident(label)));
+                self.expr_stmt(body, tail)?;
+            }
+            Expr::Match { scrutinee, arms } => {
+                let subject = match &**scrutinee {
+                    Expr::Var(name) => self.var(name),
+                    other => {
+                        self.temps += 1;
+                        let temp = format!("subject{}", self.temps);
+                        let value = self.expr(other)?;
+                        self.line(&format!("const {} = {};", temp, value));
+                        temp
+                    }
+                };
+                let mut keyword = "if";
+                let mut exhaustive = false;
+                for arm in arms {
+                    let (cond, bindings) = match &arm.pattern {
+                        Pattern::Wildcard => (None, Vec::new()),
+                        Pattern::Literal(value) => {
+                            let value = self.expr(value)?;
+                            (Some(format!("$ew.eq({}, {})", subject, value)), Vec::new())
+                        }
+                        Pattern::Variant {
+                            enum_name,
+                            variant,
+                            bindings,
+                        } => (
+                            Some(format!(
+                                "{0}.$enum === {1} && {0}.$variant === {2}",
+                                subject,
+                                js_string(enum_name),
+                                js_string(variant)
+                            )),
+                            bindings.iter().map(|b| ident(b)).collect(),
+                        ),
+                    };
+                    match cond {
+                        Some(cond) => self.line(&format!("{} ({}) {{", keyword, cond)),
+                        None if keyword == "if" => self.line("{"),
+                        None => self.line("} else {"),
+                    }
+                    self.depth += 1;
+                    if !bindings.is_empty() {
+                        self.line(&format!(
+                            "const [{}] = {}.$fields;",
+                            bindings.join(", "),
+                            subject
+                        ));
+                    }
+                    self.scopes.push(bindings.into_iter().collect());
+                    self.depth -= 1;
+                    let res = self.nested(&arm.body, tail.clone());
+                    self.scopes.pop();
+                    res?;
+                    if matches!(arm.pattern, Pattern::Wildcard) {
+                        exhaustive = true;
+                        break;
+                    }
+                    keyword = "} else if";
+                }
+                if !exhaustive {
+                    if keyword == "if" {
+                        self.line(&format!("$ew.noMatch({});", subject));
+                        return Ok(());
+                    }
+                    self.line("} else {");
+                    self.depth += 1;
+                    self.line(&format!("$ew.noMatch({});", subject));
+                    self.depth -= 1;
+                }
+                self.line("}");
+            }
+            Expr::Block(stmts) => {
+                self.line("{");
+                self.nested(stmts, tail)?;
+                self.line("}");
+            }
+            expr => {
+                let js = self.expr(expr)?;
+                match tail {
+                    Tail::Discard => self.line(&format!("{};", js)),
+                    Tail::Return => self.line(&format!("return {};", js)),
+                    Tail::Assign(name) => self.line(&format!("{} = {};", name, js)),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// `} else ...` after an `if` branch, or the closing brace when there is
+    /// no else.
+    fn else_branch(&mut self, else_: &[Stmt], tail: Tail) -> Result<(), String> {
+        match else_ {
+            [] => self.line("}"),
+            [
+                Stmt::Expr(Expr::If {
+                    cond, then, else_, ..
+                }),
+            ] => {
+                let cond = self.expr(cond)?;
+                self.line(&format!("}} else if ({}) {{", cond));
+                self.nested(then, tail.clone())?;
+                self.else_branch(else_, tail)?;
+            }
+            stmts => {
+                self.line("} else {");
+                self.nested(stmts, tail)?;
+                self.line("}");
+            }
+        }
+        Ok(())
+    }
+
+    /// A loop's body and closing brace. The loop's own value is unit unless
+    /// it is left with `break value`.
+    fn loop_body(&mut self, body: &[Stmt], tail: Tail) -> Result<(), String> {
+        self.loops.push(tail.clone());
+        let res = self.nested(body, Tail::Discard);
+        self.loops.pop();
+        res?;
+        self.line("}");
+        Ok(())
+    }
+
+    fn access(&mut self, name: &str, indices: &[Box<Expr>]) -> Result<String, String> {
+        let mut js = self.var(name);
+        for index in indices {
+            js = match plain_index(index) {
+                true => format!("{}[{}]", js, self.expr(index)?),
+                false => format!("$ew.at({}, {})", js, self.expr(index)?),
+            };
+        }
+        Ok(js)
+    }
+
+    fn var(&self, name: &str) -> String {
+        let js = ident(name);
+        if !self.declared(&js) && !self.functions.contains(name) && self.is_runtime_call(name) {
+            return format!("$ew.{}", name);
+        }
+        js
+    }
+
+    fn is_runtime_call(&self, name: &str) -> bool {
+        !self.declared(&ident(name))
+            && !self.functions.contains(name)
+            && RUNTIME_BUILTINS.contains(&name)
+    }
+
+    /// What calling `name` compiles to: the user's function or variable if
+    /// there is one, otherwise the runtime's builtin.
+    fn callee(&self, name: &str) -> Result<String, String> {
+        if self.declared(&ident(name)) || self.functions.contains(name) {
+            Ok(ident(name))
+        } else if RUNTIME_BUILTINS.contains(&name) {
+            Ok(format!("$ew.{}", name))
+        } else if builtin_doc(name).is_some() {
+            Err(format!("{}() has no JavaScript equivalent", name))
+        } else {
+            Ok(ident(name))
+        }
+    }
+
+    fn list(&mut self, exprs: &[Expr]) -> Result<String, String> {
+        let items: Vec<String> = exprs
+            .iter()
+            .map(|e| self.expr(e))
+            .collect::<Result<_, _>>()?;
+        Ok(items.join(", "))
+    }
+
+    fn operand(&mut self, expr: &Expr, min: u8) -> Result<String, String> {
+        let js = self.expr(expr)?;
+        Ok(if precedence(expr) < min {
+            format!("({})", js)
+        } else {
+            js
+        })
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Result<String, String> {
+        Ok(match expr {
+            Expr::Int(n) if n.unsigned_abs() <= MAX_SAFE_INTEGER => n.to_string(),
+            Expr::Int(_) | Expr::BigInt(_) => {
+                return Err("Integers past 2^53 can't be compiled to JavaScript".into());
+            }
+            Expr::Float(f) => format!("$ew.float({})", f),
+            Expr::Bool(b) => b.to_string(),
+            Expr::Str { value: s, .. } => js_string(s),
+            Expr::Null => "null".to_string(),
+            Expr::Array { items, .. } => format!("[{}]", self.list(items)?),
+            Expr::ArrayAccess { name, indices, .. } => self.access(name, indices)?,
+            // JavaScript's slice counts negative bounds from the end too.
+            Expr::Slice {
+                name,
+                indices,
+                start,
+                end,
+                ..
+            } => {
+                let value = self.access(name, indices)?;
+                let start = match start {
+                    Some(start) => self.expr(start)?,
+                    None => "0".to_string(),
+                };
+                match end {
+                    Some(end) => format!("{}.slice({}, {})", value, start, self.expr(end)?),
+                    None => format!("{}.slice({})", value, start),
+                }
+            }
+            Expr::Var(name) => self.var(name),
+            Expr::Unary {
+                op: UnaryOp::Neg,
+                expr,
+                ..
+            } => match &**expr {
+                Expr::Int(_) => format!("-{}", self.expr(expr)?),
+                Expr::Float(f) => format!("$ew.float({})", -f),
+                _ => format!("$ew.neg({})", self.expr(expr)?),
+            },
+            Expr::Unary {
+                op: UnaryOp::Not,
+                expr,
+                ..
+            } => format!("!{}", self.operand(expr, 7)?),
+            Expr::Binary { op, lhs, rhs, .. } => self.binary(*op, lhs, rhs)?,
+            Expr::Call { name, args, .. } => {
+                format!("{}({})", self.callee(name)?, self.list(args)?)
+            }
+            Expr::Spread(expr) => format!("...{}", self.operand(expr, 8)?),
+            Expr::Variant {
+                enum_name,
+                variant,
+                args,
+                ..
+            } => format!(
+                "$ew.variant({}, {}, [{}])",
+                js_string(enum_name),
+                js_string(variant),
+                self.list(args)?
+            ),
+            Expr::Comprehension {
+                key,
+                value,
+                var,
+                source,
+                cond,
+                ..
+            } => {
+                let source = match source {
+                    Iteration::Range { start, end } => {
+                        format!("$ew.range({}, {})", self.expr(start)?, self.expr(end)?)
+                    }
+                    Iteration::Each(items) => self.operand(items, 8)?,
+                };
+                let js = ident(var);
+                self.scopes.push(HashSet::from([js.clone()]));
+                let res = (|| -> Result<String, String> {
+                    let mut chain = source;
+                    if let Some(cond) = cond {
+                        chain.push_str(&format!(".filter(({}) => {})", js, self.expr(cond)?));
+                    }
+                    Ok(match key {
+                        Some(key) => format!(
+                            "Object.fromEntries({}.map(({}) => [{}, {}]))",
+                            chain,
+                            js,
+                            self.expr(key)?,
+                            self.expr(value)?
+                        ),
+                        None => format!("{}.map(({}) => {})", chain, js, self.expr(value)?),
+                    })
+                })();
+                self.scopes.pop();
+                res?
+            }
+            Expr::If {
+                cond, then, else_, ..
+            } => match (&then[..], &else_[..]) {
+                // Simple conditional values read best as a ternary.
+                ([Stmt::Expr(a)], [Stmt::Expr(b)]) if !is_statement(a) && !is_statement(b) => {
+                    format!(
+                        "{} ? {} : {}",
+                        self.operand(cond, 1)?,
+                        self.operand(a, 1)?,
+                        self.operand(b, 1)?
+                    )
+                }
+                _ => self.immediate(expr)?,
+            },
+            _ => self.immediate(expr)?,
+        })
+    }
+
+    /// A statement used as a value where no tail position is available, as
+    /// an immediately called arrow function.
+    fn immediate(&mut self, expr: &Expr) -> Result<String, String> {
+        let outer = std::mem::take(&mut self.out);
+        let outer_loops = std::mem::take(&mut self.loops);
+        self.depth += 1;
+        let res = self.expr_stmt(expr, Tail::Return);
+        self.depth -= 1;
+        self.loops = outer_loops;
+        let body = std::mem::replace(&mut self.out, outer);
+        res?;
+        Ok(format!(
+            "(() => {{\n{}{}}})()",
+            body,
+            INDENT.repeat(self.depth)
+        ))
+    }
+
+    fn binary(&mut self, op: BinaryOp, lhs: &Expr, rhs: &Expr) -> Result<String, String> {
+        if let Some(helper) = helper(op, lhs, rhs) {
+            return Ok(format!(
+                "$ew.{}({}, {})",
+                helper,
+                self.expr(lhs)?,
+                self.expr(rhs)?
+            ));
+        }
+        let prec = precedence_of(op);
+        let (left, right) = match op {
+            _ if prec == 3 => (prec + 1, prec + 1),
+            _ => (prec, prec + 1),
+        };
+        let symbol = match op {
+            BinaryOp::Add => "+",
+            BinaryOp::Sub => "-",
+            BinaryOp::Mul => "*",
+            BinaryOp::Div => "/",
+            BinaryOp::Mod => "%",
+            BinaryOp::Pow => "**",
+            BinaryOp::Eq => "===",
+            BinaryOp::Ne => "!==",
+            BinaryOp::Lt => "<",
+            BinaryOp::Le => "<=",
+            BinaryOp::Gt => ">",
+            BinaryOp::Ge => ">=",
+            BinaryOp::And => "&&",
+            BinaryOp::Or => "||",
+        };
+        Ok(format!(
+            "{} {} {}",
+            self.operand(lhs, left)?,
+            symbol,
+            self.operand(rhs, right)?
+        ))
+    }
+}
+
+/// Whether `expr` compiles to statements rather than an expression.
+pub(crate) fn is_statement(expr: &Expr) -> bool {
+    match expr {
+        Expr::If { then, else_, .. } => !matches!(
+            (&then[..], &else_[..]),
+            ([Stmt::Expr(a)], [Stmt::Expr(b)]) if !is_statement(a) && !is_statement(b)
+        ),
+        Expr::IfLet { .. }
+        | Expr::LetIn { .. }
+        | Expr::While { .. }
+        | Expr::WhileLet { .. }
+        | Expr::For { .. }
+        | Expr::Loop(_)
+        | Expr::Labeled { .. }
+        | Expr::Match { .. }
+        | Expr::Block(_) => true,
+        _ => false,
+    }
+}
+
+fn precedence_of(op: BinaryOp) -> u8 {
+    match op {
+        BinaryOp::Or => 1,
+        BinaryOp::And => 2,
+        BinaryOp::Eq | BinaryOp::Ne | BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => {
+            3
+        }
+        BinaryOp::Add | BinaryOp::Sub => 4,
+        BinaryOp::Mul | BinaryOp::Div | BinaryOp::Mod => 5,
+        BinaryOp::Pow => 6,
+    }
+}
+
+/// The runtime function that applies `op`, unless the JavaScript operator
+/// means the same for these operands. Arithmetic always goes through the
+/// runtime, which checks for overflow and keeps Floats boxed; only `+` with
+/// a string literal is plain concatenation. `==` is `===` when one side is
+/// a literal JavaScript compares by value.
+fn helper(op: BinaryOp, lhs: &Expr, rhs: &Expr) -> Option<&'static str> {
+    let either = |f: fn(&Expr) -> bool| f(lhs) || f(rhs);
+    match op {
+        BinaryOp::Add if either(|e| matches!(e, Expr::Str { .. })) => None,
+        BinaryOp::Add => Some("add"),
+        BinaryOp::Sub => Some("sub"),
+        BinaryOp::Mul => Some("mul"),
+        BinaryOp::Div => Some("div"),
+        BinaryOp::Mod => Some("mod"),
+        BinaryOp::Pow => Some("pow"),
+        BinaryOp::Eq | BinaryOp::Ne
+            if either(|e| {
+                matches!(
+                    e,
+                    Expr::Int(_) | Expr::Str { .. } | Expr::Bool(_) | Expr::Null
+                )
+            }) =>
+        {
+            None
+        }
+        BinaryOp::Eq => Some("eq"),
+        BinaryOp::Ne => Some("ne"),
+        _ => None,
+    }
+}
+
+/// How tightly the JavaScript for `expr` binds; ternaries bind loosest and
+/// calls, literals and runtime helpers tightest.
+fn precedence(expr: &Expr) -> u8 {
+    match expr {
+        Expr::Binary { op, lhs, rhs, .. } => match helper(*op, lhs, rhs) {
+            Some(_) => 8,
+            None => precedence_of(*op),
+        },
+        Expr::Unary {
+            op: UnaryOp::Neg,
+            expr,
+            ..
+        } if !matches!(**expr, Expr::Int(_)) => 8,
+        Expr::Unary { .. } => 7,
+        Expr::If { .. } => 0,
+        _ => 8,
+    }
+}
+
+fn ident(name: &str) -> String {
+    if JS_RESERVED.contains(&name) {
+        format!("{}_", name)
+    } else {
+        name.to_string()
+    }
+}
+
+fn js_string(s: &str) -> String {
+    serde_json::to_string(s).unwrap()
+}
+
+fn collect_functions(stmts: &[Stmt], names: &mut HashSet<String>) {
+    struct Collector<'a>(&'a mut HashSet<String>);
+    impl crate::ast::Visitor for Collector<'_> {
+        fn visit_stmt(&mut self, stmt: &Stmt) {
+            if let Stmt::Function { name, .. } = stmt {
+                self.0.insert(name.clone());
+            }
+            crate::ast::walk_stmt(self, stmt);
+        }
+    }
+    let mut collector = Collector(names);
+    for stmt in stmts {
+        crate::ast::Visitor::visit_stmt(&mut collector, stmt);
+    }
+}
+
+fn js_names(names: HashSet<String>) -> HashSet<String> {
+    names.iter().map(|name| ident(name)).collect()
+}
+
+/// Whether `index` can be used as is: one that might be negative goes
+/// through the runtime, which counts it from the end.
+fn plain_index(index: &Expr) -> bool {
+    matches!(index, Expr::Int(n) if *n >= 0) || matches!(index, Expr::Str { .. })
+}
+
+/// The variables `stmts` assigns through an index, leaving out nested
+/// functions, which have their own.
+pub(crate) fn index_assigned(stmts: &[Stmt]) -> HashSet<String> {
+    struct Finder(HashSet<String>);
+    impl crate::ast::Visitor for Finder {
+        fn visit_stmt(&mut self, stmt: &Stmt) {
+            match stmt {
+                Stmt::Function { .. } => {}
+                Stmt::Reassignment {
+                    target: AssignmentTarget::ArrayAccess { name, .. },
+                    ..
+                } => {
+                    self.0.insert(name.clone());
+                    crate::ast::walk_stmt(self, stmt);
+                }
+                _ => crate::ast::walk_stmt(self, stmt),
+            }
+        }
+    }
+    let mut finder = Finder(HashSet::new());
+    for stmt in stmts {
+        crate::ast::Visitor::visit_stmt(&mut finder, stmt);
+    }
+    finder.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    /// The script without the runtime in front of it.
+    fn program(source: &str) -> String {
+        let js = to_js(&parse(source).unwrap()).unwrap();
+        js[RUNTIME.len() + 1..].to_string()
+    }
+
+    #[test]
+    fn test_functions_return_their_last_value() {
+        assert_eq!(
+            program(
+                "fn sign(x) {\n if (x < 0) { \"-\" } else if (x == 0) { \"0\" } else { let s = \"+\"\n s }\n}\nprintln(sign(-2))"
+            ),
+            "function sign(x) {\n\
+             \x20   if (x < 0) {\n\
+             \x20       return \"-\";\n\
+             \x20   } else if (x === 0) {\n\
+             \x20       return \"0\";\n\
+             \x20   } else {\n\
+             \x20       let s = \"+\";\n\
+             \x20       return s;\n\
+             \x20   }\n\
+             }\n\
+             $ew.println(sign(-2));\n\
+             $ew.flush();\n"
+        );
+    }
+
+    #[test]
+    fn test_operators_and_values() {
+        assert_eq!(
+            program(
+                "let a = [1] + [2]\nlet n = (1 + 2) * 3 / 2\nlet s = \"ab\" * 2\nlet t = if (n > 1) { 1 } else { 2 }"
+            ),
+            "let a = $ew.add([1], [2]);\n\
+             let n = $ew.div($ew.mul($ew.add(1, 2), 3), 2);\n\
+             let s = $ew.mul(\"ab\", 2);\n\
+             let t = n > 1 ? 1 : 2;\n\
+             $ew.flush();\n"
+        );
+        assert_eq!(
+            program("let xs = [x * x for x in 0..5 if x % 2 == 0]\nlet class = -(2) ** 2"),
+            "let xs = $ew.range(0, 5).filter((x) => $ew.mod(x, 2) === 0).map((x) => $ew.mul(x, x));\n\
+             let class_ = $ew.pow(-2, 2);\n\
+             $ew.flush();\n"
+        );
+        assert_eq!(
+            program("let f = -1.5 / 2.0\nlet g = -f\nlet same = f == g"),
+            "let f = $ew.div($ew.float(-1.5), $ew.float(2));\n\
+             let g = $ew.neg(f);\n\
+             let same = $ew.eq(f, g);\n\
+             $ew.flush();\n"
+        );
+    }
+
+    #[test]
+    fn test_arrays_keep_value_semantics() {
+        let js = program("fn zero(xs) { xs[0] = 0\n xs }\nlet a = [1]\nlet b = a\nb[0] = 2");
+        assert!(js.contains("xs = $ew.copy(xs);"), "{}", js);
+        assert!(js.contains("let b = $ew.copy(a);"), "{}", js);
+        assert!(js.contains("let a = [1];"), "{}", js);
+    }
+
+    #[test]
+    fn test_match_and_loops_as_values() {
+        let js = program(
+            "enum Shape { Circle(r), Dot }\n\
+             fn area(s) { match (s) { Shape::Circle(r) => r * r * 3, _ => 0 } }\n\
+             let found = loop { break 7 }",
+        );
+        assert!(
+            js.contains("if (s.$enum === \"Shape\" && s.$variant === \"Circle\") {\n        const [r] = s.$fields;\n        return $ew.mul($ew.mul(r, r), 3);\n    } else {\n        return 0;\n    }"),
+            "{}",
+            js
+        );
+        assert!(
+            js.contains("let found;\nwhile (true) {\n    found = 7;\n    break;\n}"),
+            "{}",
+            js
+        );
+    }
+
+    #[test]
+    fn test_negative_indices_and_slices() {
+        let js = program(
+            "let xs = [1, 2, 3]\nlet i = -1\nxs[i] = xs[0] + xs[i]\nlet ys = xs[1..]\nlet t = \"abc\"\nlet s = t[..-1]",
+        );
+        assert!(
+            js.contains("$ew.set(xs, i, $ew.add(xs[0], $ew.at(xs, i)));"),
+            "{}",
+            js
+        );
+        assert!(js.contains("let ys = xs.slice(1);"), "{}", js);
+        assert!(js.contains("let s = t.slice(0, -1);"), "{}", js);
+    }
+
+    #[test]
+    fn test_let_in() {
+        let js =
+            program("fn f(n) { let h = n / 2 in h * h }\nlet x = 1\nlet y = let x = x + 1 in x");
+        assert!(
+            js.contains("{\n        let h = $ew.div(n, 2);\n        return $ew.mul(h, h);\n    }"),
+            "{}",
+            js
+        );
+        assert!(
+            js.contains("let bound1 = $ew.add(x, 1);\n{\n    let x = bound1;\n    y = x;\n}"),
+            "{}",
+            js
+        );
+    }
+
+    #[test]
+    fn test_unsupported_builtins() {
+        assert_eq!(
+            to_js(&parse("sleep(1)").unwrap()),
+            Err("sleep() has no JavaScript equivalent".to_string())
+        );
+        // A user's function of the same name is fine.
+        assert!(to_js(&parse("fn sleep(n) { n }\nsleep(1)").unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_pragmas() {
+        assert!(to_js(&parse("#pragma version 0.1\n1").unwrap()).is_ok());
+        assert_eq!(
+            to_js(&parse("#feature(strict_types)\n1").unwrap()),
+            Err("`#feature(strict_types)` is only supported when interpreting".to_string())
+        );
+    }
+}
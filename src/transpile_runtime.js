@@ -0,0 +1,276 @@
+// The runtime `ew build --target js` puts at the top of every script: the
+// ew builtins the script may call, and the operators whose meaning differs
+// from JavaScript's. Output goes through `$ew.write`, which a page can
+// replace to show it somewhere other than the console.
+const $ew = (() => {
+  let pending = "";
+
+  // Ints are plain numbers and Floats are boxed, so that `4.0 / 8.0`
+  // divides as floats and `1.0 == 1` compares different types, as in ew.
+  // `valueOf` lets `<` and the Math functions take either.
+  class Float {
+    constructor(value) {
+      this.value = value;
+    }
+    valueOf() {
+      return this.value;
+    }
+  }
+  const float = (x) => new Float(x);
+  const num = (v) => (v instanceof Float ? v.value : v);
+
+  // An Int result, which must be exact: JavaScript numbers only hold
+  // integers up to 2^53, where ew's hold 64 bits and then grow.
+  function int(n) {
+    if (!Number.isSafeInteger(n)) {
+      throw new Error("Integer overflow: compiled programs don't support big integers");
+    }
+    return n;
+  }
+
+  function typeName(v) {
+    if (v === null) return "Null";
+    if (v instanceof Float) return "Float";
+    if (Array.isArray(v)) return "Array";
+    switch (typeof v) {
+      case "number":
+        return "Int";
+      case "string":
+        return "Str";
+      case "boolean":
+        return "Bool";
+      case "function":
+        return "Function";
+    }
+    return v.$variant !== undefined ? v.$enum : "Map";
+  }
+
+  function badOperands(op, a, b) {
+    throw new Error("Cannot apply " + op + " to " + typeName(a) + " and " + typeName(b));
+  }
+
+  const isInt = (v) => typeof v === "number";
+  const bothFloat = (a, b) => a instanceof Float && b instanceof Float;
+
+  // `x` the way ew prints a Float: never in exponent form, and without a
+  // fraction when it is whole.
+  function showFloat(x) {
+    if (Number.isNaN(x)) return "NaN";
+    if (x === Infinity) return "inf";
+    if (x === -Infinity) return "-inf";
+    if (Object.is(x, -0)) return "-0";
+    const s = String(x);
+    if (!s.includes("e")) return s;
+    if (Number.isInteger(x)) return BigInt(x).toString();
+    const [mantissa, exponent] = s.split("e");
+    const sign = x < 0 ? "-" : "";
+    const digits = mantissa.replace(/[-.]/g, "");
+    return sign + "0." + "0".repeat(-Number(exponent) - 1) + digits;
+  }
+
+  const quote = (s) => JSON.stringify(s);
+  const nested = (v) => (typeof v === "string" ? quote(v) : show(v));
+
+  // A value as ew's `str` and `println` show it.
+  function show(v) {
+    if (v === undefined) return "()";
+    if (v === null) return "null";
+    if (v instanceof Float) return showFloat(v.value);
+    if (Array.isArray(v)) return "[" + v.map(nested).join(", ") + "]";
+    if (typeof v === "function") return "<fn " + v.name + ">";
+    if (typeof v === "object") {
+      if (v.$variant !== undefined) {
+        const fields = v.$fields.length ? "(" + v.$fields.map(nested).join(", ") + ")" : "";
+        return v.$enum + "::" + v.$variant + fields;
+      }
+      const entries = Object.keys(v).sort().map((k) => quote(k) + ": " + nested(v[k]));
+      return "{" + entries.join(", ") + "}";
+    }
+    return String(v);
+  }
+
+  function copy(v) {
+    if (Array.isArray(v)) return v.map(copy);
+    if (v !== null && typeof v === "object" && !(v instanceof Float)) {
+      if (v.$variant !== undefined) return { ...v, $fields: v.$fields.map(copy) };
+      return Object.fromEntries(Object.entries(v).map(([k, x]) => [k, copy(x)]));
+    }
+    return v;
+  }
+
+  // Where index `i` of `v` is, counting negative ones from the end.
+  const position = (v, i) => (typeof i === "number" && i < 0 ? v.length + i : i);
+
+  function eq(a, b) {
+    if (bothFloat(a, b)) return a.value === b.value;
+    if (Array.isArray(a) && Array.isArray(b)) {
+      return a.length === b.length && a.every((x, i) => eq(x, b[i]));
+    }
+    if (a !== null && b !== null && typeof a === "object" && typeof b === "object") {
+      const ka = Object.keys(a).sort();
+      const kb = Object.keys(b).sort();
+      return eq(ka, kb) && ka.every((k) => eq(a[k], b[k]));
+    }
+    return a === b;
+  }
+
+  const compare = (a, b) => (a < b ? -1 : a > b ? 1 : 0);
+
+  return {
+    write(text) {
+      const lines = (pending + text).split("\n");
+      pending = lines.pop();
+      for (const line of lines) console.log(line);
+    },
+    flush() {
+      if (pending !== "") console.log(pending);
+      pending = "";
+    },
+
+    // Operators.
+    float,
+    add(a, b) {
+      if (isInt(a) && isInt(b)) return int(a + b);
+      if (bothFloat(a, b)) return float(a.value + b.value);
+      if (typeof a === "string" && typeof b === "string") return a + b;
+      if (Array.isArray(a) && Array.isArray(b)) return a.concat(b);
+      badOperands("Add", a, b);
+    },
+    sub(a, b) {
+      if (isInt(a) && isInt(b)) return int(a - b);
+      if (bothFloat(a, b)) return float(a.value - b.value);
+      badOperands("Sub", a, b);
+    },
+    mul(a, b) {
+      if (isInt(a) && isInt(b)) return int(a * b);
+      if (bothFloat(a, b)) return float(a.value * b.value);
+      if (typeof a === "string" && isInt(b)) return a.repeat(b);
+      if (isInt(a) && typeof b === "string") return b.repeat(a);
+      badOperands("Mul", a, b);
+    },
+    div(a, b) {
+      if (isInt(a) && isInt(b)) {
+        if (b === 0) throw new Error("Division by zero");
+        return int(Math.trunc(a / b));
+      }
+      if (bothFloat(a, b)) {
+        if (b.value === 0) throw new Error("Division by zero");
+        return float(a.value / b.value);
+      }
+      badOperands("Div", a, b);
+    },
+    mod(a, b) {
+      if (isInt(a) && isInt(b)) {
+        if (b === 0) throw new Error("Modulo by zero");
+        return a % b;
+      }
+      if (bothFloat(a, b)) {
+        if (b.value === 0) throw new Error("Modulo by zero");
+        return float(a.value % b.value);
+      }
+      badOperands("Mod", a, b);
+    },
+    pow(a, b) {
+      if (isInt(a) && isInt(b)) return b < 0 ? float(a ** b) : int(a ** b);
+      if (a instanceof Float && (b instanceof Float || isInt(b))) return float(a.value ** num(b));
+      badOperands("Pow", a, b);
+    },
+    neg(v) {
+      if (isInt(v)) return int(-v);
+      if (v instanceof Float) return float(-v.value);
+      throw new Error("Cannot apply Neg to " + typeName(v));
+    },
+    eq,
+    ne: (a, b) => !eq(a, b),
+    // Indexing with an index that may be negative.
+    at: (v, i) => v[position(v, i)],
+    set(v, i, x) {
+      v[position(v, i)] = x;
+    },
+    range: (start, end) => Array.from({ length: Math.max(end - start, 0) }, (_, i) => start + i),
+    variant: (enumName, variant, fields) => ({ $enum: enumName, $variant: variant, $fields: fields }),
+    noMatch(v) {
+      throw new Error("No match arm for " + show(v));
+    },
+    memoize(f) {
+      const cache = new Map();
+      return (...args) => {
+        const key = JSON.stringify(args);
+        if (!cache.has(key)) cache.set(key, f(...args));
+        return cache.get(key);
+      };
+    },
+
+    // Builtins.
+    print(...values) {
+      this.write(values.map(show).join(""));
+    },
+    println(...values) {
+      this.write(values.map(show).join(" ") + "\n");
+    },
+    pprint(value) {
+      this.write(show(value) + "\n");
+    },
+    str: show,
+    repr: nested,
+    len(v) {
+      if (typeof v === "string") return [...v].length;
+      if (Array.isArray(v)) return v.length;
+      return Object.keys(v).length;
+    },
+    keys: (map) => Object.keys(map).sort(),
+    copy,
+    deep_copy: copy,
+    deep_eq: eq,
+    sort: (xs) => [...xs].sort(compare),
+    sort_by: (xs, cmp) => [...xs].sort(cmp),
+    abs: (x) => (x instanceof Float ? float(Math.abs(x.value)) : int(Math.abs(x))),
+    sqrt: (x) => float(Math.sqrt(x)),
+    floor: (x) => int(Math.floor(x)),
+    sin: (x) => float(Math.sin(x)),
+    cos: (x) => float(Math.cos(x)),
+    round(x, digits) {
+      if (isInt(x) && digits >= 0) return x;
+      const scale = 10 ** digits;
+      // Halves round away from zero, as Rust's `round` does.
+      return float((Math.sign(x) * Math.round(Math.abs(x * scale))) / scale);
+    },
+    // toFixed already takes halves away from zero, as ew does; around it
+    // are the cases where it differs: non-finite numbers, exponent form from
+    // 1e21 up, and the sign of -0.
+    to_fixed(x, digits) {
+      if (digits > 100) throw new Error("to_fixed() can show at most 100 digits, got " + digits);
+      const v = num(x);
+      if (!Number.isFinite(v)) return showFloat(v);
+      const a = Math.abs(v);
+      const text =
+        a < 1e21 ? a.toFixed(digits) : BigInt(a) + (digits > 0 ? "." + "0".repeat(digits) : "");
+      return (v < 0 || Object.is(v, -0) ? "-" : "") + text;
+    },
+    divmod(a, b) {
+      if (isInt(a) && isInt(b)) {
+        if (b === 0) throw new Error("Division by zero");
+        const q = Math.floor(a / b);
+        return [int(q), a - b * q];
+      }
+      const q = Math.floor(num(a) / num(b));
+      return [float(q), float(num(a) - num(b) * q)];
+    },
+    approx_eq: (a, b, eps) => a === b || Math.abs(a - b) <= eps,
+    is_nan: (x) => Number.isNaN(num(x)),
+    is_inf: (x) => Math.abs(x) === Infinity,
+    parse_int(s) {
+      const t = s.trim();
+      return /^[+-]?\d+$/.test(t) ? int(Number(t)) : null;
+    },
+    // What Rust's `f64::from_str` accepts, where Number() also takes hex
+    // and "Infinity" but not "inf".
+    parse_float(s) {
+      const t = s.trim();
+      const m = /^([+-]?)(?:(inf|infinity)|(nan)|(?:\d+\.?\d*|\.\d+)(?:e[+-]?\d+)?)$/i.exec(t);
+      if (!m) return null;
+      if (m[2]) return float(m[1] === "-" ? -Infinity : Infinity);
+      return float(m[3] ? NaN : Number(t));
+    },
+  };
+})();
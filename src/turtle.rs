@@ -0,0 +1,165 @@
+//! The turtle behind `forward`, `turn`, `pen_up`, `pen_down` and
+//! `save_svg`. It records the lines it draws rather than showing them, so
+//! drawings can be exported and tested without a display.
+
+/// How far the turtle may get from the origin along either axis: far past
+/// any real drawing, but close enough that the SVG's sizes stay finite.
+const MAX_COORDINATE: f64 = 1e12;
+
+/// Starts at the origin facing up, with the pen down. Coordinates follow
+/// SVG: y grows downwards.
+pub struct Turtle {
+    x: f64,
+    y: f64,
+    /// Degrees clockwise from up.
+    heading: f64,
+    pen_down: bool,
+    /// Runs of connected points, one per stretch drawn without lifting the
+    /// pen or jumping.
+    paths: Vec<Vec<(f64, f64)>>,
+}
+
+impl Default for Turtle {
+    fn default() -> Self {
+        Turtle {
+            x: 0.0,
+            y: 0.0,
+            heading: 0.0,
+            pen_down: true,
+            paths: Vec::new(),
+        }
+    }
+}
+
+impl Turtle {
+    /// Moves `distance` along the heading (backwards when negative),
+    /// drawing a line if the pen is down. Fails without moving if that
+    /// would take the turtle past `MAX_COORDINATE`.
+    pub fn forward(&mut self, distance: f64) -> Result<(), String> {
+        let from = (self.x, self.y);
+        let radians = self.heading.to_radians();
+        let to = (
+            self.x + distance * radians.sin(),
+            self.y - distance * radians.cos(),
+        );
+        if !(to.0.abs() <= MAX_COORDINATE && to.1.abs() <= MAX_COORDINATE) {
+            return Err(format!(
+                "forward({}) would take the turtle more than {} from the origin",
+                distance, MAX_COORDINATE
+            ));
+        }
+        (self.x, self.y) = to;
+        if !self.pen_down {
+            return Ok(());
+        }
+        match self.paths.last_mut() {
+            Some(path) if path.last() == Some(&from) => path.push(to),
+            _ => self.paths.push(vec![from, to]),
+        }
+        Ok(())
+    }
+
+    /// Turns clockwise by `degrees`.
+    pub fn turn(&mut self, degrees: f64) {
+        self.heading = (self.heading + degrees).rem_euclid(360.0);
+    }
+
+    pub fn set_pen(&mut self, down: bool) {
+        self.pen_down = down;
+    }
+
+    /// An SVG document of everything drawn, framed with a small margin.
+    pub fn to_svg(&self) -> String {
+        let points = || self.paths.iter().flatten();
+        let (min_x, max_x, min_y, max_y) = points().fold(
+            (0.0f64, 0.0f64, 0.0f64, 0.0f64),
+            |(min_x, max_x, min_y, max_y), &(x, y)| {
+                (min_x.min(x), max_x.max(x), min_y.min(y), max_y.max(y))
+            },
+        );
+        let margin = 10.0;
+        let mut svg = format!(
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"{} {} {} {}\">\n",
+            num(min_x - margin),
+            num(min_y - margin),
+            num(max_x - min_x + 2.0 * margin),
+            num(max_y - min_y + 2.0 * margin)
+        );
+        for path in &self.paths {
+            let d: Vec<String> = path
+                .iter()
+                .enumerate()
+                .map(|(i, &(x, y))| {
+                    format!("{} {} {}", if i == 0 { "M" } else { "L" }, num(x), num(y))
+                })
+                .collect();
+            svg.push_str(&format!(
+                "  <path d=\"{}\" fill=\"none\" stroke=\"black\"/>\n",
+                d.join(" ")
+            ));
+        }
+        svg.push_str("</svg>\n");
+        svg
+    }
+}
+
+/// `n` to two decimal places, without trailing zeros or a negative zero.
+fn num(n: f64) -> String {
+    let rounded = (n * 100.0).round() / 100.0;
+    if rounded == 0.0 {
+        "0".to_string()
+    } else {
+        rounded.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_square() {
+        let mut turtle = Turtle::default();
+        for _ in 0..4 {
+            turtle.forward(50.0).unwrap();
+            turtle.turn(90.0);
+        }
+        assert_eq!(
+            turtle.to_svg(),
+            "<svg xmlns=\"http://www.w3.org/2000/svg\" viewBox=\"-10 -60 70 70\">\n  \
+             <path d=\"M 0 0 L 0 -50 L 50 -50 L 50 0 L 0 0\" fill=\"none\" stroke=\"black\"/>\n\
+             </svg>\n"
+        );
+    }
+
+    #[test]
+    fn test_pen_up_starts_a_new_path() {
+        let mut turtle = Turtle::default();
+        turtle.turn(-270.0);
+        turtle.forward(10.0).unwrap();
+        turtle.set_pen(false);
+        turtle.forward(5.0).unwrap();
+        turtle.set_pen(true);
+        turtle.forward(-20.0).unwrap();
+        let svg = turtle.to_svg();
+        assert!(svg.contains("d=\"M 0 0 L 10 0\""), "{}", svg);
+        assert!(svg.contains("d=\"M 15 0 L -5 0\""), "{}", svg);
+    }
+
+    #[test]
+    fn test_forward_out_of_range() {
+        let mut turtle = Turtle::default();
+        turtle.forward(MAX_COORDINATE).unwrap();
+        assert!(turtle.forward(1.0).is_err());
+        assert!(turtle.forward(f64::MAX).is_err());
+        turtle.turn(180.0);
+        turtle.forward(MAX_COORDINATE).unwrap();
+        // The failed moves left the turtle where it was.
+        let svg = turtle.to_svg();
+        assert!(
+            svg.contains("d=\"M 0 0 L 0 -1000000000000 L 0 0\""),
+            "{}",
+            svg
+        );
+    }
+}
@@ -0,0 +1,77 @@
+//! Language versions and the feature gates they are made of. A script opts
+//! into breaking changes with `#pragma version 0.2`, taking every feature of
+//! that version, or one at a time with `#feature(strict_types)`; scripts
+//! without either keep the semantics of `DEFAULT_VERSION`.
+
+use crate::ast::Pragma;
+use crate::formatter;
+
+/// A change to the language's semantics that scripts must ask for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Feature {
+    /// `TypeChecking::Strict`: non-Bool conditions are errors whatever the
+    /// truthiness.
+    StrictTypes,
+    /// `Division::Float`: `7 / 2` is `3.5`.
+    FloatDivision,
+}
+
+impl Feature {
+    pub const ALL: [Feature; 2] = [Feature::StrictTypes, Feature::FloatDivision];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Feature::StrictTypes => "strict_types",
+            Feature::FloatDivision => "float_division",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Feature, String> {
+        Feature::ALL
+            .into_iter()
+            .find(|feature| feature.name() == name)
+            .ok_or_else(|| {
+                let known: Vec<_> = Feature::ALL.iter().map(|f| f.name()).collect();
+                format!(
+                    "Unknown feature '{}'; the known features are {}",
+                    name,
+                    known.join(", ")
+                )
+            })
+    }
+}
+
+/// Every language version, oldest first, with the features it turns on.
+pub const VERSIONS: &[(&str, &[Feature])] = &[("0.1", &[]), ("0.2", &[Feature::FloatDivision])];
+
+/// The version of scripts without `#pragma version`.
+pub const DEFAULT_VERSION: &str = "0.1";
+
+/// Fails for a pragma that turns on any feature, for the compilers, which
+/// only implement the default semantics.
+pub(crate) fn require_default(pragma: &Pragma) -> Result<(), String> {
+    if pragma.features()?.is_empty() {
+        Ok(())
+    } else {
+        Err(format!(
+            "`{}` is only supported when interpreting",
+            formatter::pragma(pragma)
+        ))
+    }
+}
+
+/// The features `version` turns on.
+pub fn features(version: &str) -> Result<&'static [Feature], String> {
+    VERSIONS
+        .iter()
+        .find(|(v, _)| *v == version)
+        .map(|(_, features)| *features)
+        .ok_or_else(|| {
+            let known: Vec<_> = VERSIONS.iter().map(|(v, _)| *v).collect();
+            format!(
+                "Unknown language version {}; the known versions are {}",
+                version,
+                known.join(", ")
+            )
+        })
+}
@@ -0,0 +1,1304 @@
+//! `ew build --target wasm`: compiles a script's top-level functions to a
+//! WebAssembly module that exports them by name. The rest of the script
+//! doesn't run; the module is a library for whoever instantiates it.
+//!
+//! Only functions over numbers and booleans can be compiled, since those are
+//! the values WebAssembly has: an Int is an i64 (a BigInt in JavaScript), a
+//! Float an f64 and a Bool an i32. Types come from how values are used; ew
+//! never converts between Int and Float on its own, so the uses of a value
+//! agree on one type or the function isn't compiled. A value nothing pins
+//! down is an Int. A function that can't be compiled, say for using strings
+//! or arrays, is left out with a note saying why, and so are its callers.
+//!
+//! Where the interpreter would fail, the module traps instead: on division
+//! by zero, and on integer overflow, which the interpreter would have
+//! promoted to a big integer.
+
+use std::collections::HashMap;
+
+use crate::ast::{AssignmentTarget, BinaryOp, Expr, Stmt, UnaryOp};
+use crate::version;
+
+/// Compiles the top-level functions of `program` to a WebAssembly module,
+/// returning it with a note for each function that had to be left out.
+pub fn to_wasm(program: &[Stmt]) -> Result<(Vec<u8>, Vec<String>), String> {
+    let mut functions: Vec<Function> = Vec::new();
+    let mut notes = Vec::new();
+    let mut redefined = Vec::new();
+    for stmt in program {
+        if let Stmt::Pragma(pragma) = stmt {
+            version::require_default(pragma)?;
+        }
+        if let Stmt::Function {
+            name,
+            params,
+            body,
+            decorators,
+            ..
+        } = stmt
+        {
+            if !decorators.is_empty() {
+                notes.push(format!("{}() isn't exported: it has decorators", name));
+            } else if functions.iter().any(|f| f.name == name) {
+                redefined.push(name.as_str());
+            } else {
+                functions.push(Function { name, params, body });
+            }
+        }
+    }
+    for name in redefined {
+        if let Some(i) = functions.iter().position(|f| f.name == name) {
+            functions.remove(i);
+            notes.push(format!(
+                "{}() isn't exported: it is defined more than once",
+                name
+            ));
+        }
+    }
+    if functions.is_empty() && notes.is_empty() {
+        return Err("The script has no top-level functions to export".to_string());
+    }
+
+    // Leaving a function out can leave out its callers, so compile until
+    // everything left compiles.
+    loop {
+        match compile(&functions) {
+            Ok(_) if functions.is_empty() => {
+                return Err(format!(
+                    "No function could be compiled to WebAssembly:\n{}",
+                    notes.join("\n")
+                ));
+            }
+            Ok(module) => return Ok((module, notes)),
+            Err((i, reason)) => {
+                let name = functions.remove(i).name;
+                notes.push(format!("{}() isn't exported: {}", name, reason));
+            }
+        }
+    }
+}
+
+struct Function<'a> {
+    name: &'a str,
+    params: &'a [String],
+    body: &'a [Stmt],
+}
+
+/// The parameter and result types of each function, by name, with its
+/// index.
+type Signatures<'a> = HashMap<&'a str, (u32, Vec<Var>, Var)>;
+
+/// Compiles `functions`, or says which of them can't be compiled and why.
+fn compile(functions: &[Function]) -> Result<Vec<u8>, (usize, String)> {
+    let mut types = Types::default();
+    let mut signatures = Signatures::new();
+    for (i, f) in functions.iter().enumerate() {
+        let params = f.params.iter().map(|_| types.fresh()).collect();
+        signatures.insert(f.name, (i as u32, params, types.fresh()));
+    }
+
+    let mut infos = Vec::new();
+    for (i, f) in functions.iter().enumerate() {
+        let (_, params, ret) = &signatures[f.name];
+        let mut checker = Checker {
+            types: &mut types,
+            signatures: &signatures,
+            info: Info::default(),
+            scopes: vec![HashMap::new()],
+            loops: Vec::new(),
+            ret: *ret,
+        };
+        for (param, ty) in f.params.iter().zip(params) {
+            let local = checker.local(*ty);
+            checker.scopes[0].insert(param, local);
+        }
+        checker
+            .block(f.body, true)
+            .and_then(|body| checker.types.unify(body, *ret))
+            .map_err(|e| (i, e))?;
+        infos.push(checker.info);
+    }
+
+    let mut module = Module::new(functions.len());
+    for (i, (f, info)) in functions.iter().zip(&infos).enumerate() {
+        let (_, params, ret) = &signatures[f.name];
+        let params: Vec<Ty> = params.iter().map(|p| types.resolve(*p)).collect();
+        if params.contains(&Ty::Unit) {
+            return Err((i, "a parameter is always ()".to_string()));
+        }
+        let ret = types.resolve(*ret);
+        let mut generator = Generator {
+            types: &mut types,
+            signatures: &signatures,
+            info,
+            module: &mut module,
+            code: Code::default(),
+            slots: Vec::new(),
+            depth: 0,
+            loops: Vec::new(),
+        };
+        let body = generator
+            .function(params.len(), f.body)
+            .map_err(|e| (i, e))?;
+        module.functions.push((params, ret, body));
+        module.exports.push(f.name.to_string());
+    }
+    Ok(module.finish())
+}
+
+/// The WebAssembly types of ew values.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Ty {
+    Int,
+    Float,
+    Bool,
+    Unit,
+}
+
+impl Ty {
+    /// The value type's encoding, or None for `()`, which takes no space.
+    fn encoding(self) -> Option<u8> {
+        match self {
+            Ty::Int => Some(I64),
+            Ty::Float => Some(F64),
+            Ty::Bool => Some(I32),
+            Ty::Unit => None,
+        }
+    }
+
+    /// The block type of a block with a value of this type.
+    fn block_type(self) -> u8 {
+        self.encoding().unwrap_or(EMPTY)
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Ty::Int => "Int",
+            Ty::Float => "Float",
+            Ty::Bool => "Bool",
+            Ty::Unit => "()",
+        }
+    }
+}
+
+/// A type variable.
+type Var = usize;
+
+/// Type variables, unified as the functions are checked.
+#[derive(Default)]
+struct Types {
+    parent: Vec<Var>,
+    known: Vec<Option<Ty>>,
+    /// Whether the variable must be an Int or a Float.
+    numeric: Vec<bool>,
+}
+
+impl Types {
+    fn fresh(&mut self) -> Var {
+        self.parent.push(self.parent.len());
+        self.known.push(None);
+        self.numeric.push(false);
+        self.parent.len() - 1
+    }
+
+    fn of(&mut self, ty: Ty) -> Var {
+        let var = self.fresh();
+        self.known[var] = Some(ty);
+        var
+    }
+
+    fn find(&mut self, var: Var) -> Var {
+        let parent = self.parent[var];
+        if parent == var {
+            return var;
+        }
+        let root = self.find(parent);
+        self.parent[var] = root;
+        root
+    }
+
+    fn unify(&mut self, a: Var, b: Var) -> Result<(), String> {
+        let (a, b) = (self.find(a), self.find(b));
+        if a == b {
+            return Ok(());
+        }
+        let known = match (self.known[a], self.known[b]) {
+            (Some(x), Some(y)) if x != y => {
+                return Err(format!(
+                    "a value would have to be both {} and {}",
+                    x.name(),
+                    y.name()
+                ));
+            }
+            (x, y) => x.or(y),
+        };
+        self.parent[a] = b;
+        self.known[b] = known;
+        self.numeric[b] |= self.numeric[a];
+        self.check_numeric(b)
+    }
+
+    fn require(&mut self, var: Var, ty: Ty) -> Result<(), String> {
+        let known = self.of(ty);
+        self.unify(var, known)
+    }
+
+    fn require_numeric(&mut self, var: Var) -> Result<(), String> {
+        let root = self.find(var);
+        self.numeric[root] = true;
+        self.check_numeric(root)
+    }
+
+    fn check_numeric(&self, root: Var) -> Result<(), String> {
+        match self.known[root] {
+            Some(ty @ (Ty::Bool | Ty::Unit)) if self.numeric[root] => Err(format!(
+                "a value would have to be both {} and a number",
+                ty.name()
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// The type `var` ended up with.
+    fn resolve(&mut self, var: Var) -> Ty {
+        let root = self.find(var);
+        self.known[root].unwrap_or(Ty::Int)
+    }
+}
+
+/// What checking a function found out, for generating its code.
+#[derive(Default)]
+struct Info {
+    /// The type of each local, parameters first.
+    locals: Vec<Var>,
+    exprs: HashMap<*const Expr, Var>,
+    /// The local each variable reads or `let ... in` binds, and the first of
+    /// the three locals of each `for` loop: the variable, the counter and
+    /// the end.
+    uses: HashMap<*const Expr, usize>,
+    /// The local each `let` or assignment sets.
+    sets: HashMap<*const Stmt, usize>,
+}
+
+/// Infers the types in one function.
+struct Checker<'a, 'f> {
+    types: &'a mut Types,
+    signatures: &'a Signatures<'f>,
+    info: Info,
+    scopes: Vec<HashMap<&'f str, usize>>,
+    /// The label and type of each enclosing loop, innermost last.
+    loops: Vec<(Option<&'f str>, Var)>,
+    ret: Var,
+}
+
+impl<'f> Checker<'_, 'f> {
+    fn local(&mut self, ty: Var) -> usize {
+        self.info.locals.push(ty);
+        self.info.locals.len() - 1
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+    }
+
+    /// The type of a block. When its value isn't `used`, its last statement
+    /// is checked like any other.
+    fn block(&mut self, stmts: &'f [Stmt], used: bool) -> Result<Var, String> {
+        self.scopes.push(HashMap::new());
+        let mut ty = self.types.of(Ty::Unit);
+        for (i, stmt) in stmts.iter().enumerate() {
+            ty = self.stmt(stmt, used && i + 1 == stmts.len())?;
+        }
+        self.scopes.pop();
+        Ok(ty)
+    }
+
+    fn stmt(&mut self, stmt: &'f Stmt, used: bool) -> Result<Var, String> {
+        match stmt {
+            Stmt::Function { .. } => return Err("it defines a nested function".to_string()),
+            Stmt::Enum { .. } => return Err("it defines an enum".to_string()),
+            Stmt::Pragma(_) => return Err("it contains a pragma".to_string()),
+            Stmt::Return(value) => {
+                let ty = self.expr(value, true)?;
+                self.types.unify(ty, self.ret)?;
+                // Nothing follows a `return`, so it fits any type.
+                return Ok(self.types.fresh());
+            }
+            Stmt::Break(value) => {
+                let (_, ty) = *self
+                    .loops
+                    .last()
+                    .ok_or("it uses `break` outside of a loop")?;
+                let value = match value {
+                    Some(value) => self.expr(value, true)?,
+                    None => self.types.of(Ty::Unit),
+                };
+                self.types.unify(value, ty)?;
+                return Ok(self.types.fresh());
+            }
+            Stmt::BreakLabel(label) => {
+                let (_, ty) = *self
+                    .loops
+                    .iter()
+                    .rev()
+                    .find(|(l, _)| *l == Some(label.as_str()))
+                    .ok_or_else(|| format!("no enclosing loop is labeled {}", label))?;
+                self.types.require(ty, Ty::Unit)?;
+                return Ok(self.types.fresh());
+            }
+            Stmt::Assignment { name, value } => {
+                let ty = self.expr(value, true)?;
+                let local = self.local(ty);
+                self.scopes.last_mut().unwrap().insert(name, local);
+                self.info.sets.insert(stmt, local);
+            }
+            Stmt::Reassignment { target, value } => {
+                let AssignmentTarget::Ident(name) = target else {
+                    return Err("it assigns through an index".to_string());
+                };
+                let local = self
+                    .lookup(name)
+                    .ok_or_else(|| format!("it assigns to {}, which isn't declared", name))?;
+                let ty = self.expr(value, true)?;
+                self.types.unify(ty, self.info.locals[local])?;
+                self.info.sets.insert(stmt, local);
+            }
+            Stmt::Expr(expr) => return self.expr(expr, used),
+        }
+        Ok(self.types.of(Ty::Unit))
+    }
+
+    fn expr(&mut self, expr: &'f Expr, used: bool) -> Result<Var, String> {
+        let ty = self.infer(expr, used)?;
+        self.info.exprs.insert(expr, ty);
+        Ok(ty)
+    }
+
+    fn infer(&mut self, expr: &'f Expr, used: bool) -> Result<Var, String> {
+        let unsupported = match expr {
+            Expr::BigInt(_) => "it uses an integer too big for 64 bits",
+            Expr::Str { .. } => "it uses a string",
+            Expr::Null => "it uses null",
+            Expr::Array { .. } | Expr::Comprehension { .. } => "it uses an array",
+            Expr::ArrayAccess { .. } | Expr::Slice { .. } => "it indexes into a value",
+            Expr::Spread(_) => "it spreads arguments",
+            Expr::Variant { .. } | Expr::Match { .. } => "it uses enums or match",
+            Expr::IfLet { .. } | Expr::WhileLet { .. } => "it tests for null",
+            _ => "",
+        };
+        if !unsupported.is_empty() {
+            return Err(unsupported.to_string());
+        }
+
+        Ok(match expr {
+            Expr::Int(_) => self.types.of(Ty::Int),
+            Expr::Float(_) => self.types.of(Ty::Float),
+            Expr::Bool(_) => self.types.of(Ty::Bool),
+            Expr::Var(name) => match self.lookup(name) {
+                Some(local) => {
+                    self.info.uses.insert(expr, local);
+                    self.info.locals[local]
+                }
+                None if name == "NAN" || name == "INF" => self.types.of(Ty::Float),
+                None => {
+                    return Err(format!(
+                        "it uses {}, which isn't one of its variables",
+                        name
+                    ));
+                }
+            },
+            Expr::Unary { op, expr, .. } => {
+                let ty = self.expr(expr, true)?;
+                match op {
+                    UnaryOp::Neg => self.types.require_numeric(ty)?,
+                    UnaryOp::Not => self.types.require(ty, Ty::Bool)?,
+                }
+                ty
+            }
+            Expr::Binary { op, lhs, rhs, .. } => {
+                let (l, r) = (self.expr(lhs, true)?, self.expr(rhs, true)?);
+                match op {
+                    BinaryOp::And | BinaryOp::Or => {
+                        self.types.require(l, Ty::Bool)?;
+                        self.types.require(r, Ty::Bool)?;
+                        l
+                    }
+                    BinaryOp::Eq | BinaryOp::Ne => {
+                        self.types.unify(l, r)?;
+                        self.types.of(Ty::Bool)
+                    }
+                    BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => {
+                        self.types.unify(l, r)?;
+                        self.types.require_numeric(l)?;
+                        self.types.of(Ty::Bool)
+                    }
+                    _ => {
+                        self.types.unify(l, r)?;
+                        self.types.require_numeric(l)?;
+                        l
+                    }
+                }
+            }
+            Expr::Call { name, args, .. } => self.call(name, args)?,
+            Expr::If {
+                cond, then, else_, ..
+            } => {
+                let cond = self.expr(cond, true)?;
+                self.types.require(cond, Ty::Bool)?;
+                let (then, else_) = (self.block(then, used)?, self.block(else_, used)?);
+                if !used {
+                    return Ok(self.types.of(Ty::Unit));
+                }
+                self.types.unify(then, else_)?;
+                then
+            }
+            Expr::While { .. } | Expr::For { .. } | Expr::Loop(_) => self.looping(expr, None)?,
+            Expr::Labeled { label, body } => {
+                let ty = self.looping(body, Some(label))?;
+                self.info.exprs.insert(&**body, ty);
+                ty
+            }
+            Expr::Block(stmts) => self.block(stmts, used)?,
+            Expr::LetIn { name, value, body } => {
+                let ty = self.expr(value, true)?;
+                let local = self.local(ty);
+                self.info.uses.insert(expr, local);
+                self.scopes.push(HashMap::from([(name.as_str(), local)]));
+                let ty = self.expr(body, used);
+                self.scopes.pop();
+                ty?
+            }
+            _ => unreachable!("rejected above"),
+        })
+    }
+
+    fn call(&mut self, name: &'f str, args: &'f [Expr]) -> Result<Var, String> {
+        let mut types = Vec::new();
+        for arg in args {
+            types.push(self.expr(arg, true)?);
+        }
+        if self.lookup(name).is_some() {
+            return Err(format!("it calls {}, a function in a variable", name));
+        }
+        if let Some((_, params, ret)) = self.signatures.get(name) {
+            if params.len() != args.len() {
+                return Err(format!(
+                    "it calls {}() with {} arguments instead of {}",
+                    name,
+                    args.len(),
+                    params.len()
+                ));
+            }
+            for (arg, param) in types.iter().zip(params) {
+                self.types.unify(*arg, *param)?;
+            }
+            return Ok(*ret);
+        }
+        if !matches!(name, "sqrt" | "abs" | "floor") {
+            return Err(format!("it calls {}(), which can't be compiled", name));
+        }
+        let [arg] = types[..] else {
+            return Err(format!("it calls {}() with {} arguments", name, args.len()));
+        };
+        self.types.require_numeric(arg)?;
+        Ok(match name {
+            "sqrt" => self.types.of(Ty::Float),
+            "floor" => self.types.of(Ty::Int),
+            _ => arg,
+        })
+    }
+
+    /// The type of a loop: that of its `break` values, or `()` for a loop
+    /// that can also end by itself.
+    fn looping(&mut self, expr: &'f Expr, label: Option<&'f str>) -> Result<Var, String> {
+        let ty = self.types.fresh();
+        let (body, scope) = match expr {
+            Expr::While { cond, body, .. } => {
+                let cond = self.expr(cond, true)?;
+                self.types.require(cond, Ty::Bool)?;
+                self.types.require(ty, Ty::Unit)?;
+                (body, HashMap::new())
+            }
+            Expr::For {
+                var,
+                start,
+                end,
+                body,
+            } => {
+                for bound in [start, end] {
+                    let bound = self.expr(bound, true)?;
+                    self.types.require(bound, Ty::Int)?;
+                }
+                self.types.require(ty, Ty::Unit)?;
+                let int = self.types.of(Ty::Int);
+                let local = self.local(int);
+                self.local(int);
+                self.local(int);
+                self.info.uses.insert(expr, local);
+                (body, HashMap::from([(var.as_str(), local)]))
+            }
+            Expr::Loop(body) => (body, HashMap::new()),
+            _ => return Err("it labels something other than a loop".to_string()),
+        };
+        self.loops.push((label, ty));
+        self.scopes.push(scope);
+        self.block(body, false)?;
+        self.scopes.pop();
+        self.loops.pop();
+        Ok(ty)
+    }
+}
+
+/// Generates the code of one checked function.
+struct Generator<'a, 'f> {
+    types: &'a mut Types,
+    signatures: &'a Signatures<'f>,
+    info: &'a Info,
+    module: &'a mut Module,
+    code: Code,
+    /// The WebAssembly local of each local, if it isn't `()`.
+    slots: Vec<Option<u32>>,
+    /// How many blocks are open.
+    depth: u32,
+    /// The label of each enclosing loop and the depth of the block it
+    /// leaves, innermost last.
+    loops: Vec<(Option<&'f str>, u32)>,
+}
+
+impl<'f> Generator<'_, 'f> {
+    /// The function's encoded body, locals first.
+    fn function(&mut self, params: usize, body: &'f [Stmt]) -> Result<Vec<u8>, String> {
+        let mut declared = Vec::new();
+        for (i, ty) in self.info.locals.iter().enumerate() {
+            let encoding = self.types.resolve(*ty).encoding();
+            self.slots.push(match encoding {
+                _ if i < params => Some(i as u32),
+                Some(encoding) => {
+                    declared.push(encoding);
+                    Some((params + declared.len() - 1) as u32)
+                }
+                None => None,
+            });
+        }
+        self.block(body, true)?;
+
+        let mut out = Vec::new();
+        uleb(&mut out, declared.len() as u64);
+        for encoding in declared {
+            out.extend([1, encoding]);
+        }
+        out.extend(&self.code.0);
+        out.push(END);
+        Ok(out)
+    }
+
+    fn ty(&mut self, expr: &Expr) -> Ty {
+        self.types.resolve(self.info.exprs[&(expr as *const Expr)])
+    }
+
+    /// Opens a block, loop or if, whose value has type `ty`.
+    fn open(&mut self, op: u8, ty: Ty) {
+        self.code.op(op);
+        self.code.op(ty.block_type());
+        self.depth += 1;
+    }
+
+    fn close(&mut self) {
+        self.code.op(END);
+        self.depth -= 1;
+    }
+
+    /// A block's statements, leaving the last one's value if `keep`.
+    fn block(&mut self, stmts: &'f [Stmt], keep: bool) -> Result<(), String> {
+        for (i, stmt) in stmts.iter().enumerate() {
+            self.stmt(stmt, keep && i + 1 == stmts.len())?;
+        }
+        Ok(())
+    }
+
+    fn stmt(&mut self, stmt: &'f Stmt, keep: bool) -> Result<(), String> {
+        match stmt {
+            Stmt::Return(value) => {
+                self.expr(value)?;
+                self.code.op(RETURN);
+            }
+            Stmt::Break(value) => {
+                if let Some(value) = value {
+                    self.expr(value)?;
+                }
+                let (_, exit) = self.loops.last().unwrap();
+                self.code.op_u(BR, (self.depth - exit) as u64);
+            }
+            Stmt::BreakLabel(label) => {
+                let (_, exit) = self
+                    .loops
+                    .iter()
+                    .rev()
+                    .find(|(l, _)| *l == Some(label.as_str()))
+                    .unwrap();
+                self.code.op_u(BR, (self.depth - exit) as u64);
+            }
+            Stmt::Assignment { value, .. } | Stmt::Reassignment { value, .. } => {
+                self.expr(value)?;
+                let local = self.info.sets[&(stmt as *const Stmt)];
+                if let Some(slot) = self.slots[local] {
+                    self.code.op_u(LOCAL_SET, slot as u64);
+                }
+            }
+            Stmt::Expr(expr) => {
+                self.expr(expr)?;
+                if !keep && self.ty(expr) != Ty::Unit {
+                    self.code.op(DROP);
+                }
+            }
+            Stmt::Function { .. } | Stmt::Enum { .. } | Stmt::Pragma(_) => {
+                unreachable!("rejected when checking")
+            }
+        }
+        Ok(())
+    }
+
+    /// Leaves the value of `expr` on the stack, unless it is `()`.
+    fn expr(&mut self, expr: &'f Expr) -> Result<(), String> {
+        match expr {
+            Expr::Int(n) => self.code.i64_const(*n),
+            Expr::Float(f) => self.code.f64_const(*f),
+            Expr::Bool(b) => self.code.i32_const(*b as i32),
+            Expr::Var(name) => match self.info.uses.get(&(expr as *const Expr)) {
+                Some(local) => {
+                    if let Some(slot) = self.slots[*local] {
+                        self.code.op_u(LOCAL_GET, slot as u64);
+                    }
+                }
+                None if name == "NAN" => self.code.f64_const(f64::NAN),
+                None => self.code.f64_const(f64::INFINITY),
+            },
+            Expr::Unary { op, expr, .. } => {
+                let ty = self.ty(expr);
+                match (op, ty) {
+                    (UnaryOp::Neg, Ty::Int) => {
+                        self.code.i64_const(0);
+                        self.expr(expr)?;
+                        self.call_helper(Helper::Sub);
+                    }
+                    (UnaryOp::Neg, _) => {
+                        self.expr(expr)?;
+                        self.code.op(F64_NEG);
+                    }
+                    (UnaryOp::Not, _) => {
+                        self.expr(expr)?;
+                        self.code.op(I32_EQZ);
+                    }
+                }
+            }
+            Expr::Binary { op, lhs, rhs, .. } => {
+                let ty = self.ty(lhs);
+                self.expr(lhs)?;
+                self.expr(rhs)?;
+                self.binary(*op, ty)?;
+            }
+            Expr::Call { name, args, .. } => {
+                for arg in args {
+                    self.expr(arg)?;
+                }
+                if let Some((index, ..)) = self.signatures.get(name.as_str()) {
+                    self.code.op_u(CALL, *index as u64);
+                    return Ok(());
+                }
+                match (name.as_str(), self.ty(&args[0])) {
+                    ("sqrt", ty) => {
+                        if ty == Ty::Int {
+                            self.code.op(F64_CONVERT_I64_S);
+                        }
+                        self.code.op(F64_SQRT);
+                    }
+                    ("abs", Ty::Int) => self.call_helper(Helper::Abs),
+                    ("abs", _) => self.code.op(F64_ABS),
+                    ("floor", Ty::Int) => {}
+                    // Rounded down, then saturated, as `as i64` does.
+                    (_, _) => {
+                        self.code.op(F64_FLOOR);
+                        self.code.0.extend(I64_TRUNC_SAT_F64_S);
+                    }
+                }
+            }
+            Expr::If {
+                cond, then, else_, ..
+            } => {
+                let ty = self.ty(expr);
+                self.expr(cond)?;
+                self.open(IF, ty);
+                self.block(then, ty != Ty::Unit)?;
+                if !else_.is_empty() {
+                    self.code.op(ELSE);
+                    self.block(else_, ty != Ty::Unit)?;
+                }
+                self.close();
+            }
+            Expr::While { .. } | Expr::For { .. } | Expr::Loop(_) => self.looping(expr, None)?,
+            Expr::Labeled { label, body } => self.looping(body, Some(label))?,
+            Expr::Block(stmts) => self.block(stmts, true)?,
+            Expr::LetIn { value, body, .. } => {
+                self.expr(value)?;
+                if let Some(slot) = self.slots[self.info.uses[&(expr as *const Expr)]] {
+                    self.code.op_u(LOCAL_SET, slot as u64);
+                }
+                self.expr(body)?;
+            }
+            _ => unreachable!("rejected when checking"),
+        }
+        Ok(())
+    }
+
+    fn binary(&mut self, op: BinaryOp, ty: Ty) -> Result<(), String> {
+        let helper = match (op, ty) {
+            (BinaryOp::Add, Ty::Int) => Some(Helper::Add),
+            (BinaryOp::Sub, Ty::Int) => Some(Helper::Sub),
+            (BinaryOp::Mul, Ty::Int) => Some(Helper::Mul),
+            (BinaryOp::Pow, Ty::Int) => Some(Helper::Pow),
+            (BinaryOp::Div, Ty::Float) => Some(Helper::FloatDiv),
+            _ => None,
+        };
+        if let Some(helper) = helper {
+            self.call_helper(helper);
+            return Ok(());
+        }
+        let code = match (op, ty) {
+            // Both trap on a zero divisor, and `/` on overflow.
+            (BinaryOp::Div, Ty::Int) => I64_DIV_S,
+            (BinaryOp::Mod, Ty::Int) => I64_REM_S,
+            (BinaryOp::Mod, _) => return Err("it takes the remainder of floats".to_string()),
+            (BinaryOp::Pow, _) => return Err("it raises a float to a power".to_string()),
+            (BinaryOp::Add, _) => F64_ADD,
+            (BinaryOp::Div, _) => unreachable!("checked to be numbers"),
+            (BinaryOp::Sub, _) => F64_SUB,
+            (BinaryOp::Mul, _) => F64_MUL,
+            (BinaryOp::And, _) => I32_AND,
+            (BinaryOp::Or, _) => I32_OR,
+            (_, Ty::Unit) => return Err("it compares () with ()".to_string()),
+            (BinaryOp::Eq, Ty::Bool) => I32_EQ,
+            (BinaryOp::Ne, Ty::Bool) => I32_NE,
+            (BinaryOp::Eq, Ty::Int) => I64_EQ,
+            (BinaryOp::Ne, Ty::Int) => I64_NE,
+            (BinaryOp::Lt, Ty::Int) => I64_LT_S,
+            (BinaryOp::Le, Ty::Int) => I64_LE_S,
+            (BinaryOp::Gt, Ty::Int) => I64_GT_S,
+            (BinaryOp::Ge, Ty::Int) => I64_GE_S,
+            (BinaryOp::Eq, _) => F64_EQ,
+            (BinaryOp::Ne, _) => F64_NE,
+            (BinaryOp::Lt, _) => F64_LT,
+            (BinaryOp::Le, _) => F64_LE,
+            (BinaryOp::Gt, _) => F64_GT,
+            (BinaryOp::Ge, _) => F64_GE,
+        };
+        self.code.op(code);
+        Ok(())
+    }
+
+    fn call_helper(&mut self, helper: Helper) {
+        let index = self.module.helper(helper);
+        self.code.op_u(CALL, index as u64);
+    }
+
+    /// A loop in a block that `break` leaves, with the loop's value.
+    fn looping(&mut self, expr: &'f Expr, label: Option<&'f str>) -> Result<(), String> {
+        let ty = self.ty(expr);
+        let body = match expr {
+            Expr::While { cond, body, .. } => {
+                self.open(BLOCK, ty);
+                self.open(LOOP, Ty::Unit);
+                self.expr(cond)?;
+                self.code.op(I32_EQZ);
+                self.code.op_u(BR_IF, 1);
+                body
+            }
+            Expr::For {
+                start, end, body, ..
+            } => {
+                let var = self.info.uses[&(expr as *const Expr)];
+                let [var, counter, end_] = [0, 1, 2].map(|i| self.slots[var + i].unwrap() as u64);
+                self.expr(start)?;
+                self.code.op_u(LOCAL_SET, counter);
+                self.expr(end)?;
+                self.code.op_u(LOCAL_SET, end_);
+                self.open(BLOCK, ty);
+                self.open(LOOP, Ty::Unit);
+                self.code.op_u(LOCAL_GET, counter);
+                self.code.op_u(LOCAL_GET, end_);
+                self.code.op(I64_GE_S);
+                self.code.op_u(BR_IF, 1);
+                self.code.op_u(LOCAL_GET, counter);
+                self.code.op_u(LOCAL_SET, var);
+                // The counter is below the end, so this can't overflow.
+                self.code.op_u(LOCAL_GET, counter);
+                self.code.i64_const(1);
+                self.code.op(I64_ADD);
+                self.code.op_u(LOCAL_SET, counter);
+                body
+            }
+            Expr::Loop(body) => {
+                self.open(BLOCK, ty);
+                self.open(LOOP, Ty::Unit);
+                body
+            }
+            _ => unreachable!("rejected when checking"),
+        };
+        self.loops.push((label, self.depth - 1));
+        self.block(body, false)?;
+        self.loops.pop();
+        self.code.op_u(BR, 0);
+        self.close();
+        if matches!(expr, Expr::Loop(_)) {
+            // Only a `break` leaves the loop, never the end of the body.
+            self.code.op(UNREACHABLE);
+        }
+        self.close();
+        Ok(())
+    }
+}
+
+/// Functions the generated code calls for operations that need more than
+/// one instruction.
+#[derive(Clone, Copy, PartialEq)]
+enum Helper {
+    /// Int `+`, trapping on overflow.
+    Add,
+    /// Int `-`, trapping on overflow.
+    Sub,
+    /// Int `*`, trapping on overflow.
+    Mul,
+    /// `abs` of an Int, trapping on the smallest one.
+    Abs,
+    /// Int `**`, trapping on overflow and on exponents that aren't a u32;
+    /// the interpreter gives a Float for a negative exponent.
+    Pow,
+    /// Float `/`, trapping on division by zero.
+    FloatDiv,
+}
+
+impl Helper {
+    /// The helper's parameters, result and encoded body, calling other
+    /// helpers through `module`.
+    fn build(self, module: &mut Module) -> (Vec<Ty>, Ty, Vec<u8>) {
+        let mut c = Code::default();
+        let (a, b, r) = (0, 1, 2);
+        let params = match self {
+            Helper::Abs => vec![Ty::Int],
+            Helper::FloatDiv => vec![Ty::Float, Ty::Float],
+            _ => vec![Ty::Int, Ty::Int],
+        };
+        let trap_if = |c: &mut Code| {
+            c.op(IF);
+            c.op(EMPTY);
+            c.op(UNREACHABLE);
+            c.op(END);
+        };
+        match self {
+            // Overflow iff both operands' signs differ from the result's.
+            Helper::Add => {
+                c.op_u(LOCAL_GET, a);
+                c.op_u(LOCAL_GET, b);
+                c.op(I64_ADD);
+                c.op_u(LOCAL_TEE, r);
+                c.op_u(LOCAL_GET, a);
+                c.op(I64_XOR);
+                c.op_u(LOCAL_GET, r);
+                c.op_u(LOCAL_GET, b);
+                c.op(I64_XOR);
+                c.op(I64_AND);
+                c.i64_const(0);
+                c.op(I64_LT_S);
+                trap_if(&mut c);
+                c.op_u(LOCAL_GET, r);
+            }
+            // Overflow iff the operands' signs differ, and the result's
+            // differs from the first.
+            Helper::Sub => {
+                c.op_u(LOCAL_GET, a);
+                c.op_u(LOCAL_GET, b);
+                c.op(I64_SUB);
+                c.op_u(LOCAL_SET, r);
+                c.op_u(LOCAL_GET, a);
+                c.op_u(LOCAL_GET, b);
+                c.op(I64_XOR);
+                c.op_u(LOCAL_GET, a);
+                c.op_u(LOCAL_GET, r);
+                c.op(I64_XOR);
+                c.op(I64_AND);
+                c.i64_const(0);
+                c.op(I64_LT_S);
+                trap_if(&mut c);
+                c.op_u(LOCAL_GET, r);
+            }
+            // Overflow iff dividing the result by a doesn't give b back;
+            // the division itself traps for i64::MIN / -1.
+            Helper::Mul => {
+                c.op_u(LOCAL_GET, a);
+                c.op(I64_EQZ);
+                c.op(IF);
+                c.op(I64);
+                c.i64_const(0);
+                c.op(ELSE);
+                c.op_u(LOCAL_GET, a);
+                c.op_u(LOCAL_GET, b);
+                c.op(I64_MUL);
+                c.op_u(LOCAL_TEE, r);
+                c.op_u(LOCAL_GET, a);
+                c.op(I64_DIV_S);
+                c.op_u(LOCAL_GET, b);
+                c.op(I64_NE);
+                trap_if(&mut c);
+                c.op_u(LOCAL_GET, r);
+                c.op(END);
+            }
+            Helper::Abs => {
+                let sub = module.helper(Helper::Sub);
+                c.op_u(LOCAL_GET, a);
+                c.i64_const(0);
+                c.op(I64_LT_S);
+                c.op(IF);
+                c.op(I64);
+                c.i64_const(0);
+                c.op_u(LOCAL_GET, a);
+                c.op_u(CALL, sub as u64);
+                c.op(ELSE);
+                c.op_u(LOCAL_GET, a);
+                c.op(END);
+            }
+            // Squaring and multiplying, squaring only while bits remain so
+            // that a needless square can't overflow.
+            Helper::Pow => {
+                let mul = module.helper(Helper::Mul) as u64;
+                c.op_u(LOCAL_GET, b);
+                c.i64_const(u32::MAX as i64);
+                c.op(I64_GT_U);
+                trap_if(&mut c);
+                c.i64_const(1);
+                c.op_u(LOCAL_SET, r);
+                c.op(BLOCK);
+                c.op(EMPTY);
+                c.op(LOOP);
+                c.op(EMPTY);
+                c.op_u(LOCAL_GET, b);
+                c.op(I64_EQZ);
+                c.op_u(BR_IF, 1);
+                c.op_u(LOCAL_GET, b);
+                c.op(I32_WRAP_I64);
+                c.i32_const(1);
+                c.op(I32_AND);
+                c.op(IF);
+                c.op(EMPTY);
+                c.op_u(LOCAL_GET, r);
+                c.op_u(LOCAL_GET, a);
+                c.op_u(CALL, mul);
+                c.op_u(LOCAL_SET, r);
+                c.op(END);
+                c.op_u(LOCAL_GET, b);
+                c.i64_const(1);
+                c.op(I64_SHR_S);
+                c.op_u(LOCAL_TEE, b);
+                c.op(I64_EQZ);
+                c.op_u(BR_IF, 1);
+                c.op_u(LOCAL_GET, a);
+                c.op_u(LOCAL_GET, a);
+                c.op_u(CALL, mul);
+                c.op_u(LOCAL_SET, a);
+                c.op_u(BR, 0);
+                c.op(END);
+                c.op(END);
+                c.op_u(LOCAL_GET, r);
+            }
+            Helper::FloatDiv => {
+                c.op_u(LOCAL_GET, b);
+                c.f64_const(0.0);
+                c.op(F64_EQ);
+                trap_if(&mut c);
+                c.op_u(LOCAL_GET, a);
+                c.op_u(LOCAL_GET, b);
+                c.op(F64_DIV);
+            }
+        }
+
+        let (locals, ret): (&[u8], Ty) = match self {
+            Helper::Add | Helper::Sub | Helper::Mul | Helper::Pow => (&[1, 1, I64], Ty::Int),
+            Helper::Abs => (&[0], Ty::Int),
+            Helper::FloatDiv => (&[0], Ty::Float),
+        };
+        let mut body = locals.to_vec();
+        body.extend(c.0);
+        body.push(END);
+        (params, ret, body)
+    }
+}
+
+/// Instructions being encoded.
+#[derive(Default)]
+struct Code(Vec<u8>);
+
+impl Code {
+    fn op(&mut self, op: u8) {
+        self.0.push(op);
+    }
+
+    /// An instruction with an index or depth.
+    fn op_u(&mut self, op: u8, n: u64) {
+        self.0.push(op);
+        uleb(&mut self.0, n);
+    }
+
+    fn i32_const(&mut self, n: i32) {
+        self.0.push(I32_CONST);
+        sleb(&mut self.0, n as i64);
+    }
+
+    fn i64_const(&mut self, n: i64) {
+        self.0.push(I64_CONST);
+        sleb(&mut self.0, n);
+    }
+
+    fn f64_const(&mut self, f: f64) {
+        self.0.push(F64_CONST);
+        self.0.extend(f.to_le_bytes());
+    }
+}
+
+/// A module's functions, exported ones first, then the helpers they use.
+struct Module {
+    functions: Vec<(Vec<Ty>, Ty, Vec<u8>)>,
+    exports: Vec<String>,
+    /// How many functions are exported, which is where the helpers start.
+    exported: usize,
+    helpers: Vec<Helper>,
+}
+
+impl Module {
+    fn new(exported: usize) -> Self {
+        Module {
+            functions: Vec::new(),
+            exports: Vec::new(),
+            exported,
+            helpers: Vec::new(),
+        }
+    }
+
+    /// The index of `helper`, which is added if this is its first use.
+    fn helper(&mut self, helper: Helper) -> u32 {
+        let i = match self.helpers.iter().position(|h| *h == helper) {
+            Some(i) => i,
+            None => {
+                self.helpers.push(helper);
+                self.helpers.len() - 1
+            }
+        };
+        (self.exported + i) as u32
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        // Building a helper can add the ones it calls.
+        let mut i = 0;
+        while i < self.helpers.len() {
+            let function = self.helpers[i].build(&mut self);
+            self.functions.push(function);
+            i += 1;
+        }
+
+        let mut types = Vec::new();
+        uleb(&mut types, self.functions.len() as u64);
+        for (params, ret, _) in &self.functions {
+            types.push(FUNC);
+            uleb(&mut types, params.len() as u64);
+            types.extend(params.iter().filter_map(|p| p.encoding()));
+            let results: Vec<u8> = ret.encoding().into_iter().collect();
+            uleb(&mut types, results.len() as u64);
+            types.extend(results);
+        }
+
+        let mut functions = Vec::new();
+        uleb(&mut functions, self.functions.len() as u64);
+        for i in 0..self.functions.len() {
+            uleb(&mut functions, i as u64);
+        }
+
+        let mut exports = Vec::new();
+        uleb(&mut exports, self.exports.len() as u64);
+        for (i, name) in self.exports.iter().enumerate() {
+            uleb(&mut exports, name.len() as u64);
+            exports.extend(name.as_bytes());
+            exports.push(EXPORT_FUNC);
+            uleb(&mut exports, i as u64);
+        }
+
+        let mut code = Vec::new();
+        uleb(&mut code, self.functions.len() as u64);
+        for (_, _, body) in &self.functions {
+            uleb(&mut code, body.len() as u64);
+            code.extend(body);
+        }
+
+        let mut out = b"\0asm\x01\0\0\0".to_vec();
+        for (id, contents) in [(1, types), (3, functions), (7, exports), (10, code)] {
+            out.push(id);
+            uleb(&mut out, contents.len() as u64);
+            out.extend(contents);
+        }
+        out
+    }
+}
+
+fn uleb(out: &mut Vec<u8>, mut n: u64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn sleb(out: &mut Vec<u8>, mut n: i64) {
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        let done = (n == 0 && byte & 0x40 == 0) || (n == -1 && byte & 0x40 != 0);
+        if done {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+const I32: u8 = 0x7f;
+const I64: u8 = 0x7e;
+const F64: u8 = 0x7c;
+const EMPTY: u8 = 0x40;
+const FUNC: u8 = 0x60;
+const EXPORT_FUNC: u8 = 0x00;
+
+const UNREACHABLE: u8 = 0x00;
+const BLOCK: u8 = 0x02;
+const LOOP: u8 = 0x03;
+const IF: u8 = 0x04;
+const ELSE: u8 = 0x05;
+const END: u8 = 0x0b;
+const BR: u8 = 0x0c;
+const BR_IF: u8 = 0x0d;
+const RETURN: u8 = 0x0f;
+const CALL: u8 = 0x10;
+const DROP: u8 = 0x1a;
+const LOCAL_GET: u8 = 0x20;
+const LOCAL_SET: u8 = 0x21;
+const LOCAL_TEE: u8 = 0x22;
+const I32_CONST: u8 = 0x41;
+const I64_CONST: u8 = 0x42;
+const F64_CONST: u8 = 0x44;
+const I32_EQZ: u8 = 0x45;
+const I32_EQ: u8 = 0x46;
+const I32_NE: u8 = 0x47;
+const I64_EQZ: u8 = 0x50;
+const I64_EQ: u8 = 0x51;
+const I64_NE: u8 = 0x52;
+const I64_LT_S: u8 = 0x53;
+const I64_GT_S: u8 = 0x55;
+const I64_GT_U: u8 = 0x56;
+const I64_LE_S: u8 = 0x57;
+const I64_GE_S: u8 = 0x59;
+const F64_EQ: u8 = 0x61;
+const F64_NE: u8 = 0x62;
+const F64_LT: u8 = 0x63;
+const F64_GT: u8 = 0x64;
+const F64_LE: u8 = 0x65;
+const F64_GE: u8 = 0x66;
+const I32_AND: u8 = 0x71;
+const I32_OR: u8 = 0x72;
+const I64_ADD: u8 = 0x7c;
+const I64_SUB: u8 = 0x7d;
+const I64_MUL: u8 = 0x7e;
+const I64_DIV_S: u8 = 0x7f;
+const I64_REM_S: u8 = 0x81;
+const I64_AND: u8 = 0x83;
+const I64_XOR: u8 = 0x85;
+const I64_SHR_S: u8 = 0x87;
+const F64_ABS: u8 = 0x99;
+const F64_NEG: u8 = 0x9a;
+const F64_FLOOR: u8 = 0x9c;
+const F64_SQRT: u8 = 0x9f;
+const F64_ADD: u8 = 0xa0;
+const F64_SUB: u8 = 0xa1;
+const F64_MUL: u8 = 0xa2;
+const F64_DIV: u8 = 0xa3;
+const I32_WRAP_I64: u8 = 0xa7;
+const F64_CONVERT_I64_S: u8 = 0xb9;
+const I64_TRUNC_SAT_F64_S: [u8; 2] = [0xfc, 0x06];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse;
+
+    fn wasm(source: &str) -> (Vec<u8>, Vec<String>) {
+        to_wasm(&parse(source).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_module() {
+        let (module, notes) = wasm("fn answer() { 42 }\nprintln(answer())");
+        assert!(notes.is_empty());
+        #[rustfmt::skip]
+        assert_eq!(module, [
+            0x00, 0x61, 0x73, 0x6d, 0x01, 0x00, 0x00, 0x00,
+            // Types: () -> i64.
+            0x01, 0x05, 0x01, 0x60, 0x00, 0x01, 0x7e,
+            // Functions.
+            0x03, 0x02, 0x01, 0x00,
+            // Exports: "answer".
+            0x07, 0x0a, 0x01, 0x06, b'a', b'n', b's', b'w', b'e', b'r', 0x00, 0x00,
+            // Code: i64.const 42.
+            0x0a, 0x06, 0x01, 0x04, 0x00, 0x42, 0x2a, 0x0b,
+        ]);
+    }
+
+    #[test]
+    fn test_inferred_types() {
+        // f64 -> f64, then i64 i64 -> i32.
+        let (module, _) = wasm("fn half(x) { x / 2.0 }\nfn before(a, b) { a < b }");
+        let types = [
+            0x60, 0x01, 0x7c, 0x01, 0x7c, 0x60, 0x02, 0x7e, 0x7e, 0x01, 0x7f,
+        ];
+        assert!(module.windows(types.len()).any(|w| w == types));
+    }
+
+    #[test]
+    fn test_left_out_functions() {
+        let (_, notes) = wasm(
+            "fn greet(name) { \"hi \" + name }\nfn twice(x) { greet(x) }\n\
+             fn mixed(x) { if (x) { 1 } else { 2.0 } }\nfn ok(x) { x }",
+        );
+        assert_eq!(
+            notes,
+            [
+                "greet() isn't exported: it uses a string",
+                "twice() isn't exported: it calls greet(), which can't be compiled",
+                "mixed() isn't exported: a value would have to be both Int and Float",
+            ]
+        );
+        assert_eq!(
+            to_wasm(&parse("println(1)").unwrap()),
+            Err("The script has no top-level functions to export".to_string())
+        );
+    }
+
+    #[test]
+    fn test_leb128() {
+        let mut out = Vec::new();
+        uleb(&mut out, 624485);
+        sleb(&mut out, -123456);
+        sleb(&mut out, 64);
+        assert_eq!(out, [0xe5, 0x8e, 0x26, 0xc0, 0xbb, 0x78, 0xc0, 0x00]);
+    }
+}
@@ -0,0 +1,140 @@
+//! The wildcard patterns behind `glob_match` and `like`, for matching names
+//! and text without a regular expression.
+
+enum Token {
+    Literal(char),
+    /// `?`: any one character.
+    Any,
+    /// `*`: any run of characters, including none.
+    Star,
+    /// `[a-z_]`, or `[!a-z_]` when negated.
+    Class {
+        negated: bool,
+        ranges: Vec<(char, char)>,
+    },
+}
+
+/// Whether `name` matches the glob `pattern`. `*` matches any run of
+/// characters and `?` any one, neither crossing a `/`, and `[abc]`, `[a-z]`
+/// and `[!abc]` match one character in, or not in, the set.
+pub fn glob_match(pattern: &str, name: &str) -> Result<bool, String> {
+    let mut tokens = Vec::new();
+    let mut chars = pattern.chars();
+    while let Some(c) = chars.next() {
+        tokens.push(match c {
+            '*' => Token::Star,
+            '?' => Token::Any,
+            '[' => {
+                let rest = chars.as_str();
+                let negated = rest.starts_with('!');
+                let body = &rest[negated as usize..];
+                // A `]` first in the set is a member rather than its end.
+                let Some((end, _)) = body.char_indices().skip(1).find(|&(_, c)| c == ']') else {
+                    return Err(format!("glob_match() found an unclosed [ in {:?}", pattern));
+                };
+                let set: Vec<char> = body[..end].chars().collect();
+                let mut ranges = Vec::new();
+                let mut i = 0;
+                while i < set.len() {
+                    if i + 2 < set.len() && set[i + 1] == '-' {
+                        ranges.push((set[i], set[i + 2]));
+                        i += 3;
+                    } else {
+                        ranges.push((set[i], set[i]));
+                        i += 1;
+                    }
+                }
+                chars = body[end + 1..].chars();
+                Token::Class { negated, ranges }
+            }
+            c => Token::Literal(c),
+        });
+    }
+    Ok(matches(&tokens, name, Some('/')))
+}
+
+/// Whether `s` matches `pattern`, where `*` matches any run of characters
+/// and `?` any one.
+pub fn like(s: &str, pattern: &str) -> bool {
+    let tokens: Vec<_> = pattern
+        .chars()
+        .map(|c| match c {
+            '*' => Token::Star,
+            '?' => Token::Any,
+            c => Token::Literal(c),
+        })
+        .collect();
+    matches(&tokens, s, None)
+}
+
+/// Tracks every position in `text` the tokens so far can reach, so a
+/// pattern with many stars takes time proportional to its length times the
+/// text's rather than backtracking.
+fn matches(tokens: &[Token], text: &str, separator: Option<char>) -> bool {
+    let text: Vec<char> = text.chars().collect();
+    let mut reached = vec![false; text.len() + 1];
+    reached[0] = true;
+    for token in tokens {
+        let mut next = vec![false; text.len() + 1];
+        for i in 0..=text.len() {
+            match token {
+                Token::Star => {
+                    next[i] = reached[i] || i > 0 && next[i - 1] && Some(text[i - 1]) != separator;
+                }
+                _ if i == text.len() || !reached[i] => {}
+                Token::Literal(c) => next[i + 1] = text[i] == *c,
+                Token::Any => next[i + 1] = Some(text[i]) != separator,
+                Token::Class { negated, ranges } => {
+                    let c = text[i];
+                    next[i + 1] = Some(c) != separator
+                        && ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated;
+                }
+            }
+        }
+        reached = next;
+    }
+    reached[text.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_match() {
+        for (pattern, name, expected) in [
+            ("*.txt", "notes.txt", true),
+            ("*.txt", "notes.txt.bak", false),
+            ("*.txt", "docs/notes.txt", false),
+            ("docs/*.txt", "docs/notes.txt", true),
+            ("file?.rs", "file1.rs", true),
+            ("file?.rs", "file10.rs", false),
+            ("[a-c]*", "banana", true),
+            ("[!a-c]*", "banana", false),
+            ("[]x]", "]", true),
+            ("*a*b*", "xaybz", true),
+            ("", "", true),
+            ("*", "", true),
+        ] {
+            assert_eq!(
+                glob_match(pattern, name).unwrap(),
+                expected,
+                "{} {}",
+                pattern,
+                name
+            );
+        }
+        assert!(glob_match("[ab", "a").is_err());
+    }
+
+    #[test]
+    fn test_like() {
+        assert!(like("hello world", "hello*"));
+        assert!(like("a/b/c", "a*c"));
+        assert!(like("cat", "c?t"));
+        assert!(like("[x]", "[x]"));
+        assert!(!like("cart", "c?t"));
+        assert!(!like("hello", "*x*"));
+        assert!(like(&"a".repeat(50), &"a*".repeat(20)));
+    }
+}
@@ -0,0 +1,143 @@
+//! Runs every program in `examples/` and compares what it prints with the
+//! `.out` file next to it. After a deliberate change in output, rerun with
+//! `EW_BLESS=1` to rewrite the `.out` files, and review the diff.
+//!
+//! When `node` is installed, each example is also compiled to JavaScript
+//! and must print the same thing there, and likewise as an executable when
+//! there is a C compiler.
+
+use std::{env, fs, path::PathBuf, process::Command, thread};
+
+fn examples() -> Vec<PathBuf> {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("examples");
+    let mut paths: Vec<PathBuf> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "ew"))
+        .collect();
+    paths.sort();
+    paths
+}
+
+/// What the program at `path` prints. Examples recurse, so they get the
+/// stack of a main thread rather than a test thread's 2 MB.
+fn output(path: &PathBuf) -> Result<String, ew::EwError> {
+    let source = fs::read_to_string(path).unwrap();
+    thread::Builder::new()
+        .stack_size(8 << 20)
+        .spawn(move || {
+            ew::eval_captured(&source)
+                .map(|(_, output)| output)
+                .map_err(|(e, _)| e)
+        })
+        .unwrap()
+        .join()
+        .unwrap()
+}
+
+#[test]
+fn examples_match_golden_output() {
+    let paths = examples();
+    assert!(!paths.is_empty(), "no examples found");
+    let bless = env::var_os("EW_BLESS").is_some();
+
+    let mut failures = Vec::new();
+    for path in &paths {
+        let golden = path.with_extension("out");
+        let actual = match output(path) {
+            Ok(actual) => actual,
+            Err(e) => {
+                failures.push(format!("{} failed: {}", path.display(), e));
+                continue;
+            }
+        };
+        if bless {
+            fs::write(&golden, &actual).unwrap();
+            continue;
+        }
+        let expected = fs::read_to_string(&golden).unwrap_or_default();
+        if actual != expected {
+            failures.push(format!(
+                "{} printed something other than {}:\n{}",
+                path.display(),
+                golden.display(),
+                actual
+            ));
+        }
+    }
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}
+
+#[test]
+fn examples_match_golden_output_as_javascript() {
+    if Command::new("node").arg("--version").output().is_err() {
+        eprintln!("node not found; skipping the JavaScript examples");
+        return;
+    }
+    let dir = env::temp_dir().join(format!("ew-examples-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let mut failures = Vec::new();
+    for path in &examples() {
+        let program = ew::parse(&fs::read_to_string(path).unwrap()).unwrap();
+        let js = match ew::to_js(&program) {
+            Ok(js) => js,
+            Err(e) => {
+                failures.push(format!("{} failed to compile: {}", path.display(), e));
+                continue;
+            }
+        };
+        let script = dir.join(path.with_extension("js").file_name().unwrap());
+        fs::write(&script, js).unwrap();
+        let run = Command::new("node")
+            .arg("--stack-size=8000")
+            .arg(&script)
+            .output()
+            .unwrap();
+        let actual = String::from_utf8_lossy(&run.stdout);
+        let expected = fs::read_to_string(path.with_extension("out")).unwrap_or_default();
+        if !run.status.success() || actual != expected {
+            failures.push(format!(
+                "{} printed something else as JavaScript:\n{}{}",
+                path.display(),
+                actual,
+                String::from_utf8_lossy(&run.stderr)
+            ));
+        }
+    }
+    fs::remove_dir_all(&dir).ok();
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}
+
+#[test]
+fn examples_match_golden_output_natively() {
+    if Command::new("cc").arg("--version").output().is_err() {
+        eprintln!("cc not found; skipping the native examples");
+        return;
+    }
+    let dir = env::temp_dir().join(format!("ew-native-examples-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let mut failures = Vec::new();
+    for path in &examples() {
+        let program = ew::parse(&fs::read_to_string(path).unwrap()).unwrap();
+        let binary = dir.join(path.file_stem().unwrap());
+        if let Err(e) = ew::build_native(&program, None, &binary) {
+            failures.push(format!("{} failed to compile: {}", path.display(), e));
+            continue;
+        }
+        let run = Command::new(&binary).output().unwrap();
+        let actual = String::from_utf8_lossy(&run.stdout);
+        let expected = fs::read_to_string(path.with_extension("out")).unwrap_or_default();
+        if !run.status.success() || actual != expected {
+            failures.push(format!(
+                "{} printed something else natively:\n{}{}",
+                path.display(),
+                actual,
+                String::from_utf8_lossy(&run.stderr)
+            ));
+        }
+    }
+    fs::remove_dir_all(&dir).ok();
+    assert!(failures.is_empty(), "{}", failures.join("\n\n"));
+}
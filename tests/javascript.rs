@@ -0,0 +1,59 @@
+//! Compiles programs to JavaScript and runs them under `node`, when it is
+//! installed, checking that numbers come out as the interpreter prints them.
+
+use std::{env, fs, process::Command};
+
+const PROGRAM: &str = r#"
+fn half(x) { x / 2.0 }
+println(4.0 / 8.0, 7 / 2, half(3.0), 1.0, -0.5 * 2.0)
+println(2 ** 52, 2 ** -1, 2.0 ** 3, 7 % 3, 7.5 % 2.0)
+println([1.5, 2.0], sqrt(16), abs(-2.5), abs(-2), floor(2.7))
+println(round(2.5, 0), round(-2.5, 0), divmod(7, -2), divmod(7.0, 2.0))
+println(1.0 == 1.0, 1000000000000000000000.0, 0.0000001, parse_float("3"))
+let x = 0.1 + 0.2
+println(x, -x, x < 1.0, sort([2.5, 0.5]))
+println(to_fixed(0.125, 2), to_fixed(2.5, 0), to_fixed(-0.0, 1), to_fixed(-0.001, 2))
+println(to_fixed(1000000000000000000000.0, 1), to_fixed(parse_float("nan"), 2))
+println(parse_float("inf"), parse_float("-Infinity"), parse_float("0x10"), parse_float(".5e1"))
+"#;
+
+/// Runs `source` compiled to JavaScript, returning whether it succeeded,
+/// and its output and errors.
+fn node(source: &str) -> (bool, String, String) {
+    let js = ew::to_js(&ew::parse(source).unwrap()).unwrap();
+    let path = env::temp_dir().join(format!("ew-js-{}-{}.js", std::process::id(), js.len()));
+    fs::write(&path, js).unwrap();
+    let run = Command::new("node").arg(&path).output().unwrap();
+    fs::remove_file(&path).ok();
+    (
+        run.status.success(),
+        String::from_utf8_lossy(&run.stdout).into_owned(),
+        String::from_utf8_lossy(&run.stderr).into_owned(),
+    )
+}
+
+#[test]
+fn javascript_numbers_match_the_interpreter() {
+    if Command::new("node").arg("--version").output().is_err() {
+        eprintln!("node not found; skipping the JavaScript tests");
+        return;
+    }
+    let (ok, output, errors) = node(PROGRAM);
+    assert!(ok, "{}", errors);
+    let (_, expected) = ew::eval_captured(PROGRAM).unwrap();
+    assert_eq!(output, expected);
+
+    // Int results past 2^53 overflow rather than losing precision.
+    for source in [
+        "println(2 ** 70)",
+        "let n = 4503599627370496\nprintln(n * 2)",
+    ] {
+        let (ok, output, errors) = node(source);
+        assert!(!ok, "{} printed {}", source, output);
+        assert!(errors.contains("Integer overflow"), "{}", errors);
+    }
+    assert_eq!(
+        ew::to_js(&ew::parse("9223372036854775807 + 1").unwrap()).err(),
+        Some("Integers past 2^53 can't be compiled to JavaScript".to_string())
+    );
+}
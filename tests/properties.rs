@@ -0,0 +1,120 @@
+//! Property tests: no input, however odd, should make the parser or the
+//! interpreter panic. Errors are fine, crashes are not.
+
+use ew::{
+    Interpreter, Redeclaration,
+    ast::{BinaryOp, Expr, Stmt, UnaryOp},
+    parse, parse_all, tokenize,
+};
+use proptest::{prelude::*, sample::select};
+
+const VARS: &[&str] = &["a", "b", "xs", "s"];
+
+/// Builtins without side effects or unbounded cost.
+const PURE_BUILTINS: &[&str] = &[
+    "len",
+    "abs",
+    "floor",
+    "sqrt",
+    "sin",
+    "cos",
+    "parse_int",
+    "parse_float",
+];
+
+fn binary_op() -> impl Strategy<Value = BinaryOp> {
+    // `**` is left out: nested powers of big integers grow without bound.
+    select(vec![
+        BinaryOp::Add,
+        BinaryOp::Sub,
+        BinaryOp::Mul,
+        BinaryOp::Div,
+        BinaryOp::Mod,
+        BinaryOp::Eq,
+        BinaryOp::Ne,
+        BinaryOp::Lt,
+        BinaryOp::Le,
+        BinaryOp::Gt,
+        BinaryOp::Ge,
+        BinaryOp::And,
+        BinaryOp::Or,
+    ])
+}
+
+fn leaf() -> impl Strategy<Value = Expr> {
+    prop_oneof![
+        (-8i64..8).prop_map(Expr::Int),
+        (-8.0f64..8.0).prop_map(Expr::Float),
+        any::<bool>().prop_map(Expr::Bool),
+        "[a-z0-9 ]{0,6}".prop_map(|s| Expr::str(&s)),
+        Just(Expr::Null),
+        select(VARS).prop_map(Expr::var),
+    ]
+}
+
+fn expr() -> impl Strategy<Value = Expr> {
+    leaf().prop_recursive(3, 48, 4, |inner| {
+        let body = prop::collection::vec(inner.clone().prop_map(Stmt::Expr), 0..3);
+        prop_oneof![
+            (binary_op(), inner.clone(), inner.clone())
+                .prop_map(|(op, l, r)| Expr::binary(op, l, r)),
+            (select(vec![UnaryOp::Neg, UnaryOp::Not]), inner.clone())
+                .prop_map(|(op, e)| Expr::unary(op, e)),
+            prop::collection::vec(inner.clone(), 0..4).prop_map(Expr::array),
+            (select(VARS), inner.clone()).prop_map(|(name, i)| Expr::index(name, [i])),
+            (
+                select(PURE_BUILTINS),
+                prop::collection::vec(inner.clone(), 0..3)
+            )
+                .prop_map(|(name, args)| Expr::call(name, args)),
+            (inner.clone(), body.clone(), body.clone())
+                .prop_map(|(cond, then, else_)| Expr::if_else(cond, then, else_)),
+            (select(VARS), -2i64..4, body).prop_map(|(var, n, body)| Expr::for_range(
+                var,
+                Expr::int(0),
+                Expr::int(n),
+                body
+            )),
+        ]
+    })
+}
+
+fn stmt() -> impl Strategy<Value = Stmt> {
+    prop_oneof![
+        (select(VARS), expr()).prop_map(|(name, e)| Stmt::assign(name, e)),
+        (select(VARS), expr()).prop_map(|(name, e)| Stmt::reassign(name, e)),
+        (select(VARS), expr(), expr()).prop_map(|(name, i, e)| Stmt::reassign_index(name, [i], e)),
+        expr().prop_map(Stmt::Expr),
+    ]
+}
+
+proptest! {
+    #[test]
+    fn parser_never_panics(source in "\\PC{0,200}") {
+        let _ = parse(&source);
+        let _ = parse_all(&source);
+        let _ = tokenize(&source);
+    }
+
+    #[test]
+    fn parser_never_panics_on_code_like_input(
+        source in r#"[a-z0-9(){}\[\]"=+*/%<>!&|.,\- \n]{0,120}"#
+    ) {
+        let _ = parse(&source);
+        let _ = parse_all(&source);
+    }
+
+    #[test]
+    fn interpreter_never_panics(program in prop::collection::vec(stmt(), 0..8)) {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_redeclaration(Redeclaration::Lenient);
+        let prelude = parse(r#"let a = 3  let b = 1.5  let xs = [1, [2, 3]]  let s = "str""#);
+        interpreter.run(&prelude.unwrap()).unwrap();
+
+        // Statements run one at a time so an early runtime error doesn't
+        // hide the rest of the program.
+        for stmt in program {
+            let _ = interpreter.run(&[stmt]);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Compiles functions to WebAssembly and calls them under `node`, when it is
+//! installed, checking the results against the interpreter's.
+
+use std::{env, fs, process::Command};
+
+const PROGRAM: &str = r#"
+fn fib(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }
+fn hyp(a, b) { sqrt(a * a + b * b) }
+fn fact(n) {
+    let acc = 1
+    for i in 1..n + 1 { acc = acc * i }
+    acc
+}
+fn first_square_over(n) {
+    let i = 0
+    loop {
+        if (i * i > n) { break i }
+        i = i + 1
+    }
+}
+fn find_pair(total) {
+    let found = -1
+    outer: for a in 0..total {
+        for b in 0..total {
+            if (a * b == total) {
+                found = a * 100 + b
+                break outer
+            }
+        }
+    }
+    found
+}
+fn collatz(n) {
+    let steps = 0
+    while (n != 1) {
+        if (n % 2 == 0) { n = n / 2 } else { n = 3 * n + 1 }
+        steps = steps + 1
+    }
+    steps
+}
+fn implies(a, b) { !a || b }
+fn floored(x) { floor(x * 1.0) - abs(-3) }
+fn power(b, e) { b ** e }
+fn ratio(a, b) { a / b }
+fn shifted(n) { let m = n + 1 in let n = m * 2 in n + m }
+"#;
+
+/// The calls to make, in ew and in JavaScript.
+const CALLS: &[(&str, &str)] = &[
+    ("fib(20)", "e.fib(20n)"),
+    ("hyp(3, 4)", "e.hyp(3n, 4n)"),
+    ("fact(20)", "e.fact(20n)"),
+    ("first_square_over(50)", "e.first_square_over(50n)"),
+    ("find_pair(12)", "e.find_pair(12n)"),
+    ("collatz(27)", "e.collatz(27n)"),
+    ("implies(true, false)", "e.implies(1, 0) === 1"),
+    ("floored(-2.5)", "e.floored(-2.5)"),
+    ("power(-2, 63)", "e.power(-2n, 63n)"),
+    ("shifted(4)", "e.shifted(4n)"),
+];
+
+#[test]
+fn wasm_functions_match_the_interpreter() {
+    if Command::new("node").arg("--version").output().is_err() {
+        eprintln!("node not found; skipping the WebAssembly tests");
+        return;
+    }
+    let program = ew::parse(PROGRAM).unwrap();
+    let (module, notes) = ew::to_wasm(&program).unwrap();
+    assert!(notes.is_empty(), "{:?}", notes);
+
+    let path = env::temp_dir().join(format!("ew-wasm-{}.wasm", std::process::id()));
+    fs::write(&path, module).unwrap();
+    let mut script = format!(
+        "const e = new WebAssembly.Instance(new WebAssembly.Module(\
+         require('fs').readFileSync({:?}))).exports;\n",
+        path
+    );
+    for (_, js) in CALLS {
+        script.push_str(&format!("console.log(String({}));\n", js));
+    }
+    // Overflow and division by zero trap.
+    script.push_str("for (const f of [() => e.fact(21n), () => e.ratio(1n, 0n)]) {\n");
+    script.push_str("  try { f(); console.log('no trap'); } catch { console.log('trap'); }\n}\n");
+    let run = Command::new("node")
+        .arg("-e")
+        .arg(&script)
+        .output()
+        .unwrap();
+    fs::remove_file(&path).ok();
+    assert!(
+        run.status.success(),
+        "{}",
+        String::from_utf8_lossy(&run.stderr)
+    );
+
+    let mut expected = String::new();
+    for (ew, _) in CALLS {
+        let source = format!("{}\n{}", PROGRAM, ew);
+        expected.push_str(&format!("{}\n", ew::run(&source).unwrap()));
+    }
+    expected.push_str("trap\ntrap\n");
+    assert_eq!(String::from_utf8_lossy(&run.stdout), expected);
+}